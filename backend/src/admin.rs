@@ -0,0 +1,18 @@
+use axum::Router;
+
+use crate::{AppState, audit, auth, invite, quota, reconcile};
+
+/// Instance administration endpoints, mounted under `/admin`. Modules add
+/// their admin routes here so they all sit behind the admin check.
+pub(crate) fn routes(app_state: AppState) -> Router {
+    Router::new()
+        .merge(audit::admin_routes(app_state.clone()))
+        .merge(auth::admin_routes(app_state.clone()))
+        .merge(invite::admin_routes(app_state.clone()))
+        .merge(quota::admin_routes(app_state.clone()))
+        .merge(reconcile::admin_routes(app_state.clone()))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state,
+            auth::middleware::require_admin,
+        ))
+}
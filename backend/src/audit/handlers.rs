@@ -0,0 +1,47 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use sdk::dtos::audit::AuditEvent;
+use serde::Deserialize;
+
+use super::repository::{AuditRepository, DbAuditRepository};
+use crate::{AppState, error::Result, ulid::Id};
+
+const DEFAULT_PAGE_SIZE: u64 = 100;
+const MAX_PAGE_SIZE: u64 = 1000;
+
+#[derive(Deserialize)]
+pub(super) struct AuditQuery {
+    user_id: Option<Id>,
+    limit: Option<u64>,
+}
+
+/// The most recent audit events, newest first.
+pub(super) async fn list_audit_events(
+    State(state): State<AppState>,
+    Query(params): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let repo = DbAuditRepository {
+        db: state.db.clone(),
+    };
+    let events = repo
+        .list_events(params.user_id.as_ref(), limit)
+        .await?
+        .into_iter()
+        .map(|row| AuditEvent {
+            id: row.id.into(),
+            kind: row.kind,
+            user_id: row.user_id.map(Into::into),
+            username: row.username,
+            ip: row.ip,
+            created_at: row.created_at,
+        })
+        .collect();
+
+    Ok(Json(events))
+}
@@ -0,0 +1,66 @@
+//! Audit trail of security-relevant events, such as failed and throttled
+//! sign-ins. Each event is also logged under the `audit` target. Recording
+//! is best effort: an event that can't be stored is logged, but never fails
+//! the request it describes. Events are kept for the retention period only.
+
+mod handlers;
+mod repository;
+mod routes;
+
+use std::net::IpAddr;
+
+use sdk::dtos::audit::AuditEventKind;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+
+use crate::AppState;
+use crate::database::DbPool;
+use crate::ulid::Id;
+
+use repository::{AuditRepository, DbAuditRepository};
+
+pub(crate) use routes::admin_routes;
+
+pub(crate) struct AuditEntry<'a> {
+    pub kind: AuditEventKind,
+    pub user_id: Option<Id>,
+    pub username: Option<&'a str>,
+    pub ip: Option<IpAddr>,
+}
+
+pub(crate) async fn record(db: &DbPool, entry: AuditEntry<'_>) {
+    info!(
+        target: "audit",
+        kind = repository::kind_str(entry.kind),
+        user_id = entry.user_id.map(|id| id.to_string()),
+        username = entry.username,
+        ip = entry.ip.map(|ip| ip.to_string()),
+        "Audit event"
+    );
+    let repo = DbAuditRepository { db: db.clone() };
+    // already logged by the repository
+    let _ = repo.save_event(&entry).await;
+}
+
+/// Delete audit events older than the retention period.
+pub(crate) async fn purge_old_events(state: AppState) {
+    let interval_secs = state.config.audit.purge_interval_secs;
+    let retention = Duration::days(state.config.audit.retention_days);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        let repo = DbAuditRepository {
+            db: state.db.clone(),
+        };
+        match repo
+            .delete_events_before(OffsetDateTime::now_utc() - retention)
+            .await
+        {
+            Ok(0) => {}
+            Ok(count) => info!(count, "Purged old audit events"),
+            Err(e) => error!(error = %e, "Failed to purge old audit events"),
+        }
+    }
+}
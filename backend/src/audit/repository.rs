@@ -0,0 +1,178 @@
+use sdk::dtos::audit::AuditEventKind;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
+use time::OffsetDateTime;
+use tracing::error;
+
+use super::AuditEntry;
+use crate::database::DbPool;
+use crate::entity::{audit_events, prelude::AuditEvents};
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+/// Longest username kept, so sign-ins with made-up names can't bloat the
+/// table.
+const MAX_USERNAME_CHARS: usize = 100;
+
+pub(crate) struct AuditEventRow {
+    pub id: Id,
+    pub kind: AuditEventKind,
+    pub user_id: Option<Id>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+pub(super) fn kind_str(kind: AuditEventKind) -> &'static str {
+    match kind {
+        AuditEventKind::LoginFailed => "login_failed",
+        AuditEventKind::LoginThrottled => "login_throttled",
+        AuditEventKind::AccountLocked => "account_locked",
+        AuditEventKind::IpLocked => "ip_locked",
+    }
+}
+
+fn parse_kind(kind: &str) -> Option<AuditEventKind> {
+    [
+        AuditEventKind::LoginFailed,
+        AuditEventKind::LoginThrottled,
+        AuditEventKind::AccountLocked,
+        AuditEventKind::IpLocked,
+    ]
+    .into_iter()
+    .find(|candidate| kind_str(*candidate) == kind)
+}
+
+pub(crate) trait AuditRepository {
+    async fn save_event(&self, entry: &AuditEntry<'_>) -> Result<()>;
+    /// Newest first, optionally only those naming `user_id`.
+    async fn list_events(&self, user_id: Option<&Id>, limit: u64) -> Result<Vec<AuditEventRow>>;
+    /// Returns how many events from before `cutoff` were deleted.
+    async fn delete_events_before(&self, cutoff: OffsetDateTime) -> Result<u64>;
+}
+
+pub(crate) struct DbAuditRepository {
+    pub db: DbPool,
+}
+
+impl AuditRepository for DbAuditRepository {
+    async fn save_event(&self, entry: &AuditEntry<'_>) -> Result<()> {
+        audit_events::ActiveModel {
+            id: Set(uuid::Uuid::from(Id::new())),
+            kind: Set(kind_str(entry.kind).to_string()),
+            user_id: Set(entry.user_id.map(uuid::Uuid::from)),
+            username: Set(entry
+                .username
+                .map(|name| name.chars().take(MAX_USERNAME_CHARS).collect())),
+            ip: Set(entry.ip.map(|ip| ip.to_string())),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save audit event");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    async fn list_events(&self, user_id: Option<&Id>, limit: u64) -> Result<Vec<AuditEventRow>> {
+        let mut query = AuditEvents::find();
+        if let Some(user_id) = user_id {
+            query = query.filter(audit_events::Column::UserId.eq(uuid::Uuid::from(*user_id)));
+        }
+        let rows = query
+            .order_by_desc(audit_events::Column::CreatedAt)
+            .order_by_desc(audit_events::Column::Id)
+            .limit(limit)
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list audit events");
+                Error::Database
+            })?;
+
+        rows.into_iter()
+            .map(|row| {
+                let kind = parse_kind(&row.kind).ok_or_else(|| {
+                    error!(kind = row.kind, "Unknown audit event kind");
+                    Error::Database
+                })?;
+                Ok(AuditEventRow {
+                    id: Id::from(row.id),
+                    kind,
+                    user_id: row.user_id.map(Id::from),
+                    username: row.username,
+                    ip: row.ip,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_events_before(&self, cutoff: OffsetDateTime) -> Result<u64> {
+        let result = AuditEvents::delete_many()
+            .filter(audit_events::Column::CreatedAt.lt(cutoff))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete old audit events");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use sea_orm::sea_query::Expr;
+    use time::Duration;
+
+    #[tokio::test]
+    async fn deletes_only_events_past_the_cutoff() {
+        let Some(db) = test_db().await else { return };
+        let user_id = crate::auth::new_user(&db).await;
+        let repo = DbAuditRepository { db: db.clone() };
+        let entry = |kind| AuditEntry {
+            kind,
+            user_id: Some(user_id),
+            username: None,
+            ip: None,
+        };
+        repo.save_event(&entry(AuditEventKind::LoginFailed))
+            .await
+            .unwrap();
+        AuditEvents::update_many()
+            .col_expr(
+                audit_events::Column::CreatedAt,
+                Expr::value(OffsetDateTime::now_utc() - Duration::days(2)),
+            )
+            .filter(audit_events::Column::UserId.eq(uuid::Uuid::from(user_id)))
+            .exec(&db)
+            .await
+            .unwrap();
+        repo.save_event(&entry(AuditEventKind::LoginThrottled))
+            .await
+            .unwrap();
+
+        let deleted = repo
+            .delete_events_before(OffsetDateTime::now_utc() - Duration::days(1))
+            .await
+            .unwrap();
+
+        assert!(deleted >= 1);
+        let kinds: Vec<_> = repo
+            .list_events(Some(&user_id), 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.kind)
+            .collect();
+        assert_eq!(kinds, vec![AuditEventKind::LoginThrottled]);
+    }
+}
@@ -0,0 +1,12 @@
+use axum::{Router, routing::get};
+
+use crate::AppState;
+
+use super::handlers;
+
+/// Mounted under `/admin` by [`crate::admin::routes`].
+pub(crate) fn admin_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/audit-events", get(handlers::list_audit_events))
+        .with_state(app_state)
+}
@@ -0,0 +1,253 @@
+//! In-process cache of resolved access tokens, so authenticated requests
+//! don't each cost a database round trip. Entries live for a short TTL (and
+//! never past the token's own expiry); revocations evict them right away.
+//! The cache is per process: another instance only sees a revocation once
+//! its own entry times out.
+
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+
+use crate::session::Session;
+use crate::ulid::Id;
+
+/// Keyed by the token's digest, so raw tokens aren't kept around.
+type Key = [u8; 32];
+
+struct Entry {
+    session: Session,
+    valid_until: OffsetDateTime,
+}
+
+struct Inner {
+    entries: LruCache<Key, Entry>,
+    /// Bumped on every invalidation; a lookup that raced with one doesn't
+    /// get cached.
+    generation: u64,
+}
+
+pub(crate) struct SessionCache {
+    inner: Option<Mutex<Inner>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+pub(crate) struct SessionCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl SessionCache {
+    /// A `capacity` of 0 disables caching.
+    pub fn new(capacity: usize, ttl_secs: i64) -> Self {
+        let inner = NonZeroUsize::new(capacity).map(|capacity| {
+            Mutex::new(Inner {
+                entries: LruCache::new(capacity),
+                generation: 0,
+            })
+        });
+        Self {
+            inner,
+            ttl: Duration::seconds(ttl_secs),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached session of `token`, if any. On a miss, also returns the
+    /// generation to hand back to [`Self::insert`].
+    pub fn get(&self, token: &str) -> Result<Session, u64> {
+        let Some(inner) = &self.inner else {
+            return Err(0);
+        };
+        let key = key(token);
+        let now = OffsetDateTime::now_utc();
+        let mut inner = inner.lock().unwrap();
+
+        match inner.entries.get(&key) {
+            Some(entry) if entry.valid_until > now => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(entry.session.clone());
+            }
+            Some(_) => {
+                inner.entries.pop(&key);
+            }
+            None => {}
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Err(inner.generation)
+    }
+
+    /// Cache a session looked up after `get` returned `generation`, unless
+    /// something was invalidated in between.
+    pub fn insert(
+        &self,
+        token: &str,
+        generation: u64,
+        session: Session,
+        expires_at: OffsetDateTime,
+    ) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let valid_until = expires_at.min(OffsetDateTime::now_utc() + self.ttl);
+        let mut inner = inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+        inner.entries.put(
+            key(token),
+            Entry {
+                session,
+                valid_until,
+            },
+        );
+    }
+
+    /// Evict the session with this token id.
+    pub fn invalidate_session(&self, token_id: &Id) {
+        self.invalidate(|session| session.token_id() == Some(*token_id));
+    }
+
+    /// Evict every session on the device.
+    pub fn invalidate_device(&self, device_id: &Id) {
+        self.invalidate(|session| session.device_id() == Some(*device_id));
+    }
+
+    /// Evict every session of the user, except `keep` if given.
+    pub fn invalidate_user(&self, user_id: &Id, keep: Option<Id>) {
+        self.invalidate(|session| session.user_id() == *user_id && session.token_id() != keep);
+    }
+
+    fn invalidate(&self, matches: impl Fn(&Session) -> bool) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let mut inner = inner.lock().unwrap();
+        inner.generation += 1;
+        let keys: Vec<Key> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| matches(&entry.session))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in keys {
+            inner.entries.pop(&key);
+        }
+    }
+
+    pub fn stats(&self) -> SessionCacheStats {
+        let (entries, capacity) = match &self.inner {
+            Some(inner) => {
+                let inner = inner.lock().unwrap();
+                (inner.entries.len(), inner.entries.cap().get())
+            }
+            None => (0, 0),
+        };
+        SessionCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            capacity,
+        }
+    }
+}
+
+fn key(token: &str) -> Key {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn later() -> OffsetDateTime {
+        OffsetDateTime::now_utc() + Duration::hours(1)
+    }
+
+    fn session(user_id: Id) -> Session {
+        Session::with_token(user_id, Id::new(), Id::new())
+    }
+
+    #[test]
+    fn caches_after_a_miss() {
+        let cache = SessionCache::new(10, 60);
+        let expected = session(Id::new());
+
+        let generation = cache.get("token").unwrap_err();
+        cache.insert("token", generation, expected.clone(), later());
+        let cached = cache.get("token").unwrap();
+
+        assert_eq!(cached.user_id(), expected.user_id());
+        assert_eq!(cached.token_id(), expected.token_id());
+        assert_eq!(cached.device_id(), expected.device_id());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[test]
+    fn entries_end_with_the_token() {
+        let cache = SessionCache::new(10, 60);
+        let expired = OffsetDateTime::now_utc() - Duration::seconds(1);
+
+        let generation = cache.get("token").unwrap_err();
+        cache.insert("token", generation, session(Id::new()), expired);
+
+        assert!(cache.get("token").is_err());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn invalidates_sessions_devices_and_users() {
+        let cache = SessionCache::new(10, 60);
+        let (alice, bob) = (Id::new(), Id::new());
+        let (a1, a2, a3, b1) = (session(alice), session(alice), session(alice), session(bob));
+        cache.insert("a1", 0, a1.clone(), later());
+        cache.insert("a2", 0, a2.clone(), later());
+        cache.insert("a3", 0, a3.clone(), later());
+        cache.insert("b1", 0, b1, later());
+
+        cache.invalidate_session(&a1.token_id().unwrap());
+        assert!(cache.get("a1").is_err());
+        assert!(cache.get("a2").is_ok());
+
+        cache.invalidate_device(&a3.device_id().unwrap());
+        assert!(cache.get("a3").is_err());
+        assert!(cache.get("a2").is_ok());
+
+        cache.invalidate_user(&alice, a2.token_id());
+        assert!(cache.get("a2").is_ok());
+        cache.invalidate_user(&alice, None);
+        assert!(cache.get("a2").is_err());
+        assert!(cache.get("b1").is_ok());
+    }
+
+    #[test]
+    fn skips_lookups_that_raced_an_invalidation() {
+        let cache = SessionCache::new(10, 60);
+        let user_id = Id::new();
+
+        let generation = cache.get("token").unwrap_err();
+        cache.invalidate_user(&user_id, None);
+        cache.insert("token", generation, session(user_id), later());
+
+        assert!(cache.get("token").is_err());
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        let cache = SessionCache::new(0, 60);
+
+        cache.insert("token", 0, session(Id::new()), later());
+
+        assert!(cache.get("token").is_err());
+        assert_eq!(cache.stats().capacity, 0);
+    }
+}
@@ -1,23 +1,49 @@
-use crate::{AppState, database::DbPool, error::Result, session::Session, ulid::Id};
+use crate::{
+    AppState, config::AuthConfig, database::DbPool, entity::sea_orm_active_enums::Provider,
+    error::Result, session::Session, ulid::Id,
+};
 use argon2::{
     Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
     password_hash::{SaltString, rand_core::OsRng},
 };
 use axum::{
     Json,
-    extract::{Query, State},
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use sdk::dtos::audit::AuditEventKind;
+use sdk::dtos::auth::{
+    ChangePasswordRequest, CodeLoginRequest, DeviceDetails, DeviceInfo, LoginChallenge,
+    LoginOutcome, LoginRequest, LoginResponse, PrivateKeyResponse, RefreshRequest,
+    SaveRecoveryKeyRequest, SaveRsaKeysRequest, SecondFactor, SessionCacheStats, SessionInfo,
+    TotpBackupCodesResponse, TotpCodeRequest, TotpLoginRequest, TotpSetupResponse, TotpStatus,
+    UpdatePrivateKeyRequest,
 };
-use sdk::dtos::auth::{LoginRequest, LoginResponse, PrivateKeyResponse, SaveRsaKeysRequest};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+use subtle::ConstantTimeEq;
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, error, info, warn};
+use url::Url;
 use uuid::Uuid;
 
-use super::{error::Error, repository::AuthRepository};
+use super::{
+    error::Error,
+    repository::{AuthRepository, IssuedTokens, LoginChallengeRow, LoginCodeRow},
+    throttle::{self, Reservation, Subject},
+    totp,
+};
+use crate::audit::{self, AuditEntry};
 
 #[derive(serde::Deserialize)]
 pub(super) struct RegisterRequest {
     pub username: String,
     pub password: String,
+    /// Required while open registration is off.
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(serde::Serialize)]
@@ -29,9 +55,15 @@ pub(super) async fn register(
     State(state): State<AppState>,
     Json(user): Json<RegisterRequest>,
 ) -> Result<Json<RegisterResponse>> {
-    if !state.config.registration_enabled {
-        return Err(Error::RegistrationDisabled.into());
-    }
+    // with open registration off, only invited people may register
+    let invite_code = if state.config.registration_enabled {
+        None
+    } else {
+        let Some(code) = user.invite_code.as_deref() else {
+            return Err(Error::RegistrationDisabled.into());
+        };
+        Some(code)
+    };
 
     debug!("Registering user: {}", user.username);
     let db = &state.db;
@@ -39,28 +71,613 @@ pub(super) async fn register(
     let user_id = Id::new();
     let password_hash = hash_password(&user.password)?;
 
-    AuthRepository::save_user_with_credentials(db, &user_id, &user.username, &password_hash)
-        .await?;
+    if let Some(code) = invite_code {
+        let code_hash = crate::invite::hash_code(code);
+        if !AuthRepository::save_invited_user(
+            db,
+            &user_id,
+            &user.username,
+            &password_hash,
+            &code_hash,
+        )
+        .await?
+        {
+            error!("Registration with an invalid invite code");
+            return Err(crate::error::Error::InvalidInvite);
+        }
+    } else {
+        AuthRepository::save_user_with_credentials(db, &user_id, &user.username, &password_hash)
+            .await?;
+    }
 
     let user_id = user_id.to_string();
     debug!("User registered: {}", user.username);
     Ok(Json(RegisterResponse { user_id }))
 }
 
+/// Wrong codes a login challenge takes before the login has to start over.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Sign in with a password. Accounts with TOTP on or a passkey get a
+/// challenge instead of a session, to redeem with [`login_totp`] or a passkey.
 pub(super) async fn login(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(user): Json<LoginRequest>,
+) -> Result<Json<LoginOutcome>> {
+    let db = &state.db;
+    let attempt = LoginAttempt {
+        user_id: find_user_by_username(db, &user.username).await?,
+        username: Some(&user.username),
+        ip: Some(throttle::client_ip(
+            &headers,
+            peer,
+            &state.config.login_throttle,
+        )),
+    };
+    let reservation = check_throttle(&state, &attempt).await?;
+    let user_id = match verify_user_password(db, &user.username, &user.password).await {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            record_login_failure(&state, &attempt, reservation).await;
+            return Err(e);
+        }
+    };
+
+    let methods = second_factors(db, &user_id).await?;
+    if methods.is_empty() {
+        state.login_throttle.reset(&Subject::Account(user_id));
+        let login = start_session(&state, &user_id, &user.device).await?;
+        return Ok(Json(LoginOutcome::SignedIn(login)));
+    }
+
+    let challenge = Uuid::new_v4().to_string();
+    let expires_at =
+        OffsetDateTime::now_utc() + Duration::seconds(state.config.auth.login_challenge_ttl_secs);
+    let row = LoginChallengeRow {
+        user_id,
+        device_id: user.device.device_id.map(Id::from),
+        device_name: user.device.device_name,
+        platform: user.device.platform,
+        expires_at,
+    };
+    AuthRepository::create_login_challenge(db, &challenge, &row).await?;
+
+    debug!("Login waiting for second factor");
+    Ok(Json(LoginOutcome::SecondFactorRequired(LoginChallenge {
+        challenge,
+        expires_at,
+        methods,
+    })))
+}
+
+/// Finish a password login with a TOTP or backup code.
+pub(super) async fn login_totp(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<TotpLoginRequest>,
+) -> Result<Json<LoginResponse>> {
+    let db = &state.db;
+
+    let user_id = login_challenge_user(db, &request.challenge).await?;
+    // counted against the account too, or fresh challenges would allow
+    // guessing codes without end
+    let attempt = LoginAttempt {
+        user_id: Some(user_id),
+        username: None,
+        ip: Some(throttle::client_ip(
+            &headers,
+            peer,
+            &state.config.login_throttle,
+        )),
+    };
+    let reservation = check_throttle(&state, &attempt).await?;
+    if !check_second_factor(&state, &user_id, &request.code).await? {
+        fail_login_challenge(db, &request.challenge).await?;
+        record_login_failure(&state, &attempt, reservation).await;
+        debug!("Login with invalid second factor");
+        return Err(Error::InvalidCredentials.into());
+    }
+    state.login_throttle.reset(&Subject::Account(user_id));
+
+    Ok(Json(
+        redeem_login_challenge(&state, &request.challenge).await?,
+    ))
+}
+
+/// Who a sign-in attempt is throttled and audited as.
+struct LoginAttempt<'a> {
+    /// `None` for a username no account has.
+    user_id: Option<Id>,
+    username: Option<&'a str>,
+    /// `None` for a signed-in user proving it's them again, who is only
+    /// throttled by account.
+    ip: Option<IpAddr>,
+}
+
+impl LoginAttempt<'_> {
+    /// A signed-in user re-entering a second factor or the password. It
+    /// counts against the same account budget as password logins, so a
+    /// stolen session doesn't give guesses of its own.
+    fn of_session(user_id: &Id) -> Self {
+        LoginAttempt {
+            user_id: Some(*user_id),
+            username: None,
+            ip: None,
+        }
+    }
+
+    fn subjects(&self) -> Vec<Subject> {
+        let account = match (self.user_id, self.username) {
+            (Some(user_id), _) => Subject::Account(user_id),
+            (None, username) => Subject::Username(username.unwrap_or_default().to_string()),
+        };
+        let mut subjects = vec![account];
+        subjects.extend(self.ip.map(Subject::Ip));
+        subjects
+    }
+
+    fn audit_entry(&self, kind: AuditEventKind) -> AuditEntry<'_> {
+        AuditEntry {
+            kind,
+            user_id: self.user_id,
+            username: self.username,
+            ip: self.ip,
+        }
+    }
+}
+
+/// Refuse the attempt unseen while its account or IP has to wait, else hold
+/// its place until [`record_login_failure`] or the end of the request.
+async fn check_throttle<'a>(
+    state: &'a AppState,
+    attempt: &LoginAttempt<'_>,
+) -> Result<Reservation<'a>> {
+    let now = OffsetDateTime::now_utc();
+    let refusal = match state.login_throttle.reserve(attempt.subjects(), now) {
+        Ok(reservation) => return Ok(reservation),
+        Err(refusal) => refusal,
+    };
+
+    // once per wait, or retrying in a loop would flood the trail
+    if refusal.report {
+        audit::record(
+            &state.db,
+            attempt.audit_entry(AuditEventKind::LoginThrottled),
+        )
+        .await;
+    }
+    Err(crate::error::Error::Throttled {
+        retry_after_secs: (refusal.retry_after.as_seconds_f64().ceil() as u64).max(1),
+    })
+}
+
+/// Count a wrong password or second factor against the attempt's account
+/// and IP, locking out either once it reaches its threshold.
+async fn record_login_failure(
+    state: &AppState,
+    attempt: &LoginAttempt<'_>,
+    reservation: Reservation<'_>,
+) {
+    audit::record(&state.db, attempt.audit_entry(AuditEventKind::LoginFailed)).await;
+
+    let locked = reservation.fail(OffsetDateTime::now_utc());
+    for subject in locked {
+        let kind = match subject {
+            Subject::Ip(_) => AuditEventKind::IpLocked,
+            Subject::Account(_) | Subject::Username(_) => AuditEventKind::AccountLocked,
+        };
+        info!(ip = ?attempt.ip, "Locking out after repeated failed logins");
+        audit::record(&state.db, attempt.audit_entry(kind)).await;
+    }
+}
+
+/// The user a password login is waiting on a second factor for.
+pub(crate) async fn login_challenge_user(db: &DbPool, challenge: &str) -> Result<Id> {
+    match AuthRepository::get_login_challenge(db, challenge).await? {
+        Some(row) => Ok(row.user_id),
+        None => {
+            debug!("Unknown or expired login challenge");
+            Err(crate::error::Error::LoginNotFound)
+        }
+    }
+}
+
+/// Count a failed second factor against the login challenge.
+pub(crate) async fn fail_login_challenge(db: &DbPool, challenge: &str) -> Result<()> {
+    AuthRepository::fail_login_challenge(db, challenge, MAX_CHALLENGE_ATTEMPTS).await
+}
+
+/// Sign in the login waiting on `challenge`, once its second factor checked
+/// out.
+pub(crate) async fn redeem_login_challenge(
+    state: &AppState,
+    challenge: &str,
+) -> Result<LoginResponse> {
+    let db = &state.db;
+
+    let Some(row) = AuthRepository::get_login_challenge(db, challenge).await? else {
+        return Err(crate::error::Error::LoginNotFound);
+    };
+    // a concurrent request redeemed it first
+    if !AuthRepository::delete_login_challenge(db, challenge).await? {
+        return Err(crate::error::Error::LoginNotFound);
+    }
+
+    let device = DeviceDetails {
+        device_id: row.device_id.map(Into::into),
+        device_name: row.device_name,
+        platform: row.platform,
+    };
+    start_session(state, &row.user_id, &device).await
+}
+
+/// Hand a finished sign-in to a client through a one-time code in its
+/// redirect URI, rather than the tokens themselves. Only the client holding
+/// the verifier for `code_challenge` can redeem it.
+pub(crate) async fn issue_login_code(
+    state: &AppState,
+    user_id: &Id,
+    device: DeviceDetails,
+    code_challenge: String,
+) -> Result<String> {
+    let code = Uuid::new_v4().to_string();
+    let expires_at =
+        OffsetDateTime::now_utc() + Duration::seconds(state.config.auth.login_code_ttl_secs);
+    let row = LoginCodeRow {
+        user_id: *user_id,
+        code_challenge,
+        device_id: device.device_id.map(Id::from),
+        device_name: device.device_name,
+        platform: device.platform,
+        expires_at,
+    };
+    AuthRepository::create_login_code(&state.db, &code, &row).await?;
+    Ok(code)
+}
+
+/// Whether `code_challenge` is an S256 PKCE challenge, i.e. a base64url
+/// SHA-256 digest.
+pub(crate) fn is_code_challenge(code_challenge: &str) -> bool {
+    code_challenge.len() == 43
+        && code_challenge
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+pub(crate) fn code_challenge_matches(code_challenge: &str, code_verifier: &str) -> bool {
+    let expected = Base64UrlUnpadded::encode_string(&Sha256::digest(code_verifier.as_bytes()));
+    expected.as_bytes().ct_eq(code_challenge.as_bytes()).into()
+}
+
+/// Redeem a one-time sign-in code for the session's tokens.
+pub(super) async fn login_with_code(
+    State(state): State<AppState>,
+    Json(request): Json<CodeLoginRequest>,
+) -> Result<Json<LoginResponse>> {
+    // taken either way, so a code can't be tried against many verifiers
+    let Some(row) = AuthRepository::take_login_code(&state.db, &request.code).await? else {
+        debug!("Unknown or expired login code");
+        return Err(crate::error::Error::LoginNotFound);
+    };
+    if !code_challenge_matches(&row.code_challenge, &request.code_verifier) {
+        error!("Login code redeemed with the wrong verifier");
+        return Err(crate::error::Error::LoginNotFound);
+    }
+
+    let device = DeviceDetails {
+        device_id: row.device_id.map(Into::into),
+        device_name: row.device_name,
+        platform: row.platform,
+    };
+    let login = start_session(&state, &row.user_id, &device).await?;
+    Ok(Json(login))
+}
+
+/// The user who signs in with this username and a password, if any.
+pub(crate) async fn find_user_by_username(db: &DbPool, username: &str) -> Result<Option<Id>> {
+    AuthRepository::find_account_user(db, username, Provider::Credentials).await
+}
+
+/// Exchange a refresh token for a new access token and refresh token.
+pub(super) async fn refresh(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshRequest>,
 ) -> Result<Json<LoginResponse>> {
     let db = &state.db;
-    let user_id = verify_user_password(db, &user.username, &user.password).await?;
 
-    let auth_token = Uuid::new_v4().to_string();
-    AuthRepository::save_auth_token(db, &user_id, &auth_token).await?;
+    let mut tokens = issue_tokens(&state.config.auth);
+    let lifetime = session_lifetime(&state.config.auth);
+    let Some(session) =
+        AuthRepository::refresh_session(db, &request.refresh_token, &mut tokens, lifetime).await?
+    else {
+        debug!("Refresh with unknown or expired token");
+        return Err(Error::InvalidAuthToken.into());
+    };
+    // the previous access token was replaced
+    state.session_cache.invalidate_session(&session.id);
+    AuthRepository::touch_device(db, &session.device_id, touch_interval(&state.config.auth))
+        .await?;
+
+    Ok(Json(login_response(
+        session.user_id,
+        session.device_id,
+        tokens,
+    )))
+}
+
+/// Revoke the session making the request.
+pub(super) async fn logout(State(state): State<AppState>, session: Session) -> Result<()> {
+    let Some(token_id) = session.token_id() else {
+        return Err(Error::MissingAuthContext.into());
+    };
+    AuthRepository::delete_session(&state.db, &session.user_id(), &token_id).await?;
+    state.session_cache.invalidate_session(&token_id);
+    Ok(())
+}
+
+pub(super) async fn list_sessions(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<Vec<SessionInfo>>> {
+    let sessions = AuthRepository::list_sessions(&state.db, &session.user_id())
+        .await?
+        .into_iter()
+        .map(|row| SessionInfo {
+            id: row.id.into(),
+            device_id: row.device_id.into(),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            current: session.token_id() == Some(row.id),
+        })
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+pub(super) async fn revoke_session(
+    State(state): State<AppState>,
+    session: Session,
+    Path(session_id): Path<Id>,
+) -> Result<()> {
+    if !AuthRepository::delete_session(&state.db, &session.user_id(), &session_id).await? {
+        error!(%session_id, "Session not found");
+        return Err(crate::error::Error::SessionNotFound);
+    }
+    state.session_cache.invalidate_session(&session_id);
+    Ok(())
+}
+
+/// Sign the user out everywhere, including the session making the request.
+pub(super) async fn revoke_sessions(State(state): State<AppState>, session: Session) -> Result<()> {
+    let count = AuthRepository::delete_sessions(&state.db, &session.user_id()).await?;
+    state
+        .session_cache
+        .invalidate_user(&session.user_id(), None);
+    info!(count, "Revoked all sessions");
+    Ok(())
+}
+
+pub(super) async fn list_devices(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<Vec<DeviceInfo>>> {
+    let devices = AuthRepository::list_devices(&state.db, &session.user_id())
+        .await?
+        .into_iter()
+        .map(|row| DeviceInfo {
+            id: row.id.into(),
+            name: row.name,
+            platform: row.platform,
+            created_at: row.created_at,
+            last_seen_at: row.last_seen_at,
+            last_sync_cursor: row.last_sync_cursor.map(|cursor| cursor as u64),
+            signed_in: row.signed_in,
+            current: session.device_id() == Some(row.id),
+        })
+        .collect();
+
+    Ok(Json(devices))
+}
+
+/// Forget a device and sign out every session on it, e.g. for a lost phone.
+pub(super) async fn revoke_device(
+    State(state): State<AppState>,
+    session: Session,
+    Path(device_id): Path<Id>,
+) -> Result<()> {
+    if !AuthRepository::delete_device(&state.db, &session.user_id(), &device_id).await? {
+        error!(%device_id, "Device not found");
+        return Err(crate::error::Error::DeviceNotFound);
+    }
+    state.session_cache.invalidate_device(&device_id);
+    info!(%device_id, "Revoked device");
+    Ok(())
+}
+
+/// Admin: sign a user out everywhere.
+pub(super) async fn revoke_user_sessions(
+    State(state): State<AppState>,
+    Path(user_id): Path<Id>,
+) -> Result<()> {
+    let count = AuthRepository::delete_sessions(&state.db, &user_id).await?;
+    state.session_cache.invalidate_user(&user_id, None);
+    info!(%user_id, count, "Revoked all sessions of user");
+    Ok(())
+}
+
+/// Admin: hit and miss counters of the session cache.
+pub(super) async fn session_cache_stats(State(state): State<AppState>) -> Json<SessionCacheStats> {
+    let stats = state.session_cache.stats();
+    Json(SessionCacheStats {
+        hits: stats.hits,
+        misses: stats.misses,
+        entries: stats.entries,
+        capacity: stats.capacity,
+    })
+}
+
+fn issue_tokens(config: &AuthConfig) -> IssuedTokens {
+    let now = OffsetDateTime::now_utc();
+    let refresh_ttl = Duration::days(config.refresh_token_ttl_days);
+    IssuedTokens {
+        access_token: Uuid::new_v4().to_string(),
+        expires_at: now + Duration::seconds(config.access_token_ttl_secs),
+        refresh_token: Uuid::new_v4().to_string(),
+        refresh_expires_at: now + refresh_ttl.min(session_lifetime(config)),
+    }
+}
+
+fn session_lifetime(config: &AuthConfig) -> Duration {
+    Duration::days(config.session_lifetime_days)
+}
+
+fn touch_interval(config: &AuthConfig) -> Duration {
+    Duration::seconds(config.device_touch_interval_secs)
+}
+
+/// Sign the user in on the described device.
+pub(crate) async fn start_session(
+    state: &AppState,
+    user_id: &Id,
+    device: &DeviceDetails,
+) -> Result<LoginResponse> {
+    let db = &state.db;
+    let device_id = register_device(db, user_id, device).await?;
+
+    let tokens = issue_tokens(&state.config.auth);
+    AuthRepository::create_session(db, user_id, &device_id, &tokens).await?;
 
-    Ok(Json(LoginResponse {
+    Ok(login_response(*user_id, device_id, tokens))
+}
+
+/// Find the user behind an account at an external identity provider. An
+/// account seen for the first time is linked to `link_to` if given, or else
+/// gets a new user named `name`, as far as registration is open.
+pub(crate) async fn resolve_external_account(
+    state: &AppState,
+    provider: Provider,
+    account_id: &str,
+    name: &str,
+    link_to: Option<Id>,
+) -> Result<Id> {
+    let db = &state.db;
+
+    if let Some(user_id) = link_to {
+        if !AuthRepository::link_account(db, &user_id, account_id, provider).await? {
+            error!(%user_id, "Account is linked to another user");
+            return Err(crate::error::Error::AccountConflict);
+        }
+        info!(%user_id, "Linked external account");
+        return Ok(user_id);
+    }
+
+    if let Some(user_id) =
+        AuthRepository::find_account_user(db, account_id, provider.clone()).await?
+    {
+        return Ok(user_id);
+    }
+
+    if !state.config.registration_enabled {
+        return Err(Error::RegistrationDisabled.into());
+    }
+    let user_id = Id::new();
+    AuthRepository::save_user_with_account(db, &user_id, name, account_id, provider, None).await?;
+    info!(%user_id, "Registered user from external account");
+    Ok(user_id)
+}
+
+/// Longest device name or platform kept; anything past it is cut off.
+const MAX_DEVICE_LABEL_CHARS: usize = 100;
+
+async fn register_device(db: &DbPool, user_id: &Id, device: &DeviceDetails) -> Result<Id> {
+    let label = |value: Option<&str>, default: &str| -> String {
+        value
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .unwrap_or(default)
+            .chars()
+            .take(MAX_DEVICE_LABEL_CHARS)
+            .collect()
+    };
+    let name = label(device.device_name.as_deref(), "Unknown device");
+    let platform = label(device.platform.as_deref(), "unknown");
+
+    AuthRepository::register_device(
+        db,
+        user_id,
+        device.device_id.map(Id::from),
+        &name,
+        &platform,
+    )
+    .await
+}
+
+fn login_response(user_id: Id, device_id: Id, tokens: IssuedTokens) -> LoginResponse {
+    LoginResponse {
         user_id: user_id.into(),
-        auth_token,
-    }))
+        device_id: device_id.into(),
+        auth_token: tokens.access_token,
+        expires_at: tokens.expires_at,
+        refresh_token: tokens.refresh_token,
+        refresh_expires_at: tokens.refresh_expires_at,
+    }
+}
+
+/// Remember that the session's device has applied the change feed up to
+/// `cursor`.
+pub(crate) async fn record_sync_cursor(db: &DbPool, session: &Session, cursor: i64) -> Result<()> {
+    match session.device_id() {
+        Some(device_id) => AuthRepository::record_sync_cursor(db, &device_id, cursor).await,
+        None => Ok(()),
+    }
+}
+
+/// Periodically drop sessions that can no longer be used or refreshed.
+pub(crate) async fn purge_expired_sessions(state: AppState) {
+    let interval_secs = state.config.auth.token_gc_interval_secs;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        match AuthRepository::delete_expired_sessions(&state.db, OffsetDateTime::now_utc()).await {
+            Ok(0) => {}
+            Ok(count) => info!(count, "Purged expired sessions"),
+            Err(e) => error!(error = %e, "Failed to purge expired sessions"),
+        }
+    }
+}
+
+/// Seal the TOTP secrets still kept as hex with the configured key, once at
+/// startup.
+pub(crate) async fn seal_totp_secrets(state: &AppState) -> Result<()> {
+    let secrets = AuthRepository::unsealed_totp_secrets(&state.db).await?;
+    let Some(key) = state.totp_key.as_deref() else {
+        if !secrets.is_empty() {
+            warn!(
+                count = secrets.len(),
+                "TOTP secrets are stored unsealed; set auth_totp_secret_key"
+            );
+        }
+        return Ok(());
+    };
+
+    let mut sealed = 0;
+    for (user_id, stored) in secrets {
+        let secret = totp::load_secret(None, &user_id, &stored)?;
+        let replacement = totp::store_secret(Some(key), &user_id, &secret);
+        // enrolment may have replaced it meanwhile, sealed already
+        if AuthRepository::seal_totp_secret(&state.db, &user_id, &stored, &replacement).await? {
+            sealed += 1;
+        }
+    }
+    if sealed > 0 {
+        info!(count = sealed, "Sealed TOTP secrets");
+    }
+    Ok(())
 }
 
 pub(super) async fn save_key(
@@ -71,7 +688,359 @@ pub(super) async fn save_key(
     debug!("Saving keys for user");
     let db = &state.db;
 
-    AuthRepository::save_keys(db, &session.user_id(), &keys.private_key, &keys.public_key).await?;
+    check_key_envelope(&keys.private_key)?;
+    let saved =
+        AuthRepository::save_keys(db, &session.user_id(), &keys.private_key, &keys.public_key)
+            .await?;
+    if !saved {
+        error!("User already has keys");
+        return Err(crate::error::Error::KeyConflict);
+    }
+    Ok(())
+}
+
+pub(super) async fn update_key(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<UpdatePrivateKeyRequest>,
+) -> Result<()> {
+    debug!("Updating private key for user");
+    let db = &state.db;
+
+    check_key_envelope(&request.private_key)?;
+    let user_id = session.user_id();
+    let replaced = AuthRepository::replace_private_key(
+        db,
+        &user_id,
+        &request.previous_private_key,
+        &request.private_key,
+    )
+    .await?;
+    if !replaced {
+        if AuthRepository::get_private_key(db, &user_id)
+            .await?
+            .is_none()
+        {
+            return Err(crate::error::Error::UserNotFound);
+        }
+        error!("Stored private key changed since it was unwrapped");
+        return Err(crate::error::Error::KeyConflict);
+    }
+    Ok(())
+}
+
+pub(super) async fn save_recovery_key(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<SaveRecoveryKeyRequest>,
+) -> Result<()> {
+    debug!("Saving recovery key for user");
+    let db = &state.db;
+
+    check_key_envelope(&request.recovery_key)?;
+    let user_id = session.user_id();
+    let replaced = AuthRepository::replace_recovery_key(
+        db,
+        &user_id,
+        request.previous_recovery_key.as_deref(),
+        &request.recovery_key,
+    )
+    .await?;
+    if !replaced {
+        if AuthRepository::get_private_key(db, &user_id)
+            .await?
+            .is_none()
+        {
+            error!("User has no keys to recover");
+            return Err(crate::error::Error::UserNotFound);
+        }
+        error!("Stored recovery key changed since it was fetched");
+        return Err(crate::error::Error::KeyConflict);
+    }
+    Ok(())
+}
+
+pub(super) async fn get_recovery_key(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<PrivateKeyResponse>> {
+    debug!("Getting recovery key for user");
+    let db = &state.db;
+
+    let value = AuthRepository::get_recovery_key(db, &session.user_id()).await?;
+    Ok(Json(PrivateKeyResponse { value }))
+}
+
+/// Change the login password. Every other session of the user is signed out;
+/// the one making the request stays valid.
+pub(super) async fn change_password(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<()> {
+    debug!("Changing password for user");
+    let db = &state.db;
+    let user_id = session.user_id();
+
+    let check = check_own_password(db, &user_id, &request.current_password);
+    if !check_session_credential(&state, &user_id, check).await? {
+        debug!("Password change with invalid current password");
+        return Err(Error::InvalidCredentials.into());
+    }
+
+    let password_hash = hash_password(&request.new_password)?;
+    AuthRepository::change_password(db, &user_id, &password_hash, session.token_id()).await?;
+    state
+        .session_cache
+        .invalidate_user(&user_id, session.token_id());
+    Ok(())
+}
+
+pub(super) async fn totp_status(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<TotpStatus>> {
+    let db = &state.db;
+    let user_id = session.user_id();
+
+    let enabled = AuthRepository::get_totp(db, &user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled);
+    let backup_codes_remaining = match enabled {
+        true => AuthRepository::count_backup_codes(db, &user_id).await?,
+        false => 0,
+    };
+    Ok(Json(TotpStatus {
+        enabled,
+        backup_codes_remaining,
+    }))
+}
+
+/// Start enrolling an authenticator app. Nothing changes for logins until
+/// [`confirm_totp`]; starting over replaces the pending secret.
+pub(super) async fn enroll_totp(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<TotpSetupResponse>> {
+    let db = &state.db;
+    let user_id = session.user_id();
+
+    let secret = totp::generate_secret();
+    let stored = totp::store_secret(state.totp_key.as_deref(), &user_id, &secret);
+    if !AuthRepository::start_totp_enrolment(db, &user_id, stored).await? {
+        error!("TOTP already enabled");
+        return Err(crate::error::Error::TotpConflict);
+    }
+
+    let account = AuthRepository::get_user_name(db, &user_id)
+        .await?
+        .unwrap_or_else(|| user_id.to_string());
+    Ok(Json(TotpSetupResponse {
+        secret: totp::encode_secret(&secret),
+        provisioning_uri: totp::provisioning_uri(&secret, &state.config.auth.totp_issuer, &account),
+    }))
+}
+
+/// Turn TOTP on with the first code from the enrolled app. Returns the
+/// backup codes, which can't be shown again.
+pub(super) async fn confirm_totp(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<TotpCodeRequest>,
+) -> Result<Json<TotpBackupCodesResponse>> {
+    let db = &state.db;
+    let user_id = session.user_id();
+
+    let totp = match AuthRepository::get_totp(db, &user_id).await? {
+        Some(totp) if totp.enabled => return Err(crate::error::Error::TotpConflict),
+        Some(totp) => totp,
+        None => return Err(crate::error::Error::TotpNotFound),
+    };
+    let secret = totp::load_secret(state.totp_key.as_deref(), &user_id, &totp.secret)?;
+    let Some(step) = totp::verify(&secret, request.code.trim(), OffsetDateTime::now_utc()) else {
+        debug!("TOTP confirmation with invalid code");
+        return Err(crate::error::Error::InvalidTotpCode);
+    };
+
+    let backup_codes = totp::generate_backup_codes();
+    let hashes = backup_codes
+        .iter()
+        .map(|code| totp::hash_backup_code(code))
+        .collect::<Result<_>>()?;
+    if !AuthRepository::enable_totp(db, &user_id, step, hashes).await? {
+        return Err(crate::error::Error::TotpNotFound);
+    }
+
+    info!("Enabled TOTP");
+    Ok(Json(TotpBackupCodesResponse { backup_codes }))
+}
+
+/// Replace the backup codes, e.g. when running low. Takes a current code.
+pub(super) async fn regenerate_backup_codes(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<TotpCodeRequest>,
+) -> Result<Json<TotpBackupCodesResponse>> {
+    let db = &state.db;
+    let user_id = session.user_id();
+    require_second_factor(&state, &user_id, &request.code).await?;
+
+    let backup_codes = totp::generate_backup_codes();
+    let hashes = backup_codes
+        .iter()
+        .map(|code| totp::hash_backup_code(code))
+        .collect::<Result<_>>()?;
+    AuthRepository::replace_backup_codes(db, &user_id, hashes).await?;
+
+    Ok(Json(TotpBackupCodesResponse { backup_codes }))
+}
+
+/// Turn TOTP off. Takes a current code, so a stolen session alone can't.
+pub(super) async fn disable_totp(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<TotpCodeRequest>,
+) -> Result<()> {
+    let db = &state.db;
+    let user_id = session.user_id();
+    require_second_factor(&state, &user_id, &request.code).await?;
+
+    AuthRepository::disable_totp(db, &user_id).await?;
+    info!("Disabled TOTP");
+    Ok(())
+}
+
+/// The second factors the user has set up; none means a password alone
+/// signs in.
+async fn second_factors(db: &DbPool, user_id: &Id) -> Result<Vec<SecondFactor>> {
+    let mut methods = Vec::new();
+    if AuthRepository::get_totp(db, user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled)
+    {
+        methods.push(SecondFactor::Totp);
+    }
+    if AuthRepository::has_passkeys(db, user_id).await? {
+        methods.push(SecondFactor::Passkey);
+    }
+    Ok(methods)
+}
+
+/// Make a signed-in user with a second factor prove it's them again, with a
+/// current TOTP or backup code or else the password. Users without one pass.
+pub(crate) async fn reauthenticate(
+    state: &AppState,
+    user_id: &Id,
+    code: Option<&str>,
+    password: Option<&str>,
+) -> Result<()> {
+    let db = &state.db;
+    if second_factors(db, user_id).await?.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(code) = code {
+        let check = check_second_factor(state, user_id, code);
+        if !check_session_credential(state, user_id, check).await? {
+            debug!("Re-authentication with invalid second factor");
+            return Err(crate::error::Error::InvalidTotpCode);
+        }
+        return Ok(());
+    }
+    let Some(password) = password else {
+        debug!("Re-authentication required");
+        return Err(crate::error::Error::ReauthenticationRequired);
+    };
+    let check = check_own_password(db, user_id, password);
+    if !check_session_credential(state, user_id, check).await? {
+        debug!("Re-authentication with invalid password");
+        return Err(Error::InvalidCredentials.into());
+    }
+    Ok(())
+}
+
+async fn require_second_factor(state: &AppState, user_id: &Id, code: &str) -> Result<()> {
+    let enabled = AuthRepository::get_totp(&state.db, user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled);
+    if !enabled {
+        return Err(crate::error::Error::TotpNotFound);
+    }
+    let check = check_second_factor(state, user_id, code);
+    if !check_session_credential(state, user_id, check).await? {
+        debug!("Invalid second factor");
+        return Err(crate::error::Error::InvalidTotpCode);
+    }
+    Ok(())
+}
+
+/// Run `check` of a credential a signed-in user entered, throttled and
+/// counted like a login of their account.
+async fn check_session_credential(
+    state: &AppState,
+    user_id: &Id,
+    check: impl Future<Output = Result<bool>>,
+) -> Result<bool> {
+    let attempt = LoginAttempt::of_session(user_id);
+    let reservation = check_throttle(state, &attempt).await?;
+    let verified = check.await?;
+    if verified {
+        state.login_throttle.reset(&Subject::Account(*user_id));
+    } else {
+        record_login_failure(state, &attempt, reservation).await;
+    }
+    Ok(verified)
+}
+
+/// Whether `password` is the user's; false for users without one.
+async fn check_own_password(db: &DbPool, user_id: &Id, password: &str) -> Result<bool> {
+    let Some(hash) = AuthRepository::get_password(db, user_id).await? else {
+        debug!("Password check for a user without one");
+        return Ok(false);
+    };
+    Ok(verify_password(password, &hash).is_ok())
+}
+
+/// Whether `code` is a current TOTP code or an unused backup code of the
+/// user. Either way it is used up.
+async fn check_second_factor(state: &AppState, user_id: &Id, code: &str) -> Result<bool> {
+    let db = &state.db;
+    let code = code.trim();
+    if !totp::is_totp_code(code) {
+        if !totp::is_backup_code(code) {
+            return Ok(false);
+        }
+        let hashes = AuthRepository::backup_code_hashes(db, user_id).await?;
+        let Some(hash) = hashes
+            .into_iter()
+            .find(|hash| totp::backup_code_matches(code, hash))
+        else {
+            return Ok(false);
+        };
+        // a concurrent login may have spent it first
+        return AuthRepository::use_backup_code(db, user_id, &hash).await;
+    }
+
+    let Some(totp) = AuthRepository::get_totp(db, user_id)
+        .await?
+        .filter(|totp| totp.enabled)
+    else {
+        return Ok(false);
+    };
+    let secret = totp::load_secret(state.totp_key.as_deref(), user_id, &totp.secret)?;
+    match totp::verify(&secret, code, OffsetDateTime::now_utc()) {
+        Some(step) => AuthRepository::use_totp_step(db, user_id, step).await,
+        None => Ok(false),
+    }
+}
+
+/// The server can't open the envelope, but it can refuse anything that isn't
+/// one, such as the legacy fixed-nonce format.
+pub(crate) fn check_key_envelope(private_key: &str) -> Result<()> {
+    sdk::crypto::envelope::parse_header(private_key).map_err(|e| {
+        error!(error = %e, "Rejecting private key");
+        crate::error::Error::InvalidKeyEnvelope
+    })?;
     Ok(())
 }
 
@@ -91,29 +1060,64 @@ pub struct RedirectUri {
     redirect_uri: String,
 }
 
+#[derive(Deserialize)]
+pub(super) struct DesktopLoginParams {
+    redirect_uri: String,
+    /// S256 PKCE challenge of the desktop app, which redeems the `code` it is
+    /// sent with the verifier.
+    code_challenge: String,
+    #[serde(flatten)]
+    device: DeviceDetails,
+}
+
+/// Sign the desktop app in as the web session's user: its redirect URI gets a
+/// one-time `code` to redeem at `/auth/login/code`.
 pub(super) async fn login_desktop(
     State(state): State<AppState>,
-    redirect_uri: Query<RedirectUri>,
+    Query(params): Query<DesktopLoginParams>,
     session: Session,
 ) -> Result<Json<RedirectUri>> {
-    let db = &state.db;
-
-    let auth_token = Uuid::new_v4().to_string();
-    AuthRepository::save_auth_token(db, &session.user_id(), &auth_token).await?;
+    if !is_code_challenge(&params.code_challenge) {
+        error!("Desktop login without a valid code challenge");
+        return Err(crate::error::Error::InvalidCodeChallenge);
+    }
+    let mut redirect = Url::parse(&params.redirect_uri).map_err(|e| {
+        error!(error = %e, "Invalid desktop redirect URI");
+        crate::error::Error::RedirectNotAllowed
+    })?;
 
-    let redirect_uri = format!(
-        "{}?auth_token={}&user_id={}",
-        redirect_uri.redirect_uri,
-        auth_token,
-        session.user_id(),
-    );
-    Ok(Json(RedirectUri { redirect_uri }))
+    let code = issue_login_code(
+        &state,
+        &session.user_id(),
+        params.device,
+        params.code_challenge,
+    )
+    .await?;
+    redirect.query_pairs_mut().append_pair("code", &code);
+    Ok(Json(RedirectUri {
+        redirect_uri: redirect.into(),
+    }))
 }
 
-pub(super) async fn verify_token(db: &DbPool, token: &str) -> Result<Id> {
-    AuthRepository::get_by_token(db, token)
+/// Resolve a bearer token, from the session cache when possible.
+pub(super) async fn verify_token(state: &AppState, token: &str) -> Result<Session> {
+    let generation = match state.session_cache.get(token) {
+        Ok(session) => return Ok(session),
+        Err(generation) => generation,
+    };
+
+    let row = AuthRepository::get_by_token(&state.db, token)
         .await
-        .map_err(|_| Error::InvalidAuthToken.into())
+        .map_err(|_| Error::InvalidAuthToken)?;
+    // last seen is as fresh as the cache and the touch interval let it be
+    let interval = touch_interval(&state.config.auth);
+    AuthRepository::touch_device(&state.db, &row.device_id, interval).await?;
+
+    let session = Session::with_token(row.user_id, row.id, row.device_id);
+    state
+        .session_cache
+        .insert(token, generation, session.clone(), row.expires_at);
+    Ok(session)
 }
 
 fn hash_password(password: &str) -> Result<String> {
@@ -156,3 +1160,332 @@ async fn verify_user_password(db: &DbPool, username: &str, password: &str) -> Re
 
     Ok(user.id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use crate::entity::{passkeys, user_accounts};
+    use axum::http::StatusCode;
+    use sea_orm::{ActiveModelTrait, Set};
+
+    async fn add_passkey(db: &DbPool, user_id: &Id) {
+        let id = uuid::Uuid::from(Id::new());
+        user_accounts::ActiveModel {
+            id: Set(id),
+            user_id: Set((*user_id).into()),
+            account_id: Set(id.to_string()),
+            password: Set(None),
+            provider: Set(Provider::Passkey),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+        passkeys::ActiveModel {
+            id: Set(id),
+            user_id: Set((*user_id).into()),
+            name: Set("Phone".to_string()),
+            credential: Set("{}".to_string()),
+            prf_salt: Set("salt".to_string()),
+            wrapped_private_key: Set(None),
+            last_used_at: Set(None),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn passwords_alone_sign_in_until_a_second_factor_is_set_up() {
+        let Some(db) = test_db().await else { return };
+        let user_id = super::super::new_user(&db).await;
+        assert!(second_factors(&db, &user_id).await.unwrap().is_empty());
+
+        add_passkey(&db, &user_id).await;
+        assert_eq!(
+            second_factors(&db, &user_id).await.unwrap(),
+            vec![SecondFactor::Passkey]
+        );
+
+        AuthRepository::start_totp_enrolment(&db, &user_id, "secret".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            second_factors(&db, &user_id).await.unwrap(),
+            vec![SecondFactor::Passkey]
+        );
+        AuthRepository::enable_totp(&db, &user_id, 1, Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            second_factors(&db, &user_id).await.unwrap(),
+            vec![SecondFactor::Totp, SecondFactor::Passkey]
+        );
+    }
+
+    async fn login_attempt(state: &AppState, username: &str) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        let request = LoginRequest {
+            username: username.to_string(),
+            password: "wrong".to_string(),
+            device: DeviceDetails::default(),
+        };
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        login(
+            State(state.clone()),
+            ConnectInfo(peer),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn throttled_logins_say_when_to_retry() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [
+            ("login_throttle_account_free_attempts", "1"),
+            ("login_throttle_delay_secs", "30"),
+        ];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let user_id = super::super::new_user(&state.db).await;
+        let username = format!("u{user_id}");
+
+        for _ in 0..2 {
+            let response = login_attempt(&state, &username).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = login_attempt(&state, &username).await;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[axum::http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=30).contains(&retry_after));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_logins_count_against_each_other() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [
+            ("login_throttle_account_free_attempts", "2"),
+            ("login_throttle_delay_secs", "30"),
+        ];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let user_id = super::super::new_user(&state.db).await;
+        let username = format!("u{user_id}");
+
+        let attempts = (0..10).map(|_| {
+            let state = state.clone();
+            let username = username.clone();
+            tokio::spawn(async move { login_attempt(&state, &username).await })
+        });
+        let responses: Vec<_> = futures_util::future::join_all(attempts)
+            .await
+            .into_iter()
+            .map(|response| response.unwrap())
+            .collect();
+
+        let checked = responses
+            .iter()
+            .filter(|response| response.status() == StatusCode::UNAUTHORIZED)
+            .count();
+        let throttled = responses
+            .iter()
+            .filter(|response| response.status() == StatusCode::TOO_MANY_REQUESTS)
+            .count();
+        // the free attempts, plus the one whose failure starts the delays
+        assert_eq!(checked, 3);
+        assert_eq!(throttled, 7);
+    }
+
+    #[tokio::test]
+    async fn throttling_is_audited_once_per_wait() {
+        use crate::entity::{audit_events, prelude::AuditEvents};
+        use sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter};
+
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [
+            ("login_throttle_account_free_attempts", "0"),
+            ("login_throttle_delay_secs", "30"),
+        ];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let user_id = super::super::new_user(&state.db).await;
+        let username = format!("u{user_id}");
+
+        let response = login_attempt(&state, &username).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        for _ in 0..5 {
+            let response = login_attempt(&state, &username).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        let throttled = AuditEvents::find()
+            .filter(audit_events::Column::UserId.eq(uuid::Uuid::from(user_id)))
+            .filter(audit_events::Column::Kind.eq("login_throttled"))
+            .count(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(throttled, 1);
+    }
+
+    #[tokio::test]
+    async fn signed_in_code_checks_count_against_the_account() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [
+            ("login_throttle_account_free_attempts", "1"),
+            ("login_throttle_delay_secs", "30"),
+        ];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let user_id = super::super::new_user(&state.db).await;
+        AuthRepository::start_totp_enrolment(&state.db, &user_id, "secret".to_string())
+            .await
+            .unwrap();
+        AuthRepository::enable_totp(&state.db, &user_id, 1, Vec::new())
+            .await
+            .unwrap();
+        let wrong_code = || {
+            Json(TotpCodeRequest {
+                code: "abcde-fghij".to_string(),
+            })
+        };
+
+        let result = disable_totp(State(state.clone()), Session::new(user_id), wrong_code()).await;
+        assert!(matches!(result, Err(crate::error::Error::InvalidTotpCode)));
+        let result = reauthenticate(&state, &user_id, None, Some("wrong")).await;
+        assert!(matches!(result, Err(crate::error::Error::Auth(_))));
+
+        let result =
+            regenerate_backup_codes(State(state.clone()), Session::new(user_id), wrong_code())
+                .await
+                .map(|_| ());
+        assert!(matches!(result, Err(crate::error::Error::Throttled { .. })));
+        let response = login_attempt(&state, &format!("u{user_id}")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn password_changes_count_against_the_account() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [
+            ("login_throttle_account_free_attempts", "1"),
+            ("login_throttle_delay_secs", "30"),
+        ];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let user_id = super::super::new_user(&state.db).await;
+        let change = || async {
+            let request = ChangePasswordRequest {
+                current_password: "wrong".to_string(),
+                new_password: "new password".to_string(),
+            };
+            change_password(State(state.clone()), Session::new(user_id), Json(request)).await
+        };
+
+        for _ in 0..2 {
+            assert!(matches!(change().await, Err(crate::error::Error::Auth(_))));
+        }
+
+        assert!(matches!(
+            change().await,
+            Err(crate::error::Error::Throttled { .. })
+        ));
+        let response = login_attempt(&state, &format!("u{user_id}")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    fn register_request(username: &str, invite_code: Option<&str>) -> Json<RegisterRequest> {
+        Json(RegisterRequest {
+            username: username.to_string(),
+            password: "password".to_string(),
+            invite_code: invite_code.map(str::to_string),
+        })
+    }
+
+    #[tokio::test]
+    async fn closed_registration_takes_only_invites() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let state = crate::tests::test_state(db, dir.path(), &[]).await;
+        let expires_at = OffsetDateTime::now_utc() + Duration::days(1);
+        let (invite_id, code) = crate::invite::new_invite(&state.db, 1, expires_at).await;
+        let username = format!("u{}", Id::new());
+
+        let result = register(State(state.clone()), register_request(&username, None)).await;
+        assert_eq!(
+            result.err().unwrap().status_and_message().0,
+            StatusCode::UNAUTHORIZED
+        );
+        let result = register(
+            State(state.clone()),
+            register_request(&username, Some("aaaa-bbbb-cccc-dddd")),
+        )
+        .await;
+        assert_eq!(
+            result.err().unwrap().status_and_message().0,
+            StatusCode::FORBIDDEN
+        );
+        assert!(
+            find_user_by_username(&state.db, &username)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // typed back in with other formatting
+        let typed = code.to_uppercase().replace('-', " ");
+        let response = register(
+            State(state.clone()),
+            register_request(&username, Some(&typed)),
+        )
+        .await
+        .unwrap();
+        let user_id = find_user_by_username(&state.db, &username).await.unwrap();
+        assert_eq!(user_id.map(|id| id.to_string()), Some(response.0.user_id));
+        assert_eq!(crate::invite::invite_uses(&state.db, &invite_id).await, 1);
+
+        let other = format!("u{}", Id::new());
+        let result = register(State(state.clone()), register_request(&other, Some(&code))).await;
+        assert!(matches!(result, Err(crate::error::Error::InvalidInvite)));
+    }
+
+    #[tokio::test]
+    async fn open_registration_needs_no_invite() {
+        let Some(db) = test_db().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = [("registration_enabled", "true")];
+        let state = crate::tests::test_state(db, dir.path(), &overrides).await;
+        let username = format!("u{}", Id::new());
+
+        let response = register(State(state.clone()), register_request(&username, None))
+            .await
+            .unwrap();
+
+        let user_id = find_user_by_username(&state.db, &username).await.unwrap();
+        assert_eq!(user_id.map(|id| id.to_string()), Some(response.0.user_id));
+    }
+
+    #[test]
+    fn checks_s256_code_challenges() {
+        let verifier = "M25iVXpKU3puUjFaYWg3T1NDTDQ2dnVldEJKSGRDX0dFenl5RXNCcQ";
+        let challenge = "Zn7ndpQ01vslaBidDJ7aGRTxim6LmbmYHH4n_W--Big";
+
+        assert!(is_code_challenge(challenge));
+        assert!(code_challenge_matches(challenge, verifier));
+        assert!(!code_challenge_matches(challenge, "another verifier"));
+        // a plain challenge is the verifier itself, which isn't accepted
+        assert!(!code_challenge_matches(verifier, verifier));
+        assert!(!is_code_challenge(""));
+        assert!(!is_code_challenge(&challenge.replace('_', "/")));
+    }
+}
@@ -5,7 +5,7 @@ use axum::{
     middleware::Next,
     response::Response,
 };
-use tracing::debug;
+use tracing::{debug, error};
 
 use crate::{
     AppState,
@@ -28,6 +28,24 @@ pub(crate) async fn require_auth(
     Ok(next.run(request).await)
 }
 
+/// Admits only administrators; must run inside [`require_auth`].
+pub(crate) async fn require_admin(
+    State(state): State<AppState>,
+    session: Session,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response> {
+    debug!("require_admin middleware");
+
+    let user_id = session.user_id();
+    if !super::repository::AuthRepository::is_admin(&state.db, &user_id).await? {
+        error!(%user_id, "User is not an admin");
+        return Err(Error::Forbidden);
+    }
+
+    Ok(next.run(request).await)
+}
+
 pub(crate) async fn session_resolver(
     State(state): State<AppState>,
     mut request: Request<Body>,
@@ -45,14 +63,11 @@ pub(crate) async fn session_resolver(
                 .map_err(|_| AuthError::InvalidAuthHeader)
         });
 
-    let db = state.db;
-    let user_id = match auth_token {
-        Ok(auth_token) => super::handlers::verify_token(&db, auth_token).await,
+    let result_session = match auth_token {
+        Ok(auth_token) => super::handlers::verify_token(&state, auth_token).await,
         Err(e) => Err(Error::Auth(e)),
     };
 
-    let result_session = user_id.map(Session::new);
-
     request.extensions_mut().insert(result_session);
 
     debug!("session_resolver next");
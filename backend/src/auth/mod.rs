@@ -1,7 +1,21 @@
+mod cache;
 pub(crate) mod error;
 mod handlers;
 pub(crate) mod middleware;
 mod repository;
 mod routes;
+mod throttle;
+mod totp;
 
-pub(crate) use routes::routes;
+pub(crate) use cache::SessionCache;
+pub(crate) use handlers::{
+    check_key_envelope, code_challenge_matches, fail_login_challenge, find_user_by_username,
+    is_code_challenge, issue_login_code, login_challenge_user, purge_expired_sessions,
+    reauthenticate, record_sync_cursor, redeem_login_challenge, resolve_external_account,
+    seal_totp_secrets, start_session,
+};
+#[cfg(test)]
+pub(crate) use repository::tests::{new_session, new_user};
+pub(crate) use routes::{admin_routes, routes};
+pub(crate) use throttle::LoginThrottle;
+pub(crate) use totp::SecretKey as TotpSecretKey;
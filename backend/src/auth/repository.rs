@@ -1,11 +1,22 @@
 use crate::database::DbPool;
-use crate::entity::prelude::{AuthTokens, UserAccounts};
+use crate::entity::prelude::{
+    AppUsers, AuthTokens, Devices, LoginChallenges, LoginCodes, Passkeys, TotpBackupCodes,
+    UserAccounts, UserKeys, UserTotp,
+};
 use crate::entity::{
-    app_users, auth_tokens, sea_orm_active_enums::Provider, user_accounts, user_keys,
+    app_users, auth_tokens, devices, login_challenges, login_codes, passkeys,
+    sea_orm_active_enums::Provider, totp_backup_codes, user_accounts, user_keys, user_totp,
 };
 use crate::error::{Error, Result};
 use crate::ulid::Id;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set, TransactionTrait};
+use sea_orm::sea_query::{Expr, ExprTrait, OnConflict};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    Set, TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use time::{Duration, OffsetDateTime};
 use tracing::error;
 
 pub(super) struct User {
@@ -13,15 +24,114 @@ pub(super) struct User {
     pub password: String,
 }
 
+pub(super) struct AuthToken {
+    pub id: Id,
+    pub user_id: Id,
+    pub device_id: Id,
+    pub expires_at: OffsetDateTime,
+}
+
+/// A new access token and the refresh token that renews it.
+pub(super) struct IssuedTokens {
+    pub access_token: String,
+    pub expires_at: OffsetDateTime,
+    pub refresh_token: String,
+    pub refresh_expires_at: OffsetDateTime,
+}
+
+pub(super) struct SessionRow {
+    pub id: Id,
+    pub device_id: Id,
+    pub created_at: OffsetDateTime,
+    /// When the session can no longer be refreshed.
+    pub expires_at: OffsetDateTime,
+}
+
+pub(super) struct DeviceRow {
+    pub id: Id,
+    pub name: String,
+    pub platform: String,
+    pub created_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
+    pub last_sync_cursor: Option<i64>,
+    /// Whether any of its sessions can still be used or refreshed.
+    pub signed_in: bool,
+}
+
+pub(super) struct TotpRow {
+    /// As stored, see [`super::totp::load_secret`].
+    pub secret: String,
+    /// Unset while the enrolment waits for its first code.
+    pub enabled: bool,
+}
+
+/// A password login waiting for its second factor.
+pub(super) struct LoginChallengeRow {
+    pub user_id: Id,
+    pub device_id: Option<Id>,
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// A finished sign-in waiting for its client to redeem the code.
+pub(super) struct LoginCodeRow {
+    pub user_id: Id,
+    /// S256 PKCE challenge the client's verifier must match.
+    pub code_challenge: String,
+    pub device_id: Option<Id>,
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// How a bearer token is kept at rest: its SHA-256 digest (hex), found by
+/// the first few digits of it and then compared in constant time.
+struct TokenDigest {
+    prefix: String,
+    hash: String,
+}
+
+impl TokenDigest {
+    const PREFIX_LEN: usize = 8;
+
+    fn of(token: &str) -> Self {
+        let hash = hex::encode(Sha256::digest(token.as_bytes()));
+        Self {
+            prefix: hash[..Self::PREFIX_LEN].to_string(),
+            hash,
+        }
+    }
+
+    fn matches(&self, stored: &str) -> bool {
+        self.hash.as_bytes().ct_eq(stored.as_bytes()).into()
+    }
+}
+
 pub(super) struct AuthRepository;
 
 impl AuthRepository {
-    pub async fn save_auth_token(db: &DbPool, user_id: &Id, auth_token: &str) -> Result<()> {
+    /// Start a session on `device` with freshly issued tokens; returns its
+    /// id.
+    pub async fn create_session(
+        db: &DbPool,
+        user_id: &Id,
+        device_id: &Id,
+        tokens: &IssuedTokens,
+    ) -> Result<Id> {
         let token_id = Id::new();
+        let access = TokenDigest::of(&tokens.access_token);
+        let refresh = TokenDigest::of(&tokens.refresh_token);
         auth_tokens::ActiveModel {
             id: Set(uuid::Uuid::from(token_id)),
             user_id: Set(uuid::Uuid::from(*user_id)),
-            token: Set(auth_token.to_string()),
+            device_id: Set(uuid::Uuid::from(*device_id)),
+            expires_at: Set(tokens.expires_at),
+            refresh_expires_at: Set(Some(tokens.refresh_expires_at)),
+            token_prefix: Set(access.prefix),
+            token_hash: Set(access.hash),
+            refresh_token_prefix: Set(Some(refresh.prefix)),
+            refresh_token_hash: Set(Some(refresh.hash)),
             ..Default::default()
         }
         .insert(db)
@@ -31,50 +141,419 @@ impl AuthRepository {
             Error::Database
         })?;
 
-        Ok(())
+        Ok(token_id)
     }
 
-    pub async fn get_by_token(db: &DbPool, auth_token: &str) -> Result<Id> {
+    /// The session of an access token that hasn't expired.
+    pub async fn get_by_token(db: &DbPool, auth_token: &str) -> Result<AuthToken> {
+        let digest = TokenDigest::of(auth_token);
         let row = AuthTokens::find()
-            .filter(auth_tokens::Column::Token.eq(auth_token))
-            .one(db)
+            .filter(auth_tokens::Column::TokenPrefix.eq(digest.prefix.as_str()))
+            .filter(auth_tokens::Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+            .all(db)
             .await
             .map_err(|e| {
                 error!(error = %e, "Could not query auth token");
                 Error::Database
             })?
+            .into_iter()
+            .find(|row| digest.matches(&row.token_hash))
             .ok_or_else(|| {
                 error!("Auth token not found");
                 Error::Database
             })?;
 
-        Ok(Id::from(row.user_id))
+        Ok(AuthToken {
+            id: Id::from(row.id),
+            user_id: Id::from(row.user_id),
+            device_id: Id::from(row.device_id),
+            expires_at: row.expires_at,
+        })
     }
 
-    pub(crate) async fn save_keys(
+    /// Swap in new tokens for the session holding `refresh_token`, if it
+    /// hasn't expired. The old refresh token stops working, so each one can
+    /// be used once. The tokens' expiry is cut short where it would outlast
+    /// `lifetime` from the session's sign-in. Returns the refreshed session.
+    pub async fn refresh_session(
+        db: &DbPool,
+        refresh_token: &str,
+        tokens: &mut IssuedTokens,
+        lifetime: Duration,
+    ) -> Result<Option<AuthToken>> {
+        let now = OffsetDateTime::now_utc();
+        let digest = TokenDigest::of(refresh_token);
+        let row = AuthTokens::find()
+            .filter(auth_tokens::Column::RefreshTokenPrefix.eq(digest.prefix.as_str()))
+            .filter(auth_tokens::Column::RefreshExpiresAt.gt(now))
+            .all(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query refresh token");
+                Error::Database
+            })?
+            .into_iter()
+            .find(|row| {
+                row.refresh_token_hash
+                    .as_deref()
+                    .is_some_and(|hash| digest.matches(hash))
+            });
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let Some(previous_hash) = row.refresh_token_hash else {
+            return Ok(None);
+        };
+        let ends_at = row.created_at + lifetime;
+        if ends_at <= now {
+            return Ok(None);
+        }
+        tokens.expires_at = std::cmp::min(tokens.expires_at, ends_at);
+        tokens.refresh_expires_at = std::cmp::min(tokens.refresh_expires_at, ends_at);
+
+        let access = TokenDigest::of(&tokens.access_token);
+        let refresh = TokenDigest::of(&tokens.refresh_token);
+        let result = AuthTokens::update_many()
+            .col_expr(auth_tokens::Column::TokenPrefix, Expr::value(access.prefix))
+            .col_expr(auth_tokens::Column::TokenHash, Expr::value(access.hash))
+            .col_expr(
+                auth_tokens::Column::ExpiresAt,
+                Expr::value(tokens.expires_at),
+            )
+            .col_expr(
+                auth_tokens::Column::RefreshTokenPrefix,
+                Expr::value(refresh.prefix),
+            )
+            .col_expr(
+                auth_tokens::Column::RefreshTokenHash,
+                Expr::value(refresh.hash),
+            )
+            .col_expr(
+                auth_tokens::Column::RefreshExpiresAt,
+                Expr::value(tokens.refresh_expires_at),
+            )
+            .filter(auth_tokens::Column::Id.eq(row.id))
+            .filter(auth_tokens::Column::RefreshTokenHash.eq(previous_hash))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not refresh session");
+                Error::Database
+            })?;
+
+        // lost a race with a concurrent refresh of the same token
+        if result.rows_affected == 0 {
+            return Ok(None);
+        }
+        Ok(Some(AuthToken {
+            id: Id::from(row.id),
+            user_id: Id::from(row.user_id),
+            device_id: Id::from(row.device_id),
+            expires_at: tokens.expires_at,
+        }))
+    }
+
+    /// The user's sessions that can still be used or refreshed, newest first.
+    pub async fn list_sessions(db: &DbPool, user_id: &Id) -> Result<Vec<SessionRow>> {
+        let now = OffsetDateTime::now_utc();
+        let rows = AuthTokens::find()
+            .filter(auth_tokens::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(
+                Condition::any()
+                    .add(auth_tokens::Column::ExpiresAt.gt(now))
+                    .add(auth_tokens::Column::RefreshExpiresAt.gt(now)),
+            )
+            .order_by_desc(auth_tokens::Column::CreatedAt)
+            .all(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list sessions");
+                Error::Database
+            })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SessionRow {
+                id: Id::from(row.id),
+                device_id: Id::from(row.device_id),
+                created_at: row.created_at,
+                expires_at: row.refresh_expires_at.unwrap_or(row.expires_at),
+            })
+            .collect())
+    }
+
+    /// Revoke one session of the user; returns whether it existed.
+    pub async fn delete_session(db: &DbPool, user_id: &Id, token_id: &Id) -> Result<bool> {
+        let result = AuthTokens::delete_many()
+            .filter(auth_tokens::Column::Id.eq(uuid::Uuid::from(*token_id)))
+            .filter(auth_tokens::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete session");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Revoke every session of the user; returns how many there were.
+    pub async fn delete_sessions(db: &DbPool, user_id: &Id) -> Result<u64> {
+        let result = AuthTokens::delete_many()
+            .filter(auth_tokens::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete sessions");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Drop sessions that can neither be used nor refreshed anymore.
+    pub async fn delete_expired_sessions(db: &DbPool, now: OffsetDateTime) -> Result<u64> {
+        let result = AuthTokens::delete_many()
+            .filter(auth_tokens::Column::ExpiresAt.lte(now))
+            .filter(
+                Condition::any()
+                    .add(auth_tokens::Column::RefreshExpiresAt.is_null())
+                    .add(auth_tokens::Column::RefreshExpiresAt.lte(now)),
+            )
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired sessions");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// The device to sign in on: `existing` if it is one of the user's,
+    /// renamed to `name` and `platform`, or else a new one.
+    pub async fn register_device(
         db: &DbPool,
         user_id: &Id,
-        private_key: &str,
-        public_key: &str,
-    ) -> Result<()> {
-        let keys_id = Id::new();
-        user_keys::ActiveModel {
-            id: Set(uuid::Uuid::from(keys_id)),
+        existing: Option<Id>,
+        name: &str,
+        platform: &str,
+    ) -> Result<Id> {
+        let now = OffsetDateTime::now_utc();
+        if let Some(device_id) = existing {
+            let result = Devices::update_many()
+                .col_expr(devices::Column::Name, Expr::value(name))
+                .col_expr(devices::Column::Platform, Expr::value(platform))
+                .col_expr(devices::Column::LastSeenAt, Expr::value(now))
+                .filter(devices::Column::Id.eq(uuid::Uuid::from(device_id)))
+                .filter(devices::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+                .exec(db)
+                .await
+                .map_err(|e| {
+                    error!(error = %e, "Could not update device");
+                    Error::Database
+                })?;
+            if result.rows_affected > 0 {
+                return Ok(device_id);
+            }
+        }
+
+        let device_id = Id::new();
+        devices::ActiveModel {
+            id: Set(uuid::Uuid::from(device_id)),
             user_id: Set(uuid::Uuid::from(*user_id)),
-            private_key: Set(private_key.to_string()),
-            public_key: Set(public_key.to_string()),
-            ..Default::default()
+            name: Set(name.to_string()),
+            platform: Set(platform.to_string()),
+            created_at: Set(now),
+            last_seen_at: Set(now),
+            last_sync_cursor: Set(None),
         }
         .insert(db)
         .await
         .map_err(|e| {
-            error!(error = %e, "Could not save user keys");
+            error!(error = %e, "Could not save device");
             Error::Database
         })?;
 
+        Ok(device_id)
+    }
+
+    /// The user's devices, most recently seen first.
+    pub async fn list_devices(db: &DbPool, user_id: &Id) -> Result<Vec<DeviceRow>> {
+        let rows = Devices::find()
+            .filter(devices::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .order_by_desc(devices::Column::LastSeenAt)
+            .all(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list devices");
+                Error::Database
+            })?;
+        let signed_in: Vec<Id> = Self::list_sessions(db, user_id)
+            .await?
+            .into_iter()
+            .map(|session| session.device_id)
+            .collect();
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DeviceRow {
+                id: Id::from(row.id),
+                signed_in: signed_in.contains(&Id::from(row.id)),
+                name: row.name,
+                platform: row.platform,
+                created_at: row.created_at,
+                last_seen_at: row.last_seen_at,
+                last_sync_cursor: row.last_sync_cursor,
+            })
+            .collect())
+    }
+
+    /// Forget one device of the user, ending its sessions; returns whether
+    /// it existed.
+    pub async fn delete_device(db: &DbPool, user_id: &Id, device_id: &Id) -> Result<bool> {
+        let result = Devices::delete_many()
+            .filter(devices::Column::Id.eq(uuid::Uuid::from(*device_id)))
+            .filter(devices::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete device");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Mark the device as seen now, unless it was within `interval`.
+    pub async fn touch_device(db: &DbPool, device_id: &Id, interval: Duration) -> Result<()> {
+        let now = OffsetDateTime::now_utc();
+        Devices::update_many()
+            .col_expr(devices::Column::LastSeenAt, Expr::value(now))
+            .filter(devices::Column::Id.eq(uuid::Uuid::from(*device_id)))
+            .filter(devices::Column::LastSeenAt.lt(now - interval))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not update device last seen time");
+                Error::Database
+            })?;
+
+        Ok(())
+    }
+
+    /// Remember the change sequence the device has applied up to.
+    pub async fn record_sync_cursor(db: &DbPool, device_id: &Id, cursor: i64) -> Result<()> {
+        Devices::update_many()
+            .col_expr(devices::Column::LastSyncCursor, Expr::value(cursor))
+            .filter(devices::Column::Id.eq(uuid::Uuid::from(*device_id)))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not record sync cursor");
+                Error::Database
+            })?;
+
         Ok(())
     }
 
+    /// Store the user's key pair unless they already have one; returns
+    /// whether it was stored. The unique index on the user settles
+    /// concurrent uploads.
+    pub(crate) async fn save_keys(
+        db: &DbPool,
+        user_id: &Id,
+        private_key: &str,
+        public_key: &str,
+    ) -> Result<bool> {
+        let model = user_keys::ActiveModel {
+            id: Set(uuid::Uuid::from(Id::new())),
+            user_id: Set(uuid::Uuid::from(*user_id)),
+            private_key: Set(private_key.to_string()),
+            public_key: Set(public_key.to_string()),
+            ..Default::default()
+        };
+
+        let inserted = UserKeys::insert(model)
+            .on_conflict(
+                OnConflict::column(user_keys::Column::UserId)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not save user keys");
+                Error::Database
+            })?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Swap the wrapped private key, but only if the stored one is still
+    /// `previous`, so two devices re-wrapping at once can't silently overwrite
+    /// each other. Returns whether it was swapped.
+    pub(crate) async fn replace_private_key(
+        db: &DbPool,
+        user_id: &Id,
+        previous: &str,
+        private_key: &str,
+    ) -> Result<bool> {
+        let result = UserKeys::update_many()
+            .col_expr(user_keys::Column::PrivateKey, Expr::value(private_key))
+            .filter(user_keys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(user_keys::Column::PrivateKey.eq(previous))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not replace private key");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Swap the recovery key, but only if the stored one is still `previous`
+    /// (`None` for none), like [`Self::replace_private_key`]. Returns whether
+    /// it was swapped.
+    pub(crate) async fn replace_recovery_key(
+        db: &DbPool,
+        user_id: &Id,
+        previous: Option<&str>,
+        recovery_key: &str,
+    ) -> Result<bool> {
+        let unchanged = match previous {
+            Some(previous) => user_keys::Column::RecoveryKey.eq(previous),
+            None => user_keys::Column::RecoveryKey.is_null(),
+        };
+        let result = UserKeys::update_many()
+            .col_expr(user_keys::Column::RecoveryKey, Expr::value(recovery_key))
+            .filter(user_keys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(unchanged)
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not save recovery key");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    pub(crate) async fn get_recovery_key(db: &DbPool, user_id: &Id) -> Result<Option<String>> {
+        let result = user_keys::Entity::find()
+            .filter(user_keys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get recovery key");
+                Error::Database
+            })?
+            .and_then(|row| row.recovery_key);
+
+        Ok(result)
+    }
+
     pub(crate) async fn get_private_key(db: &DbPool, user_id: &Id) -> Result<Option<String>> {
         let result = user_keys::Entity::find()
             .filter(user_keys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
@@ -94,64 +573,1171 @@ impl AuthRepository {
         user_id: &Id,
         username: &str,
         password_hash: &str,
+    ) -> Result<()> {
+        Self::save_user_with_account(
+            db,
+            user_id,
+            username,
+            username,
+            Provider::Credentials,
+            Some(password_hash),
+        )
+        .await
+    }
+
+    /// Create a user who signs in with `account_id` at `provider`.
+    pub async fn save_user_with_account(
+        db: &DbPool,
+        user_id: &Id,
+        name: &str,
+        account_id: &str,
+        provider: Provider,
+        password_hash: Option<&str>,
     ) -> Result<()> {
         db.transaction::<_, (), sea_orm::DbErr>(|txn| {
+            let user_id = *user_id;
+            let name = name.to_string();
+            let account_id = account_id.to_string();
+            let password_hash = password_hash.map(str::to_string);
+            Box::pin(async move {
+                insert_user(txn, &user_id, name, account_id, provider, password_hash).await
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save user with account");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    /// Like [`Self::save_user_with_credentials`], using up one registration
+    /// of the invite with `invite_code_hash`. Returns false, creating no
+    /// user, if the invite can't be redeemed.
+    pub async fn save_invited_user(
+        db: &DbPool,
+        user_id: &Id,
+        username: &str,
+        password_hash: &str,
+        invite_code_hash: &str,
+    ) -> Result<bool> {
+        db.transaction::<_, bool, sea_orm::DbErr>(|txn| {
             let user_id = *user_id;
             let username = username.to_string();
             let password_hash = password_hash.to_string();
+            let invite_code_hash = invite_code_hash.to_string();
             Box::pin(async move {
-                app_users::ActiveModel {
-                    id: Set(uuid::Uuid::from(user_id)),
-                    name: Set(Some(username.clone())),
-                    ..Default::default()
-                }
-                .insert(txn)
-                .await?;
-
-                let account_id = Id::new();
-                user_accounts::ActiveModel {
-                    id: Set(uuid::Uuid::from(account_id)),
-                    user_id: Set(uuid::Uuid::from(user_id)),
-                    account_id: Set(username),
-                    password: Set(Some(password_hash)),
-                    provider: Set(Provider::Credentials),
-                    ..Default::default()
+                if !crate::invite::redeem_invite(txn, &invite_code_hash).await? {
+                    return Ok(false);
                 }
-                .insert(txn)
+                insert_user(
+                    txn,
+                    &user_id,
+                    username.clone(),
+                    username,
+                    Provider::Credentials,
+                    Some(password_hash),
+                )
                 .await?;
-
-                Ok(())
+                Ok(true)
             })
         })
         .await
         .map_err(|e| {
-            error!(error = %e, "Could not save user with credentials");
+            error!(error = %e, "Could not save invited user");
+            Error::Database
+        })
+    }
+
+    /// The user who signs in with `account_id` at `provider`, if any.
+    pub async fn find_account_user(
+        db: &DbPool,
+        account_id: &str,
+        provider: Provider,
+    ) -> Result<Option<Id>> {
+        let account = UserAccounts::find()
+            .filter(user_accounts::Column::AccountId.eq(account_id))
+            .filter(user_accounts::Column::Provider.eq(provider))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query user account");
+                Error::Database
+            })?;
+
+        Ok(account.map(|account| Id::from(account.user_id)))
+    }
+
+    /// Let the user also sign in with `account_id` at `provider`. Returns
+    /// false if that account already belongs to someone else.
+    pub async fn link_account(
+        db: &DbPool,
+        user_id: &Id,
+        account_id: &str,
+        provider: Provider,
+    ) -> Result<bool> {
+        if let Some(owner) = Self::find_account_user(db, account_id, provider.clone()).await? {
+            return Ok(owner == *user_id);
+        }
+
+        user_accounts::ActiveModel {
+            id: Set(uuid::Uuid::from(Id::new())),
+            user_id: Set(uuid::Uuid::from(*user_id)),
+            account_id: Set(account_id.to_string()),
+            password: Set(None),
+            provider: Set(provider),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not link user account");
             Error::Database
         })?;
 
-        Ok(())
+        Ok(true)
     }
 
-    pub async fn get_by_username(db: &DbPool, username: &str) -> Result<User> {
+    pub async fn is_admin(db: &DbPool, user_id: &Id) -> Result<bool> {
+        let user = AppUsers::find_by_id(uuid::Uuid::from(*user_id))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query user");
+                Error::Database
+            })?;
+
+        Ok(user.is_some_and(|u| u.is_admin))
+    }
+
+    /// The password hash of the user's credentials account, if they have one.
+    pub async fn get_password(db: &DbPool, user_id: &Id) -> Result<Option<String>> {
         let account = UserAccounts::find()
-            .filter(user_accounts::Column::AccountId.eq(username))
+            .filter(user_accounts::Column::UserId.eq(uuid::Uuid::from(*user_id)))
             .filter(user_accounts::Column::Provider.eq(Provider::Credentials))
             .one(db)
             .await
             .map_err(|e| {
                 error!(error = %e, "Could not query user account");
                 Error::Database
-            })?
-            .ok_or_else(|| {
-                error!("User account not found");
-                Error::Database
             })?;
 
-        Ok(User {
-            id: Id::from(account.user_id),
-            password: account
-                .password
-                .expect("Password must be set for credentials user"),
+        Ok(account.and_then(|a| a.password))
+    }
+
+    /// Set a new password hash and revoke every auth token of the user except
+    /// `keep_token`.
+    pub async fn change_password(
+        db: &DbPool,
+        user_id: &Id,
+        password_hash: &str,
+        keep_token: Option<Id>,
+    ) -> Result<()> {
+        let user_id = uuid::Uuid::from(*user_id);
+        let password_hash = password_hash.to_string();
+        let keep_token = keep_token.map(uuid::Uuid::from);
+
+        db.transaction::<_, (), sea_orm::DbErr>(|txn| {
+            Box::pin(async move {
+                UserAccounts::update_many()
+                    .col_expr(user_accounts::Column::Password, Expr::value(password_hash))
+                    .filter(user_accounts::Column::UserId.eq(user_id))
+                    .filter(user_accounts::Column::Provider.eq(Provider::Credentials))
+                    .exec(txn)
+                    .await?;
+
+                let mut revoke =
+                    AuthTokens::delete_many().filter(auth_tokens::Column::UserId.eq(user_id));
+                if let Some(keep_token) = keep_token {
+                    revoke = revoke.filter(auth_tokens::Column::Id.ne(keep_token));
+                }
+                revoke.exec(txn).await?;
+
+                Ok(())
+            })
         })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not change password");
+            Error::Database
+        })
+    }
+
+    pub async fn get_user_name(db: &DbPool, user_id: &Id) -> Result<Option<String>> {
+        let user = AppUsers::find_by_id(uuid::Uuid::from(*user_id))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query user");
+                Error::Database
+            })?;
+
+        Ok(user.and_then(|user| user.name))
+    }
+
+    pub async fn get_totp(db: &DbPool, user_id: &Id) -> Result<Option<TotpRow>> {
+        let row = UserTotp::find_by_id(uuid::Uuid::from(*user_id))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query TOTP secret");
+                Error::Database
+            })?;
+
+        Ok(row.map(|row| TotpRow {
+            secret: row.secret,
+            enabled: row.enabled_at.is_some(),
+        }))
+    }
+
+    /// Secrets still kept as hex, by user.
+    pub async fn unsealed_totp_secrets(db: &DbPool) -> Result<Vec<(Id, String)>> {
+        let rows = UserTotp::find()
+            .filter(user_totp::Column::Secret.not_like(format!("{}%", super::totp::SEALED_PREFIX)))
+            .all(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query TOTP secrets");
+                Error::Database
+            })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (Id::from(row.user_id), row.secret))
+            .collect())
+    }
+
+    /// Swap the stored secret of the user for `sealed`, if it is still
+    /// `previous`; returns whether it was.
+    pub async fn seal_totp_secret(
+        db: &DbPool,
+        user_id: &Id,
+        previous: &str,
+        sealed: &str,
+    ) -> Result<bool> {
+        let result = UserTotp::update_many()
+            .col_expr(user_totp::Column::Secret, Expr::value(sealed))
+            .filter(user_totp::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(user_totp::Column::Secret.eq(previous))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not seal TOTP secret");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Replace any pending enrolment with `secret`, in its stored form.
+    /// Returns false if the user already has TOTP enabled.
+    pub async fn start_totp_enrolment(db: &DbPool, user_id: &Id, secret: String) -> Result<bool> {
+        let user_id = uuid::Uuid::from(*user_id);
+
+        db.transaction::<_, bool, sea_orm::DbErr>(|txn| {
+            Box::pin(async move {
+                let deleted = UserTotp::delete_many()
+                    .filter(user_totp::Column::UserId.eq(user_id))
+                    .filter(user_totp::Column::EnabledAt.is_null())
+                    .exec(txn)
+                    .await?;
+                let enabled = deleted.rows_affected == 0
+                    && UserTotp::find_by_id(user_id).count(txn).await? > 0;
+                if enabled {
+                    return Ok(false);
+                }
+
+                user_totp::ActiveModel {
+                    user_id: Set(user_id),
+                    secret: Set(secret),
+                    enabled_at: Set(None),
+                    last_used_step: Set(None),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save TOTP secret");
+            Error::Database
+        })
+    }
+
+    /// Turn on the pending enrolment, confirmed by the code of `step`, with
+    /// a fresh set of backup codes. Returns false if nothing was pending.
+    pub async fn enable_totp(
+        db: &DbPool,
+        user_id: &Id,
+        step: i64,
+        backup_code_hashes: Vec<String>,
+    ) -> Result<bool> {
+        let user_id = uuid::Uuid::from(*user_id);
+
+        db.transaction::<_, bool, sea_orm::DbErr>(|txn| {
+            Box::pin(async move {
+                let result = UserTotp::update_many()
+                    .col_expr(
+                        user_totp::Column::EnabledAt,
+                        Expr::value(OffsetDateTime::now_utc()),
+                    )
+                    .col_expr(user_totp::Column::LastUsedStep, Expr::value(step))
+                    .filter(user_totp::Column::UserId.eq(user_id))
+                    .filter(user_totp::Column::EnabledAt.is_null())
+                    .exec(txn)
+                    .await?;
+                if result.rows_affected == 0 {
+                    return Ok(false);
+                }
+
+                replace_backup_codes(txn, user_id, backup_code_hashes).await?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not enable TOTP");
+            Error::Database
+        })
+    }
+
+    /// Turn TOTP off and drop the backup codes; returns whether it was on or
+    /// pending.
+    pub async fn disable_totp(db: &DbPool, user_id: &Id) -> Result<bool> {
+        let user_id = uuid::Uuid::from(*user_id);
+
+        db.transaction::<_, bool, sea_orm::DbErr>(|txn| {
+            Box::pin(async move {
+                TotpBackupCodes::delete_many()
+                    .filter(totp_backup_codes::Column::UserId.eq(user_id))
+                    .exec(txn)
+                    .await?;
+                let result = UserTotp::delete_many()
+                    .filter(user_totp::Column::UserId.eq(user_id))
+                    .exec(txn)
+                    .await?;
+                Ok(result.rows_affected > 0)
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not disable TOTP");
+            Error::Database
+        })
+    }
+
+    /// Mark the code of `step` as used. Returns false if it or a later one
+    /// already was, so each code signs in once.
+    pub async fn use_totp_step(db: &DbPool, user_id: &Id, step: i64) -> Result<bool> {
+        let result = UserTotp::update_many()
+            .col_expr(user_totp::Column::LastUsedStep, Expr::value(step))
+            .filter(user_totp::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(user_totp::Column::EnabledAt.is_not_null())
+            .filter(
+                Condition::any()
+                    .add(user_totp::Column::LastUsedStep.is_null())
+                    .add(user_totp::Column::LastUsedStep.lt(step)),
+            )
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not record TOTP step");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// The hashes of the user's unused backup codes.
+    pub async fn backup_code_hashes(db: &DbPool, user_id: &Id) -> Result<Vec<String>> {
+        let rows = TotpBackupCodes::find()
+            .filter(totp_backup_codes::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .all(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query backup codes");
+                Error::Database
+            })?;
+
+        Ok(rows.into_iter().map(|row| row.code_hash).collect())
+    }
+
+    /// Spend the backup code stored as `code_hash`; returns whether the user
+    /// still had it.
+    pub async fn use_backup_code(db: &DbPool, user_id: &Id, code_hash: &str) -> Result<bool> {
+        let result = TotpBackupCodes::delete_many()
+            .filter(totp_backup_codes::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(totp_backup_codes::Column::CodeHash.eq(code_hash))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not use backup code");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    pub async fn count_backup_codes(db: &DbPool, user_id: &Id) -> Result<u64> {
+        TotpBackupCodes::find()
+            .filter(totp_backup_codes::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .count(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not count backup codes");
+                Error::Database
+            })
+    }
+
+    /// Invalidate the user's backup codes in favour of new ones.
+    pub async fn replace_backup_codes(
+        db: &DbPool,
+        user_id: &Id,
+        code_hashes: Vec<String>,
+    ) -> Result<()> {
+        let user_id = uuid::Uuid::from(*user_id);
+
+        db.transaction::<_, (), sea_orm::DbErr>(|txn| {
+            Box::pin(async move { replace_backup_codes(txn, user_id, code_hashes).await })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not replace backup codes");
+            Error::Database
+        })
+    }
+
+    pub async fn has_passkeys(db: &DbPool, user_id: &Id) -> Result<bool> {
+        let count = Passkeys::find()
+            .filter(passkeys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .count(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not count passkeys");
+                Error::Database
+            })?;
+
+        Ok(count > 0)
+    }
+
+    /// Park a password login until its second factor arrives. Expired
+    /// challenges are cleared out on the way.
+    pub async fn create_login_challenge(
+        db: &DbPool,
+        challenge: &str,
+        row: &LoginChallengeRow,
+    ) -> Result<()> {
+        LoginChallenges::delete_many()
+            .filter(login_challenges::Column::ExpiresAt.lte(OffsetDateTime::now_utc()))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired login challenges");
+                Error::Database
+            })?;
+
+        login_challenges::ActiveModel {
+            challenge_hash: Set(TokenDigest::of(challenge).hash),
+            user_id: Set(uuid::Uuid::from(row.user_id)),
+            device_id: Set(row.device_id.map(uuid::Uuid::from)),
+            device_name: Set(row.device_name.clone()),
+            platform: Set(row.platform.clone()),
+            attempts: Set(0),
+            expires_at: Set(row.expires_at),
+        }
+        .insert(db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save login challenge");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    /// The login waiting on `challenge`, unless it expired.
+    pub async fn get_login_challenge(
+        db: &DbPool,
+        challenge: &str,
+    ) -> Result<Option<LoginChallengeRow>> {
+        let row = LoginChallenges::find_by_id(TokenDigest::of(challenge).hash)
+            .filter(login_challenges::Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query login challenge");
+                Error::Database
+            })?;
+
+        Ok(row.map(|row| LoginChallengeRow {
+            user_id: Id::from(row.user_id),
+            device_id: row.device_id.map(Id::from),
+            device_name: row.device_name,
+            platform: row.platform,
+            expires_at: row.expires_at,
+        }))
+    }
+
+    /// Count a wrong code against the challenge, dropping it once it has
+    /// had `max_attempts`.
+    pub async fn fail_login_challenge(
+        db: &DbPool,
+        challenge: &str,
+        max_attempts: i32,
+    ) -> Result<()> {
+        let hash = TokenDigest::of(challenge).hash;
+
+        db.transaction::<_, (), sea_orm::DbErr>(|txn| {
+            Box::pin(async move {
+                LoginChallenges::update_many()
+                    .col_expr(
+                        login_challenges::Column::Attempts,
+                        Expr::col(login_challenges::Column::Attempts).add(1),
+                    )
+                    .filter(login_challenges::Column::ChallengeHash.eq(hash.as_str()))
+                    .exec(txn)
+                    .await?;
+                LoginChallenges::delete_many()
+                    .filter(login_challenges::Column::ChallengeHash.eq(hash.as_str()))
+                    .filter(login_challenges::Column::Attempts.gte(max_attempts))
+                    .exec(txn)
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not record failed login challenge");
+            Error::Database
+        })
+    }
+
+    /// Redeem the challenge; returns false if someone else already did.
+    pub async fn delete_login_challenge(db: &DbPool, challenge: &str) -> Result<bool> {
+        let result = LoginChallenges::delete_many()
+            .filter(login_challenges::Column::ChallengeHash.eq(TokenDigest::of(challenge).hash))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete login challenge");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Keep the sign-in for its client to redeem with `code`.
+    pub async fn create_login_code(db: &DbPool, code: &str, row: &LoginCodeRow) -> Result<()> {
+        // unredeemed codes are cleared as new ones come in
+        LoginCodes::delete_many()
+            .filter(login_codes::Column::ExpiresAt.lte(OffsetDateTime::now_utc()))
+            .exec(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired login codes");
+                Error::Database
+            })?;
+
+        login_codes::ActiveModel {
+            code_hash: Set(TokenDigest::of(code).hash),
+            user_id: Set(uuid::Uuid::from(row.user_id)),
+            code_challenge: Set(row.code_challenge.clone()),
+            device_id: Set(row.device_id.map(uuid::Uuid::from)),
+            device_name: Set(row.device_name.clone()),
+            platform: Set(row.platform.clone()),
+            expires_at: Set(row.expires_at),
+        }
+        .insert(db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save login code");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    /// Remove and return the sign-in behind `code` unless it expired, so
+    /// each code is redeemed once.
+    pub async fn take_login_code(db: &DbPool, code: &str) -> Result<Option<LoginCodeRow>> {
+        let row = LoginCodes::delete_many()
+            .filter(login_codes::Column::CodeHash.eq(TokenDigest::of(code).hash))
+            .exec_with_returning(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not take login code");
+                Error::Database
+            })?
+            .into_iter()
+            .find(|row| row.expires_at > OffsetDateTime::now_utc());
+
+        Ok(row.map(|row| LoginCodeRow {
+            user_id: Id::from(row.user_id),
+            code_challenge: row.code_challenge,
+            device_id: row.device_id.map(Id::from),
+            device_name: row.device_name,
+            platform: row.platform,
+            expires_at: row.expires_at,
+        }))
+    }
+
+    pub async fn get_by_username(db: &DbPool, username: &str) -> Result<User> {
+        let account = UserAccounts::find()
+            .filter(user_accounts::Column::AccountId.eq(username))
+            .filter(user_accounts::Column::Provider.eq(Provider::Credentials))
+            .one(db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query user account");
+                Error::Database
+            })?
+            .ok_or_else(|| {
+                error!("User account not found");
+                Error::Database
+            })?;
+
+        Ok(User {
+            id: Id::from(account.user_id),
+            password: account
+                .password
+                .expect("Password must be set for credentials user"),
+        })
+    }
+}
+
+async fn insert_user<C: sea_orm::ConnectionTrait>(
+    db: &C,
+    user_id: &Id,
+    name: String,
+    account_id: String,
+    provider: Provider,
+    password_hash: Option<String>,
+) -> std::result::Result<(), sea_orm::DbErr> {
+    // the first account administers the instance
+    let has_admin = AppUsers::find()
+        .filter(app_users::Column::IsAdmin.eq(true))
+        .count(db)
+        .await?
+        > 0;
+
+    app_users::ActiveModel {
+        id: Set(uuid::Uuid::from(*user_id)),
+        name: Set(Some(name)),
+        is_admin: Set(!has_admin),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    user_accounts::ActiveModel {
+        id: Set(uuid::Uuid::from(Id::new())),
+        user_id: Set(uuid::Uuid::from(*user_id)),
+        account_id: Set(account_id),
+        password: Set(password_hash),
+        provider: Set(provider),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn replace_backup_codes<C: sea_orm::ConnectionTrait>(
+    db: &C,
+    user_id: uuid::Uuid,
+    code_hashes: Vec<String>,
+) -> std::result::Result<(), sea_orm::DbErr> {
+    TotpBackupCodes::delete_many()
+        .filter(totp_backup_codes::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    let rows = code_hashes
+        .into_iter()
+        .map(|code_hash| totp_backup_codes::ActiveModel {
+            user_id: Set(user_id),
+            code_hash: Set(code_hash),
+        });
+    TotpBackupCodes::insert_many(rows).exec(db).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use futures_util::future::join_all;
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    #[test]
+    fn token_digest_is_the_hex_sha256_and_its_first_digits() {
+        let digest = TokenDigest::of("abc");
+
+        assert_eq!(
+            digest.hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(digest.prefix, "ba7816bf");
+    }
+
+    #[test]
+    fn token_digest_matches_only_the_whole_hash() {
+        let digest = TokenDigest::of("abc");
+
+        assert!(digest.matches(&TokenDigest::of("abc").hash));
+        assert!(!digest.matches(&TokenDigest::of("abd").hash));
+        assert!(!digest.matches(&digest.prefix));
+        assert!(!digest.matches(&digest.hash.to_uppercase()));
+        assert!(!digest.matches(""));
+    }
+
+    /// Sessions from before tokens were digested were converted in SQL by
+    /// m20261017_000011, so both have to agree.
+    #[tokio::test]
+    async fn token_digest_agrees_with_the_migrated_digests() {
+        let Some(db) = test_db().await else { return };
+        let sql = "SELECT encode(sha256(convert_to($1, 'UTF8')), 'hex') AS hash, \
+                   left(encode(sha256(convert_to($1, 'UTF8')), 'hex'), 8) AS prefix";
+
+        for token in [uuid::Uuid::new_v4().to_string(), "päss wörd".to_string()] {
+            let row = db
+                .query_one_raw(Statement::from_sql_and_values(
+                    DbBackend::Postgres,
+                    sql,
+                    [token.clone().into()],
+                ))
+                .await
+                .unwrap()
+                .unwrap();
+            let digest = TokenDigest::of(&token);
+
+            assert_eq!(row.try_get::<String>("", "hash").unwrap(), digest.hash);
+            assert_eq!(row.try_get::<String>("", "prefix").unwrap(), digest.prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn looks_tokens_up_by_prefix_then_by_hash() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (token_id, access_token) = new_session(&db, &user_id).await;
+        let (other_id, other_token) = new_session(&db, &user_id).await;
+        // another session under the same prefix, as a prefix collision
+        AuthTokens::update_many()
+            .col_expr(
+                auth_tokens::Column::TokenPrefix,
+                Expr::value(TokenDigest::of(&access_token).prefix),
+            )
+            .filter(auth_tokens::Column::Id.eq(uuid::Uuid::from(other_id)))
+            .exec(&db)
+            .await
+            .unwrap();
+
+        let session = AuthRepository::get_by_token(&db, &access_token)
+            .await
+            .unwrap();
+
+        assert_eq!(session.id, token_id);
+        // its own prefix no longer leads to it
+        assert!(
+            AuthRepository::get_by_token(&db, &other_token)
+                .await
+                .is_err()
+        );
+    }
+
+    pub(crate) async fn new_user(db: &DbPool) -> Id {
+        let user_id = Id::new();
+        AuthRepository::save_user_with_credentials(db, &user_id, &format!("u{user_id}"), "hash")
+            .await
+            .unwrap();
+        user_id
+    }
+
+    fn issued_tokens() -> IssuedTokens {
+        let now = OffsetDateTime::now_utc();
+        IssuedTokens {
+            access_token: uuid::Uuid::new_v4().to_string(),
+            expires_at: now + Duration::hours(1),
+            refresh_token: uuid::Uuid::new_v4().to_string(),
+            refresh_expires_at: now + Duration::days(30),
+        }
+    }
+
+    /// A session on a new device; returns its id and access token.
+    pub(crate) async fn new_session(db: &DbPool, user_id: &Id) -> (Id, String) {
+        let (token_id, tokens) = new_session_tokens(db, user_id).await;
+        (token_id, tokens.access_token)
+    }
+
+    async fn new_session_tokens(db: &DbPool, user_id: &Id) -> (Id, IssuedTokens) {
+        let device_id = AuthRepository::register_device(db, user_id, None, "phone", "android")
+            .await
+            .unwrap();
+        let tokens = issued_tokens();
+        let token_id = AuthRepository::create_session(db, user_id, &device_id, &tokens)
+            .await
+            .unwrap();
+        (token_id, tokens)
+    }
+
+    async fn refresh(db: &DbPool, refresh_token: &str) -> Option<IssuedTokens> {
+        let mut tokens = issued_tokens();
+        AuthRepository::refresh_session(db, refresh_token, &mut tokens, Duration::days(90))
+            .await
+            .unwrap()
+            .map(|_| tokens)
+    }
+
+    /// Pretend the session signed in `ago`.
+    async fn backdate_session(db: &DbPool, token_id: &Id, ago: Duration) {
+        AuthTokens::update_many()
+            .col_expr(
+                auth_tokens::Column::CreatedAt,
+                Expr::value(OffsetDateTime::now_utc() - ago),
+            )
+            .filter(auth_tokens::Column::Id.eq(uuid::Uuid::from(*token_id)))
+            .exec(db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_one_key_pair_even_when_uploads_race() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+
+        let saves = (0..8).map(|i| {
+            let db = db.clone();
+            async move {
+                AuthRepository::save_keys(&db, &user_id, &format!("private-{i}"), "public")
+                    .await
+                    .unwrap()
+            }
+        });
+        let saved = join_all(saves).await;
+
+        assert_eq!(saved.iter().filter(|saved| **saved).count(), 1);
+        let again = AuthRepository::save_keys(&db, &user_id, "private", "public")
+            .await
+            .unwrap();
+        assert!(!again);
+        let stored = UserKeys::find()
+            .filter(user_keys::Column::UserId.eq(uuid::Uuid::from(user_id)))
+            .count(&db)
+            .await
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn replaces_private_key_only_if_unchanged() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        AuthRepository::save_keys(&db, &user_id, "first", "public")
+            .await
+            .unwrap();
+
+        let swapped = AuthRepository::replace_private_key(&db, &user_id, "first", "second")
+            .await
+            .unwrap();
+        // a device that still has the first key loses
+        let stale = AuthRepository::replace_private_key(&db, &user_id, "first", "third")
+            .await
+            .unwrap();
+
+        assert!(swapped);
+        assert!(!stale);
+        let stored = AuthRepository::get_private_key(&db, &user_id)
+            .await
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn replaces_recovery_key_only_if_unchanged() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        AuthRepository::save_keys(&db, &user_id, "private", "public")
+            .await
+            .unwrap();
+
+        let first = AuthRepository::replace_recovery_key(&db, &user_id, None, "first")
+            .await
+            .unwrap();
+        // neither a blind write nor one from a stale copy goes through
+        let blind = AuthRepository::replace_recovery_key(&db, &user_id, None, "blind")
+            .await
+            .unwrap();
+        let second = AuthRepository::replace_recovery_key(&db, &user_id, Some("first"), "second")
+            .await
+            .unwrap();
+        let stale = AuthRepository::replace_recovery_key(&db, &user_id, Some("first"), "stale")
+            .await
+            .unwrap();
+
+        assert_eq!((first, blind, second, stale), (true, false, true, false));
+        let stored = AuthRepository::get_recovery_key(&db, &user_id)
+            .await
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn changing_password_keeps_only_the_given_session() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (kept_id, kept_token) = new_session(&db, &user_id).await;
+        let (_, other_token) = new_session(&db, &user_id).await;
+        let bystander = new_user(&db).await;
+        let (_, bystander_token) = new_session(&db, &bystander).await;
+
+        AuthRepository::change_password(&db, &user_id, "new hash", Some(kept_id))
+            .await
+            .unwrap();
+
+        let sessions = AuthRepository::list_sessions(&db, &user_id).await.unwrap();
+        assert_eq!(
+            sessions.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![kept_id]
+        );
+        assert!(AuthRepository::get_by_token(&db, &kept_token).await.is_ok());
+        assert!(
+            AuthRepository::get_by_token(&db, &other_token)
+                .await
+                .is_err()
+        );
+        assert!(
+            AuthRepository::get_by_token(&db, &bystander_token)
+                .await
+                .is_ok()
+        );
+        let password = AuthRepository::get_password(&db, &user_id).await.unwrap();
+        assert_eq!(password.as_deref(), Some("new hash"));
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_work_once() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (_, first) = new_session_tokens(&db, &user_id).await;
+
+        let second = refresh(&db, &first.refresh_token).await.unwrap();
+        let reused = refresh(&db, &first.refresh_token).await;
+
+        assert!(reused.is_none());
+        assert!(refresh(&db, &second.refresh_token).await.is_some());
+        // the access token was swapped along with it
+        assert!(
+            AuthRepository::get_by_token(&db, &first.access_token)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_refreshes_have_one_winner() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (_, tokens) = new_session_tokens(&db, &user_id).await;
+
+        let refreshes = (0..8).map(|_| refresh(&db, &tokens.refresh_token));
+        let refreshed = join_all(refreshes).await;
+
+        assert_eq!(refreshed.iter().flatten().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn refreshing_does_not_outlast_the_session_lifetime() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (token_id, tokens) = new_session_tokens(&db, &user_id).await;
+        backdate_session(&db, &token_id, Duration::days(89)).await;
+
+        let refreshed = refresh(&db, &tokens.refresh_token).await.unwrap();
+
+        // cut short from the usual 30 days to what is left of the 90
+        let left = refreshed.refresh_expires_at - OffsetDateTime::now_utc();
+        assert!(left <= Duration::days(1) && left > Duration::hours(23));
+        assert!(refreshed.expires_at <= refreshed.refresh_expires_at);
+        let session = AuthRepository::list_sessions(&db, &user_id).await.unwrap();
+        assert_eq!(session[0].expires_at, refreshed.refresh_expires_at);
+
+        backdate_session(&db, &token_id, Duration::days(90)).await;
+        assert!(refresh(&db, &refreshed.refresh_token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn invited_registrations_that_fail_give_the_use_back() {
+        let Some(db) = test_db().await else { return };
+        let expires_at = OffsetDateTime::now_utc() + Duration::days(1);
+        let (invite_id, code) = crate::invite::new_invite(&db, 1, expires_at).await;
+        let code_hash = crate::invite::hash_code(&code);
+        let taken = format!("u{}", new_user(&db).await);
+
+        // the username is taken, so creating the user fails after the redeem
+        let result =
+            AuthRepository::save_invited_user(&db, &Id::new(), &taken, "hash", &code_hash).await;
+        assert!(result.is_err());
+        assert_eq!(crate::invite::invite_uses(&db, &invite_id).await, 0);
+
+        let user_id = Id::new();
+        let name = format!("u{user_id}");
+        assert!(
+            AuthRepository::save_invited_user(&db, &user_id, &name, "hash", &code_hash)
+                .await
+                .unwrap()
+        );
+        assert_eq!(crate::invite::invite_uses(&db, &invite_id).await, 1);
+        // used up now, and no user is created for the refused one
+        let refused = Id::new();
+        let name = format!("u{refused}");
+        assert!(
+            !AuthRepository::save_invited_user(&db, &refused, &name, "hash", &code_hash)
+                .await
+                .unwrap()
+        );
+        assert!(AuthRepository::get_by_username(&db, &name).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn login_codes_are_redeemed_once_before_they_expire() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let row = |expires_at| LoginCodeRow {
+            user_id,
+            code_challenge: "challenge".to_string(),
+            device_id: None,
+            device_name: Some("laptop".to_string()),
+            platform: None,
+            expires_at,
+        };
+        let now = OffsetDateTime::now_utc();
+        AuthRepository::create_login_code(&db, "fresh", &row(now + Duration::minutes(1)))
+            .await
+            .unwrap();
+
+        let taken = AuthRepository::take_login_code(&db, "fresh").await.unwrap();
+        let again = AuthRepository::take_login_code(&db, "fresh").await.unwrap();
+
+        let taken = taken.unwrap();
+        assert_eq!(taken.user_id, user_id);
+        assert_eq!(taken.code_challenge, "challenge");
+        assert_eq!(taken.device_name.as_deref(), Some("laptop"));
+        assert!(again.is_none());
+
+        let code = format!("stale-{user_id}");
+        AuthRepository::create_login_code(&db, &code, &row(now - Duration::seconds(1)))
+            .await
+            .unwrap();
+        let stale = AuthRepository::take_login_code(&db, &code).await.unwrap();
+        assert!(stale.is_none());
+    }
+
+    #[tokio::test]
+    async fn registering_again_updates_only_the_users_own_device() {
+        let Some(db) = test_db().await else { return };
+        let alice = new_user(&db).await;
+        let bob = new_user(&db).await;
+        let device_id = AuthRepository::register_device(&db, &alice, None, "phone", "android")
+            .await
+            .unwrap();
+
+        let again = AuthRepository::register_device(&db, &alice, Some(device_id), "tablet", "ios")
+            .await
+            .unwrap();
+        let bobs = AuthRepository::register_device(&db, &bob, Some(device_id), "mine", "ios")
+            .await
+            .unwrap();
+
+        assert_eq!(again, device_id);
+        assert_ne!(bobs, device_id);
+        let devices = AuthRepository::list_devices(&db, &alice).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "tablet");
+        assert_eq!(devices[0].platform, "ios");
+        assert!(!devices[0].signed_in);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_device_ends_its_sessions() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let (_, tokens) = new_session_tokens(&db, &user_id).await;
+        let (other_id, other_token) = new_session(&db, &user_id).await;
+        let device_id = AuthRepository::get_by_token(&db, &tokens.access_token)
+            .await
+            .unwrap()
+            .device_id;
+
+        // only the owner can
+        let stranger = new_user(&db).await;
+        assert!(
+            !AuthRepository::delete_device(&db, &stranger, &device_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            AuthRepository::delete_device(&db, &user_id, &device_id)
+                .await
+                .unwrap()
+        );
+
+        assert!(
+            AuthRepository::get_by_token(&db, &tokens.access_token)
+                .await
+                .is_err()
+        );
+        assert!(refresh(&db, &tokens.refresh_token).await.is_none());
+        let sessions = AuthRepository::list_sessions(&db, &user_id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, other_id);
+        assert!(
+            AuthRepository::get_by_token(&db, &other_token)
+                .await
+                .is_ok()
+        );
+        let devices = AuthRepository::list_devices(&db, &user_id).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(devices[0].signed_in);
+        assert!(
+            !AuthRepository::delete_device(&db, &user_id, &device_id)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn touches_devices_seen_longer_ago_than_the_interval() {
+        let Some(db) = test_db().await else { return };
+        let user_id = new_user(&db).await;
+        let device_id = AuthRepository::register_device(&db, &user_id, None, "phone", "android")
+            .await
+            .unwrap();
+        let last_seen = |db: DbPool| async move {
+            AuthRepository::list_devices(&db, &user_id).await.unwrap()[0].last_seen_at
+        };
+        let interval = Duration::minutes(5);
+
+        let registered = last_seen(db.clone()).await;
+        AuthRepository::touch_device(&db, &device_id, interval)
+            .await
+            .unwrap();
+        assert_eq!(last_seen(db.clone()).await, registered);
+
+        let earlier = OffsetDateTime::now_utc() - Duration::minutes(6);
+        Devices::update_many()
+            .col_expr(devices::Column::LastSeenAt, Expr::value(earlier))
+            .filter(devices::Column::Id.eq(uuid::Uuid::from(device_id)))
+            .exec(&db)
+            .await
+            .unwrap();
+        AuthRepository::touch_device(&db, &device_id, interval)
+            .await
+            .unwrap();
+        assert!(last_seen(db.clone()).await > registered);
     }
 }
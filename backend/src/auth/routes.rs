@@ -1,9 +1,15 @@
-use axum::routing::get;
+use axum::routing::{delete, get};
 use axum::{Router, routing::post};
 
 use crate::AppState;
 
-use super::handlers::{get_key, login, login_desktop, register, save_key};
+use super::handlers::{
+    change_password, confirm_totp, disable_totp, enroll_totp, get_key, get_recovery_key,
+    list_devices, list_sessions, login, login_desktop, login_totp, login_with_code, logout,
+    refresh, regenerate_backup_codes, register, revoke_device, revoke_session, revoke_sessions,
+    revoke_user_sessions, save_key, save_recovery_key, session_cache_stats, totp_status,
+    update_key,
+};
 
 pub(crate) fn routes(app_state: AppState) -> Router {
     let desktop = Router::new()
@@ -15,14 +21,41 @@ pub(crate) fn routes(app_state: AppState) -> Router {
         ));
 
     Router::new()
-        .route("/keys", get(get_key).post(save_key))
+        .route("/keys", get(get_key).post(save_key).put(update_key))
+        .route(
+            "/keys/recovery",
+            get(get_recovery_key).put(save_recovery_key),
+        )
+        .route("/password", post(change_password))
+        .route("/logout", post(logout))
+        .route("/sessions", get(list_sessions).delete(revoke_sessions))
+        .route("/sessions/{session_id}", delete(revoke_session))
+        .route("/devices", get(list_devices))
+        .route("/devices/{device_id}", delete(revoke_device))
+        .route(
+            "/totp",
+            get(totp_status).post(enroll_totp).delete(disable_totp),
+        )
+        .route("/totp/confirm", post(confirm_totp))
+        .route("/totp/backup-codes", post(regenerate_backup_codes))
         .route_layer(axum::middleware::from_fn(super::middleware::require_auth))
         .route_layer(axum::middleware::from_fn_with_state(
             app_state.clone(),
             super::middleware::session_resolver,
         ))
         .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
+        .route("/login/code", post(login_with_code))
+        .route("/refresh", post(refresh))
         .route("/register", post(register))
         .merge(desktop)
         .with_state(app_state)
 }
+
+/// Mounted under `/admin` by [`crate::admin::routes`].
+pub(crate) fn admin_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/users/{user_id}/sessions", delete(revoke_user_sessions))
+        .route("/session-cache", get(session_cache_stats))
+        .with_state(app_state)
+}
@@ -0,0 +1,496 @@
+//! In-process throttling of failed sign-ins, per account and per client IP.
+//! Failures are counted over a sliding window. Past the free attempts each
+//! further failure doubles the wait before the next attempt is looked at;
+//! at the lockout threshold the account or IP is refused for the lockout
+//! period. Attempts still being checked count as failures until they turn
+//! out otherwise, so concurrent requests can't slip past the limits. Like
+//! the session cache, the counts are per process.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use axum::http::HeaderMap;
+use lru::LruCache;
+use time::{Duration, OffsetDateTime};
+
+use crate::config::LoginThrottleConfig;
+use crate::ulid::Id;
+
+/// What failures are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Subject {
+    Account(Id),
+    /// A username no account has, so guessing them is throttled alike.
+    Username(String),
+    Ip(IpAddr),
+}
+
+struct Limits {
+    free_attempts: u32,
+    lockout_failures: u32,
+}
+
+#[derive(Default)]
+struct Record {
+    /// Within the window, oldest first.
+    failures: VecDeque<OffsetDateTime>,
+    locked_until: Option<OffsetDateTime>,
+    /// Attempts let through whose outcome isn't known yet.
+    pending: u32,
+    /// Until when the current refusal has already been reported.
+    reported_until: Option<OffsetDateTime>,
+}
+
+/// Why an attempt wasn't let through.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Refusal {
+    pub retry_after: Duration,
+    /// Whether this is the first refusal of a subject's current wait, so
+    /// it's audited once rather than on every retry.
+    pub report: bool,
+}
+
+/// An attempt let through by [`LoginThrottle::reserve`]. Unless it is
+/// [failed](Self::fail), dropping it takes the attempt back as a success.
+pub(crate) struct Reservation<'a> {
+    throttle: &'a LoginThrottle,
+    subjects: Vec<Subject>,
+}
+
+impl Reservation<'_> {
+    /// Count the attempt as a failure. Returns the subjects it locked out.
+    pub fn fail(mut self, now: OffsetDateTime) -> Vec<Subject> {
+        let subjects = std::mem::take(&mut self.subjects);
+        let Some(records) = &self.throttle.records else {
+            return Vec::new();
+        };
+        let mut records = records.lock().unwrap();
+        subjects
+            .into_iter()
+            .filter(|subject| {
+                let limits = self.throttle.limits(subject);
+                let record = records.get_or_insert_mut(subject.clone(), Record::default);
+                record.pending = record.pending.saturating_sub(1);
+                self.throttle.fail(limits, record, now)
+            })
+            .collect()
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let Some(records) = &self.throttle.records else {
+            return;
+        };
+        let mut records = records.lock().unwrap();
+        for subject in &self.subjects {
+            if let Some(record) = records.get_mut(subject) {
+                record.pending = record.pending.saturating_sub(1);
+            }
+        }
+    }
+}
+
+pub(crate) struct LoginThrottle {
+    records: Option<Mutex<LruCache<Subject, Record>>>,
+    window: Duration,
+    delay: Duration,
+    max_delay: Duration,
+    lockout: Duration,
+    account: Limits,
+    ip: Limits,
+}
+
+impl LoginThrottle {
+    /// A `capacity` of 0 disables throttling.
+    pub fn new(config: &LoginThrottleConfig) -> Self {
+        Self {
+            records: NonZeroUsize::new(config.capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            window: Duration::seconds(config.window_secs),
+            delay: Duration::seconds(config.delay_secs),
+            max_delay: Duration::seconds(config.max_delay_secs),
+            lockout: Duration::seconds(config.lockout_secs),
+            account: Limits {
+                free_attempts: config.account_free_attempts,
+                lockout_failures: config.account_lockout_failures,
+            },
+            ip: Limits {
+                free_attempts: config.ip_free_attempts,
+                lockout_failures: config.ip_lockout_failures,
+            },
+        }
+    }
+
+    /// Let an attempt by all of `subjects` through, unless one of them has
+    /// to wait. Checking and reserving happen under one lock, so concurrent
+    /// attempts each see the others.
+    pub fn reserve(
+        &self,
+        subjects: Vec<Subject>,
+        now: OffsetDateTime,
+    ) -> Result<Reservation<'_>, Refusal> {
+        let Some(records) = &self.records else {
+            return Ok(Reservation {
+                throttle: self,
+                subjects: Vec::new(),
+            });
+        };
+        let mut records = records.lock().unwrap();
+
+        let mut refusal: Option<Refusal> = None;
+        for subject in &subjects {
+            let limits = self.limits(subject);
+            let Some(record) = records.get_mut(subject) else {
+                continue;
+            };
+            let Some(wait) = self.wait(limits, record, now) else {
+                continue;
+            };
+            let report = record.reported_until.is_none_or(|until| until <= now);
+            if report {
+                record.reported_until = Some(now + wait);
+            }
+            refusal = Some(match refusal {
+                Some(other) => Refusal {
+                    retry_after: other.retry_after.max(wait),
+                    report: other.report || report,
+                },
+                None => Refusal {
+                    retry_after: wait,
+                    report,
+                },
+            });
+        }
+        if let Some(refusal) = refusal {
+            return Err(refusal);
+        }
+
+        for subject in &subjects {
+            records
+                .get_or_insert_mut(subject.clone(), Record::default)
+                .pending += 1;
+        }
+        Ok(Reservation {
+            throttle: self,
+            subjects,
+        })
+    }
+
+    /// How long `subject` has to wait before its next attempt, if at all.
+    #[cfg(test)]
+    pub fn retry_after(&self, subject: &Subject, now: OffsetDateTime) -> Option<Duration> {
+        let records = self.records.as_ref()?;
+        let mut records = records.lock().unwrap();
+        let record = records.get_mut(subject)?;
+        self.wait(self.limits(subject), record, now)
+    }
+
+    /// Count a failed attempt. Returns how long `subject` is now locked out
+    /// for, if this failure reached the threshold.
+    #[cfg(test)]
+    pub fn record_failure(&self, subject: Subject, now: OffsetDateTime) -> Option<Duration> {
+        let records = self.records.as_ref()?;
+        let limits = self.limits(&subject);
+        let mut records = records.lock().unwrap();
+        let record = records.get_or_insert_mut(subject, Record::default);
+        self.fail(limits, record, now).then_some(self.lockout)
+    }
+
+    /// Forget the failures of `subject`, after it signed in.
+    pub fn reset(&self, subject: &Subject) {
+        if let Some(records) = &self.records {
+            records.lock().unwrap().pop(subject);
+        }
+    }
+
+    fn limits(&self, subject: &Subject) -> &Limits {
+        match subject {
+            Subject::Account(_) | Subject::Username(_) => &self.account,
+            Subject::Ip(_) => &self.ip,
+        }
+    }
+
+    /// How long the next attempt has to wait, counting the pending ones as
+    /// failed.
+    fn wait(&self, limits: &Limits, record: &mut Record, now: OffsetDateTime) -> Option<Duration> {
+        self.prune(record, now);
+
+        if let Some(locked_until) = record.locked_until {
+            return Some(locked_until - now);
+        }
+        let failures = record.failures.len();
+        let pending = record.pending as usize;
+        if pending > 0 {
+            // were they all to fail, this attempt would be delayed or locked out
+            let delay = self.delay_after(limits, failures + pending);
+            if delay.is_positive() || failures + pending >= limits.lockout_failures as usize {
+                return Some(delay.max(Duration::SECOND));
+            }
+            return None;
+        }
+        let last = *record.failures.back()?;
+        let wait = last + self.delay_after(limits, failures) - now;
+        wait.is_positive().then_some(wait)
+    }
+
+    /// Add a failure; returns whether it locked `record` out.
+    fn fail(&self, limits: &Limits, record: &mut Record, now: OffsetDateTime) -> bool {
+        self.prune(record, now);
+
+        record.failures.push_back(now);
+        if record.failures.len() < limits.lockout_failures as usize {
+            return false;
+        }
+        record.failures.clear();
+        record.locked_until = Some(now + self.lockout);
+        true
+    }
+
+    /// The wait after the latest of `failures` failures.
+    fn delay_after(&self, limits: &Limits, failures: usize) -> Duration {
+        let Some(excess) = failures.checked_sub(limits.free_attempts as usize + 1) else {
+            return Duration::ZERO;
+        };
+        // 2^31 seconds is past any sensible maximum
+        let factor = 1i32 << excess.min(31);
+        self.delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    fn prune(&self, record: &mut Record, now: OffsetDateTime) {
+        if record.locked_until.is_some_and(|until| until <= now) {
+            record.locked_until = None;
+        }
+        while record
+            .failures
+            .front()
+            .is_some_and(|&failure| failure <= now - self.window)
+        {
+            record.failures.pop_front();
+        }
+    }
+}
+
+/// The address the request came from: the last entry of the configured
+/// proxy header, else the connection's peer.
+pub(crate) fn client_ip(
+    headers: &HeaderMap,
+    peer: SocketAddr,
+    config: &LoginThrottleConfig,
+) -> IpAddr {
+    config
+        .client_ip_header
+        .as_deref()
+        .and_then(|header| headers.get(header))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            window_secs: 600,
+            account_free_attempts: 2,
+            account_lockout_failures: 5,
+            ip_free_attempts: 3,
+            ip_lockout_failures: 10,
+            delay_secs: 1,
+            max_delay_secs: 2,
+            lockout_secs: 300,
+            capacity: 10,
+            client_ip_header: Some("x-forwarded-for".to_string()),
+        }
+    }
+
+    fn at(secs: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(1_700_000_000 + secs).unwrap()
+    }
+
+    #[test]
+    fn delays_double_past_the_free_attempts() {
+        let throttle = LoginThrottle::new(&config());
+        let alice = Subject::Account(Id::new());
+
+        throttle.record_failure(alice.clone(), at(0));
+        throttle.record_failure(alice.clone(), at(0));
+        assert_eq!(throttle.retry_after(&alice, at(0)), None);
+
+        throttle.record_failure(alice.clone(), at(0));
+        assert_eq!(throttle.retry_after(&alice, at(0)), Some(Duration::SECOND));
+        assert_eq!(throttle.retry_after(&alice, at(1)), None);
+
+        throttle.record_failure(alice.clone(), at(1));
+        assert_eq!(
+            throttle.retry_after(&alice, at(1)),
+            Some(Duration::seconds(2))
+        );
+        // an IP gets more free attempts than an account
+        let ip = Subject::Ip("10.0.0.1".parse().unwrap());
+        for _ in 0..3 {
+            throttle.record_failure(ip.clone(), at(0));
+        }
+        assert_eq!(throttle.retry_after(&ip, at(0)), None);
+    }
+
+    #[test]
+    fn locks_out_at_the_threshold() {
+        let throttle = LoginThrottle::new(&config());
+        let bob = Subject::Username("bob".to_string());
+
+        for _ in 0..4 {
+            assert_eq!(throttle.record_failure(bob.clone(), at(0)), None);
+        }
+        assert_eq!(
+            throttle.record_failure(bob.clone(), at(0)),
+            Some(Duration::seconds(300))
+        );
+        assert_eq!(
+            throttle.retry_after(&bob, at(100)),
+            Some(Duration::seconds(200))
+        );
+        // the lockout starts it over with a clean slate
+        assert_eq!(throttle.retry_after(&bob, at(300)), None);
+        throttle.record_failure(bob.clone(), at(300));
+        assert_eq!(throttle.retry_after(&bob, at(300)), None);
+    }
+
+    #[test]
+    fn failures_leave_the_window_and_resets_forget_them() {
+        let throttle = LoginThrottle::new(&config());
+        let alice = Subject::Account(Id::new());
+
+        for _ in 0..4 {
+            throttle.record_failure(alice.clone(), at(0));
+        }
+        assert_eq!(throttle.record_failure(alice.clone(), at(600)), None);
+        assert_eq!(throttle.retry_after(&alice, at(600)), None);
+
+        for _ in 0..3 {
+            throttle.record_failure(alice.clone(), at(600));
+        }
+        assert!(throttle.retry_after(&alice, at(600)).is_some());
+        throttle.reset(&alice);
+        assert_eq!(throttle.retry_after(&alice, at(600)), None);
+    }
+
+    #[test]
+    fn pending_attempts_count_as_failures() {
+        let throttle = LoginThrottle::new(&config());
+        let alice = Subject::Account(Id::new());
+
+        let reservations: Vec<_> = (0..3)
+            .map(|_| throttle.reserve(vec![alice.clone()], at(0)).unwrap())
+            .collect();
+        let refusal = throttle.reserve(vec![alice.clone()], at(0)).err().unwrap();
+        assert_eq!(refusal.retry_after, Duration::SECOND);
+
+        // attempts that didn't fail give their place back
+        drop(reservations);
+        for _ in 0..5 {
+            drop(throttle.reserve(vec![alice.clone()], at(0)).unwrap());
+        }
+        assert_eq!(throttle.retry_after(&alice, at(0)), None);
+    }
+
+    #[test]
+    fn failed_reservations_lock_out() {
+        let throttle = LoginThrottle::new(&config());
+        let bob = Subject::Username("bob".to_string());
+        let ip = Subject::Ip("10.0.0.1".parse().unwrap());
+
+        for secs in 0..4 {
+            let reservation = throttle
+                .reserve(vec![bob.clone(), ip.clone()], at(secs * 10))
+                .unwrap();
+            assert!(reservation.fail(at(secs * 10)).is_empty());
+        }
+        let reservation = throttle
+            .reserve(vec![bob.clone(), ip.clone()], at(40))
+            .unwrap();
+
+        assert_eq!(reservation.fail(at(40)), vec![bob.clone()]);
+        assert_eq!(
+            throttle.retry_after(&bob, at(40)),
+            Some(Duration::seconds(300))
+        );
+        // the IP has more failures to go, so it only waits
+        assert_eq!(
+            throttle.retry_after(&ip, at(40)),
+            Some(Duration::seconds(2))
+        );
+    }
+
+    #[test]
+    fn concurrent_reservations_see_each_other() {
+        let throttle = LoginThrottle::new(&config());
+        let alice = Subject::Account(Id::new());
+        let barrier = std::sync::Barrier::new(10);
+
+        let reserved = std::thread::scope(|scope| {
+            let attempts: Vec<_> = (0..10)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        let reservation = throttle.reserve(vec![alice.clone()], at(0));
+                        // hold the place until every attempt has been checked
+                        barrier.wait();
+                        reservation.is_ok()
+                    })
+                })
+                .collect();
+            attempts
+                .into_iter()
+                .map(|attempt| attempt.join().unwrap())
+                .filter(|&reserved| reserved)
+                .count()
+        });
+
+        // the free attempts, plus the one whose failure starts the delays
+        assert_eq!(reserved, 3);
+    }
+
+    #[test]
+    fn refusals_are_reported_once_per_wait() {
+        let throttle = LoginThrottle::new(&config());
+        let bob = Subject::Username("bob".to_string());
+        for _ in 0..5 {
+            throttle.record_failure(bob.clone(), at(0));
+        }
+
+        let first = throttle.reserve(vec![bob.clone()], at(10)).err().unwrap();
+        let again = throttle.reserve(vec![bob.clone()], at(20)).err().unwrap();
+        assert!(first.report);
+        assert!(!again.report);
+
+        // a new lockout is a new wait
+        for _ in 0..5 {
+            throttle.record_failure(bob.clone(), at(300));
+        }
+        let next = throttle.reserve(vec![bob.clone()], at(310)).err().unwrap();
+        assert!(next.report);
+    }
+
+    #[test]
+    fn client_ip_prefers_the_proxy_header() {
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        let mut config = config();
+        assert_eq!(client_ip(&headers, peer, &config), peer.ip());
+
+        headers.insert("x-forwarded-for", "1.2.3.4, 5.6.7.8".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, peer, &config),
+            "5.6.7.8".parse::<IpAddr>().unwrap()
+        );
+
+        config.client_ip_header = None;
+        assert_eq!(client_ip(&headers, peer, &config), peer.ip());
+    }
+}
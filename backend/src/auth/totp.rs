@@ -0,0 +1,428 @@
+//! Time-based one-time passwords (RFC 6238) the way authenticator apps make
+//! them: HMAC-SHA1, six digits, 30 second steps. Also the backup codes that
+//! stand in for a lost authenticator.
+//!
+//! Secrets are sealed at rest with the configured key, so a copy of the
+//! database alone doesn't give away second factors. Without a key they are
+//! kept as hex, as they were before there was one.
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, Generate, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, KeyInit, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use time::OffsetDateTime;
+use tracing::error;
+use url::Url;
+
+use crate::config::AuthConfig;
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+const DIGITS: usize = 6;
+const STEP_SECS: i64 = 30;
+/// Steps either side of the current one a code is still accepted from, for
+/// clocks that drift.
+const SKEW_STEPS: i64 = 1;
+/// 160 bits, as RFC 4226 recommends for HMAC-SHA1.
+const SECRET_LEN: usize = 20;
+
+const BACKUP_CODE_COUNT: usize = 10;
+/// Characters of a backup code, shown as two dash-separated halves.
+const BACKUP_CODE_LEN: usize = 10;
+/// Longest input taken as a backup code, leaving room for separators and
+/// spaces. Anything longer isn't hashed at all.
+const MAX_BACKUP_CODE_INPUT: usize = 32;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Marks a secret sealed with [`SecretKey`]; anything else is hex.
+pub(super) const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+/// Backup codes carry 50 random bits, so they are hashed far cheaper than
+/// passwords; a login may have to check all of them.
+const BACKUP_CODE_MEMORY_KIB: u32 = 4096;
+const BACKUP_CODE_ITERATIONS: u32 = 2;
+
+/// The key TOTP secrets are sealed with at rest (AES-256-GCM).
+pub(crate) struct SecretKey(Aes256Gcm);
+
+impl SecretKey {
+    /// The configured key, base64 of 32 bytes; `None` while unset.
+    pub fn from_config(config: &AuthConfig) -> Result<Option<Self>> {
+        let Some(encoded) = &config.totp_secret_key else {
+            return Ok(None);
+        };
+        let key = Base64::decode_vec(encoded.trim())
+            .ok()
+            .and_then(|bytes| Aes256Gcm::new_from_slice(&bytes).ok())
+            .ok_or_else(|| {
+                error!("auth_totp_secret_key must be 32 bytes, base64");
+                Error::Configuration
+            })?;
+        Ok(Some(Self(key)))
+    }
+
+    fn seal(&self, user_id: &Id, secret: &[u8]) -> String {
+        let nonce = Nonce::<U12>::generate();
+        let aad = uuid::Uuid::from(*user_id).into_bytes();
+        let ciphertext = self
+            .0
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: secret,
+                    aad: &aad,
+                },
+            )
+            .expect("AES-GCM encrypts any secret of this size");
+
+        let mut blob = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+        format!("{SEALED_PREFIX}{}", Base64::encode_string(&blob))
+    }
+
+    fn open(&self, user_id: &Id, sealed: &str) -> Option<Vec<u8>> {
+        let blob = Base64::decode_vec(sealed).ok()?;
+        if blob.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let nonce = Nonce::<U12>::try_from(nonce).ok()?;
+        let aad = uuid::Uuid::from(*user_id).into_bytes();
+        self.0
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .ok()
+    }
+}
+
+/// The secret of `user_id` as it is stored: sealed with `key`, or hex
+/// without one.
+pub(super) fn store_secret(key: Option<&SecretKey>, user_id: &Id, secret: &[u8]) -> String {
+    match key {
+        Some(key) => key.seal(user_id, secret),
+        None => hex::encode(secret),
+    }
+}
+
+/// The secret of `user_id` from its stored form.
+pub(super) fn load_secret(key: Option<&SecretKey>, user_id: &Id, stored: &str) -> Result<Vec<u8>> {
+    let secret = match (stored.strip_prefix(SEALED_PREFIX), key) {
+        (Some(sealed), Some(key)) => key.open(user_id, sealed),
+        (Some(_), None) => {
+            error!("TOTP secret is sealed, but no auth_totp_secret_key is set");
+            None
+        }
+        (None, _) => hex::decode(stored).ok(),
+    };
+    secret.ok_or_else(|| {
+        error!(%user_id, "Could not read stored TOTP secret");
+        Error::Configuration
+    })
+}
+
+pub(super) fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// The secret as authenticator apps take it when typed in.
+pub(super) fn encode_secret(secret: &[u8]) -> String {
+    base32(secret)
+}
+
+/// The `otpauth://` URI to render as a QR code for authenticator apps.
+pub(super) fn provisioning_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    let mut uri = Url::parse("otpauth://totp/").expect("static URI is valid");
+    uri.set_path(&format!("/{issuer}:{account}"));
+    uri.query_pairs_mut()
+        .append_pair("secret", &base32(secret))
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECS.to_string());
+    uri.to_string()
+}
+
+/// Whether `code` has the shape of a TOTP code, as opposed to a backup code.
+pub(super) fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The time step `code` was generated for, if it is valid around `now`.
+/// Callers must reject a step that was already used, or the same code could
+/// be replayed within its window.
+pub(super) fn verify(secret: &[u8], code: &str, now: OffsetDateTime) -> Option<i64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+    let current = now.unix_timestamp().div_euclid(STEP_SECS);
+    (current - SKEW_STEPS..=current + SKEW_STEPS).find(|&step| {
+        code_at(secret, step)
+            .as_bytes()
+            .ct_eq(code.as_bytes())
+            .into()
+    })
+}
+
+/// HOTP (RFC 4226) of the step counter.
+fn code_at(secret: &[u8], step: i64) -> String {
+    let mut mac = <Hmac<Sha1> as KeyInit>::new_from_slice(secret).expect("HMAC takes any key size");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+pub(super) fn generate_backup_codes() -> Vec<String> {
+    (0..BACKUP_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; BACKUP_CODE_LEN];
+            OsRng.fill_bytes(&mut bytes);
+            let code: String = bytes
+                .iter()
+                .map(|b| BASE32_ALPHABET[(b % 32) as usize].to_ascii_lowercase() as char)
+                .collect();
+            let (first, second) = code.split_at(BACKUP_CODE_LEN / 2);
+            format!("{first}-{second}")
+        })
+        .collect()
+}
+
+/// How a backup code is kept at rest: an argon2 hash with a salt of its
+/// own, in PHC string format.
+pub(super) fn hash_backup_code(code: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = backup_code_hasher()
+        .hash_password(normalize_backup_code(code).as_bytes(), &salt)
+        .map_err(|e| {
+            error!(error = %e, "Backup code hashing failed");
+            Error::PasswordHashing
+        })?;
+    Ok(hash.to_string())
+}
+
+/// Whether `code` is the one `stored` was hashed from. Codes handed out
+/// before they were salted are an unsalted SHA-256 (hex), accepted until
+/// they are used or replaced.
+pub(super) fn backup_code_matches(code: &str, stored: &str) -> bool {
+    let normalized = normalize_backup_code(code);
+    match PasswordHash::new(stored) {
+        Ok(hash) => backup_code_hasher()
+            .verify_password(normalized.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => hex::encode(Sha256::digest(normalized.as_bytes()))
+            .as_bytes()
+            .ct_eq(stored.as_bytes())
+            .into(),
+    }
+}
+
+/// Whether `code` has the shape of a backup code, checked before it is
+/// hashed against every stored one.
+pub(super) fn is_backup_code(code: &str) -> bool {
+    code.len() <= MAX_BACKUP_CODE_INPUT && normalize_backup_code(code).len() == BACKUP_CODE_LEN
+}
+
+/// Dashes, spaces and case don't matter when a code is typed back in.
+fn normalize_backup_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn backup_code_hasher() -> Argon2<'static> {
+    let params = Params::new(BACKUP_CODE_MEMORY_KIB, BACKUP_CODE_ITERATIONS, 1, None)
+        .expect("backup code hashing parameters are valid");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+/// RFC 4648 base32 without padding.
+fn base32(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 seed of the RFC 6238 test vectors.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn at(unix: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(unix).unwrap()
+    }
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        // the RFC lists eight digits; six are their last six
+        for (unix, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, unix / STEP_SECS), code);
+        }
+    }
+
+    #[test]
+    fn accepts_codes_from_neighbouring_steps_only() {
+        let now = at(1234567890);
+        let step = 1234567890 / STEP_SECS;
+
+        assert_eq!(verify(RFC_SECRET, "005924", now), Some(step));
+        let previous = code_at(RFC_SECRET, step - 1);
+        assert_eq!(verify(RFC_SECRET, &previous, now), Some(step - 1));
+        let stale = code_at(RFC_SECRET, step - 2);
+        assert_eq!(verify(RFC_SECRET, &stale, now), None);
+        assert_eq!(verify(RFC_SECRET, "5924", now), None);
+    }
+
+    #[test]
+    fn provisioning_uri_carries_the_secret() {
+        let uri = provisioning_uri(RFC_SECRET, "Photo store", "alice");
+
+        assert_eq!(
+            uri,
+            "otpauth://totp/Photo%20store:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=Photo+store&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn backup_codes_match_regardless_of_formatting() {
+        let codes = generate_backup_codes();
+
+        assert_eq!(codes.len(), BACKUP_CODE_COUNT);
+        assert!(!codes.iter().any(|code| is_totp_code(code)));
+        assert!(codes.iter().all(|code| is_backup_code(code)));
+        let code = &codes[0];
+        let hash = hash_backup_code(code).unwrap();
+        assert!(backup_code_matches(code, &hash));
+        assert!(backup_code_matches(
+            &code.replace('-', " ").to_uppercase(),
+            &hash
+        ));
+        assert!(!backup_code_matches(&codes[1], &hash));
+    }
+
+    #[test]
+    fn overlong_or_misshapen_backup_codes_are_refused() {
+        assert!(is_backup_code(" ABCDE FGHIJ "));
+        assert!(!is_backup_code("abcde-fghi"));
+        assert!(!is_backup_code(&format!("abcde-fghij{}", " ".repeat(32))));
+        assert!(!is_backup_code(&"a".repeat(1 << 20)));
+    }
+
+    #[test]
+    fn backup_codes_are_salted() {
+        let hashes = [
+            hash_backup_code("abcde-fghij"),
+            hash_backup_code("abcde-fghij"),
+        ];
+
+        assert_ne!(hashes[0].as_ref().unwrap(), hashes[1].as_ref().unwrap());
+        assert!(hashes[0].as_ref().unwrap().starts_with("$argon2id$"));
+    }
+
+    #[test]
+    fn legacy_backup_code_hashes_still_match() {
+        let legacy = hex::encode(Sha256::digest(b"abcdefghij"));
+
+        assert!(backup_code_matches("ABCDE-FGHIJ", &legacy));
+        assert!(!backup_code_matches("abcde-fghik", &legacy));
+    }
+
+    fn config(key: &[u8]) -> AuthConfig {
+        ::config::Config::builder()
+            .set_override("auth_totp_secret_key", Base64::encode_string(key))
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn key() -> SecretKey {
+        SecretKey::from_config(&config(&[7u8; 32]))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn sealed_secrets_open_only_for_their_user() {
+        let (alice, bob) = (Id::new(), Id::new());
+
+        let stored = store_secret(Some(&key()), &alice, RFC_SECRET);
+
+        assert!(stored.starts_with(SEALED_PREFIX));
+        assert!(!stored.contains(&hex::encode(RFC_SECRET)));
+        assert_eq!(
+            load_secret(Some(&key()), &alice, &stored).unwrap(),
+            RFC_SECRET
+        );
+        assert!(load_secret(Some(&key()), &bob, &stored).is_err());
+        assert!(load_secret(None, &alice, &stored).is_err());
+    }
+
+    #[test]
+    fn hex_secrets_load_with_or_without_a_key() {
+        let user_id = Id::new();
+
+        let stored = store_secret(None, &user_id, RFC_SECRET);
+
+        assert!(!stored.starts_with(SEALED_PREFIX));
+        assert_eq!(load_secret(None, &user_id, &stored).unwrap(), RFC_SECRET);
+        assert_eq!(
+            load_secret(Some(&key()), &user_id, &stored).unwrap(),
+            RFC_SECRET
+        );
+    }
+
+    #[test]
+    fn secret_key_must_be_32_bytes() {
+        assert!(SecretKey::from_config(&config(&[7u8; 16])).is_err());
+    }
+}
@@ -1,5 +1,8 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
 use config::Environment;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de};
 use tracing::{error, info};
 
 use crate::error::{Error, Result};
@@ -10,12 +13,84 @@ pub struct DatabaseConfig {
     pub url: String,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    #[default]
+    S3,
+    Local,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct StorageConfig {
-    #[serde(rename = "storage_url")]
-    pub url: String,
-    #[serde(rename = "storage_bucket_name")]
-    pub bucket_name: String,
+    #[serde(rename = "storage_backend", default)]
+    pub backend: StorageKind,
+
+    /// S3 endpoint and bucket, required by the `s3` backend.
+    #[serde(rename = "storage_url", default)]
+    pub url: Option<String>,
+    #[serde(rename = "storage_bucket_name", default)]
+    pub bucket_name: Option<String>,
+
+    /// Root directory of the `local` backend.
+    #[serde(rename = "storage_local_path", default)]
+    pub local_path: Option<String>,
+    /// Base URL clients reach this server on. The `local` backend serves its
+    /// presigned URLs itself, so they're built on top of this.
+    #[serde(rename = "storage_public_url", default)]
+    pub public_url: Option<String>,
+    /// Secret used to sign the `local` backend's presigned URLs.
+    #[serde(rename = "storage_signing_key", default)]
+    pub signing_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOr<T> {
+    String(String),
+    Value(T),
+}
+
+/// Environment variables reach the flattened structs below as strings (the
+/// `config` crate only converts them when deserializing a field directly),
+/// so numeric settings parse them by hand.
+fn from_env_str<'de, D, T>(de: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match StringOr::<T>::deserialize(de)? {
+        StringOr::String(value) => value.parse().map_err(de::Error::custom),
+        StringOr::Value(value) => Ok(value),
+    }
+}
+
+/// Like [`from_env_str`], for optional settings.
+fn opt_from_env_str<'de, D, T>(de: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match Option::<StringOr<T>>::deserialize(de)? {
+        Some(StringOr::String(value)) => value.parse().map(Some).map_err(de::Error::custom),
+        Some(StringOr::Value(value)) => Ok(Some(value)),
+        None => Ok(None),
+    }
+}
+
+/// Structured settings (lists of records) come from the environment as a
+/// JSON string.
+fn json_from_env_str<'de, D, T>(de: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    match StringOr::<serde_json::Value>::deserialize(de)? {
+        StringOr::String(value) => serde_json::from_str(&value).map_err(de::Error::custom),
+        StringOr::Value(value) => serde_json::from_value(value).map_err(de::Error::custom),
+    }
 }
 
 fn default_max_file_size() -> i64 {
@@ -42,30 +117,38 @@ fn default_max_segment_size() -> u32 {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct UploadConfig {
-    #[serde(rename = "upload_max_file_size", default = "default_max_file_size")]
+    #[serde(
+        rename = "upload_max_file_size",
+        default = "default_max_file_size",
+        deserialize_with = "from_env_str"
+    )]
     pub max_file_size: i64,
 
     #[serde(
         rename = "upload_session_ttl_hours",
-        default = "default_session_ttl_hours"
+        default = "default_session_ttl_hours",
+        deserialize_with = "from_env_str"
     )]
     pub session_ttl_hours: i64,
 
     #[serde(
         rename = "upload_presigned_url_ttl_secs",
-        default = "default_presigned_url_ttl_secs"
+        default = "default_presigned_url_ttl_secs",
+        deserialize_with = "from_env_str"
     )]
     pub presigned_url_ttl_secs: u64,
 
     #[serde(
         rename = "upload_gc_interval_secs",
-        default = "default_gc_interval_secs"
+        default = "default_gc_interval_secs",
+        deserialize_with = "from_env_str"
     )]
     pub gc_interval_secs: u64,
 
     #[serde(
         rename = "upload_max_concurrent_sessions",
-        default = "default_max_concurrent_sessions"
+        default = "default_max_concurrent_sessions",
+        deserialize_with = "from_env_str"
     )]
     pub max_concurrent_sessions: i64,
 
@@ -73,17 +156,439 @@ pub struct UploadConfig {
     /// validated when file metadata is uploaded.
     #[serde(
         rename = "upload_min_segment_size",
-        default = "default_min_segment_size"
+        default = "default_min_segment_size",
+        deserialize_with = "from_env_str"
     )]
     pub min_segment_size: u32,
 
     #[serde(
         rename = "upload_max_segment_size",
-        default = "default_max_segment_size"
+        default = "default_max_segment_size",
+        deserialize_with = "from_env_str"
     )]
     pub max_segment_size: u32,
 }
 
+fn default_trash_retention_days() -> i64 {
+    30
+}
+fn default_trash_purge_interval_secs() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TrashConfig {
+    /// How long a trashed file stays restorable before it's purged.
+    #[serde(
+        rename = "trash_retention_days",
+        default = "default_trash_retention_days",
+        deserialize_with = "from_env_str"
+    )]
+    pub retention_days: i64,
+
+    #[serde(
+        rename = "trash_purge_interval_secs",
+        default = "default_trash_purge_interval_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub purge_interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct QuotaConfig {
+    /// Quota of users without an admin override; unset means unlimited.
+    #[serde(
+        rename = "quota_default_bytes",
+        default,
+        deserialize_with = "opt_from_env_str"
+    )]
+    pub default_bytes: Option<u64>,
+}
+
+fn default_reconcile_interval_secs() -> u64 {
+    86400
+}
+fn default_reconcile_grace_secs() -> i64 {
+    3600
+}
+fn default_reconcile_dry_run() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReconcileConfig {
+    #[serde(
+        rename = "reconcile_interval_secs",
+        default = "default_reconcile_interval_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub interval_secs: u64,
+
+    /// Objects and multipart uploads younger than this are left alone.
+    #[serde(
+        rename = "reconcile_grace_secs",
+        default = "default_reconcile_grace_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub grace_secs: i64,
+
+    /// Only report strays instead of deleting them.
+    #[serde(
+        rename = "reconcile_dry_run",
+        default = "default_reconcile_dry_run",
+        deserialize_with = "from_env_str"
+    )]
+    pub dry_run: bool,
+}
+
+fn default_access_token_ttl_secs() -> i64 {
+    3600
+}
+fn default_refresh_token_ttl_days() -> i64 {
+    30
+}
+fn default_session_lifetime_days() -> i64 {
+    90
+}
+fn default_token_gc_interval_secs() -> u64 {
+    3600
+}
+fn default_session_cache_capacity() -> usize {
+    10_000
+}
+fn default_device_touch_interval_secs() -> i64 {
+    300
+}
+fn default_session_cache_ttl_secs() -> i64 {
+    60
+}
+fn default_totp_issuer() -> String {
+    "Photo store".to_string()
+}
+fn default_login_challenge_ttl_secs() -> i64 {
+    300
+}
+fn default_login_code_ttl_secs() -> i64 {
+    60
+}
+fn default_invite_ttl_secs() -> i64 {
+    7 * 24 * 3600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthConfig {
+    #[serde(
+        rename = "auth_access_token_ttl_secs",
+        default = "default_access_token_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub access_token_ttl_secs: i64,
+
+    /// How long a session can be kept alive by refreshing without logging in
+    /// again.
+    #[serde(
+        rename = "auth_refresh_token_ttl_days",
+        default = "default_refresh_token_ttl_days",
+        deserialize_with = "from_env_str"
+    )]
+    pub refresh_token_ttl_days: i64,
+
+    /// How long a session lasts at most from its sign-in, however often it
+    /// is refreshed.
+    #[serde(
+        rename = "auth_session_lifetime_days",
+        default = "default_session_lifetime_days",
+        deserialize_with = "from_env_str"
+    )]
+    pub session_lifetime_days: i64,
+
+    #[serde(
+        rename = "auth_token_gc_interval_secs",
+        default = "default_token_gc_interval_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub token_gc_interval_secs: u64,
+
+    /// Resolved access tokens kept in memory; 0 turns the cache off.
+    #[serde(
+        rename = "auth_session_cache_capacity",
+        default = "default_session_cache_capacity",
+        deserialize_with = "from_env_str"
+    )]
+    pub session_cache_capacity: usize,
+
+    /// How long a cached token is trusted before it is looked up again.
+    /// Revocations on this instance take effect immediately regardless.
+    #[serde(
+        rename = "auth_session_cache_ttl_secs",
+        default = "default_session_cache_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub session_cache_ttl_secs: i64,
+
+    /// How stale a device's last-seen time may get before a request on it
+    /// writes it anew.
+    #[serde(
+        rename = "auth_device_touch_interval_secs",
+        default = "default_device_touch_interval_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub device_touch_interval_secs: i64,
+
+    /// Key TOTP secrets are sealed with at rest, base64 of 32 random bytes.
+    /// While unset they are kept in the clear; once set, existing ones are
+    /// sealed at startup, and it must not change afterwards.
+    #[serde(rename = "auth_totp_secret_key", default)]
+    pub totp_secret_key: Option<String>,
+
+    /// Names the account in authenticator apps.
+    #[serde(rename = "auth_totp_issuer", default = "default_totp_issuer")]
+    pub totp_issuer: String,
+
+    /// How long a password login waits for its second factor.
+    #[serde(
+        rename = "auth_login_challenge_ttl_secs",
+        default = "default_login_challenge_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub login_challenge_ttl_secs: i64,
+
+    /// How long a client has to redeem the one-time code a finished sign-in
+    /// hands it.
+    #[serde(
+        rename = "auth_login_code_ttl_secs",
+        default = "default_login_code_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub login_code_ttl_secs: i64,
+
+    /// Lifetime of invite codes created without one.
+    #[serde(
+        rename = "auth_invite_ttl_secs",
+        default = "default_invite_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub invite_ttl_secs: i64,
+}
+
+/// An OpenID Connect issuer users can sign in with.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcProviderConfig {
+    /// Identifies the provider in routes, e.g. `/auth/oidc/{name}/authorize`.
+    pub name: String,
+    /// Discovery document is fetched from `{issuer}/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Omitted for public clients, which rely on PKCE alone.
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// This server's callback as registered at the issuer, i.e. ending in
+    /// `/auth/oidc/{name}/callback`.
+    pub redirect_uri: String,
+}
+
+fn default_oidc_login_ttl_secs() -> i64 {
+    600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcConfig {
+    /// JSON list of [`OidcProviderConfig`]; none by default.
+    #[serde(
+        rename = "oidc_providers",
+        default,
+        deserialize_with = "json_from_env_str"
+    )]
+    pub providers: Vec<OidcProviderConfig>,
+
+    /// JSON list of the client URIs a finished sign-in may hand its one-time
+    /// code to. Anything else is refused, so a crafted link can't send it
+    /// elsewhere.
+    #[serde(
+        rename = "oidc_client_redirect_uris",
+        default,
+        deserialize_with = "json_from_env_str"
+    )]
+    pub client_redirect_uris: Vec<String>,
+
+    /// How long a user has to finish signing in at the issuer.
+    #[serde(
+        rename = "oidc_login_ttl_secs",
+        default = "default_oidc_login_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub login_ttl_secs: i64,
+}
+
+fn default_webauthn_rp_name() -> String {
+    "Photo store".to_string()
+}
+fn default_webauthn_ceremony_ttl_secs() -> i64 {
+    300
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebauthnConfig {
+    /// Domain passkeys are bound to, e.g. `photos.example.com`. Passkeys
+    /// are off while unset.
+    #[serde(rename = "webauthn_rp_id", default)]
+    pub rp_id: Option<String>,
+
+    /// Shown by the authenticator when creating a passkey.
+    #[serde(rename = "webauthn_rp_name", default = "default_webauthn_rp_name")]
+    pub rp_name: String,
+
+    /// JSON list of the origins ceremonies may come from, e.g.
+    /// `https://photos.example.com`; the first is the primary one.
+    #[serde(
+        rename = "webauthn_origins",
+        default,
+        deserialize_with = "json_from_env_str"
+    )]
+    pub origins: Vec<String>,
+
+    /// How long a registration or sign-in ceremony may take.
+    #[serde(
+        rename = "webauthn_ceremony_ttl_secs",
+        default = "default_webauthn_ceremony_ttl_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub ceremony_ttl_secs: i64,
+}
+
+fn default_login_throttle_window_secs() -> i64 {
+    900
+}
+fn default_login_throttle_account_free_attempts() -> u32 {
+    3
+}
+fn default_login_throttle_account_lockout_failures() -> u32 {
+    10
+}
+fn default_login_throttle_ip_free_attempts() -> u32 {
+    20
+}
+fn default_login_throttle_ip_lockout_failures() -> u32 {
+    100
+}
+fn default_login_throttle_delay_secs() -> i64 {
+    2
+}
+fn default_login_throttle_max_delay_secs() -> i64 {
+    60
+}
+fn default_login_throttle_lockout_secs() -> i64 {
+    900
+}
+fn default_login_throttle_capacity() -> usize {
+    10000
+}
+
+/// Failed sign-ins are counted per account and per client IP over a sliding
+/// window. Past the free attempts each further failure doubles the wait
+/// before the next try; at the lockout threshold the account or IP is
+/// locked out for a while.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoginThrottleConfig {
+    /// How far back failures count.
+    #[serde(
+        rename = "login_throttle_window_secs",
+        default = "default_login_throttle_window_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub window_secs: i64,
+
+    #[serde(
+        rename = "login_throttle_account_free_attempts",
+        default = "default_login_throttle_account_free_attempts",
+        deserialize_with = "from_env_str"
+    )]
+    pub account_free_attempts: u32,
+
+    #[serde(
+        rename = "login_throttle_account_lockout_failures",
+        default = "default_login_throttle_account_lockout_failures",
+        deserialize_with = "from_env_str"
+    )]
+    pub account_lockout_failures: u32,
+
+    #[serde(
+        rename = "login_throttle_ip_free_attempts",
+        default = "default_login_throttle_ip_free_attempts",
+        deserialize_with = "from_env_str"
+    )]
+    pub ip_free_attempts: u32,
+
+    #[serde(
+        rename = "login_throttle_ip_lockout_failures",
+        default = "default_login_throttle_ip_lockout_failures",
+        deserialize_with = "from_env_str"
+    )]
+    pub ip_lockout_failures: u32,
+
+    /// Wait after the first failure past the free attempts.
+    #[serde(
+        rename = "login_throttle_delay_secs",
+        default = "default_login_throttle_delay_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub delay_secs: i64,
+
+    #[serde(
+        rename = "login_throttle_max_delay_secs",
+        default = "default_login_throttle_max_delay_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub max_delay_secs: i64,
+
+    #[serde(
+        rename = "login_throttle_lockout_secs",
+        default = "default_login_throttle_lockout_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub lockout_secs: i64,
+
+    /// Accounts and IPs tracked in memory; the least recently seen are
+    /// forgotten first.
+    #[serde(
+        rename = "login_throttle_capacity",
+        default = "default_login_throttle_capacity",
+        deserialize_with = "from_env_str"
+    )]
+    pub capacity: usize,
+
+    /// Header the reverse proxy puts the client address in, e.g.
+    /// `x-forwarded-for`; its last entry is used. Unset, the peer address of
+    /// the connection is.
+    #[serde(rename = "login_throttle_client_ip_header", default)]
+    pub client_ip_header: Option<String>,
+}
+
+fn default_audit_retention_days() -> i64 {
+    90
+}
+fn default_audit_purge_interval_secs() -> u64 {
+    86400
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuditConfig {
+    /// How long audit events are kept before they're deleted.
+    #[serde(
+        rename = "audit_retention_days",
+        default = "default_audit_retention_days",
+        deserialize_with = "from_env_str"
+    )]
+    pub retention_days: i64,
+
+    #[serde(
+        rename = "audit_purge_interval_secs",
+        default = "default_audit_purge_interval_secs",
+        deserialize_with = "from_env_str"
+    )]
+    pub purge_interval_secs: u64,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     #[serde(flatten)]
@@ -92,6 +597,22 @@ pub struct Config {
     pub storage: StorageConfig,
     #[serde(flatten)]
     pub upload: UploadConfig,
+    #[serde(flatten)]
+    pub trash: TrashConfig,
+    #[serde(flatten)]
+    pub quota: QuotaConfig,
+    #[serde(flatten)]
+    pub reconcile: ReconcileConfig,
+    #[serde(flatten)]
+    pub auth: AuthConfig,
+    #[serde(flatten)]
+    pub oidc: OidcConfig,
+    #[serde(flatten)]
+    pub webauthn: WebauthnConfig,
+    #[serde(flatten)]
+    pub login_throttle: LoginThrottleConfig,
+    #[serde(flatten)]
+    pub audit: AuditConfig,
     #[serde(default)]
     pub registration_enabled: bool,
 }
@@ -115,3 +636,67 @@ impl Config {
         Ok(settings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_settings_parse_from_env_strings() {
+        let settings: TrashConfig = config::Config::builder()
+            .set_override("trash_retention_days", "7")
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(settings.retention_days, 7);
+        assert_eq!(
+            settings.purge_interval_secs,
+            default_trash_purge_interval_secs()
+        );
+    }
+
+    #[test]
+    fn optional_settings_parse_from_env_strings() {
+        let settings: QuotaConfig = config::Config::builder()
+            .set_override("quota_default_bytes", "1073741824")
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(settings.default_bytes, Some(1 << 30));
+
+        let settings: QuotaConfig = config::Config::builder()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(settings.default_bytes, None);
+    }
+
+    #[test]
+    fn structured_settings_parse_from_env_json() {
+        let settings: OidcConfig = config::Config::builder()
+            .set_override(
+                "oidc_providers",
+                r#"[{"name": "corp", "issuer": "https://id.example.com", "client_id": "photos",
+                    "redirect_uri": "https://photos.example.com/auth/oidc/corp/callback"}]"#,
+            )
+            .unwrap()
+            .set_override("oidc_client_redirect_uris", r#"["photostore://oidc"]"#)
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(settings.providers.len(), 1);
+        assert_eq!(settings.providers[0].name, "corp");
+        assert_eq!(settings.providers[0].client_secret, None);
+        assert_eq!(settings.client_redirect_uris, ["photostore://oidc"]);
+        assert_eq!(settings.login_ttl_secs, default_oidc_login_ttl_secs());
+    }
+}
@@ -22,3 +22,31 @@ pub async fn init_db(config: &DatabaseConfig) -> Result<DbPool> {
 
     Ok(db)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tokio::sync::OnceCell;
+
+    static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+    /// The database named by `TEST_DATABASE_URL`, migrated to the latest
+    /// schema. Tests that need one skip when it isn't set:
+    /// `let Some(db) = test_db().await else { return };`
+    pub async fn test_db() -> Option<DbPool> {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping database test");
+            return None;
+        };
+        // a pool per test, as each test runs on its own runtime
+        let mut opt = ConnectOptions::new(url);
+        opt.max_connections(5).sqlx_logging(false);
+        let db = Database::connect(opt).await.expect("test database");
+        MIGRATED
+            .get_or_init(|| async {
+                Migrator::up(&db, None).await.expect("migrations");
+            })
+            .await;
+        Some(db)
+    }
+}
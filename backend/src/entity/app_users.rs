@@ -10,16 +10,47 @@ pub struct Model {
     #[sea_orm(column_type = "Text", nullable)]
     pub name: Option<String>,
     pub created_at: TimeDateTimeWithTimeZone,
+    pub change_seq: i64,
+    pub is_admin: bool,
+    pub quota_bytes: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::audit_events::Entity")]
+    AuditEvents,
     #[sea_orm(has_many = "super::auth_tokens::Entity")]
     AuthTokens,
+    #[sea_orm(has_many = "super::devices::Entity")]
+    Devices,
+    #[sea_orm(has_many = "super::file_changes::Entity")]
+    FileChanges,
+    #[sea_orm(has_many = "super::invites::Entity")]
+    Invites,
+    #[sea_orm(has_many = "super::login_challenges::Entity")]
+    LoginChallenges,
+    #[sea_orm(has_many = "super::login_codes::Entity")]
+    LoginCodes,
+    #[sea_orm(has_many = "super::oidc_logins::Entity")]
+    OidcLogins,
+    #[sea_orm(has_many = "super::passkey_ceremonies::Entity")]
+    PasskeyCeremonies,
+    #[sea_orm(has_many = "super::passkeys::Entity")]
+    Passkeys,
+    #[sea_orm(has_many = "super::totp_backup_codes::Entity")]
+    TotpBackupCodes,
     #[sea_orm(has_many = "super::user_accounts::Entity")]
     UserAccounts,
     #[sea_orm(has_many = "super::user_keys::Entity")]
     UserKeys,
+    #[sea_orm(has_one = "super::user_totp::Entity")]
+    UserTotp,
+}
+
+impl Related<super::audit_events::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuditEvents.def()
+    }
 }
 
 impl Related<super::auth_tokens::Entity> for Entity {
@@ -28,6 +59,60 @@ impl Related<super::auth_tokens::Entity> for Entity {
     }
 }
 
+impl Related<super::devices::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Devices.def()
+    }
+}
+
+impl Related<super::file_changes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::FileChanges.def()
+    }
+}
+
+impl Related<super::invites::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Invites.def()
+    }
+}
+
+impl Related<super::login_challenges::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LoginChallenges.def()
+    }
+}
+
+impl Related<super::login_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LoginCodes.def()
+    }
+}
+
+impl Related<super::oidc_logins::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OidcLogins.def()
+    }
+}
+
+impl Related<super::passkey_ceremonies::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PasskeyCeremonies.def()
+    }
+}
+
+impl Related<super::passkeys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Passkeys.def()
+    }
+}
+
+impl Related<super::totp_backup_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TotpBackupCodes.def()
+    }
+}
+
 impl Related<super::user_accounts::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserAccounts.def()
@@ -40,4 +125,10 @@ impl Related<super::user_keys::Entity> for Entity {
     }
 }
 
+impl Related<super::user_totp::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserTotp.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -8,9 +8,18 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
     pub user_id: Uuid,
-    #[sea_orm(column_type = "Text", unique)]
-    pub token: String,
     pub created_at: TimeDateTimeWithTimeZone,
+    pub expires_at: TimeDateTimeWithTimeZone,
+    pub refresh_expires_at: Option<TimeDateTimeWithTimeZone>,
+    #[sea_orm(column_type = "Text")]
+    pub token_prefix: String,
+    #[sea_orm(column_type = "Text")]
+    pub token_hash: String,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub refresh_token_prefix: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub refresh_token_hash: Option<String>,
+    pub device_id: Uuid,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -23,6 +32,18 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     AppUsers,
+    #[sea_orm(
+        belongs_to = "super::devices::Entity",
+        from = "Column::DeviceId",
+        to = "super::devices::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Devices,
+    #[sea_orm(has_many = "super::oidc_links::Entity")]
+    OidcLinks,
+    #[sea_orm(has_many = "super::oidc_logins::Entity")]
+    OidcLogins,
 }
 
 impl Related<super::app_users::Entity> for Entity {
@@ -31,4 +52,22 @@ impl Related<super::app_users::Entity> for Entity {
     }
 }
 
+impl Related<super::devices::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Devices.def()
+    }
+}
+
+impl Related<super::oidc_links::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OidcLinks.def()
+    }
+}
+
+impl Related<super::oidc_logins::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OidcLogins.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
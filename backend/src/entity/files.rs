@@ -8,15 +8,9 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
-    #[sea_orm(column_type = "Text")]
-    pub path: String,
-    #[sea_orm(column_type = "Text")]
-    pub name: String,
     pub state: FileState,
     pub created_at: TimeDateTimeWithTimeZone,
     pub added_at: TimeDateTimeWithTimeZone,
-    #[sea_orm(column_type = "Text")]
-    pub sha256: String,
     pub owner_id: Uuid,
     pub uploader_id: Uuid,
     #[sea_orm(column_type = "Text")]
@@ -31,6 +25,18 @@ pub struct Model {
     pub plaintext_size: i64,
     pub nonce_salt: i64,
     pub enc_scheme: i16,
+    pub trashed_at: Option<TimeDateTimeWithTimeZone>,
+    pub stored_size: Option<i64>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub path: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub name: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub encrypted_metadata: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub sha256: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub content_hash: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
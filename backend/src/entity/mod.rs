@@ -3,9 +3,21 @@
 pub mod prelude;
 
 pub mod app_users;
+pub mod audit_events;
 pub mod auth_tokens;
+pub mod devices;
+pub mod file_changes;
 pub mod files;
+pub mod invites;
+pub mod login_challenges;
+pub mod login_codes;
+pub mod oidc_links;
+pub mod oidc_logins;
+pub mod passkey_ceremonies;
+pub mod passkeys;
 pub mod sea_orm_active_enums;
+pub mod totp_backup_codes;
 pub mod upload_sessions;
 pub mod user_accounts;
 pub mod user_keys;
+pub mod user_totp;
@@ -3,8 +3,20 @@
 #![allow(unused)]
 
 pub use super::app_users::Entity as AppUsers;
+pub use super::audit_events::Entity as AuditEvents;
 pub use super::auth_tokens::Entity as AuthTokens;
+pub use super::devices::Entity as Devices;
+pub use super::file_changes::Entity as FileChanges;
 pub use super::files::Entity as Files;
+pub use super::invites::Entity as Invites;
+pub use super::login_challenges::Entity as LoginChallenges;
+pub use super::login_codes::Entity as LoginCodes;
+pub use super::oidc_links::Entity as OidcLinks;
+pub use super::oidc_logins::Entity as OidcLogins;
+pub use super::passkey_ceremonies::Entity as PasskeyCeremonies;
+pub use super::passkeys::Entity as Passkeys;
+pub use super::totp_backup_codes::Entity as TotpBackupCodes;
 pub use super::upload_sessions::Entity as UploadSessions;
 pub use super::user_accounts::Entity as UserAccounts;
 pub use super::user_keys::Entity as UserKeys;
+pub use super::user_totp::Entity as UserTotp;
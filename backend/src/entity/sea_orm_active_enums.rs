@@ -2,6 +2,22 @@
 
 use sea_orm::entity::prelude::*;
 
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "file_change_kind")]
+pub enum FileChangeKind {
+    #[sea_orm(string_value = "created")]
+    Created,
+    #[sea_orm(string_value = "synced")]
+    Synced,
+    #[sea_orm(string_value = "updated")]
+    Updated,
+    #[sea_orm(string_value = "deleted")]
+    Deleted,
+    #[sea_orm(string_value = "trashed")]
+    Trashed,
+    #[sea_orm(string_value = "restored")]
+    Restored,
+}
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "file_state")]
 pub enum FileState {
@@ -13,12 +29,18 @@ pub enum FileState {
     Synced,
     #[sea_orm(string_value = "failed")]
     Failed,
+    #[sea_orm(string_value = "trashed")]
+    Trashed,
 }
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "provider")]
 pub enum Provider {
     #[sea_orm(string_value = "credentials")]
     Credentials,
+    #[sea_orm(string_value = "oidc")]
+    Oidc,
+    #[sea_orm(string_value = "passkey")]
+    Passkey,
 }
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "media_type")]
@@ -5,8 +5,8 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub file_id: Uuid,
-    #[sea_orm(column_type = "Text")]
-    pub upload_id: String,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub upload_id: Option<String>,
     pub total_size: i64,
     pub chunk_size: i32,
     pub total_chunks: i32,
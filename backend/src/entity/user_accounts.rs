@@ -28,6 +28,8 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     AppUsers,
+    #[sea_orm(has_one = "super::passkeys::Entity")]
+    Passkeys,
 }
 
 impl Related<super::app_users::Entity> for Entity {
@@ -36,4 +38,10 @@ impl Related<super::app_users::Entity> for Entity {
     }
 }
 
+impl Related<super::passkeys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Passkeys.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
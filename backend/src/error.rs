@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 
@@ -37,28 +37,138 @@ pub enum Error {
     UploadExpired,
     #[error("Rate limit exceeded")]
     TooManyRequests,
+    #[error("Too many failed attempts, retry in {retry_after_secs}s")]
+    Throttled { retry_after_secs: u64 },
+    #[error("Too many content hashes, at most {max} can be looked up at once")]
+    TooManyContentHashes { max: usize },
+    #[error("Invalid pagination cursor")]
+    InvalidCursor,
+    #[error("File state conflict")]
+    FileConflict,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Upload size does not match the segment layout")]
+    UploadSizeMismatch,
+    #[error("Private key is not a valid envelope")]
+    InvalidKeyEnvelope,
+    #[error("Private key conflict")]
+    KeyConflict,
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("Device not found")]
+    DeviceNotFound,
+    #[error("Identity provider not found")]
+    ProviderNotFound,
+    #[error("Identity provider error")]
+    IdentityProvider,
+    #[error("Invalid ID token")]
+    InvalidIdToken,
+    #[error("Sign-in not found or expired")]
+    LoginNotFound,
+    #[error("Redirect URI not allowed")]
+    RedirectNotAllowed,
+    #[error("Missing or malformed PKCE code challenge")]
+    InvalidCodeChallenge,
+    #[error("Account is linked to another user")]
+    AccountConflict,
+    #[error("TOTP not enrolled")]
+    TotpNotFound,
+    #[error("TOTP already enabled")]
+    TotpConflict,
+    #[error("Invalid TOTP code")]
+    InvalidTotpCode,
+    #[error("Passkeys are not configured")]
+    PasskeysDisabled,
+    #[error("Passkey not found")]
+    PasskeyNotFound,
+    #[error("Passkey ceremony not found or expired")]
+    CeremonyNotFound,
+    #[error("Invalid passkey credential")]
+    InvalidPasskey,
+    #[error("A second factor or the password is required")]
+    ReauthenticationRequired,
+    #[error("Invite code is unknown, used up, expired or revoked")]
+    InvalidInvite,
+    #[error("Invite not found")]
+    InviteNotFound,
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        let (status, message) = match &self {
-            Error::Auth(_) => (StatusCode::UNAUTHORIZED, "Unauthorized"),
-            Error::Forbidden => (StatusCode::FORBIDDEN, "Forbidden"),
-            Error::FileNotFound | Error::UploadNotFound => (StatusCode::NOT_FOUND, "Not found"),
-            Error::FileUpload | Error::UploadIncomplete => (StatusCode::BAD_REQUEST, "Bad request"),
-            Error::UploadConflict => (StatusCode::CONFLICT, "Conflict"),
+impl Error {
+    /// Status code and client-facing message the error is reported with.
+    pub(crate) fn status_and_message(&self) -> (StatusCode, &'static str) {
+        match self {
+            Error::Auth(_) | Error::InvalidIdToken => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            Error::Forbidden | Error::InvalidInvite | Error::ReauthenticationRequired => {
+                (StatusCode::FORBIDDEN, "Forbidden")
+            }
+            Error::FileNotFound
+            | Error::UploadNotFound
+            | Error::UserNotFound
+            | Error::SessionNotFound
+            | Error::DeviceNotFound
+            | Error::ProviderNotFound
+            | Error::TotpNotFound
+            | Error::PasskeysDisabled
+            | Error::PasskeyNotFound
+            | Error::InviteNotFound => (StatusCode::NOT_FOUND, "Not found"),
+            Error::FileUpload
+            | Error::UploadIncomplete
+            | Error::InvalidCursor
+            | Error::InvalidKeyEnvelope
+            | Error::LoginNotFound
+            | Error::RedirectNotAllowed
+            | Error::InvalidCodeChallenge
+            | Error::InvalidTotpCode
+            | Error::CeremonyNotFound
+            | Error::InvalidPasskey => (StatusCode::BAD_REQUEST, "Bad request"),
+            Error::UploadConflict
+            | Error::FileConflict
+            | Error::KeyConflict
+            | Error::AccountConflict
+            | Error::TotpConflict => (StatusCode::CONFLICT, "Conflict"),
+            Error::UploadSizeMismatch => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Upload size does not match the segment layout",
+            ),
+            Error::QuotaExceeded => (StatusCode::PAYLOAD_TOO_LARGE, "Quota exceeded"),
+            Error::TooManyContentHashes { .. } => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "Too many content hashes")
+            }
             Error::UploadExpired => (StatusCode::GONE, "Gone"),
-            Error::TooManyRequests => (StatusCode::TOO_MANY_REQUESTS, "Too many requests"),
+            Error::TooManyRequests | Error::Throttled { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+            }
+            Error::IdentityProvider => (StatusCode::BAD_GATEWAY, "Identity provider error"),
             Error::Storage
             | Error::Database
             | Error::DbMigration
             | Error::PasswordHashing
             | Error::Configuration
             | Error::Crypto(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
-        };
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, message) = self.status_and_message();
 
         tracing::error!(status = status.as_u16(), error = %self, "Request failed");
 
+        if let Error::Throttled { retry_after_secs } = self {
+            return (
+                status,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                message,
+            )
+                .into_response();
+        }
+        // the limit is part of the message, so clients know how to split
+        if let Error::TooManyContentHashes { .. } = self {
+            return (status, self.to_string()).into_response();
+        }
         (status, message).into_response()
     }
 }
@@ -1,19 +1,26 @@
 use axum::extract::Query;
+use axum::http::StatusCode;
 use axum::{
     Json,
     extract::{Path, State},
 };
-use sdk::dtos::file::{DownloadUrlResponse, FileMetadata, FilesUploadRequest};
+use sdk::crypto::metadata::MAX_SEALED_METADATA_SIZE;
+use sdk::dtos::file::{
+    ContentHashRequest, ContentLookupRequest, ContentLookupResponse, DownloadUrlResponse,
+    FileMetadata, FileMetadataPage, FileUploadResult, FileUploadStatus, FilesUploadRequest,
+    FilesUploadResponse, KnownContent, SealMetadataRequest, TrashedFile,
+};
 use sdk::media::MediaType;
 use serde::{Deserialize, Deserializer, de};
 use std::ops::RangeInclusive;
 use time::{Duration, OffsetDateTime};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 use super::File;
+use super::pagination::{FileCursor, FileListQuery, FileOrder, SortDirection};
 use super::repository::DbFileRepository;
 use crate::file::repository::FileRepository;
-use crate::storage::{presign_get_object, presigning_config, s3_original_key, s3_thumbnail_key};
+use crate::storage::{StorageBackend, delete_file_objects, s3_original_key, s3_thumbnail_key};
 use crate::ulid::Id;
 use crate::{
     AppState,
@@ -26,88 +33,116 @@ pub(super) async fn upload_files_metadata(
     State(state): State<AppState>,
     session: Session,
     Json(request): Json<FilesUploadRequest>,
-) -> Result<()> {
+) -> Result<Json<FilesUploadResponse>> {
     debug!(count = request.files.len(), "Uploading files metadata",);
 
     let segment_bounds =
         state.config.upload.min_segment_size..=state.config.upload.max_segment_size;
     let mut repo = DbFileRepository { db: state.db };
-    upload_files_metadata_internal(&mut repo, session, request, segment_bounds).await?;
+    let response =
+        upload_files_metadata_internal(&mut repo, session, request, segment_bounds).await?;
 
-    Ok(())
+    Ok(Json(response))
 }
 
 /// Validate the client-supplied media metadata. The server can't inspect the
-/// ciphertext, so these are the only guard rails before persisting.
-fn validate_metadata(item: &FileMetadata, segment_bounds: &RangeInclusive<u32>) -> Result<()> {
+/// ciphertext, so these are the only guard rails before persisting. The error
+/// is the reason reported back to the client.
+fn validate_metadata(
+    item: &FileMetadata,
+    segment_bounds: &RangeInclusive<u32>,
+) -> std::result::Result<(), &'static str> {
+    validate_sealed_metadata(item.encrypted_metadata.as_deref())?;
+    if item.content_hash.as_deref().is_none_or(str::is_empty) {
+        return Err("content_hash is missing");
+    }
     if item.content_type.trim().is_empty() {
-        error!("Rejecting file with empty content_type");
-        return Err(Error::FileUpload);
+        return Err("content_type is empty");
     }
     if item.plaintext_size == 0 {
-        error!("Rejecting file with zero plaintext_size");
-        return Err(Error::FileUpload);
+        return Err("plaintext_size is zero");
     }
     if item.width == 0 || item.height == 0 {
-        error!(
-            width = item.width,
-            height = item.height,
-            "Rejecting file with zero dimensions"
-        );
-        return Err(Error::FileUpload);
+        return Err("dimensions are zero");
     }
     if !segment_bounds.contains(&item.segment_size) {
-        error!(
-            segment_size = item.segment_size,
-            "segment_size out of range"
-        );
-        return Err(Error::FileUpload);
+        return Err("segment_size out of range");
     }
     if item.enc_scheme != sdk::crypto::ENC_SCHEME_SEGMENTED {
-        error!(
-            enc_scheme = item.enc_scheme,
-            "Unsupported encryption scheme"
-        );
-        return Err(Error::FileUpload);
+        return Err("unsupported enc_scheme");
     }
     if item.media_type == MediaType::Video && item.duration_ms.is_none() {
-        error!("Rejecting video without duration_ms");
-        return Err(Error::FileUpload);
+        return Err("video without duration_ms");
     }
     Ok(())
 }
 
+fn validate_sealed_metadata(sealed: Option<&str>) -> std::result::Result<(), &'static str> {
+    match sealed {
+        None => Err("encrypted_metadata is missing"),
+        Some("") => Err("encrypted_metadata is empty"),
+        Some(sealed) if sealed.len() > MAX_SEALED_METADATA_SIZE => {
+            Err("encrypted_metadata too large")
+        }
+        Some(_) => Ok(()),
+    }
+}
+
 async fn upload_files_metadata_internal(
     repo: &mut impl FileRepository,
     session: Session,
     request: FilesUploadRequest,
     segment_bounds: RangeInclusive<u32>,
-) -> Result<()> {
+) -> Result<FilesUploadResponse> {
     let request_user_id: Id = request.user_id.into();
     if request_user_id != session.user_id() {
         error!("Upload authorization mismatch");
         return Err(Error::Forbidden);
     }
 
+    let mut results = Vec::with_capacity(request.files.len());
     for item in request.files {
         let file_id: Id = item.id.into();
-        let exists = repo.exists(&file_id).await?;
+        let result = |status, reason: Option<&str>| FileUploadResult {
+            id: item.id,
+            status,
+            reason: reason.map(str::to_string),
+        };
 
-        if exists {
-            warn!(%file_id, "File already exists, skipping upload");
+        if let Some(existing) = repo.find(&file_id).await? {
+            let identical =
+                existing.content_hash == item.content_hash && existing.enc_key == item.key;
+            if existing.owner_id != request_user_id {
+                warn!(%file_id, "File id belongs to another user");
+                results.push(result(FileUploadStatus::NotFound, None));
+            } else if identical {
+                debug!(%file_id, "File already exists");
+                results.push(result(FileUploadStatus::AlreadyExists, None));
+            } else {
+                warn!(%file_id, "A different file with this id already exists");
+                results.push(result(
+                    FileUploadStatus::Conflict,
+                    Some("a different file with this id exists"),
+                ));
+            }
             continue;
         }
 
-        validate_metadata(&item, &segment_bounds)?;
+        if let Err(reason) = validate_metadata(&item, &segment_bounds) {
+            warn!(%file_id, reason, "Rejecting file metadata");
+            results.push(result(FileUploadStatus::Rejected, Some(reason)));
+            continue;
+        }
 
         let file = File {
             id: file_id,
-            path: item.path.clone(),
-            name: item.path.split('/').next_back().unwrap().to_string(),
+            encrypted_metadata: item.encrypted_metadata.clone(),
+            legacy_path: None,
+            legacy_name: None,
             state: FileState::New,
             created_at: item.date,
             added_at: OffsetDateTime::now_utc(),
-            sha256: item.sha256.clone(),
+            content_hash: item.content_hash.clone(),
             owner_id: request_user_id,
             uploader_id: session.user_id(),
             enc_key: item.key.clone(),
@@ -120,19 +155,83 @@ async fn upload_files_metadata_internal(
             plaintext_size: item.plaintext_size,
             nonce_salt: item.nonce_salt,
             enc_scheme: item.enc_scheme,
+            trashed_at: None,
+            stored_size: None,
         };
 
         debug!("Saving file metadata");
         repo.save(&file).await?;
+        results.push(result(FileUploadStatus::Created, None));
     }
 
-    Ok(())
+    Ok(FilesUploadResponse { files: results })
 }
 
-#[derive(Debug, Deserialize)]
+/// Upper bound on the hashes a client may look up at once.
+const MAX_CONTENT_LOOKUP: usize = 1000;
+
+pub(super) async fn lookup_content(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<ContentLookupRequest>,
+) -> Result<Json<ContentLookupResponse>> {
+    debug!(
+        count = request.content_hashes.len(),
+        "Looking up content hashes"
+    );
+
+    let repo = DbFileRepository { db: state.db };
+    let response = lookup_content_internal(&repo, &session.user_id(), request).await?;
+
+    Ok(Json(response))
+}
+
+async fn lookup_content_internal(
+    repo: &impl FileRepository,
+    user_id: &Id,
+    request: ContentLookupRequest,
+) -> Result<ContentLookupResponse> {
+    if request.content_hashes.len() > MAX_CONTENT_LOOKUP {
+        error!(
+            len = request.content_hashes.len(),
+            "Too many content hashes"
+        );
+        return Err(Error::TooManyContentHashes {
+            max: MAX_CONTENT_LOOKUP,
+        });
+    }
+
+    let files = repo
+        .find_by_content_hashes(user_id, &request.content_hashes)
+        .await?
+        .into_iter()
+        .filter_map(|file| {
+            Some(KnownContent {
+                content_hash: file.content_hash?,
+                file_id: file.id.into(),
+                trashed: file.state == FileState::Trashed,
+            })
+        })
+        .collect();
+
+    Ok(ContentLookupResponse { files })
+}
+
+/// Page size used when the client doesn't ask for one.
+const DEFAULT_PAGE_SIZE: u64 = 500;
+/// Upper bound on the page size a client may ask for.
+const MAX_PAGE_SIZE: u64 = 1000;
+
+#[derive(Debug, Default, Deserialize)]
 pub struct DownloadParams {
     #[serde(default, deserialize_with = "empty_string_as_none")]
     from: Option<OffsetDateTime>,
+    #[serde(default)]
+    order_by: FileOrder,
+    #[serde(default)]
+    direction: SortDirection,
+    cursor: Option<String>,
+    limit: Option<u64>,
 }
 
 fn empty_string_as_none<'de, D: Deserializer<'de>>(
@@ -156,35 +255,75 @@ pub(super) async fn get_files_metadata(
     State(state): State<AppState>,
     Query(params): Query<DownloadParams>,
     session: Session,
-) -> Result<Json<Vec<FileMetadata>>> {
-    debug!("Getting files metadata");
+) -> Result<Json<FileMetadataPage>> {
+    debug!(
+        order_by = ?params.order_by,
+        direction = ?params.direction,
+        "Getting files metadata"
+    );
 
     let repo = DbFileRepository { db: state.db };
-    let files = repo
-        .find_synced_files(&session.user_id(), params.from)
-        .await?;
+    let page = get_files_metadata_internal(&repo, session, params).await?;
 
-    let metadata: Vec<FileMetadata> = files
-        .into_iter()
-        .map(|file| FileMetadata {
-            path: file.path,
-            id: file.id.into(),
-            date: file.created_at,
-            sha256: file.sha256,
-            key: file.enc_key,
-            media_type: file.media_type,
-            content_type: file.content_type,
-            width: file.width,
-            height: file.height,
-            duration_ms: file.duration_ms,
-            segment_size: file.segment_size,
-            plaintext_size: file.plaintext_size,
-            nonce_salt: file.nonce_salt,
-            enc_scheme: file.enc_scheme,
+    Ok(Json(page))
+}
+
+async fn get_files_metadata_internal(
+    repo: &impl FileRepository,
+    session: Session,
+    params: DownloadParams,
+) -> Result<FileMetadataPage> {
+    let after = match params.cursor.as_deref() {
+        None | Some("") => None,
+        Some(value) => {
+            let cursor = FileCursor::decode(value).ok_or_else(|| {
+                error!("Malformed files cursor");
+                Error::InvalidCursor
+            })?;
+            if cursor.order != params.order_by || cursor.direction != params.direction {
+                error!("Files cursor was issued for a different ordering");
+                return Err(Error::InvalidCursor);
+            }
+            Some((cursor.timestamp, cursor.id))
+        }
+    };
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // Fetch one extra row to learn whether another page follows.
+    let query = FileListQuery {
+        order: params.order_by,
+        direction: params.direction,
+        from: params.from,
+        after,
+        limit: limit + 1,
+    };
+    let mut files = repo.find_synced_files(&session.user_id(), &query).await?;
+
+    let next_cursor = if files.len() as u64 > limit {
+        files.truncate(limit as usize);
+        files.last().map(|last| {
+            FileCursor {
+                order: params.order_by,
+                direction: params.direction,
+                timestamp: match params.order_by {
+                    FileOrder::AddedAt => last.added_at,
+                    FileOrder::CreatedAt => last.created_at,
+                },
+                id: last.id,
+            }
+            .encode()
         })
-        .collect();
+    } else {
+        None
+    };
 
-    Ok(Json(metadata))
+    Ok(FileMetadataPage {
+        files: files.into_iter().map(FileMetadata::from).collect(),
+        next_cursor,
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -192,7 +331,7 @@ pub(super) struct FileDownloadParams {
     variant: Option<sdk::thumbnails::ThumbnailVariant>,
 }
 
-/// Issue a short-lived presigned S3 GET URL for the requested object. The
+/// Issue a short-lived presigned GET URL for the requested object. The
 /// client fetches the (encrypted) bytes directly from storage — supporting
 /// HTTP Range so it can pull and decrypt individual segments to seek — rather
 /// than streaming them through the backend.
@@ -217,23 +356,304 @@ pub(super) async fn download_file(
     };
 
     let ttl_secs = state.config.upload.presigned_url_ttl_secs;
-    let presigning = presigning_config(ttl_secs)?;
-    let url = presign_get_object(
-        &state.s3_client,
-        &state.config.storage.bucket_name,
-        &key,
-        &presigning,
-    )
-    .await?;
+    let url = state
+        .storage
+        .presign_get_object(&key, std::time::Duration::from_secs(ttl_secs))
+        .await?;
     let expires_at = OffsetDateTime::now_utc() + Duration::seconds(ttl_secs as i64);
 
     Ok(Json(DownloadUrlResponse { url, expires_at }))
 }
 
+/// Load a file the session's user owns.
+async fn find_owned_file(
+    repo: &impl FileRepository,
+    session: &Session,
+    file_id: &Id,
+) -> Result<File> {
+    let file = repo.find(file_id).await?.ok_or_else(|| {
+        error!(%file_id, "File not found");
+        Error::FileNotFound
+    })?;
+
+    if file.owner_id != session.user_id() {
+        error!(%file_id, "File ownership mismatch");
+        return Err(Error::Forbidden);
+    }
+
+    Ok(file)
+}
+
+pub(super) async fn get_trash(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<Vec<TrashedFile>>> {
+    debug!("Getting trashed files");
+
+    let repo = DbFileRepository { db: state.db };
+    let files = repo.find_trashed_files(&session.user_id()).await?;
+
+    let trash = files
+        .into_iter()
+        .filter_map(|file| {
+            let trashed_at = file.trashed_at?;
+            Some(TrashedFile {
+                file: file.into(),
+                trashed_at,
+            })
+        })
+        .collect();
+
+    Ok(Json(trash))
+}
+
+pub(super) async fn trash_file(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+) -> Result<StatusCode> {
+    debug!(%file_id, "Moving file to trash");
+
+    let repo = DbFileRepository { db: state.db };
+    trash_file_internal(&repo, &session, &file_id, OffsetDateTime::now_utc()).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Only synced files go to the trash; trashing a trashed file is a no-op.
+async fn trash_file_internal(
+    repo: &impl FileRepository,
+    session: &Session,
+    file_id: &Id,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let file = find_owned_file(repo, session, file_id).await?;
+
+    match file.state {
+        FileState::Trashed => Ok(()),
+        FileState::Synced => repo.trash(file_id, now).await,
+        state => {
+            error!(%file_id, ?state, "Only synced files can be trashed");
+            Err(Error::FileConflict)
+        }
+    }
+}
+
+pub(super) async fn restore_file(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+) -> Result<StatusCode> {
+    debug!(%file_id, "Restoring file from trash");
+
+    let repo = DbFileRepository { db: state.db };
+    restore_file_internal(&repo, &session, &file_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn restore_file_internal(
+    repo: &impl FileRepository,
+    session: &Session,
+    file_id: &Id,
+) -> Result<()> {
+    let file = find_owned_file(repo, session, file_id).await?;
+
+    match file.state {
+        FileState::Synced => Ok(()),
+        FileState::Trashed => repo.restore(file_id).await,
+        state => {
+            error!(%file_id, ?state, "File is not in the trash");
+            Err(Error::FileConflict)
+        }
+    }
+}
+
+/// Seal the metadata of a file uploaded before metadata was encrypted. The
+/// client builds the blob from the file's legacy path and name.
+pub(super) async fn seal_metadata(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+    Json(request): Json<SealMetadataRequest>,
+) -> Result<StatusCode> {
+    debug!(%file_id, "Sealing file metadata");
+
+    let repo = DbFileRepository { db: state.db };
+    seal_metadata_internal(&repo, &session, &file_id, request).await?;
+
+    info!(%file_id, "File metadata sealed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sealed metadata is never replaced, so a file that has some conflicts.
+async fn seal_metadata_internal(
+    repo: &impl FileRepository,
+    session: &Session,
+    file_id: &Id,
+    request: SealMetadataRequest,
+) -> Result<()> {
+    if let Err(reason) = validate_sealed_metadata(Some(&request.encrypted_metadata)) {
+        error!(%file_id, reason, "Invalid sealed metadata");
+        return Err(Error::FileUpload);
+    }
+
+    let file = find_owned_file(repo, session, file_id).await?;
+    // checked again by the update, in case another device sealed it meanwhile
+    if file.encrypted_metadata.is_some()
+        || !repo
+            .seal_metadata(file_id, &request.encrypted_metadata)
+            .await?
+    {
+        error!(%file_id, "File metadata is already sealed");
+        return Err(Error::FileConflict);
+    }
+
+    Ok(())
+}
+
+/// Backfill the keyed content hash of a file uploaded before hashes were
+/// keyed. The client computes it from the decrypted original.
+pub(super) async fn backfill_content_hash(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+    Json(request): Json<ContentHashRequest>,
+) -> Result<StatusCode> {
+    debug!(%file_id, "Backfilling content hash");
+
+    let repo = DbFileRepository { db: state.db };
+    backfill_content_hash_internal(&repo, &session, &file_id, request).await?;
+
+    info!(%file_id, "Content hash backfilled");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Like sealed metadata, a keyed hash is never replaced.
+async fn backfill_content_hash_internal(
+    repo: &impl FileRepository,
+    session: &Session,
+    file_id: &Id,
+    request: ContentHashRequest,
+) -> Result<()> {
+    if request.content_hash.is_empty() {
+        error!(%file_id, "Empty content hash");
+        return Err(Error::FileUpload);
+    }
+
+    let file = find_owned_file(repo, session, file_id).await?;
+    if file.content_hash.is_some()
+        || !repo
+            .backfill_content_hash(file_id, &request.content_hash)
+            .await?
+    {
+        error!(%file_id, "File already has a content hash");
+        return Err(Error::FileConflict);
+    }
+
+    Ok(())
+}
+
+/// Delete a file permanently, whether or not it went through the trash.
+pub(super) async fn delete_file(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+) -> Result<StatusCode> {
+    debug!(%file_id, "Deleting file");
+
+    let repo = DbFileRepository { db: state.db };
+    delete_file_internal(&repo, &state.storage, &session, &file_id).await?;
+
+    info!(%file_id, "File deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_file_internal(
+    repo: &impl FileRepository,
+    storage: &impl StorageBackend,
+    session: &Session,
+    file_id: &Id,
+) -> Result<()> {
+    let file = find_owned_file(repo, session, file_id).await?;
+
+    if file.state == FileState::SyncInProgress {
+        error!(%file_id, "Cannot delete a file while it is uploading");
+        return Err(Error::FileConflict);
+    }
+
+    // objects first: if this fails the row is still there to retry with
+    delete_file_objects(storage, &file).await?;
+    repo.delete(file_id).await
+}
+
+/// Permanently delete files that sat in the trash past the retention period.
+pub(crate) async fn purge_expired_trash(state: AppState) {
+    let interval_secs = state.config.trash.purge_interval_secs;
+    let retention = Duration::days(state.config.trash.retention_days);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        let repo = DbFileRepository {
+            db: state.db.clone(),
+        };
+
+        let before = OffsetDateTime::now_utc() - retention;
+        let expired = match repo.find_expired_trash(before).await {
+            Ok(files) => files,
+            Err(e) => {
+                error!(error = %e, "Failed to query expired trash");
+                continue;
+            }
+        };
+
+        if expired.is_empty() {
+            continue;
+        }
+
+        info!(count = expired.len(), "Purging expired trash");
+        purge_trashed_files(&repo, &state.storage, &expired, before).await;
+    }
+}
+
+/// Delete `files` that are still trashed since before `before`. Each row is
+/// removed before its objects, so a file restored after `files` was read is
+/// left alone; objects whose deletion fails afterwards are only logged.
+async fn purge_trashed_files(
+    repo: &impl FileRepository,
+    storage: &impl StorageBackend,
+    files: &[File],
+    before: OffsetDateTime,
+) {
+    for file in files {
+        let file = match repo.delete_expired(&file.id, before).await {
+            Ok(Some(file)) => file,
+            Ok(None) => {
+                info!(file_id = %file.id, "Trashed file was restored, not purging it");
+                continue;
+            }
+            Err(e) => {
+                error!(file_id = %file.id, error = %e, "Failed to delete trashed file");
+                continue;
+            }
+        };
+
+        if let Err(e) = delete_file_objects(storage, &file).await {
+            error!(file_id = %file.id, error = %e, "Failed to delete objects of purged file");
+            continue;
+        }
+
+        info!(file_id = %file.id, "Purged trashed file");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::file::repository::tests::InMemoryFileRepository;
+    use crate::sync::ChangeKind;
     use time::OffsetDateTime;
 
     /// A wide bound so tests don't depend on production defaults.
@@ -241,12 +661,14 @@ mod tests {
         1..=u32::MAX
     }
 
-    fn image_metadata(id: ulid::Ulid, path: &str) -> FileMetadata {
+    fn image_metadata(id: ulid::Ulid) -> FileMetadata {
         FileMetadata {
             id,
-            path: path.to_string(),
+            encrypted_metadata: Some("c2VhbGVk".to_string()),
+            legacy_path: None,
+            legacy_name: None,
             date: OffsetDateTime::now_utc(),
-            sha256: "sha256".to_string(),
+            content_hash: Some("content-hash".to_string()),
             key: "key".to_string(),
             media_type: MediaType::Image,
             content_type: "image/jpeg".to_string(),
@@ -288,7 +710,7 @@ mod tests {
         let file_id = ulid::Ulid::new();
         let request = FilesUploadRequest {
             user_id,
-            files: vec![image_metadata(file_id, "/home/pics/test.jpg")],
+            files: vec![image_metadata(file_id)],
         };
         let metadata = request.files[0].clone();
         let session = Session::new(user_id.into());
@@ -301,12 +723,11 @@ mod tests {
         // then
         let files = repo.files.borrow();
         let file = &files[0];
-        assert_eq!(file.path, "/home/pics/test.jpg");
-        assert_eq!(file.name, "test.jpg");
+        assert_eq!(file.encrypted_metadata, metadata.encrypted_metadata);
         assert!(matches!(file.state, FileState::New));
         assert_eq!(file.id, file_id.into());
         assert_eq!(file.created_at, metadata.date);
-        assert_eq!(file.sha256, "sha256");
+        assert_eq!(file.content_hash.as_deref(), Some("content-hash"));
         assert_eq!(file.owner_id, user_id.into());
         assert_eq!(file.uploader_id, user_id.into());
         assert_eq!(file.enc_key, "key");
@@ -322,7 +743,7 @@ mod tests {
     async fn rejects_video_without_duration() {
         let mut repo = InMemoryFileRepository::new();
         let user_id = ulid::Ulid::new();
-        let mut item = image_metadata(ulid::Ulid::new(), "/clips/movie.mp4");
+        let mut item = image_metadata(ulid::Ulid::new());
         item.media_type = MediaType::Video;
         item.content_type = "video/mp4".to_string();
         item.duration_ms = None;
@@ -332,10 +753,37 @@ mod tests {
         };
         let session = Session::new(user_id.into());
 
-        let result =
-            upload_files_metadata_internal(&mut repo, session, request, any_segment_size()).await;
+        let response =
+            upload_files_metadata_internal(&mut repo, session, request, any_segment_size())
+                .await
+                .unwrap();
 
-        assert!(matches!(result.unwrap_err(), Error::FileUpload));
+        assert_eq!(response.files[0].status, FileUploadStatus::Rejected);
+        assert!(repo.files.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_files_without_sealed_metadata() {
+        let mut repo = InMemoryFileRepository::new();
+        let user_id = ulid::Ulid::new();
+        let mut item = image_metadata(ulid::Ulid::new());
+        item.encrypted_metadata = None;
+        let request = FilesUploadRequest {
+            user_id,
+            files: vec![item],
+        };
+        let session = Session::new(user_id.into());
+
+        let response =
+            upload_files_metadata_internal(&mut repo, session, request, any_segment_size())
+                .await
+                .unwrap();
+
+        assert_eq!(response.files[0].status, FileUploadStatus::Rejected);
+        assert_eq!(
+            response.files[0].reason.as_deref(),
+            Some("encrypted_metadata is missing")
+        );
         assert!(repo.files.borrow().is_empty());
     }
 
@@ -343,7 +791,7 @@ mod tests {
     async fn rejects_segment_size_out_of_bounds() {
         let mut repo = InMemoryFileRepository::new();
         let user_id = ulid::Ulid::new();
-        let mut item = image_metadata(ulid::Ulid::new(), "/home/pics/big.jpg");
+        let mut item = image_metadata(ulid::Ulid::new());
         item.segment_size = 64;
         let request = FilesUploadRequest {
             user_id,
@@ -352,9 +800,623 @@ mod tests {
         let session = Session::new(user_id.into());
 
         // bounds that exclude 64
-        let result = upload_files_metadata_internal(&mut repo, session, request, 1024..=4096).await;
+        let response = upload_files_metadata_internal(&mut repo, session, request, 1024..=4096)
+            .await
+            .unwrap();
 
-        assert!(matches!(result.unwrap_err(), Error::FileUpload));
+        assert_eq!(response.files[0].status, FileUploadStatus::Rejected);
+        assert_eq!(
+            response.files[0].reason.as_deref(),
+            Some("segment_size out of range")
+        );
         assert!(repo.files.borrow().is_empty());
     }
+
+    #[tokio::test]
+    async fn persists_valid_items_next_to_rejected_ones() {
+        let mut repo = InMemoryFileRepository::new();
+        let user_id = ulid::Ulid::new();
+        let mut invalid = image_metadata(ulid::Ulid::new());
+        invalid.width = 0;
+        let valid = image_metadata(ulid::Ulid::new());
+        let request = FilesUploadRequest {
+            user_id,
+            files: vec![invalid, valid.clone()],
+        };
+
+        let response = upload_files_metadata_internal(
+            &mut repo,
+            Session::new(user_id.into()),
+            request,
+            any_segment_size(),
+        )
+        .await
+        .unwrap();
+
+        let statuses: Vec<FileUploadStatus> = response.files.iter().map(|f| f.status).collect();
+        assert_eq!(
+            statuses,
+            vec![FileUploadStatus::Rejected, FileUploadStatus::Created]
+        );
+        let files = repo.files.borrow();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].id, valid.id.into());
+    }
+
+    #[tokio::test]
+    async fn reports_existing_files_as_identical_or_conflicting() {
+        let mut repo = InMemoryFileRepository::new();
+        let user_id = ulid::Ulid::new();
+        let item = image_metadata(ulid::Ulid::new());
+        let first = FilesUploadRequest {
+            user_id,
+            files: vec![item.clone()],
+        };
+        upload_files_metadata_internal(
+            &mut repo,
+            Session::new(user_id.into()),
+            first,
+            any_segment_size(),
+        )
+        .await
+        .unwrap();
+
+        let mut changed = item.clone();
+        changed.content_hash = Some("other".to_string());
+        let second = FilesUploadRequest {
+            user_id,
+            files: vec![item, changed],
+        };
+        let response = upload_files_metadata_internal(
+            &mut repo,
+            Session::new(user_id.into()),
+            second,
+            any_segment_size(),
+        )
+        .await
+        .unwrap();
+
+        let statuses: Vec<FileUploadStatus> = response.files.iter().map(|f| f.status).collect();
+        assert_eq!(
+            statuses,
+            vec![FileUploadStatus::AlreadyExists, FileUploadStatus::Conflict]
+        );
+        assert_eq!(repo.files.borrow().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn hides_that_another_users_file_has_the_id() {
+        let mut repo = InMemoryFileRepository::new();
+        let owner_id = ulid::Ulid::new();
+        let item = image_metadata(ulid::Ulid::new());
+        upload_files_metadata_internal(
+            &mut repo,
+            Session::new(owner_id.into()),
+            FilesUploadRequest {
+                user_id: owner_id,
+                files: vec![item.clone()],
+            },
+            any_segment_size(),
+        )
+        .await
+        .unwrap();
+
+        let other_id = ulid::Ulid::new();
+        let response = upload_files_metadata_internal(
+            &mut repo,
+            Session::new(other_id.into()),
+            FilesUploadRequest {
+                user_id: other_id,
+                files: vec![item],
+            },
+            any_segment_size(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.files[0].status, FileUploadStatus::NotFound);
+        assert_eq!(response.files[0].reason, None);
+        let files = repo.files.borrow();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].owner_id, owner_id.into());
+    }
+
+    fn synced_file(owner_id: Id, added_at: OffsetDateTime, created_at: OffsetDateTime) -> File {
+        File {
+            id: Id::new(),
+            encrypted_metadata: None,
+            legacy_path: None,
+            legacy_name: None,
+            state: FileState::Synced,
+            created_at,
+            added_at,
+            content_hash: None,
+            owner_id,
+            uploader_id: owner_id,
+            enc_key: "key".to_string(),
+            media_type: MediaType::Image,
+            content_type: "image/jpeg".to_string(),
+            width: 640,
+            height: 480,
+            duration_ms: None,
+            segment_size: sdk::segment::DEFAULT_SEGMENT_SIZE,
+            plaintext_size: 4096,
+            nonce_salt: 42,
+            enc_scheme: sdk::crypto::ENC_SCHEME_SEGMENTED,
+            trashed_at: None,
+            stored_size: None,
+        }
+    }
+
+    /// Five synced files added a minute apart (and taken in reverse order),
+    /// plus one file of another user and one that isn't synced yet.
+    fn library(user_id: Id) -> InMemoryFileRepository {
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut files: Vec<File> = (0..5)
+            .map(|i| {
+                synced_file(
+                    user_id,
+                    start + Duration::minutes(i),
+                    start - Duration::days(i),
+                )
+            })
+            .collect();
+        files.push(synced_file(Id::new(), start, start));
+        let mut pending = synced_file(user_id, start, start);
+        pending.state = FileState::New;
+        files.push(pending);
+        InMemoryFileRepository::with_files(files)
+    }
+
+    async fn list_all(
+        repo: &InMemoryFileRepository,
+        user_id: Id,
+        order_by: FileOrder,
+        direction: SortDirection,
+    ) -> Vec<Vec<ulid::Ulid>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let params = DownloadParams {
+                order_by,
+                direction,
+                cursor,
+                limit: Some(2),
+                ..Default::default()
+            };
+            let page = get_files_metadata_internal(repo, Session::new(user_id), params)
+                .await
+                .unwrap();
+            pages.push(page.files.iter().map(|f| f.id).collect());
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_through_synced_files_in_added_order() {
+        let user_id = Id::new();
+        let repo = library(user_id);
+        let expected: Vec<ulid::Ulid> = repo.files.borrow()[..5]
+            .iter()
+            .map(|f| f.id.into())
+            .collect();
+
+        let pages = list_all(&repo, user_id, FileOrder::AddedAt, SortDirection::Asc).await;
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].len(), 2);
+        assert_eq!(pages[2].len(), 1);
+        assert_eq!(pages.concat(), expected);
+    }
+
+    #[tokio::test]
+    async fn pages_by_created_at_descending() {
+        let user_id = Id::new();
+        let repo = library(user_id);
+        // files were taken in reverse order of being added
+        let expected: Vec<ulid::Ulid> = repo.files.borrow()[..5]
+            .iter()
+            .map(|f| f.id.into())
+            .collect();
+
+        let pages = list_all(&repo, user_id, FileOrder::CreatedAt, SortDirection::Desc).await;
+
+        assert_eq!(pages.concat(), expected);
+    }
+
+    #[tokio::test]
+    async fn last_page_has_no_cursor() {
+        let user_id = Id::new();
+        let repo = library(user_id);
+        let params = DownloadParams {
+            limit: Some(5),
+            ..Default::default()
+        };
+
+        let page = get_files_metadata_internal(&repo, Session::new(user_id), params)
+            .await
+            .unwrap();
+
+        assert_eq!(page.files.len(), 5);
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_cursor_for_another_ordering() {
+        let user_id = Id::new();
+        let repo = library(user_id);
+        let first = get_files_metadata_internal(
+            &repo,
+            Session::new(user_id),
+            DownloadParams {
+                limit: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let params = DownloadParams {
+            order_by: FileOrder::CreatedAt,
+            cursor: first.next_cursor,
+            ..Default::default()
+        };
+        let result = get_files_metadata_internal(&repo, Session::new(user_id), params).await;
+
+        assert!(matches!(result, Err(Error::InvalidCursor)));
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_cursor() {
+        let user_id = Id::new();
+        let repo = library(user_id);
+        let params = DownloadParams {
+            cursor: Some("garbage".to_string()),
+            ..Default::default()
+        };
+
+        let result = get_files_metadata_internal(&repo, Session::new(user_id), params).await;
+
+        assert!(matches!(result, Err(Error::InvalidCursor)));
+    }
+
+    fn single_file(file: File) -> (InMemoryFileRepository, Id, Session) {
+        let (file_id, session) = (file.id, Session::new(file.owner_id));
+        (
+            InMemoryFileRepository::with_files(vec![file]),
+            file_id,
+            session,
+        )
+    }
+
+    fn change_kinds(repo: &InMemoryFileRepository) -> Vec<ChangeKind> {
+        repo.changes.borrow().iter().map(|(_, c)| c.kind).collect()
+    }
+
+    #[tokio::test]
+    async fn trashes_and_restores_synced_file() {
+        // given
+        let now = OffsetDateTime::now_utc();
+        let (repo, file_id, session) = single_file(synced_file(Id::new(), now, now));
+
+        // when
+        trash_file_internal(&repo, &session, &file_id, now)
+            .await
+            .unwrap();
+
+        // then
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.state, FileState::Trashed);
+        assert_eq!(file.trashed_at, Some(now));
+        let trash = repo.find_trashed_files(&session.user_id()).await.unwrap();
+        assert_eq!(trash.len(), 1);
+
+        // when
+        restore_file_internal(&repo, &session, &file_id)
+            .await
+            .unwrap();
+
+        // then
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.state, FileState::Synced);
+        assert_eq!(file.trashed_at, None);
+        assert_eq!(
+            change_kinds(&repo),
+            vec![ChangeKind::Trashed, ChangeKind::Restored]
+        );
+    }
+
+    #[tokio::test]
+    async fn trash_and_restore_are_idempotent() {
+        let now = OffsetDateTime::now_utc();
+        let (repo, file_id, session) = single_file(synced_file(Id::new(), now, now));
+
+        restore_file_internal(&repo, &session, &file_id)
+            .await
+            .unwrap();
+        trash_file_internal(&repo, &session, &file_id, now)
+            .await
+            .unwrap();
+        trash_file_internal(&repo, &session, &file_id, now + Duration::hours(1))
+            .await
+            .unwrap();
+
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.trashed_at, Some(now));
+        assert_eq!(change_kinds(&repo), vec![ChangeKind::Trashed]);
+    }
+
+    #[tokio::test]
+    async fn only_synced_files_can_be_trashed() {
+        let now = OffsetDateTime::now_utc();
+        let mut file = synced_file(Id::new(), now, now);
+        file.state = FileState::SyncInProgress;
+        let (repo, file_id, session) = single_file(file);
+
+        let trashed = trash_file_internal(&repo, &session, &file_id, now).await;
+        let restored = restore_file_internal(&repo, &session, &file_id).await;
+
+        assert!(matches!(trashed, Err(Error::FileConflict)));
+        assert!(matches!(restored, Err(Error::FileConflict)));
+        assert!(repo.changes.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cannot_trash_file_of_another_user() {
+        let now = OffsetDateTime::now_utc();
+        let (repo, file_id, _) = single_file(synced_file(Id::new(), now, now));
+
+        let result = trash_file_internal(&repo, &Session::new(Id::new()), &file_id, now).await;
+
+        assert!(matches!(result, Err(Error::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn looks_up_content_in_own_library_only() {
+        let now = OffsetDateTime::now_utc();
+        let user_id = Id::new();
+        let with_hash = |owner_id, hash: &str, state| File {
+            content_hash: Some(hash.to_string()),
+            state,
+            ..synced_file(owner_id, now, now)
+        };
+        let synced = with_hash(user_id, "a", FileState::Synced);
+        let trashed = with_hash(user_id, "b", FileState::Trashed);
+        let repo = InMemoryFileRepository::with_files(vec![
+            synced.clone(),
+            trashed.clone(),
+            with_hash(user_id, "c", FileState::Failed),
+            with_hash(Id::new(), "d", FileState::Synced),
+        ]);
+        let request = ContentLookupRequest {
+            content_hashes: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        };
+
+        let response = lookup_content_internal(&repo, &user_id, request)
+            .await
+            .unwrap();
+
+        let found: Vec<(String, Id, bool)> = response
+            .files
+            .into_iter()
+            .map(|f| (f.content_hash, f.file_id.into(), f.trashed))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("a".to_string(), synced.id, false),
+                ("b".to_string(), trashed.id, true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_too_many_content_hashes() {
+        let repo = InMemoryFileRepository::new();
+        let request = ContentLookupRequest {
+            content_hashes: vec!["a".to_string(); MAX_CONTENT_LOOKUP + 1],
+        };
+
+        let error = lookup_content_internal(&repo, &Id::new(), request)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::TooManyContentHashes {
+                max: MAX_CONTENT_LOOKUP
+            }
+        ));
+        let response = axum::response::IntoResponse::into_response(error);
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("at most 1000"));
+    }
+
+    #[tokio::test]
+    async fn finds_trash_past_retention() {
+        let now = OffsetDateTime::now_utc();
+        let user_id = Id::new();
+        let old = synced_file(user_id, now, now);
+        let recent = synced_file(user_id, now, now);
+        let repo = InMemoryFileRepository::with_files(vec![old.clone(), recent.clone()]);
+        repo.trash(&old.id, now - Duration::days(40)).await.unwrap();
+        repo.trash(&recent.id, now - Duration::days(1))
+            .await
+            .unwrap();
+
+        let expired = repo
+            .find_expired_trash(now - Duration::days(30))
+            .await
+            .unwrap();
+
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, old.id);
+    }
+
+    #[tokio::test]
+    async fn deletes_file_with_all_its_objects() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let storage = crate::storage::LocalStorage::new(dir.path(), "http://localhost", b"key")
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc();
+        let file = synced_file(Id::new(), now, now);
+        let mut keys = vec![s3_original_key(&file)];
+        for variant in sdk::media::required_variants(file.media_type) {
+            keys.push(s3_thumbnail_key(&file, &variant.to_string()));
+        }
+        for key in &keys {
+            let path = dir.path().join("objects").join(key);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"ciphertext").unwrap();
+        }
+        let (repo, file_id, session) = single_file(file);
+
+        // when
+        delete_file_internal(&repo, &storage, &session, &file_id)
+            .await
+            .unwrap();
+
+        // then
+        for key in &keys {
+            assert_eq!(storage.head_object(key).await.unwrap(), None);
+        }
+        assert!(repo.find(&file_id).await.unwrap().is_none());
+        assert_eq!(change_kinds(&repo), vec![ChangeKind::Deleted]);
+    }
+
+    #[tokio::test]
+    async fn purge_spares_files_restored_after_listing() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let storage = crate::storage::LocalStorage::new(dir.path(), "http://localhost", b"key")
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc();
+        let user_id = Id::new();
+        let purged = synced_file(user_id, now, now);
+        let restored = synced_file(user_id, now, now);
+        for file in [&purged, &restored] {
+            let path = dir.path().join("objects").join(s3_original_key(file));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"ciphertext").unwrap();
+        }
+        let repo = InMemoryFileRepository::with_files(vec![purged.clone(), restored.clone()]);
+        for file in [&purged, &restored] {
+            repo.trash(&file.id, now - Duration::days(40))
+                .await
+                .unwrap();
+        }
+        let before = now - Duration::days(30);
+        let expired = repo.find_expired_trash(before).await.unwrap();
+        assert_eq!(expired.len(), 2);
+
+        // when
+        repo.restore(&restored.id).await.unwrap();
+        purge_trashed_files(&repo, &storage, &expired, before).await;
+
+        // then
+        assert!(repo.find(&purged.id).await.unwrap().is_none());
+        let purged_key = s3_original_key(&purged);
+        assert_eq!(storage.head_object(&purged_key).await.unwrap(), None);
+        let file = repo.find(&restored.id).await.unwrap().unwrap();
+        assert_eq!(file.state, FileState::Synced);
+        let restored_key = s3_original_key(&restored);
+        assert!(storage.head_object(&restored_key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn cannot_delete_file_while_uploading() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = crate::storage::LocalStorage::new(dir.path(), "http://localhost", b"key")
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc();
+        let mut file = synced_file(Id::new(), now, now);
+        file.state = FileState::SyncInProgress;
+        let (repo, file_id, session) = single_file(file);
+
+        let result = delete_file_internal(&repo, &storage, &session, &file_id).await;
+
+        assert!(matches!(result, Err(Error::FileConflict)));
+        assert!(repo.find(&file_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn seals_legacy_metadata_once() {
+        // given
+        let now = OffsetDateTime::now_utc();
+        let mut file = synced_file(Id::new(), now, now);
+        file.legacy_path = Some("/photos/2019".to_string());
+        file.legacy_name = Some("beach.jpg".to_string());
+        let (repo, file_id, session) = single_file(file);
+        let request = |sealed: &str| SealMetadataRequest {
+            encrypted_metadata: sealed.to_string(),
+        };
+
+        // when
+        seal_metadata_internal(&repo, &session, &file_id, request("c2VhbGVk"))
+            .await
+            .unwrap();
+
+        // then
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.encrypted_metadata.as_deref(), Some("c2VhbGVk"));
+        assert_eq!(file.legacy_path, None);
+        assert_eq!(file.legacy_name, None);
+        assert_eq!(change_kinds(&repo), vec![ChangeKind::Updated]);
+
+        // sealed metadata isn't replaced
+        let again = seal_metadata_internal(&repo, &session, &file_id, request("b3RoZXI=")).await;
+        assert!(matches!(again, Err(Error::FileConflict)));
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.encrypted_metadata.as_deref(), Some("c2VhbGVk"));
+    }
+
+    #[tokio::test]
+    async fn cannot_seal_empty_metadata_or_another_users_file() {
+        let now = OffsetDateTime::now_utc();
+        let (repo, file_id, session) = single_file(synced_file(Id::new(), now, now));
+        let request = |sealed: &str| SealMetadataRequest {
+            encrypted_metadata: sealed.to_string(),
+        };
+
+        let empty = seal_metadata_internal(&repo, &session, &file_id, request("")).await;
+        let other = Session::new(Id::new());
+        let foreign = seal_metadata_internal(&repo, &other, &file_id, request("c2VhbGVk")).await;
+
+        assert!(matches!(empty, Err(Error::FileUpload)));
+        assert!(matches!(foreign, Err(Error::Forbidden)));
+        assert!(repo.changes.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn backfills_content_hash_once() {
+        let now = OffsetDateTime::now_utc();
+        let (repo, file_id, session) = single_file(synced_file(Id::new(), now, now));
+        let request = |hash: &str| ContentHashRequest {
+            content_hash: hash.to_string(),
+        };
+
+        backfill_content_hash_internal(&repo, &session, &file_id, request("keyed"))
+            .await
+            .unwrap();
+        let again =
+            backfill_content_hash_internal(&repo, &session, &file_id, request("other")).await;
+
+        assert!(matches!(again, Err(Error::FileConflict)));
+        let file = repo.find(&file_id).await.unwrap().unwrap();
+        assert_eq!(file.content_hash.as_deref(), Some("keyed"));
+        assert_eq!(change_kinds(&repo), vec![ChangeKind::Updated]);
+        let other = Session::new(Id::new());
+        let foreign = backfill_content_hash_internal(&repo, &other, &file_id, request("x")).await;
+        assert!(matches!(foreign, Err(Error::Forbidden)));
+    }
 }
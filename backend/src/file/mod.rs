@@ -1,22 +1,28 @@
 mod handlers;
+pub(crate) mod pagination;
 pub(crate) mod repository;
 mod routes;
 
+pub(crate) use handlers::purge_expired_trash;
 pub(crate) use routes::routes;
 use time::OffsetDateTime;
 
+use sdk::dtos::file::FileMetadata;
 use sdk::media::MediaType;
+use sdk::segment::SegmentLayout;
 
 use crate::entity::sea_orm_active_enums::FileState as EntityFileState;
 use crate::entity::sea_orm_active_enums::MediaType as EntityMediaType;
 use crate::ulid::Id;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum FileState {
     New,
     SyncInProgress,
     Synced,
     Failed,
+    /// Soft-deleted; restorable until the retention period purges it.
+    Trashed,
 }
 
 impl From<EntityFileState> for FileState {
@@ -26,6 +32,7 @@ impl From<EntityFileState> for FileState {
             EntityFileState::SyncInProgress => FileState::SyncInProgress,
             EntityFileState::Synced => FileState::Synced,
             EntityFileState::Failed => FileState::Failed,
+            EntityFileState::Trashed => FileState::Trashed,
         }
     }
 }
@@ -37,6 +44,7 @@ impl From<FileState> for EntityFileState {
             FileState::SyncInProgress => EntityFileState::SyncInProgress,
             FileState::Synced => EntityFileState::Synced,
             FileState::Failed => EntityFileState::Failed,
+            FileState::Trashed => EntityFileState::Trashed,
         }
     }
 }
@@ -62,13 +70,20 @@ impl From<MediaType> for EntityMediaType {
 #[derive(Debug, Clone)]
 pub(crate) struct File {
     pub id: Id,
-    pub path: String,
-    pub name: String,
+    /// Client-sealed path, name and other private attributes; `None` for
+    /// files uploaded before metadata was encrypted.
+    pub encrypted_metadata: Option<String>,
+    /// Plaintext path and name, kept only for files without sealed metadata
+    /// until their owner's client seals them.
+    pub legacy_path: Option<String>,
+    pub legacy_name: Option<String>,
     pub state: FileState,
     pub created_at: OffsetDateTime,
     #[allow(dead_code)]
     pub added_at: OffsetDateTime,
-    pub sha256: String,
+    /// Keyed hash of the plaintext; `None` for files uploaded before hashes
+    /// were keyed.
+    pub content_hash: Option<String>,
     pub owner_id: Id,
     pub uploader_id: Id,
     pub enc_key: String,
@@ -81,28 +96,36 @@ pub(crate) struct File {
     pub plaintext_size: u64,
     pub nonce_salt: u32,
     pub enc_scheme: u8,
+    pub trashed_at: Option<OffsetDateTime>,
+    /// Bytes in storage (original plus thumbnails), known once synced.
+    pub stored_size: Option<u64>,
+}
+
+impl File {
+    /// Size of the encrypted original in storage, derived from its segment
+    /// layout; `None` if the declared layout is invalid.
+    pub fn ciphertext_size(&self) -> Option<u64> {
+        SegmentLayout::new(self.segment_size, self.plaintext_size).map(|l| l.ciphertext_size())
+    }
 }
 
 impl sdk::crypto::CryptoFileDesc for File {
     fn id(&self) -> ulid::Ulid {
         self.id.into()
     }
-
-    fn sha256(&self) -> &str {
-        &self.sha256
-    }
 }
 
 impl From<crate::entity::files::Model> for File {
     fn from(m: crate::entity::files::Model) -> Self {
         File {
             id: Id::from(m.id),
-            path: m.path,
-            name: m.name,
+            encrypted_metadata: m.encrypted_metadata,
+            legacy_path: m.path,
+            legacy_name: m.name,
             state: m.state.into(),
             created_at: m.created_at,
             added_at: m.added_at,
-            sha256: m.sha256,
+            content_hash: m.content_hash,
             owner_id: Id::from(m.owner_id),
             uploader_id: Id::from(m.uploader_id),
             enc_key: m.enc_key,
@@ -115,6 +138,31 @@ impl From<crate::entity::files::Model> for File {
             plaintext_size: m.plaintext_size as u64,
             nonce_salt: m.nonce_salt as u32,
             enc_scheme: m.enc_scheme as u8,
+            trashed_at: m.trashed_at,
+            stored_size: m.stored_size.map(|s| s as u64),
+        }
+    }
+}
+
+impl From<File> for FileMetadata {
+    fn from(file: File) -> Self {
+        FileMetadata {
+            encrypted_metadata: file.encrypted_metadata,
+            legacy_path: file.legacy_path,
+            legacy_name: file.legacy_name,
+            id: file.id.into(),
+            date: file.created_at,
+            content_hash: file.content_hash,
+            key: file.enc_key,
+            media_type: file.media_type,
+            content_type: file.content_type,
+            width: file.width,
+            height: file.height,
+            duration_ms: file.duration_ms,
+            segment_size: file.segment_size,
+            plaintext_size: file.plaintext_size,
+            nonce_salt: file.nonce_salt,
+            enc_scheme: file.enc_scheme,
         }
     }
 }
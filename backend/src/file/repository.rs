@@ -1,5 +1,8 @@
 use sea_orm::sea_query::Expr;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, ColumnTrait, EntityTrait, NotSet, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
+};
 use time::OffsetDateTime;
 use tracing::error;
 
@@ -8,20 +11,50 @@ use crate::entity::files;
 use crate::entity::prelude::Files;
 use crate::entity::sea_orm_active_enums::FileState as EntityFileState;
 use crate::error::Result;
+use crate::sync::ChangeKind;
+use crate::sync::repository::record_change;
 use crate::ulid::Id;
 
+use super::pagination::{FileListQuery, FileOrder, SortDirection};
 use super::{File, FileState};
 
+/// Every mutation is recorded in the owner's change feed (see
+/// [`crate::sync`]) together with the change itself.
 pub(crate) trait FileRepository {
-    async fn exists(&self, id: &Id) -> Result<bool>;
     async fn find(&self, id: &Id) -> Result<Option<File>>;
-    async fn find_synced_files(
+    /// One page of the user's synced files, in the order the query asks for.
+    async fn find_synced_files(&self, user_id: &Id, query: &FileListQuery) -> Result<Vec<File>>;
+    /// The user's synced or trashed files whose content hash is one of
+    /// `content_hashes`.
+    async fn find_by_content_hashes(
         &self,
         user_id: &Id,
-        from: Option<OffsetDateTime>,
+        content_hashes: &[String],
     ) -> Result<Vec<File>>;
     async fn save(&mut self, file: &File) -> Result<()>;
     async fn update_state(&self, file_id: &Id, state: FileState) -> Result<()>;
+    /// Mark an uploaded file synced, along with what it occupies in storage.
+    async fn mark_synced(&self, file_id: &Id, stored_size: u64) -> Result<()>;
+    /// The user's trashed files, most recently trashed first.
+    async fn find_trashed_files(&self, user_id: &Id) -> Result<Vec<File>>;
+    /// Trashed files of any user that were trashed before `before`.
+    async fn find_expired_trash(&self, before: OffsetDateTime) -> Result<Vec<File>>;
+    /// Move a synced file to the trash.
+    async fn trash(&self, file_id: &Id, at: OffsetDateTime) -> Result<()>;
+    /// Bring a trashed file back to synced.
+    async fn restore(&self, file_id: &Id) -> Result<()>;
+    /// Remove the row for good. Stored objects must be deleted beforehand.
+    async fn delete(&self, file_id: &Id) -> Result<()>;
+    /// Remove the row of a file that is still in the trash since before
+    /// `before`, returning it if it was. Its stored objects are deleted
+    /// afterwards, so a file restored in the meantime keeps them.
+    async fn delete_expired(&self, file_id: &Id, before: OffsetDateTime) -> Result<Option<File>>;
+    /// Store sealed metadata for a file that has none yet, forgetting its
+    /// plaintext path and name. Returns whether the file had none.
+    async fn seal_metadata(&self, file_id: &Id, encrypted_metadata: &str) -> Result<bool>;
+    /// Store the keyed content hash of a file that has none yet, forgetting
+    /// its plaintext SHA-256. Returns whether the file had none.
+    async fn backfill_content_hash(&self, file_id: &Id, content_hash: &str) -> Result<bool>;
 }
 
 pub(crate) struct DbFileRepository {
@@ -29,17 +62,6 @@ pub(crate) struct DbFileRepository {
 }
 
 impl FileRepository for DbFileRepository {
-    async fn exists(&self, id: &Id) -> Result<bool> {
-        let result = Files::find_by_id(uuid::Uuid::from(*id))
-            .one(&self.db)
-            .await
-            .map_err(|e| {
-                error!(error = %e, "Could not check if file exists");
-                crate::error::Error::Database
-            })?;
-        Ok(result.is_some())
-    }
-
     async fn find(&self, id: &Id) -> Result<Option<File>> {
         let file = Files::find_by_id(uuid::Uuid::from(*id))
             .one(&self.db)
@@ -53,20 +75,28 @@ impl FileRepository for DbFileRepository {
         Ok(file)
     }
 
-    async fn find_synced_files(
-        &self,
-        user_id: &Id,
-        from: Option<OffsetDateTime>,
-    ) -> Result<Vec<File>> {
-        let mut query = Files::find()
+    async fn find_synced_files(&self, user_id: &Id, query: &FileListQuery) -> Result<Vec<File>> {
+        let mut select = Files::find()
             .filter(files::Column::OwnerId.eq(uuid::Uuid::from(*user_id)))
             .filter(files::Column::State.eq(EntityFileState::Synced));
 
-        if let Some(from) = from {
-            query = query.filter(files::Column::AddedAt.gte(from));
+        if let Some(from) = query.from {
+            select = select.filter(files::Column::AddedAt.gte(from));
+        }
+
+        let mut cursor = match query.order {
+            FileOrder::AddedAt => select.cursor_by((files::Column::AddedAt, files::Column::Id)),
+            FileOrder::CreatedAt => select.cursor_by((files::Column::CreatedAt, files::Column::Id)),
+        };
+        if query.direction == SortDirection::Desc {
+            cursor.desc();
+        }
+        if let Some((timestamp, id)) = query.after {
+            cursor.after((timestamp, uuid::Uuid::from(id)));
         }
 
-        let files = query
+        let files = cursor
+            .first(query.limit)
             .all(&self.db)
             .await
             .map_err(|e| {
@@ -80,16 +110,40 @@ impl FileRepository for DbFileRepository {
         Ok(files)
     }
 
+    async fn find_by_content_hashes(
+        &self,
+        user_id: &Id,
+        content_hashes: &[String],
+    ) -> Result<Vec<File>> {
+        let files = Files::find()
+            .filter(files::Column::OwnerId.eq(uuid::Uuid::from(*user_id)))
+            .filter(files::Column::ContentHash.is_in(content_hashes.iter().cloned()))
+            .filter(files::Column::State.is_in([EntityFileState::Synced, EntityFileState::Trashed]))
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not find files by content hash");
+                crate::error::Error::Database
+            })?
+            .into_iter()
+            .map(File::from)
+            .collect();
+
+        Ok(files)
+    }
+
     async fn save(&mut self, file: &File) -> Result<()> {
         let entity_state: EntityFileState = file.state.clone().into();
-        files::ActiveModel {
+        let model = files::ActiveModel {
             id: Set(uuid::Uuid::from(file.id)),
-            path: Set(file.path.clone()),
-            name: Set(file.name.clone()),
+            path: Set(file.legacy_path.clone()),
+            name: Set(file.legacy_name.clone()),
+            encrypted_metadata: Set(file.encrypted_metadata.clone()),
             state: Set(entity_state),
             created_at: Set(file.created_at),
             added_at: Set(file.added_at),
-            sha256: Set(file.sha256.clone()),
+            sha256: NotSet,
+            content_hash: Set(file.content_hash.clone()),
             owner_id: Set(uuid::Uuid::from(file.owner_id)),
             uploader_id: Set(uuid::Uuid::from(file.uploader_id)),
             enc_key: Set(file.enc_key.clone()),
@@ -102,23 +156,260 @@ impl FileRepository for DbFileRepository {
             plaintext_size: Set(file.plaintext_size as i64),
             nonce_salt: Set(file.nonce_salt as i64),
             enc_scheme: Set(file.enc_scheme as i16),
-        }
-        .insert(&self.db)
-        .await
-        .map_err(|e| {
-            error!(error = %e, "Could not save file");
-            crate::error::Error::Database
-        })?;
+            trashed_at: Set(file.trashed_at),
+            stored_size: Set(file.stored_size.map(|s| s as i64)),
+        };
+        let (file_id, owner_id) = (file.id, file.owner_id);
+
+        self.db
+            .transaction::<_, (), sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    model.insert(txn).await?;
+                    record_change(txn, &owner_id, &file_id, ChangeKind::Created).await?;
+                    Ok(())
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not save file");
+                crate::error::Error::Database
+            })?;
 
         Ok(())
     }
 
     async fn update_state(&self, file_id: &Id, state: FileState) -> Result<()> {
+        let kind = change_kind_for(&state);
+        self.transition(file_id, None, state, None, None, kind)
+            .await
+    }
+
+    async fn mark_synced(&self, file_id: &Id, stored_size: u64) -> Result<()> {
+        self.transition(
+            file_id,
+            None,
+            FileState::Synced,
+            None,
+            Some(stored_size),
+            ChangeKind::Synced,
+        )
+        .await
+    }
+
+    async fn find_trashed_files(&self, user_id: &Id) -> Result<Vec<File>> {
+        let files = Files::find()
+            .filter(files::Column::OwnerId.eq(uuid::Uuid::from(*user_id)))
+            .filter(files::Column::State.eq(EntityFileState::Trashed))
+            .order_by_desc(files::Column::TrashedAt)
+            .order_by_desc(files::Column::Id)
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get trashed files");
+                crate::error::Error::Database
+            })?
+            .into_iter()
+            .map(File::from)
+            .collect();
+
+        Ok(files)
+    }
+
+    async fn find_expired_trash(&self, before: OffsetDateTime) -> Result<Vec<File>> {
+        let files = Files::find()
+            .filter(files::Column::State.eq(EntityFileState::Trashed))
+            .filter(files::Column::TrashedAt.lt(before))
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get expired trash");
+                crate::error::Error::Database
+            })?
+            .into_iter()
+            .map(File::from)
+            .collect();
+
+        Ok(files)
+    }
+
+    async fn trash(&self, file_id: &Id, at: OffsetDateTime) -> Result<()> {
+        self.transition(
+            file_id,
+            Some(FileState::Synced),
+            FileState::Trashed,
+            Some(at),
+            None,
+            ChangeKind::Trashed,
+        )
+        .await
+    }
+
+    async fn restore(&self, file_id: &Id) -> Result<()> {
+        self.transition(
+            file_id,
+            Some(FileState::Trashed),
+            FileState::Synced,
+            None,
+            None,
+            ChangeKind::Restored,
+        )
+        .await
+    }
+
+    async fn delete(&self, file_id: &Id) -> Result<()> {
+        let file_id = *file_id;
+
+        self.db
+            .transaction::<_, (), sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let deleted = Files::delete_many()
+                        .filter(files::Column::Id.eq(uuid::Uuid::from(file_id)))
+                        .exec_with_returning(txn)
+                        .await?;
+                    for file in deleted {
+                        let owner_id = Id::from(file.owner_id);
+                        record_change(txn, &owner_id, &file_id, ChangeKind::Deleted).await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(%file_id, error = %e, "Could not delete file");
+                crate::error::Error::Database
+            })?;
+
+        Ok(())
+    }
+
+    async fn delete_expired(&self, file_id: &Id, before: OffsetDateTime) -> Result<Option<File>> {
+        let file_id = *file_id;
+
+        self.db
+            .transaction::<_, Option<File>, sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let deleted = Files::delete_many()
+                        .filter(files::Column::Id.eq(uuid::Uuid::from(file_id)))
+                        .filter(files::Column::State.eq(EntityFileState::Trashed))
+                        .filter(files::Column::TrashedAt.lt(before))
+                        .exec_with_returning(txn)
+                        .await?;
+                    let Some(file) = deleted.into_iter().next() else {
+                        return Ok(None);
+                    };
+                    let owner_id = Id::from(file.owner_id);
+                    record_change(txn, &owner_id, &file_id, ChangeKind::Deleted).await?;
+                    Ok(Some(File::from(file)))
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(%file_id, error = %e, "Could not delete expired file");
+                crate::error::Error::Database
+            })
+    }
+
+    async fn seal_metadata(&self, file_id: &Id, encrypted_metadata: &str) -> Result<bool> {
+        let file_id = *file_id;
+        let encrypted_metadata = encrypted_metadata.to_string();
+
+        self.db
+            .transaction::<_, bool, sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let updated = Files::update_many()
+                        .col_expr(
+                            files::Column::EncryptedMetadata,
+                            Expr::value(encrypted_metadata),
+                        )
+                        .col_expr(files::Column::Path, Expr::value(Option::<String>::None))
+                        .col_expr(files::Column::Name, Expr::value(Option::<String>::None))
+                        .filter(files::Column::Id.eq(uuid::Uuid::from(file_id)))
+                        .filter(files::Column::EncryptedMetadata.is_null())
+                        .exec_with_returning(txn)
+                        .await?;
+                    for file in &updated {
+                        let owner_id = Id::from(file.owner_id);
+                        record_change(txn, &owner_id, &file_id, ChangeKind::Updated).await?;
+                    }
+                    Ok(!updated.is_empty())
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(%file_id, error = %e, "Could not seal file metadata");
+                crate::error::Error::Database
+            })
+    }
+
+    async fn backfill_content_hash(&self, file_id: &Id, content_hash: &str) -> Result<bool> {
+        let file_id = *file_id;
+        let content_hash = content_hash.to_string();
+
+        self.db
+            .transaction::<_, bool, sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let updated = Files::update_many()
+                        .col_expr(files::Column::ContentHash, Expr::value(content_hash))
+                        .col_expr(files::Column::Sha256, Expr::value(Option::<String>::None))
+                        .filter(files::Column::Id.eq(uuid::Uuid::from(file_id)))
+                        .filter(files::Column::ContentHash.is_null())
+                        .exec_with_returning(txn)
+                        .await?;
+                    for file in &updated {
+                        let owner_id = Id::from(file.owner_id);
+                        record_change(txn, &owner_id, &file_id, ChangeKind::Updated).await?;
+                    }
+                    Ok(!updated.is_empty())
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(%file_id, error = %e, "Could not backfill content hash");
+                crate::error::Error::Database
+            })
+    }
+}
+
+impl DbFileRepository {
+    /// Set the state (and `trashed_at`, cleared outside the trash) of a file,
+    /// optionally only if it's currently in state `from`, and record `kind`
+    /// in the owner's feed if the row changed. `stored_size` is only written
+    /// when given.
+    async fn transition(
+        &self,
+        file_id: &Id,
+        from: Option<FileState>,
+        state: FileState,
+        trashed_at: Option<OffsetDateTime>,
+        stored_size: Option<u64>,
+        kind: ChangeKind,
+    ) -> Result<()> {
         let entity_state: EntityFileState = state.into();
-        Files::update_many()
-            .col_expr(files::Column::State, Expr::value(entity_state))
-            .filter(files::Column::Id.eq(uuid::Uuid::from(*file_id)))
-            .exec(&self.db)
+        let from: Option<EntityFileState> = from.map(Into::into);
+        let file_id = *file_id;
+
+        self.db
+            .transaction::<_, (), sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let mut update = Files::update_many()
+                        .col_expr(files::Column::State, entity_state.as_enum())
+                        .col_expr(files::Column::TrashedAt, Expr::value(trashed_at))
+                        .filter(files::Column::Id.eq(uuid::Uuid::from(file_id)));
+                    if let Some(size) = stored_size {
+                        update =
+                            update.col_expr(files::Column::StoredSize, Expr::value(size as i64));
+                    }
+                    if let Some(from) = from {
+                        update = update.filter(files::Column::State.eq(from));
+                    }
+
+                    let updated = update.exec_with_returning(txn).await?;
+                    for file in updated {
+                        record_change(txn, &Id::from(file.owner_id), &file_id, kind).await?;
+                    }
+                    Ok(())
+                })
+            })
             .await
             .map_err(|e| {
                 error!(%file_id, error = %e, "Could not update file state");
@@ -129,35 +420,83 @@ impl FileRepository for DbFileRepository {
     }
 }
 
+/// Reaching Synced is what makes a file visible to other devices; every
+/// other transition is a plain update.
+fn change_kind_for(state: &FileState) -> ChangeKind {
+    match state {
+        FileState::Synced => ChangeKind::Synced,
+        _ => ChangeKind::Updated,
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::sync::FileChange;
     use std::cell::RefCell;
 
     pub struct InMemoryFileRepository {
         pub files: RefCell<Vec<File>>,
+        /// Change feed entries, tagged with the owner they belong to.
+        pub changes: RefCell<Vec<(Id, FileChange)>>,
     }
 
     impl InMemoryFileRepository {
         pub fn new() -> Self {
-            Self {
-                files: RefCell::new(Vec::new()),
-            }
+            Self::with_files(Vec::new())
         }
 
         pub fn with_files(files: Vec<File>) -> Self {
             Self {
                 files: RefCell::new(files),
+                changes: RefCell::new(Vec::new()),
             }
         }
-    }
 
-    impl FileRepository for InMemoryFileRepository {
-        async fn exists(&self, id: &Id) -> Result<bool> {
-            let exists = self.files.borrow().iter().any(|f| f.id == *id);
-            Ok(exists)
+        fn transition(
+            &self,
+            file_id: &Id,
+            from: Option<FileState>,
+            state: FileState,
+            trashed_at: Option<OffsetDateTime>,
+            stored_size: Option<u64>,
+            kind: ChangeKind,
+        ) {
+            let owner_id = self
+                .files
+                .borrow_mut()
+                .iter_mut()
+                .find(|f| f.id == *file_id)
+                .filter(|f| from.as_ref().is_none_or(|from| *from == f.state))
+                .map(|file| {
+                    file.state = state;
+                    file.trashed_at = trashed_at;
+                    if stored_size.is_some() {
+                        file.stored_size = stored_size;
+                    }
+                    file.owner_id
+                });
+            if let Some(owner_id) = owner_id {
+                self.record_change(owner_id, *file_id, kind);
+            }
         }
 
+        fn record_change(&self, owner_id: Id, file_id: Id, kind: ChangeKind) {
+            let mut changes = self.changes.borrow_mut();
+            let seq = changes.iter().filter(|(id, _)| *id == owner_id).count() as i64 + 1;
+            changes.push((
+                owner_id,
+                FileChange {
+                    seq,
+                    file_id,
+                    kind,
+                    changed_at: OffsetDateTime::now_utc(),
+                },
+            ));
+        }
+    }
+
+    impl FileRepository for InMemoryFileRepository {
         async fn find(&self, id: &Id) -> Result<Option<File>> {
             Ok(self.files.borrow().iter().find(|f| f.id == *id).cloned())
         }
@@ -165,34 +504,190 @@ pub mod tests {
         async fn find_synced_files(
             &self,
             user_id: &Id,
-            from: Option<OffsetDateTime>,
+            query: &FileListQuery,
         ) -> Result<Vec<File>> {
-            Ok(self
+            let sort_key = |f: &File| match query.order {
+                FileOrder::AddedAt => (f.added_at, f.id.to_string()),
+                FileOrder::CreatedAt => (f.created_at, f.id.to_string()),
+            };
+            let after = query.after.map(|(ts, id)| (ts, id.to_string()));
+
+            let mut files: Vec<File> = self
                 .files
                 .borrow()
                 .iter()
                 .filter(|f| f.owner_id == *user_id)
                 .filter(|f| matches!(f.state, FileState::Synced))
-                .filter(|f| from.is_none() || f.added_at >= from.unwrap())
+                .filter(|f| query.from.is_none() || f.added_at >= query.from.unwrap())
+                .filter(|f| match (&after, query.direction) {
+                    (None, _) => true,
+                    (Some(after), SortDirection::Asc) => sort_key(f) > *after,
+                    (Some(after), SortDirection::Desc) => sort_key(f) < *after,
+                })
+                .cloned()
+                .collect();
+
+            files.sort_by_key(sort_key);
+            if query.direction == SortDirection::Desc {
+                files.reverse();
+            }
+            files.truncate(query.limit as usize);
+            Ok(files)
+        }
+
+        async fn find_by_content_hashes(
+            &self,
+            user_id: &Id,
+            content_hashes: &[String],
+        ) -> Result<Vec<File>> {
+            Ok(self
+                .files
+                .borrow()
+                .iter()
+                .filter(|f| f.owner_id == *user_id)
+                .filter(|f| matches!(f.state, FileState::Synced | FileState::Trashed))
+                .filter(|f| {
+                    f.content_hash
+                        .as_ref()
+                        .is_some_and(|h| content_hashes.contains(h))
+                })
                 .cloned()
                 .collect())
         }
 
         async fn save(&mut self, file: &File) -> Result<()> {
             self.files.borrow_mut().push(file.clone());
+            self.record_change(file.owner_id, file.id, ChangeKind::Created);
             Ok(())
         }
 
         async fn update_state(&self, file_id: &Id, state: FileState) -> Result<()> {
-            if let Some(file) = self
+            let kind = change_kind_for(&state);
+            self.transition(file_id, None, state, None, None, kind);
+            Ok(())
+        }
+
+        async fn mark_synced(&self, file_id: &Id, stored_size: u64) -> Result<()> {
+            self.transition(
+                file_id,
+                None,
+                FileState::Synced,
+                None,
+                Some(stored_size),
+                ChangeKind::Synced,
+            );
+            Ok(())
+        }
+
+        async fn find_trashed_files(&self, user_id: &Id) -> Result<Vec<File>> {
+            let mut files: Vec<File> = self
+                .files
+                .borrow()
+                .iter()
+                .filter(|f| f.owner_id == *user_id && matches!(f.state, FileState::Trashed))
+                .cloned()
+                .collect();
+            files.sort_by_key(|f| std::cmp::Reverse((f.trashed_at, f.id.to_string())));
+            Ok(files)
+        }
+
+        async fn find_expired_trash(&self, before: OffsetDateTime) -> Result<Vec<File>> {
+            let files = self
+                .files
+                .borrow()
+                .iter()
+                .filter(|f| matches!(f.state, FileState::Trashed))
+                .filter(|f| f.trashed_at.is_some_and(|at| at < before))
+                .cloned()
+                .collect();
+            Ok(files)
+        }
+
+        async fn trash(&self, file_id: &Id, at: OffsetDateTime) -> Result<()> {
+            self.transition(
+                file_id,
+                Some(FileState::Synced),
+                FileState::Trashed,
+                Some(at),
+                None,
+                ChangeKind::Trashed,
+            );
+            Ok(())
+        }
+
+        async fn restore(&self, file_id: &Id) -> Result<()> {
+            self.transition(
+                file_id,
+                Some(FileState::Trashed),
+                FileState::Synced,
+                None,
+                None,
+                ChangeKind::Restored,
+            );
+            Ok(())
+        }
+
+        async fn delete(&self, file_id: &Id) -> Result<()> {
+            let mut files = self.files.borrow_mut();
+            if let Some(index) = files.iter().position(|f| f.id == *file_id) {
+                let file = files.remove(index);
+                drop(files);
+                self.record_change(file.owner_id, file.id, ChangeKind::Deleted);
+            }
+            Ok(())
+        }
+
+        async fn delete_expired(
+            &self,
+            file_id: &Id,
+            before: OffsetDateTime,
+        ) -> Result<Option<File>> {
+            let mut files = self.files.borrow_mut();
+            let Some(index) = files.iter().position(|f| {
+                f.id == *file_id
+                    && f.state == FileState::Trashed
+                    && f.trashed_at.is_some_and(|at| at < before)
+            }) else {
+                return Ok(None);
+            };
+            let file = files.remove(index);
+            drop(files);
+            self.record_change(file.owner_id, file.id, ChangeKind::Deleted);
+            Ok(Some(file))
+        }
+
+        async fn seal_metadata(&self, file_id: &Id, encrypted_metadata: &str) -> Result<bool> {
+            let owner_id = self
                 .files
                 .borrow_mut()
                 .iter_mut()
-                .find(|f| f.id == *file_id)
-            {
-                file.state = state;
+                .find(|f| f.id == *file_id && f.encrypted_metadata.is_none())
+                .map(|file| {
+                    file.encrypted_metadata = Some(encrypted_metadata.to_string());
+                    file.legacy_path = None;
+                    file.legacy_name = None;
+                    file.owner_id
+                });
+            if let Some(owner_id) = owner_id {
+                self.record_change(owner_id, *file_id, ChangeKind::Updated);
             }
-            Ok(())
+            Ok(owner_id.is_some())
+        }
+
+        async fn backfill_content_hash(&self, file_id: &Id, content_hash: &str) -> Result<bool> {
+            let owner_id = self
+                .files
+                .borrow_mut()
+                .iter_mut()
+                .find(|f| f.id == *file_id && f.content_hash.is_none())
+                .map(|file| {
+                    file.content_hash = Some(content_hash.to_string());
+                    file.owner_id
+                });
+            if let Some(owner_id) = owner_id {
+                self.record_change(owner_id, *file_id, ChangeKind::Updated);
+            }
+            Ok(owner_id.is_some())
         }
     }
 }
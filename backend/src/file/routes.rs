@@ -1,4 +1,7 @@
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    routing::{delete, get, post, put},
+};
 
 use crate::AppState;
 
@@ -10,6 +13,16 @@ pub(crate) fn routes(app_state: AppState) -> Router {
             "/files/metadata",
             get(handlers::get_files_metadata).post(handlers::upload_files_metadata),
         )
+        .route("/files/lookup", post(handlers::lookup_content))
+        .route("/files/trash", get(handlers::get_trash))
+        .route("/files/{file_id}", delete(handlers::delete_file))
         .route("/files/{file_id}/data", get(handlers::download_file))
+        .route("/files/{file_id}/metadata", put(handlers::seal_metadata))
+        .route(
+            "/files/{file_id}/content-hash",
+            put(handlers::backfill_content_hash),
+        )
+        .route("/files/{file_id}/trash", post(handlers::trash_file))
+        .route("/files/{file_id}/restore", post(handlers::restore_file))
         .with_state(app_state)
 }
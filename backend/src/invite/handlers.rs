@@ -0,0 +1,100 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use sdk::dtos::invite::{CreateInviteRequest, CreatedInvite, InviteInfo};
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+
+use super::repository::{DbInviteRepository, InviteRepository, InviteRow, NewInvite};
+use crate::{
+    AppState,
+    error::{Error, Result},
+    session::Session,
+    ulid::Id,
+};
+
+/// Longest note kept; anything past it is cut off.
+const MAX_NOTE_CHARS: usize = 200;
+
+pub(super) async fn create_invite(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<CreateInviteRequest>,
+) -> Result<Json<CreatedInvite>> {
+    let code = super::generate_code();
+    let lifetime = request
+        .expires_in_secs
+        .map(|secs| i64::from(secs.get()))
+        .unwrap_or(state.config.auth.invite_ttl_secs);
+    let note: Option<String> = request
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty())
+        .map(|note| note.chars().take(MAX_NOTE_CHARS).collect());
+    // the column is signed
+    let max_uses = request
+        .max_uses
+        .map_or(1, |uses| i32::try_from(uses.get()).unwrap_or(i32::MAX));
+
+    let invite = repository(&state)
+        .create_invite(NewInvite {
+            code_hash: &super::hash_code(&code),
+            note: note.as_deref(),
+            max_uses,
+            expires_at: OffsetDateTime::now_utc() + Duration::seconds(lifetime),
+            created_by: session.user_id(),
+        })
+        .await?;
+
+    info!(invite_id = %invite.id, max_uses, "Created invite");
+    Ok(Json(CreatedInvite {
+        invite: invite_info(invite),
+        code,
+    }))
+}
+
+pub(super) async fn list_invites(State(state): State<AppState>) -> Result<Json<Vec<InviteInfo>>> {
+    let invites = repository(&state)
+        .list_invites()
+        .await?
+        .into_iter()
+        .map(invite_info)
+        .collect();
+
+    Ok(Json(invites))
+}
+
+/// Stop the invite from taking further registrations. Those it already
+/// took stay.
+pub(super) async fn revoke_invite(
+    State(state): State<AppState>,
+    Path(invite_id): Path<Id>,
+) -> Result<()> {
+    if !repository(&state).revoke_invite(&invite_id).await? {
+        error!(%invite_id, "Invite not found or already revoked");
+        return Err(Error::InviteNotFound);
+    }
+    info!(%invite_id, "Revoked invite");
+    Ok(())
+}
+
+fn invite_info(row: InviteRow) -> InviteInfo {
+    InviteInfo {
+        id: row.id.into(),
+        note: row.note,
+        max_uses: row.max_uses.max(0) as u32,
+        uses: row.uses.max(0) as u32,
+        expires_at: row.expires_at,
+        revoked_at: row.revoked_at,
+        created_by: row.created_by.map(Into::into),
+        created_at: row.created_at,
+    }
+}
+
+fn repository(state: &AppState) -> DbInviteRepository {
+    DbInviteRepository {
+        db: state.db.clone(),
+    }
+}
@@ -0,0 +1,65 @@
+//! Invite codes admins hand out so specific people can register while open
+//! registration is off. An invite takes a limited number of registrations
+//! before it expires; admins can revoke it early. Codes are shown once at
+//! creation and kept only as a digest.
+
+mod handlers;
+mod repository;
+mod routes;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+pub(crate) use repository::redeem_invite;
+#[cfg(test)]
+pub(crate) use repository::tests::{invite_uses, new_invite};
+pub(crate) use routes::admin_routes;
+
+/// Characters of a code, shown in dash-separated groups of four. 80 bits.
+const CODE_LEN: usize = 16;
+
+/// Lowercase base32, like backup codes: no digits 0 or 1 to mix up with o
+/// and l.
+const CODE_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+fn generate_code() -> String {
+    let mut bytes = [0u8; CODE_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: Vec<char> = bytes
+        .iter()
+        .map(|b| CODE_ALPHABET[(b % 32) as usize] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How a code is looked up. Dashes, spaces and case don't matter when it is
+/// typed back in.
+pub(crate) fn hash_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_hash_regardless_of_formatting() {
+        let code = generate_code();
+
+        assert_eq!(code.len(), CODE_LEN + CODE_LEN / 4 - 1);
+        assert_eq!(
+            hash_code(&code),
+            hash_code(&code.replace('-', " ").to_uppercase())
+        );
+        assert_ne!(hash_code(&code), hash_code(&generate_code()));
+    }
+}
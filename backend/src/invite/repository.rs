@@ -0,0 +1,208 @@
+use sea_orm::sea_query::{Expr, ExprTrait};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::database::DbPool;
+use crate::entity::{invites, prelude::Invites};
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+pub(crate) struct InviteRow {
+    pub id: Id,
+    pub note: Option<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: OffsetDateTime,
+    pub revoked_at: Option<OffsetDateTime>,
+    pub created_by: Option<Id>,
+    pub created_at: OffsetDateTime,
+}
+
+impl From<invites::Model> for InviteRow {
+    fn from(row: invites::Model) -> Self {
+        Self {
+            id: Id::from(row.id),
+            note: row.note,
+            max_uses: row.max_uses,
+            uses: row.uses,
+            expires_at: row.expires_at,
+            revoked_at: row.revoked_at,
+            created_by: row.created_by.map(Id::from),
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub(crate) struct NewInvite<'a> {
+    pub code_hash: &'a str,
+    pub note: Option<&'a str>,
+    pub max_uses: i32,
+    pub expires_at: OffsetDateTime,
+    pub created_by: Id,
+}
+
+pub(crate) trait InviteRepository {
+    async fn create_invite(&self, invite: NewInvite<'_>) -> Result<InviteRow>;
+    /// Every invite, newest first.
+    async fn list_invites(&self) -> Result<Vec<InviteRow>>;
+    /// Returns false if there is no such unrevoked invite.
+    async fn revoke_invite(&self, id: &Id) -> Result<bool>;
+}
+
+pub(crate) struct DbInviteRepository {
+    pub db: DbPool,
+}
+
+impl InviteRepository for DbInviteRepository {
+    async fn create_invite(&self, invite: NewInvite<'_>) -> Result<InviteRow> {
+        let row = invites::ActiveModel {
+            id: Set(uuid::Uuid::from(Id::new())),
+            code_hash: Set(invite.code_hash.to_string()),
+            note: Set(invite.note.map(str::to_string)),
+            max_uses: Set(invite.max_uses),
+            uses: Set(0),
+            expires_at: Set(invite.expires_at),
+            revoked_at: Set(None),
+            created_by: Set(Some(uuid::Uuid::from(invite.created_by))),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save invite");
+            Error::Database
+        })?;
+
+        Ok(row.into())
+    }
+
+    async fn list_invites(&self) -> Result<Vec<InviteRow>> {
+        let rows = Invites::find()
+            .order_by_desc(invites::Column::CreatedAt)
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list invites");
+                Error::Database
+            })?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn revoke_invite(&self, id: &Id) -> Result<bool> {
+        let result = Invites::update_many()
+            .col_expr(
+                invites::Column::RevokedAt,
+                Expr::value(OffsetDateTime::now_utc()),
+            )
+            .filter(invites::Column::Id.eq(uuid::Uuid::from(*id)))
+            .filter(invites::Column::RevokedAt.is_null())
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not revoke invite");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+}
+
+/// Use up one registration of the invite with this code digest. Returns
+/// false if it is unknown, used up, expired or revoked. Meant to run in the
+/// transaction creating the user, so a failed registration gives the use
+/// back.
+pub(crate) async fn redeem_invite<C: ConnectionTrait>(
+    db: &C,
+    code_hash: &str,
+) -> std::result::Result<bool, DbErr> {
+    let result = Invites::update_many()
+        .col_expr(
+            invites::Column::Uses,
+            Expr::col(invites::Column::Uses).add(1),
+        )
+        .filter(invites::Column::CodeHash.eq(code_hash))
+        .filter(invites::Column::RevokedAt.is_null())
+        .filter(invites::Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .filter(Expr::col(invites::Column::Uses).lt(Expr::col(invites::Column::MaxUses)))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected > 0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use time::Duration;
+
+    /// An invite for `max_uses` registrations; returns its id and code.
+    pub(crate) async fn new_invite(
+        db: &DbPool,
+        max_uses: i32,
+        expires_at: OffsetDateTime,
+    ) -> (Id, String) {
+        let code = super::super::generate_code();
+        let repo = DbInviteRepository { db: db.clone() };
+        let invite = repo
+            .create_invite(NewInvite {
+                code_hash: &super::super::hash_code(&code),
+                note: None,
+                max_uses,
+                expires_at,
+                created_by: crate::auth::new_user(db).await,
+            })
+            .await
+            .unwrap();
+        (invite.id, code)
+    }
+
+    pub(crate) async fn invite_uses(db: &DbPool, id: &Id) -> i32 {
+        Invites::find_by_id(uuid::Uuid::from(*id))
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .uses
+    }
+
+    fn in_a_day() -> OffsetDateTime {
+        OffsetDateTime::now_utc() + Duration::days(1)
+    }
+
+    #[tokio::test]
+    async fn invites_take_their_number_of_registrations() {
+        let Some(db) = test_db().await else { return };
+        let (id, code) = new_invite(&db, 2, in_a_day()).await;
+        let code_hash = super::super::hash_code(&code);
+
+        assert!(redeem_invite(&db, &code_hash).await.unwrap());
+        assert!(redeem_invite(&db, &code_hash).await.unwrap());
+        assert!(!redeem_invite(&db, &code_hash).await.unwrap());
+        assert_eq!(invite_uses(&db, &id).await, 2);
+    }
+
+    #[tokio::test]
+    async fn expired_revoked_and_unknown_invites_are_refused() {
+        let Some(db) = test_db().await else { return };
+        let repo = DbInviteRepository { db: db.clone() };
+        let (expired_id, expired) =
+            new_invite(&db, 1, OffsetDateTime::now_utc() - Duration::seconds(1)).await;
+        let (revoked_id, revoked) = new_invite(&db, 1, in_a_day()).await;
+        assert!(repo.revoke_invite(&revoked_id).await.unwrap());
+
+        for code in [expired, revoked, super::super::generate_code()] {
+            let code_hash = super::super::hash_code(&code);
+            assert!(!redeem_invite(&db, &code_hash).await.unwrap());
+        }
+        assert_eq!(invite_uses(&db, &expired_id).await, 0);
+        assert_eq!(invite_uses(&db, &revoked_id).await, 0);
+        // revoking twice finds nothing left to revoke
+        assert!(!repo.revoke_invite(&revoked_id).await.unwrap());
+    }
+}
@@ -0,0 +1,19 @@
+use axum::{
+    Router,
+    routing::{delete, get},
+};
+
+use crate::AppState;
+
+use super::handlers;
+
+/// Mounted under `/admin` by [`crate::admin::routes`].
+pub(crate) fn admin_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/invites",
+            get(handlers::list_invites).post(handlers::create_invite),
+        )
+        .route("/invites/{invite_id}", delete(handlers::revoke_invite))
+        .with_state(app_state)
+}
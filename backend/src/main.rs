@@ -1,4 +1,6 @@
-use aws_config::BehaviorVersion;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
 use axum::{Router, http::Request};
 use http::header::AUTHORIZATION;
 use tokio::net::TcpListener;
@@ -11,16 +13,25 @@ use tracing_subscriber::EnvFilter;
 use config::Config;
 use database::DbPool;
 use error::Result;
+use storage::Storage;
 
+mod admin;
+mod audit;
 mod auth;
 mod config;
 mod database;
 mod entity;
 mod error;
 mod file;
+mod invite;
 mod migration;
+mod oidc;
+mod passkey;
+mod quota;
+mod reconcile;
 mod session;
 mod storage;
+mod sync;
 mod ulid;
 mod upload;
 
@@ -28,7 +39,13 @@ mod upload;
 pub struct AppState {
     db: DbPool,
     config: Config,
-    s3_client: aws_sdk_s3::Client,
+    storage: Storage,
+    session_cache: Arc<auth::SessionCache>,
+    login_throttle: Arc<auth::LoginThrottle>,
+    oidc: Arc<oidc::OidcProviders>,
+    webauthn: Option<Arc<webauthn_rs::prelude::Webauthn>>,
+    /// Seals TOTP secrets at rest, when configured.
+    totp_key: Option<Arc<auth::TotpSecretKey>>,
 }
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
@@ -42,32 +59,62 @@ async fn main() -> Result<()> {
     let config = Config::load()?;
     let pool = database::init_db(&config.database).await?;
 
-    let aws_config = aws_config::defaults(BehaviorVersion::latest())
-        .region("auto")
-        .endpoint_url(&config.storage.url)
-        .load()
-        .await;
-    let s3_client = aws_sdk_s3::Client::new(&aws_config);
+    let storage = Storage::from_config(&config.storage).await?;
+
+    let session_cache = Arc::new(auth::SessionCache::new(
+        config.auth.session_cache_capacity,
+        config.auth.session_cache_ttl_secs,
+    ));
+
+    let login_throttle = Arc::new(auth::LoginThrottle::new(&config.login_throttle));
+
+    let oidc = Arc::new(oidc::OidcProviders::from_config(&config.oidc));
+
+    let webauthn = passkey::relying_party(&config.webauthn)?.map(Arc::new);
+
+    let totp_key = auth::TotpSecretKey::from_config(&config.auth)?.map(Arc::new);
 
     let state = AppState {
         db: pool,
         config,
-        s3_client,
+        storage,
+        session_cache,
+        login_throttle,
+        oidc,
+        webauthn,
+        totp_key,
     };
 
+    auth::seal_totp_secrets(&state).await?;
+
     let x_request_id = http::HeaderName::from_static(REQUEST_ID_HEADER);
 
     tokio::spawn(upload::cleanup_expired_uploads(state.clone()));
+    tokio::spawn(file::purge_expired_trash(state.clone()));
+    tokio::spawn(reconcile::reconcile_storage(state.clone()));
+    tokio::spawn(auth::purge_expired_sessions(state.clone()));
+    tokio::spawn(audit::purge_old_events(state.clone()));
 
-    let app = Router::new()
+    let mut app = Router::new()
         .merge(file::routes(state.clone()))
         .merge(upload::routes(state.clone()))
+        .merge(sync::routes(state.clone()))
+        .merge(quota::routes(state.clone()))
+        .nest("/admin", admin::routes(state.clone()))
         .layer(axum::middleware::from_fn(auth::middleware::require_auth))
         .layer(axum::middleware::from_fn_with_state(
             state.clone(),
             auth::middleware::session_resolver,
         ))
         .nest("/auth", auth::routes(state.clone()))
+        .nest("/auth/oidc", oidc::routes(state.clone()))
+        .nest("/auth/passkeys", passkey::routes(state.clone()));
+
+    if let Storage::Local(local) = &state.storage {
+        app = app.merge(storage::routes(local.clone()));
+    }
+
+    let app = app
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -94,9 +141,62 @@ async fn main() -> Result<()> {
 
     info!("Listening on localhost:3000");
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An [`AppState`] on the test database, with local storage in `dir` and
+    /// the defaults for any setting `overrides` leaves out.
+    pub(crate) async fn test_state(
+        db: DbPool,
+        dir: &std::path::Path,
+        overrides: &[(&str, &str)],
+    ) -> AppState {
+        let builder = ::config::Config::builder()
+            .set_override("database_url", "postgres://unused")
+            .unwrap()
+            .set_override("storage_backend", "local")
+            .unwrap()
+            .set_override("storage_local_path", dir.to_str().unwrap())
+            .unwrap()
+            .set_override("storage_public_url", "http://localhost:3000")
+            .unwrap()
+            .set_override("storage_signing_key", "test-signing-key")
+            .unwrap();
+        let config: Config = overrides
+            .iter()
+            .fold(builder, |builder, (key, value)| {
+                builder.set_override(*key, *value).unwrap()
+            })
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        AppState {
+            db,
+            storage: Storage::from_config(&config.storage).await.unwrap(),
+            session_cache: Arc::new(auth::SessionCache::new(
+                config.auth.session_cache_capacity,
+                config.auth.session_cache_ttl_secs,
+            )),
+            login_throttle: Arc::new(auth::LoginThrottle::new(&config.login_throttle)),
+            oidc: Arc::new(oidc::OidcProviders::from_config(&config.oidc)),
+            webauthn: passkey::relying_party(&config.webauthn)
+                .unwrap()
+                .map(Arc::new),
+            totp_key: None,
+            config,
+        }
+    }
+}
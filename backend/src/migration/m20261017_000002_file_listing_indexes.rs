@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // keyset pagination of a user's synced files, by upload time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_owner_state_added_at")
+                    .table(File::Table)
+                    .col(File::OwnerId)
+                    .col(File::State)
+                    .col(File::AddedAt)
+                    .col(File::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        // ... and by capture time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_owner_state_created_at")
+                    .table(File::Table)
+                    .col(File::OwnerId)
+                    .col(File::State)
+                    .col(File::CreatedAt)
+                    .col(File::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    Id,
+    State,
+    CreatedAt,
+    AddedAt,
+    OwnerId,
+}
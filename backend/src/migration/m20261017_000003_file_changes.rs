@@ -0,0 +1,155 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // last change sequence number handed out per user
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AppUser::Table)
+                    .add_column(
+                        ColumnDef::new(AppUser::ChangeSeq)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // file_change_kind enum
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(FileChangeKindEnum::Type)
+                    .values([
+                        FileChangeKindEnum::Created,
+                        FileChangeKindEnum::Synced,
+                        FileChangeKindEnum::Updated,
+                        FileChangeKindEnum::Deleted,
+                    ])
+                    .to_owned(),
+            )
+            .await?;
+
+        // file_changes table; no foreign key to files so tombstones outlive
+        // the rows they describe
+        manager
+            .create_table(
+                Table::create()
+                    .table(FileChange::Table)
+                    .col(ColumnDef::new(FileChange::UserId).uuid().not_null())
+                    .col(ColumnDef::new(FileChange::Seq).big_integer().not_null())
+                    .col(ColumnDef::new(FileChange::FileId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(FileChange::Kind)
+                            .custom(FileChangeKindEnum::Type)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(FileChange::ChangedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(Index::create().col(FileChange::UserId).col(FileChange::Seq))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(FileChange::Table, FileChange::UserId)
+                            .to(AppUser::Table, AppUser::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // files stored before the feed existed: one entry each, numbered per
+        // owner in upload order, so a full sync from 0 still sees them
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(FileChange::Table)
+                    .columns([
+                        FileChange::UserId,
+                        FileChange::Seq,
+                        FileChange::FileId,
+                        FileChange::Kind,
+                        FileChange::ChangedAt,
+                    ])
+                    .select_from(
+                        Query::select()
+                            .column(File::OwnerId)
+                            .expr(Expr::cust(
+                                "ROW_NUMBER() OVER (PARTITION BY owner_id ORDER BY added_at, id)",
+                            ))
+                            .column(File::Id)
+                            .expr(Expr::cust(
+                                "CASE WHEN state = 'synced' THEN 'synced'::file_change_kind \
+                                 ELSE 'created'::file_change_kind END",
+                            ))
+                            .column(File::AddedAt)
+                            .from(File::Table)
+                            .to_owned(),
+                    )
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(AppUser::Table)
+                    .value(
+                        AppUser::ChangeSeq,
+                        Expr::cust(
+                            "(SELECT count(*) FROM files WHERE files.owner_id = app_users.id)",
+                        ),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+    ChangeSeq,
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    Id,
+    OwnerId,
+    AddedAt,
+}
+
+#[derive(DeriveIden)]
+enum FileChangeKindEnum {
+    #[sea_orm(iden = "file_change_kind")]
+    Type,
+    Created,
+    Synced,
+    Updated,
+    Deleted,
+}
+
+#[derive(DeriveIden)]
+enum FileChange {
+    #[sea_orm(iden = "file_changes")]
+    Table,
+    UserId,
+    Seq,
+    FileId,
+    Kind,
+    ChangedAt,
+}
@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(FileStateEnum::Type)
+                    .add_value(FileStateEnum::Trashed),
+            )
+            .await?;
+
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(FileChangeKindEnum::Type)
+                    .add_value(FileChangeKindEnum::Trashed),
+            )
+            .await?;
+
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(FileChangeKindEnum::Type)
+                    .add_value(FileChangeKindEnum::Restored),
+            )
+            .await?;
+
+        // when the file was moved to the trash; drives the retention purge
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::TrashedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_trashed_at")
+                    .table(File::Table)
+                    .col(File::TrashedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileStateEnum {
+    #[sea_orm(iden = "file_state")]
+    Type,
+    Trashed,
+}
+
+#[derive(DeriveIden)]
+enum FileChangeKindEnum {
+    #[sea_orm(iden = "file_change_kind")]
+    Type,
+    Trashed,
+    Restored,
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    TrashedAt,
+}
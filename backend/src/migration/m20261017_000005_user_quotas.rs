@@ -0,0 +1,93 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // admin flag and per-user quota override (NULL = server default)
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AppUser::Table)
+                    .add_column(
+                        ColumnDef::new(AppUser::IsAdmin)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(AppUser::QuotaBytes).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        // the oldest account administers an existing instance
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(AppUser::Table)
+                    .value(AppUser::IsAdmin, true)
+                    .and_where(
+                        Expr::col(AppUser::Id).in_subquery(
+                            Query::select()
+                                .column(AppUser::Id)
+                                .from(AppUser::Table)
+                                .order_by(AppUser::CreatedAt, Order::Asc)
+                                .order_by(AppUser::Id, Order::Asc)
+                                .limit(1)
+                                .to_owned(),
+                        ),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // bytes in storage for the original plus thumbnails, set on completion
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::StoredSize).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        // files uploaded before this migration: the ciphertext size of the
+        // original as derived from its segment layout (16-byte tag per
+        // segment); thumbnails weren't measured
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(File::Table)
+                    .value(
+                        File::StoredSize,
+                        Expr::cust(
+                            "plaintext_size + (plaintext_size + segment_size - 1) / segment_size * 16",
+                        ),
+                    )
+                    .and_where(Expr::cust("state IN ('synced', 'trashed')"))
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+    CreatedAt,
+    IsAdmin,
+    QuotaBytes,
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    StoredSize,
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // small files are uploaded with a single PUT, without a multipart upload
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .modify_column(ColumnDef::new(UploadSession::UploadId).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum UploadSession {
+    #[sea_orm(iden = "upload_sessions")]
+    Table,
+    UploadId,
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // path and name move into the client-sealed metadata blob. The server
+        // can't seal for files uploaded before, so their plaintext columns
+        // stay (nullable) until their owner's client seals them; a later
+        // migration drops the columns once no file needs them
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::EncryptedMetadata).text())
+                    .modify_column(ColumnDef::new(File::Path).text().null())
+                    .modify_column(ColumnDef::new(File::Name).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    Path,
+    Name,
+    EncryptedMetadata,
+}
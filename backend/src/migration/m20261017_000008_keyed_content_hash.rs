@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // plaintext SHA-256 is replaced by a per-user keyed hash the server
+        // can't compute. Files uploaded before keep their SHA-256 (nullable)
+        // until their owner's client backfills the keyed hash; a later
+        // migration drops the column once no file needs it
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::ContentHash).text())
+                    .modify_column(ColumnDef::new(File::Sha256).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // duplicate lookups within a user's library
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_owner_content_hash")
+                    .table(File::Table)
+                    .col(File::OwnerId)
+                    .col(File::ContentHash)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    #[sea_orm(iden = "files")]
+    Table,
+    OwnerId,
+    Sha256,
+    ContentHash,
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // second copy of the private key, sealed under the recovery phrase
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserKey::Table)
+                    .add_column(ColumnDef::new(UserKey::RecoveryKey).text())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserKey {
+    #[sea_orm(iden = "user_keys")]
+    Table,
+    RecoveryKey,
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // access tokens expire and are renewed with a refresh token; tokens
+        // issued before expired on the spot, so their holders log in again
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthToken::Table)
+                    .add_column(
+                        ColumnDef::new(AuthToken::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .add_column(ColumnDef::new(AuthToken::RefreshToken).text().unique_key())
+                    .add_column(
+                        ColumnDef::new(AuthToken::RefreshExpiresAt).timestamp_with_time_zone(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // sessions of a user, for listing and revocation
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_tokens_user_id")
+                    .table(AuthToken::Table)
+                    .col(AuthToken::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthToken {
+    #[sea_orm(iden = "auth_tokens")]
+    Table,
+    UserId,
+    ExpiresAt,
+    RefreshToken,
+    RefreshExpiresAt,
+}
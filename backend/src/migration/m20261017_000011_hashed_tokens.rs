@@ -0,0 +1,94 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // tokens are kept as a SHA-256 digest (hex), looked up by the first
+        // 8 hex digits of it and then compared in full
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthToken::Table)
+                    .add_column(ColumnDef::new(AuthToken::TokenPrefix).text())
+                    .add_column(ColumnDef::new(AuthToken::TokenHash).text())
+                    .add_column(ColumnDef::new(AuthToken::RefreshTokenPrefix).text())
+                    .add_column(ColumnDef::new(AuthToken::RefreshTokenHash).text())
+                    .to_owned(),
+            )
+            .await?;
+
+        // digest the existing tokens in place, as the columns are required;
+        // they all expired in the previous migration and have no refresh
+        // token, so no session survives this one either
+        let digest = |column: &str| format!("encode(sha256(convert_to({column}, 'UTF8')), 'hex')");
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(AuthToken::Table)
+                    .value(AuthToken::TokenHash, Expr::cust(digest("token")))
+                    .value(
+                        AuthToken::TokenPrefix,
+                        Expr::cust(format!("left({}, 8)", digest("token"))),
+                    )
+                    .value(
+                        AuthToken::RefreshTokenHash,
+                        Expr::cust(digest("refresh_token")),
+                    )
+                    .value(
+                        AuthToken::RefreshTokenPrefix,
+                        Expr::cust(format!("left({}, 8)", digest("refresh_token"))),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthToken::Table)
+                    .modify_column(ColumnDef::new(AuthToken::TokenPrefix).text().not_null())
+                    .modify_column(ColumnDef::new(AuthToken::TokenHash).text().not_null())
+                    .drop_column(AuthToken::Token)
+                    .drop_column(AuthToken::RefreshToken)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_tokens_token_prefix")
+                    .table(AuthToken::Table)
+                    .col(AuthToken::TokenPrefix)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_tokens_refresh_token_prefix")
+                    .table(AuthToken::Table)
+                    .col(AuthToken::RefreshTokenPrefix)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthToken {
+    #[sea_orm(iden = "auth_tokens")]
+    Table,
+    Token,
+    TokenPrefix,
+    TokenHash,
+    RefreshToken,
+    RefreshTokenPrefix,
+    RefreshTokenHash,
+}
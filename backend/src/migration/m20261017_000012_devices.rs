@@ -0,0 +1,149 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // devices table: what each session signs in from
+        manager
+            .create_table(
+                Table::create()
+                    .table(Device::Table)
+                    .col(ColumnDef::new(Device::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(Device::UserId).uuid().not_null())
+                    .col(ColumnDef::new(Device::Name).text().not_null())
+                    .col(ColumnDef::new(Device::Platform).text().not_null())
+                    .col(
+                        ColumnDef::new(Device::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(Device::LastSeenAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(Device::LastSyncCursor).big_integer())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Device::Table, Device::UserId)
+                            .to(AppUser::Table, AppUser::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_devices_user_id")
+                    .table(Device::Table)
+                    .col(Device::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        // every token belongs to a device; revoking the device ends them
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthToken::Table)
+                    .add_column(ColumnDef::new(AuthToken::DeviceId).uuid())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_auth_tokens_device_id")
+                            .from_tbl(AuthToken::Table)
+                            .from_col(AuthToken::DeviceId)
+                            .to_tbl(Device::Table)
+                            .to_col(Device::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // sessions from before devices each get one of their own, reusing
+        // the token's id
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(Device::Table)
+                    .columns([
+                        Device::Id,
+                        Device::UserId,
+                        Device::Name,
+                        Device::Platform,
+                        Device::CreatedAt,
+                        Device::LastSeenAt,
+                    ])
+                    .select_from(
+                        Query::select()
+                            .column(AuthToken::Id)
+                            .column(AuthToken::UserId)
+                            .expr(Expr::val("Unknown device"))
+                            .expr(Expr::val("unknown"))
+                            .column(AuthToken::CreatedAt)
+                            .column(AuthToken::CreatedAt)
+                            .from(AuthToken::Table)
+                            .to_owned(),
+                    )
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(AuthToken::Table)
+                    .value(AuthToken::DeviceId, Expr::col(AuthToken::Id))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthToken::Table)
+                    .modify_column(ColumnDef::new(AuthToken::DeviceId).uuid().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Device {
+    #[sea_orm(iden = "devices")]
+    Table,
+    Id,
+    UserId,
+    Name,
+    Platform,
+    CreatedAt,
+    LastSeenAt,
+    LastSyncCursor,
+}
+
+#[derive(DeriveIden)]
+enum AuthToken {
+    #[sea_orm(iden = "auth_tokens")]
+    Table,
+    Id,
+    UserId,
+    CreatedAt,
+    DeviceId,
+}
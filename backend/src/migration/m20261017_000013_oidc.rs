@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(ProviderEnum::Type)
+                    .add_value(ProviderEnum::Oidc),
+            )
+            .await?;
+
+        // sign-ins started at an OpenID Connect issuer, until its callback
+        manager
+            .create_table(
+                Table::create()
+                    .table(OidcLogin::Table)
+                    .col(
+                        ColumnDef::new(OidcLogin::State)
+                            .text()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(OidcLogin::Provider).text().not_null())
+                    .col(ColumnDef::new(OidcLogin::Nonce).text().not_null())
+                    .col(ColumnDef::new(OidcLogin::CodeVerifier).text().not_null())
+                    .col(
+                        ColumnDef::new(OidcLogin::ClientRedirectUri)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(OidcLogin::LinkUserId).uuid())
+                    .col(ColumnDef::new(OidcLogin::DeviceId).uuid())
+                    .col(ColumnDef::new(OidcLogin::DeviceName).text())
+                    .col(ColumnDef::new(OidcLogin::Platform).text())
+                    .col(
+                        ColumnDef::new(OidcLogin::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OidcLogin::Table, OidcLogin::LinkUserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ProviderEnum {
+    #[sea_orm(iden = "provider")]
+    Type,
+    Oidc,
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum OidcLogin {
+    #[sea_orm(iden = "oidc_logins")]
+    Table,
+    State,
+    Provider,
+    Nonce,
+    CodeVerifier,
+    ClientRedirectUri,
+    LinkUserId,
+    DeviceId,
+    DeviceName,
+    Platform,
+    ExpiresAt,
+}
@@ -0,0 +1,138 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // a user's TOTP secret; pending until the first code confirms it
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserTotp::Table)
+                    .col(
+                        ColumnDef::new(UserTotp::UserId)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserTotp::Secret).text().not_null())
+                    .col(ColumnDef::new(UserTotp::EnabledAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(UserTotp::LastUsedStep).big_integer())
+                    .col(
+                        ColumnDef::new(UserTotp::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserTotp::Table, UserTotp::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // single-use codes for when the authenticator is lost, kept hashed
+        manager
+            .create_table(
+                Table::create()
+                    .table(TotpBackupCode::Table)
+                    .col(ColumnDef::new(TotpBackupCode::UserId).uuid().not_null())
+                    .col(ColumnDef::new(TotpBackupCode::CodeHash).text().not_null())
+                    .primary_key(
+                        Index::create()
+                            .col(TotpBackupCode::UserId)
+                            .col(TotpBackupCode::CodeHash),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(TotpBackupCode::Table, TotpBackupCode::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // password logins waiting for their second factor
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginChallenge::Table)
+                    .col(
+                        ColumnDef::new(LoginChallenge::ChallengeHash)
+                            .text()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LoginChallenge::UserId).uuid().not_null())
+                    .col(ColumnDef::new(LoginChallenge::DeviceId).uuid())
+                    .col(ColumnDef::new(LoginChallenge::DeviceName).text())
+                    .col(ColumnDef::new(LoginChallenge::Platform).text())
+                    .col(
+                        ColumnDef::new(LoginChallenge::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(LoginChallenge::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(LoginChallenge::Table, LoginChallenge::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum UserTotp {
+    #[sea_orm(iden = "user_totp")]
+    Table,
+    UserId,
+    Secret,
+    EnabledAt,
+    LastUsedStep,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum TotpBackupCode {
+    #[sea_orm(iden = "totp_backup_codes")]
+    Table,
+    UserId,
+    CodeHash,
+}
+
+#[derive(DeriveIden)]
+enum LoginChallenge {
+    #[sea_orm(iden = "login_challenges")]
+    Table,
+    ChallengeHash,
+    UserId,
+    DeviceId,
+    DeviceName,
+    Platform,
+    Attempts,
+    ExpiresAt,
+}
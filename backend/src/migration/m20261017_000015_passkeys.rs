@@ -0,0 +1,138 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name(ProviderEnum::Type)
+                    .add_value(ProviderEnum::Passkey),
+            )
+            .await?;
+
+        // a passkey account's credential; the account id is its credential id
+        manager
+            .create_table(
+                Table::create()
+                    .table(Passkey::Table)
+                    .col(ColumnDef::new(Passkey::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(Passkey::UserId).uuid().not_null())
+                    .col(ColumnDef::new(Passkey::Name).text().not_null())
+                    .col(ColumnDef::new(Passkey::Credential).text().not_null())
+                    .col(ColumnDef::new(Passkey::PrfSalt).text().not_null())
+                    .col(ColumnDef::new(Passkey::WrappedPrivateKey).text())
+                    .col(
+                        ColumnDef::new(Passkey::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(Passkey::LastUsedAt).timestamp_with_time_zone())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Passkey::Table, Passkey::Id)
+                            .to(UserAccount::Table, UserAccount::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Passkey::Table, Passkey::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_passkeys_user_id")
+                    .table(Passkey::Table)
+                    .col(Passkey::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        // registrations and sign-ins between their two round trips
+        manager
+            .create_table(
+                Table::create()
+                    .table(PasskeyCeremony::Table)
+                    .col(
+                        ColumnDef::new(PasskeyCeremony::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PasskeyCeremony::UserId).uuid().not_null())
+                    .col(ColumnDef::new(PasskeyCeremony::Kind).text().not_null())
+                    .col(ColumnDef::new(PasskeyCeremony::State).text().not_null())
+                    .col(
+                        ColumnDef::new(PasskeyCeremony::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PasskeyCeremony::Table, PasskeyCeremony::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ProviderEnum {
+    #[sea_orm(iden = "provider")]
+    Type,
+    Passkey,
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum UserAccount {
+    #[sea_orm(iden = "user_accounts")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Passkey {
+    #[sea_orm(iden = "passkeys")]
+    Table,
+    Id,
+    UserId,
+    Name,
+    Credential,
+    PrfSalt,
+    WrappedPrivateKey,
+    CreatedAt,
+    LastUsedAt,
+}
+
+#[derive(DeriveIden)]
+enum PasskeyCeremony {
+    #[sea_orm(iden = "passkey_ceremonies")]
+    Table,
+    Id,
+    UserId,
+    Kind,
+    State,
+    ExpiresAt,
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // security-relevant events; kept when the user they name is deleted
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditEvent::Table)
+                    .col(
+                        ColumnDef::new(AuditEvent::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AuditEvent::Kind).text().not_null())
+                    .col(ColumnDef::new(AuditEvent::UserId).uuid())
+                    .col(ColumnDef::new(AuditEvent::Username).text())
+                    .col(ColumnDef::new(AuditEvent::Ip).text())
+                    .col(
+                        ColumnDef::new(AuditEvent::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(AuditEvent::Table, AuditEvent::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_user_id")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::UserId)
+                    .col(AuditEvent::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_created_at")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum AuditEvent {
+    #[sea_orm(iden = "audit_events")]
+    Table,
+    Id,
+    Kind,
+    UserId,
+    Username,
+    Ip,
+    CreatedAt,
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // codes that let someone register while open registration is off;
+        // only their digest is kept
+        manager
+            .create_table(
+                Table::create()
+                    .table(Invite::Table)
+                    .col(ColumnDef::new(Invite::Id).uuid().not_null().primary_key())
+                    .col(
+                        ColumnDef::new(Invite::CodeHash)
+                            .text()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Invite::Note).text())
+                    .col(ColumnDef::new(Invite::MaxUses).integer().not_null())
+                    .col(ColumnDef::new(Invite::Uses).integer().not_null().default(0))
+                    .col(
+                        ColumnDef::new(Invite::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Invite::RevokedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(Invite::CreatedBy).uuid())
+                    .col(
+                        ColumnDef::new(Invite::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Invite::Table, Invite::CreatedBy)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Invite {
+    #[sea_orm(iden = "invites")]
+    Table,
+    Id,
+    CodeHash,
+    Note,
+    MaxUses,
+    Uses,
+    ExpiresAt,
+    RevokedAt,
+    CreatedBy,
+    CreatedAt,
+}
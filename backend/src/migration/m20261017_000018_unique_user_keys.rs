@@ -0,0 +1,146 @@
+use sea_orm_migration::prelude::*;
+
+/// Key pairs of a user other than the oldest one.
+const NOT_OLDEST: &str = "EXISTS (SELECT 1 FROM user_keys older \
+                          WHERE older.user_id = user_keys.user_id \
+                          AND (older.created_at, older.id) < (user_keys.created_at, user_keys.id))";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Concurrent key uploads could each store a key pair, and lookups
+        // returned whichever one Postgres found first, so clients may have
+        // wrapped file keys with any of them. The oldest stays; the others
+        // are set aside rather than deleted, to recover files from by hand.
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserKeyDuplicate::Table)
+                    .col(
+                        ColumnDef::new(UserKeyDuplicate::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserKeyDuplicate::UserId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(UserKeyDuplicate::PrivateKey)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserKeyDuplicate::PublicKey)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserKeyDuplicate::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UserKeyDuplicate::RecoveryKey).text())
+                    .col(
+                        ColumnDef::new(UserKeyDuplicate::MovedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserKeyDuplicate::Table, UserKeyDuplicate::UserId)
+                            .to(AppUser::Table, AppUser::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let columns = [
+            UserKey::Id,
+            UserKey::UserId,
+            UserKey::PrivateKey,
+            UserKey::PublicKey,
+            UserKey::CreatedAt,
+            UserKey::RecoveryKey,
+        ];
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(UserKeyDuplicate::Table)
+                    .columns([
+                        UserKeyDuplicate::Id,
+                        UserKeyDuplicate::UserId,
+                        UserKeyDuplicate::PrivateKey,
+                        UserKeyDuplicate::PublicKey,
+                        UserKeyDuplicate::CreatedAt,
+                        UserKeyDuplicate::RecoveryKey,
+                    ])
+                    .select_from(
+                        Query::select()
+                            .columns(columns)
+                            .from(UserKey::Table)
+                            .and_where(Expr::cust(NOT_OLDEST))
+                            .to_owned(),
+                    )
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table(UserKey::Table)
+                    .and_where(Expr::cust(NOT_OLDEST))
+                    .to_owned(),
+            )
+            .await?;
+
+        // one key pair per user
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_keys_user_id")
+                    .table(UserKey::Table)
+                    .col(UserKey::UserId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserKey {
+    #[sea_orm(iden = "user_keys")]
+    Table,
+    Id,
+    UserId,
+    PrivateKey,
+    PublicKey,
+    CreatedAt,
+    RecoveryKey,
+}
+
+#[derive(DeriveIden)]
+enum UserKeyDuplicate {
+    #[sea_orm(iden = "user_keys_duplicates")]
+    Table,
+    Id,
+    UserId,
+    PrivateKey,
+    PublicKey,
+    CreatedAt,
+    RecoveryKey,
+    MovedAt,
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
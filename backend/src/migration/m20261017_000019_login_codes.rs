@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // finished sign-ins handed to a client through its redirect URI, until
+        // the client redeems the code with its PKCE verifier
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginCode::Table)
+                    .col(
+                        ColumnDef::new(LoginCode::CodeHash)
+                            .text()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LoginCode::UserId).uuid().not_null())
+                    .col(ColumnDef::new(LoginCode::CodeChallenge).text().not_null())
+                    .col(ColumnDef::new(LoginCode::DeviceId).uuid())
+                    .col(ColumnDef::new(LoginCode::DeviceName).text())
+                    .col(ColumnDef::new(LoginCode::Platform).text())
+                    .col(
+                        ColumnDef::new(LoginCode::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(LoginCode::Table, LoginCode::UserId)
+                            .to(AppUser::Table, AppUser::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AppUser {
+    #[sea_orm(iden = "app_users")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum LoginCode {
+    #[sea_orm(iden = "login_codes")]
+    Table,
+    CodeHash,
+    UserId,
+    CodeChallenge,
+    DeviceId,
+    DeviceName,
+    Platform,
+    ExpiresAt,
+}
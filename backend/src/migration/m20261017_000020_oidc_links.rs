@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // sign-ins in flight were started without a client challenge; they
+        // last minutes, so they are dropped rather than carried over
+        manager
+            .exec_stmt(Query::delete().from_table(OidcLogin::Table).to_owned())
+            .await?;
+
+        // the client's own PKCE challenge, and for links the session that
+        // started it, so only that session can finish it
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(OidcLogin::Table)
+                    .add_column(
+                        ColumnDef::new(OidcLogin::ClientCodeChallenge)
+                            .text()
+                            .not_null(),
+                    )
+                    .add_column(ColumnDef::new(OidcLogin::LinkTokenId).uuid())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_oidc_logins_link_token_id")
+                            .from_tbl(OidcLogin::Table)
+                            .from_col(OidcLogin::LinkTokenId)
+                            .to_tbl(AuthToken::Table)
+                            .to_col(AuthToken::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // accounts an issuer vouched for, until the session that asked to
+        // link them redeems the code with its PKCE verifier
+        manager
+            .create_table(
+                Table::create()
+                    .table(OidcLink::Table)
+                    .col(
+                        ColumnDef::new(OidcLink::CodeHash)
+                            .text()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(OidcLink::TokenId).uuid().not_null())
+                    .col(ColumnDef::new(OidcLink::CodeChallenge).text().not_null())
+                    .col(ColumnDef::new(OidcLink::AccountId).text().not_null())
+                    .col(
+                        ColumnDef::new(OidcLink::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OidcLink::Table, OidcLink::TokenId)
+                            .to(AuthToken::Table, AuthToken::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthToken {
+    #[sea_orm(iden = "auth_tokens")]
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum OidcLogin {
+    #[sea_orm(iden = "oidc_logins")]
+    Table,
+    ClientCodeChallenge,
+    LinkTokenId,
+}
+
+#[derive(DeriveIden)]
+enum OidcLink {
+    #[sea_orm(iden = "oidc_links")]
+    Table,
+    CodeHash,
+    TokenId,
+    CodeChallenge,
+    AccountId,
+    ExpiresAt,
+}
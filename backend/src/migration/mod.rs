@@ -1,12 +1,52 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20240101_000001_initial_schema;
+mod m20261017_000002_file_listing_indexes;
+mod m20261017_000003_file_changes;
+mod m20261017_000004_file_trash;
+mod m20261017_000005_user_quotas;
+mod m20261017_000006_simple_uploads;
+mod m20261017_000007_encrypted_metadata;
+mod m20261017_000008_keyed_content_hash;
+mod m20261017_000009_recovery_keys;
+mod m20261017_000010_token_expiry;
+mod m20261017_000011_hashed_tokens;
+mod m20261017_000012_devices;
+mod m20261017_000013_oidc;
+mod m20261017_000014_totp;
+mod m20261017_000015_passkeys;
+mod m20261017_000016_audit_events;
+mod m20261017_000017_invites;
+mod m20261017_000018_unique_user_keys;
+mod m20261017_000019_login_codes;
+mod m20261017_000020_oidc_links;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20240101_000001_initial_schema::Migration)]
+        vec![
+            Box::new(m20240101_000001_initial_schema::Migration),
+            Box::new(m20261017_000002_file_listing_indexes::Migration),
+            Box::new(m20261017_000003_file_changes::Migration),
+            Box::new(m20261017_000004_file_trash::Migration),
+            Box::new(m20261017_000005_user_quotas::Migration),
+            Box::new(m20261017_000006_simple_uploads::Migration),
+            Box::new(m20261017_000007_encrypted_metadata::Migration),
+            Box::new(m20261017_000008_keyed_content_hash::Migration),
+            Box::new(m20261017_000009_recovery_keys::Migration),
+            Box::new(m20261017_000010_token_expiry::Migration),
+            Box::new(m20261017_000011_hashed_tokens::Migration),
+            Box::new(m20261017_000012_devices::Migration),
+            Box::new(m20261017_000013_oidc::Migration),
+            Box::new(m20261017_000014_totp::Migration),
+            Box::new(m20261017_000015_passkeys::Migration),
+            Box::new(m20261017_000016_audit_events::Migration),
+            Box::new(m20261017_000017_invites::Migration),
+            Box::new(m20261017_000018_unique_user_keys::Migration),
+            Box::new(m20261017_000019_login_codes::Migration),
+            Box::new(m20261017_000020_oidc_links::Migration),
+        ]
     }
 }
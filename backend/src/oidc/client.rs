@@ -0,0 +1,584 @@
+//! The relying-party side of OpenID Connect: discovery, the authorization
+//! code flow with PKCE, and validation of the returned ID token against the
+//! issuer's JWKS. Only RS256 is accepted, the one algorithm every OpenID
+//! provider must support.
+
+use std::collections::HashMap;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::signature::Verifier;
+use rsa::{BoxedUint, RsaPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::sync::{OnceCell, RwLock};
+use tracing::{error, warn};
+use url::Url;
+
+use crate::config::OidcProviderConfig;
+use crate::error::{Error, Result};
+
+/// Clock skew tolerated on `exp` and `iat`.
+const LEEWAY_SECS: i64 = 60;
+/// Least time between two JWKS fetches prompted by an unknown key id.
+const JWKS_REFRESH_SECS: i64 = 60;
+
+/// The parts of the discovery document the flow needs.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default, rename = "use")]
+    usage: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: HashMap<Option<String>, RsaPublicKey>,
+    fetched_at: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Audience::One(_) => 1,
+            Audience::Many(auds) => auds.len(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    #[serde(default)]
+    azp: Option<String>,
+    exp: i64,
+    #[serde(default)]
+    iat: Option<i64>,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+}
+
+/// Who the issuer says signed in.
+#[derive(Debug)]
+pub(crate) struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    /// Display name, falling back to the email address.
+    pub name: Option<String>,
+}
+
+/// Secrets of one sign-in attempt, kept until its callback.
+pub(crate) struct LoginSecrets {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl LoginSecrets {
+    pub fn generate() -> Self {
+        Self {
+            state: random_token(),
+            nonce: random_token(),
+            code_verifier: random_token(),
+        }
+    }
+
+    fn code_challenge(&self) -> String {
+        Base64UrlUnpadded::encode_string(&Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+/// 256 random bits, base64url; also valid as a PKCE verifier.
+fn random_token() -> String {
+    use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    Base64UrlUnpadded::encode_string(&bytes)
+}
+
+/// How long connecting to an issuer may take.
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// How long a whole request to an issuer may take, so a hanging issuer
+/// doesn't hold up sign-ins.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+pub(crate) struct OidcClient {
+    config: OidcProviderConfig,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    keys: RwLock<KeyCache>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcProviderConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client builds with the default TLS backend"),
+            metadata: OnceCell::new(),
+            keys: RwLock::new(KeyCache::default()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Where to send the user to sign in.
+    pub async fn authorization_url(&self, secrets: &LoginSecrets) -> Result<String> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint).map_err(|e| {
+            error!(error = %e, provider = self.name(), "Invalid authorization endpoint");
+            Error::IdentityProvider
+        })?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", "openid profile email")
+            .append_pair("state", &secrets.state)
+            .append_pair("nonce", &secrets.nonce)
+            .append_pair("code_challenge", &secrets.code_challenge())
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// Redeem the authorization code and validate the ID token it yields.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<Identity> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint);
+        match &self.config.client_secret {
+            Some(secret) => request = request.basic_auth(&self.config.client_id, Some(secret)),
+            None => form.push(("client_id", &self.config.client_id)),
+        }
+
+        let response = request
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                error!(error = %e, provider = self.name(), "Code exchange failed");
+                Error::IdentityProvider
+            })?;
+        let tokens: TokenResponse = response.json().await.map_err(|e| {
+            error!(error = %e, provider = self.name(), "Invalid token response");
+            Error::IdentityProvider
+        })?;
+
+        self.verify_id_token(&tokens.id_token, nonce, OffsetDateTime::now_utc())
+            .await
+    }
+
+    async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+        now: OffsetDateTime,
+    ) -> Result<Identity> {
+        let metadata = self.metadata().await?;
+        let invalid = |reason: &str| {
+            warn!(provider = self.name(), reason, "Rejected ID token");
+            Error::InvalidIdToken
+        };
+
+        let Some((signed, signature)) = id_token.rsplit_once('.') else {
+            return Err(invalid("malformed"));
+        };
+        let Some((header, payload)) = signed.split_once('.') else {
+            return Err(invalid("malformed"));
+        };
+        let header: JwtHeader = decode_json(header).ok_or_else(|| invalid("bad header"))?;
+        if header.alg != "RS256" {
+            return Err(invalid("unsupported algorithm"));
+        }
+
+        let key = self
+            .key(header.kid, now)
+            .await?
+            .ok_or_else(|| invalid("unknown key"))?;
+        let signature = Base64UrlUnpadded::decode_vec(signature)
+            .ok()
+            .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+            .ok_or_else(|| invalid("bad signature encoding"))?;
+        VerifyingKey::<Sha256>::new(key)
+            .verify(signed.as_bytes(), &signature)
+            .map_err(|_| invalid("bad signature"))?;
+
+        let claims: Claims = decode_json(payload).ok_or_else(|| invalid("bad claims"))?;
+        let now = now.unix_timestamp();
+        if claims.iss != metadata.issuer {
+            return Err(invalid("wrong issuer"));
+        }
+        if !claims.aud.contains(&self.config.client_id) {
+            return Err(invalid("wrong audience"));
+        }
+        if claims.aud.len() > 1 && claims.azp.as_deref() != Some(&self.config.client_id) {
+            return Err(invalid("wrong authorized party"));
+        }
+        if claims.exp + LEEWAY_SECS < now {
+            return Err(invalid("expired"));
+        }
+        if claims.iat.is_some_and(|iat| iat - LEEWAY_SECS > now) {
+            return Err(invalid("issued in the future"));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid("wrong nonce"));
+        }
+
+        Ok(Identity {
+            issuer: claims.iss,
+            subject: claims.sub,
+            name: claims.name.or(claims.email),
+        })
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self.get_json(&url).await?;
+                // the document must be about the issuer it was fetched for
+                if metadata.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/')
+                {
+                    error!(
+                        provider = self.name(),
+                        issuer = metadata.issuer,
+                        "Discovery document names another issuer"
+                    );
+                    return Err(Error::IdentityProvider);
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// The signing key with this id. An unknown id refetches the JWKS, as
+    /// the issuer may have rotated its keys, but not more than once per
+    /// [`JWKS_REFRESH_SECS`].
+    async fn key(&self, kid: Option<String>, now: OffsetDateTime) -> Result<Option<RsaPublicKey>> {
+        {
+            let cache = self.keys.read().await;
+            if let Some(key) = cache.keys.get(&kid) {
+                return Ok(Some(key.clone()));
+            }
+            if cache
+                .fetched_at
+                .is_some_and(|at| (now - at).whole_seconds() < JWKS_REFRESH_SECS)
+            {
+                return Ok(None);
+            }
+        }
+
+        let metadata = self.metadata().await?;
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        let keys: HashMap<_, _> = jwks
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.kty == "RSA" && jwk.usage.as_deref().is_none_or(|u| u == "sig"))
+            .filter_map(|jwk| Some((jwk.kid.clone(), rsa_key(&jwk)?)))
+            .collect();
+
+        let mut cache = self.keys.write().await;
+        cache.keys = keys;
+        cache.fetched_at = Some(now);
+        Ok(cache.keys.get(&kid).cloned())
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                error!(error = %e, provider = self.name(), url, "Identity provider request failed");
+                Error::IdentityProvider
+            })?
+            .json()
+            .await
+            .map_err(|e| {
+                error!(error = %e, provider = self.name(), url, "Invalid identity provider response");
+                Error::IdentityProvider
+            })
+    }
+}
+
+fn rsa_key(jwk: &Jwk) -> Option<RsaPublicKey> {
+    let n = Base64UrlUnpadded::decode_vec(jwk.n.as_deref()?).ok()?;
+    let e = Base64UrlUnpadded::decode_vec(jwk.e.as_deref()?).ok()?;
+    RsaPublicKey::new(
+        BoxedUint::from_be_slice_vartime(&n),
+        BoxedUint::from_be_slice_vartime(&e),
+    )
+    .ok()
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(part: &str) -> Option<T> {
+    let bytes = Base64UrlUnpadded::decode_vec(part).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Json, Router, extract::State, routing::get, routing::post};
+    use rsa::RsaPrivateKey;
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::traits::PublicKeyParts;
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    const CLIENT_ID: &str = "photos";
+
+    /// A minimal issuer on a local port, signing with `key`.
+    struct MockIssuer {
+        url: String,
+        key: RsaPrivateKey,
+    }
+
+    impl MockIssuer {
+        async fn start(key: RsaPrivateKey, id_token: Arc<std::sync::Mutex<String>>) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+
+            let public = key.to_public_key();
+            let jwks = json!({"keys": [{
+                "kty": "RSA", "kid": "k1", "use": "sig", "alg": "RS256",
+                "n": Base64UrlUnpadded::encode_string(&public.n().to_be_bytes()),
+                "e": Base64UrlUnpadded::encode_string(&public.e().to_be_bytes()),
+            }]});
+            let discovery = json!({
+                "issuer": url,
+                "authorization_endpoint": format!("{url}/authorize"),
+                "token_endpoint": format!("{url}/token"),
+                "jwks_uri": format!("{url}/jwks"),
+            });
+
+            let app = Router::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    get(move || async move { Json(discovery) }),
+                )
+                .route("/jwks", get(move || async move { Json(jwks) }))
+                .route(
+                    "/token",
+                    post(
+                        |State(id_token): State<Arc<std::sync::Mutex<String>>>| async move {
+                            let id_token = id_token.lock().unwrap().clone();
+                            Json(json!({"id_token": id_token, "token_type": "Bearer"}))
+                        },
+                    ),
+                )
+                .with_state(id_token);
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { url, key }
+        }
+
+        fn sign(&self, header: Value, claims: Value) -> String {
+            let encode =
+                |value: &Value| Base64UrlUnpadded::encode_string(value.to_string().as_bytes());
+            let signed = format!("{}.{}", encode(&header), encode(&claims));
+            let signature = SigningKey::<Sha256>::new(self.key.clone()).sign(signed.as_bytes());
+            format!(
+                "{signed}.{}",
+                Base64UrlUnpadded::encode_string(&signature.to_bytes())
+            )
+        }
+
+        fn claims(&self) -> Value {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            json!({
+                "iss": self.url, "sub": "alice", "aud": CLIENT_ID,
+                "exp": now + 300, "iat": now, "nonce": "n0nce",
+                "email": "alice@example.com",
+            })
+        }
+
+        fn client(&self) -> OidcClient {
+            OidcClient::new(OidcProviderConfig {
+                name: "mock".to_string(),
+                issuer: self.url.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: Some("secret".to_string()),
+                redirect_uri: "http://localhost/auth/oidc/mock/callback".to_string(),
+            })
+        }
+    }
+
+    fn header() -> Value {
+        json!({"alg": "RS256", "kid": "k1", "typ": "JWT"})
+    }
+
+    async fn issuer() -> (MockIssuer, Arc<std::sync::Mutex<String>>) {
+        let id_token = Arc::new(std::sync::Mutex::new(String::new()));
+        let issuer = MockIssuer::start(sdk::crypto::rsa::generate_key(), id_token.clone()).await;
+        (issuer, id_token)
+    }
+
+    async fn verify(issuer: &MockIssuer, header: Value, claims: Value) -> Result<Identity> {
+        let token = issuer.sign(header, claims);
+        issuer
+            .client()
+            .verify_id_token(&token, "n0nce", OffsetDateTime::now_utc())
+            .await
+    }
+
+    #[tokio::test]
+    async fn builds_authorization_url_from_discovery() {
+        let (issuer, _) = issuer().await;
+        let secrets = LoginSecrets::generate();
+
+        let url = issuer.client().authorization_url(&secrets).await.unwrap();
+
+        let url = Url::parse(&url).unwrap();
+        assert_eq!(url.path(), "/authorize");
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["state"], secrets.state);
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["code_challenge"], secrets.code_challenge());
+    }
+
+    #[tokio::test]
+    async fn exchanges_code_for_a_verified_identity() {
+        let (issuer, id_token) = issuer().await;
+        *id_token.lock().unwrap() = issuer.sign(header(), issuer.claims());
+
+        let identity = issuer
+            .client()
+            .exchange_code("code", "verifier", "n0nce")
+            .await
+            .unwrap();
+
+        assert_eq!(identity.issuer, issuer.url);
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.name.as_deref(), Some("alice@example.com"));
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_and_foreign_tokens() {
+        let (issuer, _) = issuer().await;
+
+        let token = issuer.sign(header(), issuer.claims());
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        let forged = format!("{signed}.{}", Base64UrlUnpadded::encode_string(&[0u8; 256]));
+        assert!(
+            issuer
+                .client()
+                .verify_id_token(&forged, "n0nce", OffsetDateTime::now_utc())
+                .await
+                .is_err()
+        );
+
+        let (other, _) = self::issuer().await;
+        let foreign = other.sign(header(), issuer.claims());
+        assert!(
+            issuer
+                .client()
+                .verify_id_token(&foreign, "n0nce", OffsetDateTime::now_utc())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_claims() {
+        let (issuer, _) = issuer().await;
+        let with = |key: &str, value: Value| {
+            let mut claims = issuer.claims();
+            claims[key] = value;
+            claims
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        assert!(verify(&issuer, header(), issuer.claims()).await.is_ok());
+        for claims in [
+            with("iss", json!("https://evil.example.com")),
+            with("aud", json!("someone-else")),
+            with("aud", json!([CLIENT_ID, "someone-else"])),
+            with("exp", json!(now - 3600)),
+            with("nonce", json!("replayed")),
+        ] {
+            assert!(verify(&issuer, header(), claims).await.is_err());
+        }
+        let none = json!({"alg": "none", "kid": "k1"});
+        assert!(verify(&issuer, none, issuer.claims()).await.is_err());
+        let unknown_kid = json!({"alg": "RS256", "kid": "k2"});
+        assert!(verify(&issuer, unknown_kid, issuer.claims()).await.is_err());
+    }
+}
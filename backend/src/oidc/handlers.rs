@@ -0,0 +1,280 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Redirect,
+};
+use sdk::dtos::auth::{CodeLoginRequest, DeviceDetails, OidcAuthorizeResponse};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, error, info, warn};
+use url::Url;
+use uuid::Uuid;
+
+use super::client::{LoginSecrets, OidcClient};
+use super::repository::{DbOidcLoginRepository, OidcLoginRepository, PendingLink, PendingLogin};
+use crate::{
+    AppState,
+    entity::sea_orm_active_enums::Provider,
+    error::{Error, Result},
+    session::Session,
+    ulid::Id,
+};
+
+#[derive(Deserialize)]
+pub(super) struct AuthorizeParams {
+    /// Where the finished sign-in sends its one-time `code`; must be
+    /// configured in `oidc_client_redirect_uris`.
+    redirect_uri: String,
+    /// S256 PKCE challenge of the client, which redeems the `code` with the
+    /// verifier.
+    code_challenge: String,
+    #[serde(flatten)]
+    device: DeviceDetails,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CallbackParams {
+    state: String,
+    #[serde(default)]
+    code: Option<String>,
+    /// Set instead of `code` when the user or the issuer declined.
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Names of the configured issuers, for clients to offer.
+pub(super) async fn list_providers(State(state): State<AppState>) -> Json<Vec<String>> {
+    Json(state.oidc.names())
+}
+
+/// Start signing in at the issuer.
+pub(super) async fn authorize(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(params): Query<AuthorizeParams>,
+) -> Result<Json<OidcAuthorizeResponse>> {
+    begin(&state, &provider, params, None).await
+}
+
+/// Start linking an issuer account to the signed-in user, so they can sign
+/// in with either. Only this session can finish it, at [`finish_link`].
+pub(super) async fn link(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(params): Query<AuthorizeParams>,
+    session: Session,
+) -> Result<Json<OidcAuthorizeResponse>> {
+    let Some(token_id) = session.token_id() else {
+        error!("Account link outside a token session");
+        return Err(Error::Forbidden);
+    };
+    begin(
+        &state,
+        &provider,
+        params,
+        Some((session.user_id(), token_id)),
+    )
+    .await
+}
+
+/// Link the account the issuer vouched for, redeeming the `code` the
+/// callback sent to the client's redirect URI.
+pub(super) async fn finish_link(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<CodeLoginRequest>,
+) -> Result<StatusCode> {
+    let repo = DbOidcLoginRepository {
+        db: state.db.clone(),
+    };
+    // taken either way, so a code can't be tried against many verifiers
+    let Some(link) = repo
+        .take_link(&request.code, OffsetDateTime::now_utc())
+        .await?
+    else {
+        debug!("Unknown or expired account link");
+        return Err(Error::LoginNotFound);
+    };
+    if session.token_id() != Some(link.token_id)
+        || !crate::auth::code_challenge_matches(&link.code_challenge, &request.code_verifier)
+    {
+        error!("Account link redeemed by another session or with the wrong verifier");
+        return Err(Error::LoginNotFound);
+    }
+
+    crate::auth::resolve_external_account(
+        &state,
+        Provider::Oidc,
+        &link.account_id,
+        &link.account_id,
+        Some(session.user_id()),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `link` is the user and session linking an account, if not signing in.
+async fn begin(
+    state: &AppState,
+    provider: &str,
+    params: AuthorizeParams,
+    link: Option<(Id, Id)>,
+) -> Result<Json<OidcAuthorizeResponse>> {
+    let client = provider_client(state, provider)?;
+    if !crate::auth::is_code_challenge(&params.code_challenge) {
+        error!("OpenID Connect sign-in without a valid code challenge");
+        return Err(Error::InvalidCodeChallenge);
+    }
+    if !state
+        .config
+        .oidc
+        .client_redirect_uris
+        .contains(&params.redirect_uri)
+    {
+        error!(
+            redirect_uri = params.redirect_uri,
+            "Redirect URI not allowed"
+        );
+        return Err(Error::RedirectNotAllowed);
+    }
+
+    let secrets = LoginSecrets::generate();
+    let authorization_url = client.authorization_url(&secrets).await?;
+
+    let repo = DbOidcLoginRepository {
+        db: state.db.clone(),
+    };
+    repo.save_login(PendingLogin {
+        state: secrets.state,
+        provider: provider.to_string(),
+        nonce: secrets.nonce,
+        code_verifier: secrets.code_verifier,
+        client_redirect_uri: params.redirect_uri,
+        client_code_challenge: params.code_challenge,
+        link_user_id: link.map(|(user_id, _)| user_id),
+        link_token_id: link.map(|(_, token_id)| token_id),
+        device_id: params.device.device_id.map(Id::from),
+        device_name: params.device.device_name,
+        platform: params.device.platform,
+        expires_at: OffsetDateTime::now_utc() + Duration::seconds(state.config.oidc.login_ttl_secs),
+    })
+    .await?;
+
+    debug!(provider, "Started OpenID Connect sign-in");
+    Ok(Json(OidcAuthorizeResponse { authorization_url }))
+}
+
+/// Where the issuer sends the user back to. Once the sign-in is known, the
+/// client's redirect URI gets either a one-time `code` or an `error` code.
+/// The code is redeemed at `/auth/login/code`, or for a link at `/link`,
+/// with the verifier of the client's challenge, so whoever else sees the
+/// redirect can't use it.
+pub(super) async fn callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(params): Query<CallbackParams>,
+) -> Result<Redirect> {
+    let client = provider_client(&state, &provider)?;
+    let repo = DbOidcLoginRepository {
+        db: state.db.clone(),
+    };
+    let Some(login) = repo
+        .take_login(&params.state, &provider, OffsetDateTime::now_utc())
+        .await?
+    else {
+        error!(provider, "Unknown or expired sign-in");
+        return Err(Error::LoginNotFound);
+    };
+
+    let query = match finish(&state, client, &login, params).await {
+        Ok(code) => vec![("code", code)],
+        Err(e) => {
+            warn!(error = %e, provider, "OpenID Connect sign-in failed");
+            vec![("error", error_code(&e).to_string())]
+        }
+    };
+
+    let mut redirect = Url::parse(&login.client_redirect_uri).map_err(|e| {
+        error!(error = %e, "Invalid client redirect URI");
+        Error::Configuration
+    })?;
+    redirect.query_pairs_mut().extend_pairs(query);
+    Ok(Redirect::to(redirect.as_str()))
+}
+
+/// Verify the issuer's answer and hand the result to the client as a
+/// one-time code.
+async fn finish(
+    state: &AppState,
+    client: &OidcClient,
+    login: &PendingLogin,
+    params: CallbackParams,
+) -> Result<String> {
+    let code = match (params.code, params.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            debug!(error, "Issuer declined sign-in");
+            return Err(Error::LoginNotFound);
+        }
+    };
+
+    let identity = client
+        .exchange_code(&code, &login.code_verifier, &login.nonce)
+        .await?;
+    let account_id = super::account_id(&identity.issuer, &identity.subject);
+    let expires_at =
+        OffsetDateTime::now_utc() + Duration::seconds(state.config.auth.login_code_ttl_secs);
+
+    // linked once the session that asked redeems the code
+    if let Some(token_id) = login.link_token_id {
+        let code = Uuid::new_v4().to_string();
+        let repo = DbOidcLoginRepository {
+            db: state.db.clone(),
+        };
+        repo.save_link(
+            &code,
+            PendingLink {
+                token_id,
+                code_challenge: login.client_code_challenge.clone(),
+                account_id,
+                expires_at,
+            },
+        )
+        .await?;
+        info!(%token_id, "Issuer vouched for account to link");
+        return Ok(code);
+    }
+
+    let name = identity.name.as_deref().unwrap_or(&identity.subject);
+    let user_id =
+        crate::auth::resolve_external_account(state, Provider::Oidc, &account_id, name, None)
+            .await?;
+
+    let device = DeviceDetails {
+        device_id: login.device_id.map(Into::into),
+        device_name: login.device_name.clone(),
+        platform: login.platform.clone(),
+    };
+    crate::auth::issue_login_code(state, &user_id, device, login.client_code_challenge.clone())
+        .await
+}
+
+fn provider_client<'a>(state: &'a AppState, provider: &str) -> Result<&'a OidcClient> {
+    state.oidc.get(provider).ok_or_else(|| {
+        error!(provider, "Unknown identity provider");
+        Error::ProviderNotFound
+    })
+}
+
+/// What the client's redirect URI is told when signing in failed.
+fn error_code(error: &Error) -> &'static str {
+    match error {
+        Error::LoginNotFound => "access_denied",
+        Error::Auth(crate::auth::error::Error::RegistrationDisabled) => "registration_disabled",
+        Error::AccountConflict => "account_conflict",
+        Error::InvalidIdToken => "invalid_id_token",
+        Error::IdentityProvider => "provider_error",
+        _ => "server_error",
+    }
+}
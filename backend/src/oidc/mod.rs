@@ -0,0 +1,54 @@
+//! Sign-in with OpenID Connect issuers, using the authorization code flow
+//! with PKCE. A client asks for an authorization URL and opens it; the
+//! issuer sends the user back to our callback, which signs them in and
+//! hands the client's redirect URI a one-time code, like desktop login does.
+//! The client redeems it with the verifier of the PKCE challenge it started
+//! with. Linking an account to a signed-in user works alike, except that
+//! the account is only linked once the session that started it redeems the
+//! code.
+//!
+//! An issuer account is linked by `(account_id, provider)` with provider
+//! `oidc` and the account id `{issuer}|{sub}`, as `sub` is only unique per
+//! issuer.
+
+mod client;
+mod handlers;
+mod repository;
+mod routes;
+
+pub(crate) use routes::routes;
+
+use std::collections::HashMap;
+
+use crate::config::OidcConfig;
+
+use self::client::OidcClient;
+
+/// Clients of the configured issuers, by provider name.
+pub(crate) struct OidcProviders(HashMap<String, OidcClient>);
+
+impl OidcProviders {
+    pub fn from_config(config: &OidcConfig) -> Self {
+        Self(
+            config
+                .providers
+                .iter()
+                .map(|provider| (provider.name.clone(), OidcClient::new(provider.clone())))
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &str) -> Option<&OidcClient> {
+        self.0.get(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.0.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+fn account_id(issuer: &str, subject: &str) -> String {
+    format!("{issuer}|{subject}")
+}
@@ -0,0 +1,288 @@
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::database::DbPool;
+use crate::entity::prelude::{OidcLinks, OidcLogins};
+use crate::entity::{oidc_links, oidc_logins};
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+/// A sign-in waiting for the issuer to call back.
+pub(crate) struct PendingLogin {
+    pub state: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub client_redirect_uri: String,
+    /// The S256 PKCE challenge of the client, which redeems the finished
+    /// sign-in with its verifier.
+    pub client_code_challenge: String,
+    /// Set when a signed-in user links the account rather than signs in.
+    pub link_user_id: Option<Id>,
+    /// The session linking the account, which alone can finish it.
+    pub link_token_id: Option<Id>,
+    pub device_id: Option<Id>,
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// An issuer account vouched for at the callback, until the session that
+/// asked to link it redeems the code.
+pub(crate) struct PendingLink {
+    pub token_id: Id,
+    pub code_challenge: String,
+    pub account_id: String,
+    pub expires_at: OffsetDateTime,
+}
+
+pub(crate) trait OidcLoginRepository {
+    async fn save_login(&self, login: PendingLogin) -> Result<()>;
+    /// Remove and return the unexpired sign-in with this state, so each can
+    /// complete once.
+    async fn take_login(
+        &self,
+        state: &str,
+        provider: &str,
+        now: OffsetDateTime,
+    ) -> Result<Option<PendingLogin>>;
+    async fn save_link(&self, code: &str, link: PendingLink) -> Result<()>;
+    /// Remove and return the unexpired link with this code, so each can be
+    /// redeemed once.
+    async fn take_link(&self, code: &str, now: OffsetDateTime) -> Result<Option<PendingLink>>;
+}
+
+/// Link codes are kept as their SHA-256 digest (hex), like tokens.
+fn code_hash(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+pub(crate) struct DbOidcLoginRepository {
+    pub db: DbPool,
+}
+
+impl OidcLoginRepository for DbOidcLoginRepository {
+    async fn save_login(&self, login: PendingLogin) -> Result<()> {
+        // abandoned sign-ins are cleared as new ones come in
+        OidcLogins::delete_many()
+            .filter(oidc_logins::Column::ExpiresAt.lte(OffsetDateTime::now_utc()))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired sign-ins");
+                Error::Database
+            })?;
+
+        oidc_logins::ActiveModel {
+            state: Set(login.state),
+            provider: Set(login.provider),
+            nonce: Set(login.nonce),
+            code_verifier: Set(login.code_verifier),
+            client_redirect_uri: Set(login.client_redirect_uri),
+            client_code_challenge: Set(login.client_code_challenge),
+            link_user_id: Set(login.link_user_id.map(uuid::Uuid::from)),
+            link_token_id: Set(login.link_token_id.map(uuid::Uuid::from)),
+            device_id: Set(login.device_id.map(uuid::Uuid::from)),
+            device_name: Set(login.device_name),
+            platform: Set(login.platform),
+            expires_at: Set(login.expires_at),
+        }
+        .insert(&self.db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save sign-in");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    async fn take_login(
+        &self,
+        state: &str,
+        provider: &str,
+        now: OffsetDateTime,
+    ) -> Result<Option<PendingLogin>> {
+        let row = OidcLogins::find_by_id(state.to_string())
+            .one(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query sign-in");
+                Error::Database
+            })?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let result = OidcLogins::delete_many()
+            .filter(oidc_logins::Column::State.eq(state))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete sign-in");
+                Error::Database
+            })?;
+        // a concurrent callback got it first
+        if result.rows_affected == 0 {
+            return Ok(None);
+        }
+        if row.provider != provider || row.expires_at <= now {
+            return Ok(None);
+        }
+
+        Ok(Some(PendingLogin {
+            state: row.state,
+            provider: row.provider,
+            nonce: row.nonce,
+            code_verifier: row.code_verifier,
+            client_redirect_uri: row.client_redirect_uri,
+            client_code_challenge: row.client_code_challenge,
+            link_user_id: row.link_user_id.map(Id::from),
+            link_token_id: row.link_token_id.map(Id::from),
+            device_id: row.device_id.map(Id::from),
+            device_name: row.device_name,
+            platform: row.platform,
+            expires_at: row.expires_at,
+        }))
+    }
+    async fn save_link(&self, code: &str, link: PendingLink) -> Result<()> {
+        OidcLinks::delete_many()
+            .filter(oidc_links::Column::ExpiresAt.lte(OffsetDateTime::now_utc()))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired account links");
+                Error::Database
+            })?;
+
+        oidc_links::ActiveModel {
+            code_hash: Set(code_hash(code)),
+            token_id: Set(uuid::Uuid::from(link.token_id)),
+            code_challenge: Set(link.code_challenge),
+            account_id: Set(link.account_id),
+            expires_at: Set(link.expires_at),
+        }
+        .insert(&self.db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save account link");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    async fn take_link(&self, code: &str, now: OffsetDateTime) -> Result<Option<PendingLink>> {
+        let rows = OidcLinks::delete_many()
+            .filter(oidc_links::Column::CodeHash.eq(code_hash(code)))
+            .exec_with_returning(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not take account link");
+                Error::Database
+            })?;
+
+        Ok(rows
+            .into_iter()
+            .find(|row| row.expires_at > now)
+            .map(|row| PendingLink {
+                token_id: Id::from(row.token_id),
+                code_challenge: row.code_challenge,
+                account_id: row.account_id,
+                expires_at: row.expires_at,
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use crate::entity::auth_tokens;
+    use crate::entity::prelude::AuthTokens;
+    use time::Duration;
+
+    fn pending_link(token_id: Id, expires_at: OffsetDateTime) -> PendingLink {
+        PendingLink {
+            token_id,
+            code_challenge: "challenge".to_string(),
+            account_id: "https://issuer|alice".to_string(),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn links_are_taken_once_before_they_expire() {
+        let Some(db) = test_db().await else { return };
+        let user_id = crate::auth::new_user(&db).await;
+        let (token_id, _) = crate::auth::new_session(&db, &user_id).await;
+        let repo = DbOidcLoginRepository { db };
+        let now = OffsetDateTime::now_utc();
+        let fresh = format!("fresh-{token_id}");
+        let stale = format!("stale-{token_id}");
+        repo.save_link(&fresh, pending_link(token_id, now + Duration::minutes(1)))
+            .await
+            .unwrap();
+        repo.save_link(&stale, pending_link(token_id, now + Duration::seconds(1)))
+            .await
+            .unwrap();
+
+        let taken = repo.take_link(&fresh, now).await.unwrap().unwrap();
+        let again = repo.take_link(&fresh, now).await.unwrap();
+        let expired = repo
+            .take_link(&stale, now + Duration::seconds(1))
+            .await
+            .unwrap();
+
+        assert_eq!(taken.token_id, token_id);
+        assert_eq!(taken.account_id, "https://issuer|alice");
+        assert!(again.is_none());
+        assert!(expired.is_none());
+    }
+
+    #[tokio::test]
+    async fn ending_the_session_drops_its_links() {
+        let Some(db) = test_db().await else { return };
+        let user_id = crate::auth::new_user(&db).await;
+        let (token_id, _) = crate::auth::new_session(&db, &user_id).await;
+        let repo = DbOidcLoginRepository { db: db.clone() };
+        let now = OffsetDateTime::now_utc();
+        let state = format!("state-{token_id}");
+        let code = format!("code-{token_id}");
+        repo.save_login(PendingLogin {
+            state: state.clone(),
+            provider: "issuer".to_string(),
+            nonce: "nonce".to_string(),
+            code_verifier: "verifier".to_string(),
+            client_redirect_uri: "photostore://oidc".to_string(),
+            client_code_challenge: "challenge".to_string(),
+            link_user_id: Some(user_id),
+            link_token_id: Some(token_id),
+            device_id: None,
+            device_name: None,
+            platform: None,
+            expires_at: now + Duration::minutes(10),
+        })
+        .await
+        .unwrap();
+        repo.save_link(&code, pending_link(token_id, now + Duration::minutes(1)))
+            .await
+            .unwrap();
+
+        AuthTokens::delete_many()
+            .filter(auth_tokens::Column::Id.eq(uuid::Uuid::from(token_id)))
+            .exec(&db)
+            .await
+            .unwrap();
+
+        assert!(
+            repo.take_login(&state, "issuer", now)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(repo.take_link(&code, now).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,26 @@
+use axum::{
+    Router,
+    routing::{get, post},
+};
+
+use crate::AppState;
+
+use super::handlers;
+
+/// Mounted under `/auth/oidc`.
+pub(crate) fn routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/{provider}/link", get(handlers::link))
+        .route("/link", post(handlers::finish_link))
+        .route_layer(axum::middleware::from_fn(
+            crate::auth::middleware::require_auth,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            crate::auth::middleware::session_resolver,
+        ))
+        .route("/providers", get(handlers::list_providers))
+        .route("/{provider}/authorize", get(handlers::authorize))
+        .route("/{provider}/callback", get(handlers::callback))
+        .with_state(app_state)
+}
@@ -0,0 +1,1043 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use hmac::{Hmac, KeyInit, Mac};
+use sdk::crypto::prf::PRF_OUTPUT_LEN;
+use sdk::dtos::auth::{
+    DeletePasskeyRequest, FinishPasskeyLoginRequest, FinishPasskeyRegistrationRequest,
+    FinishPasskeySecondFactorRequest, LoginResponse, PasskeyInfo, PasskeyLoginOptions,
+    PasskeyLoginResponse, PasskeyRegistrationOptions, SavePasskeyKeyRequest,
+    StartPasskeyLoginRequest, StartPasskeyRegistrationRequest, StartPasskeySecondFactorRequest,
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, error, info};
+use webauthn_rs::prelude::{
+    Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, Webauthn,
+};
+
+use super::repository::{
+    CeremonyKind, DbPasskeyRepository, NewPasskey, PasskeyRepository, PasskeyRow,
+};
+use crate::{
+    AppState,
+    auth::error::Error as AuthError,
+    error::{Error, Result},
+    session::Session,
+    ulid::Id,
+};
+
+/// Longest passkey name kept; anything past it is cut off.
+const MAX_NAME_CHARS: usize = 100;
+
+/// Keys the made-up credentials offered for usernames without passkeys, so
+/// the same name gets the same ones for as long as the server runs.
+static DECOY_KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+});
+
+/// What a registration keeps between its two round trips.
+#[derive(Serialize, Deserialize)]
+struct RegistrationCeremony {
+    registration: PasskeyRegistration,
+    prf_salt: String,
+}
+
+pub(super) async fn list_passkeys(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<Vec<PasskeyInfo>>> {
+    let passkeys = repository(&state)
+        .list_passkeys(&session.user_id())
+        .await?
+        .iter()
+        .map(passkey_info)
+        .collect();
+
+    Ok(Json(passkeys))
+}
+
+/// Start adding a passkey to the signed-in user. Users who already have a
+/// second factor have to prove it's them again first, see
+/// [`StartPasskeyRegistrationRequest`]; the body may be left out otherwise.
+pub(super) async fn start_registration(
+    State(state): State<AppState>,
+    session: Session,
+    request: Option<Json<StartPasskeyRegistrationRequest>>,
+) -> Result<Json<PasskeyRegistrationOptions>> {
+    let webauthn = relying_party(&state)?;
+    let user_id = session.user_id();
+
+    let Json(request) = request.unwrap_or_default();
+    crate::auth::reauthenticate(
+        &state,
+        &user_id,
+        request.code.as_deref(),
+        request.password.as_deref(),
+    )
+    .await?;
+
+    let options = start_registration_internal(
+        webauthn,
+        &repository(&state),
+        user_id,
+        ceremony_expiry(&state),
+    )
+    .await?;
+    Ok(Json(options))
+}
+
+async fn start_registration_internal(
+    webauthn: &Webauthn,
+    repo: &impl PasskeyRepository,
+    user_id: Id,
+    expires_at: OffsetDateTime,
+) -> Result<PasskeyRegistrationOptions> {
+    // so an authenticator already holding one of them isn't registered twice
+    let registered = repo
+        .list_passkeys(&user_id)
+        .await?
+        .into_iter()
+        .map(|row| row.passkey.cred_id().clone())
+        .collect();
+    let name = repo
+        .user_name(&user_id)
+        .await?
+        .unwrap_or_else(|| user_id.to_string());
+    let (options, registration) = webauthn
+        .start_passkey_registration(uuid::Uuid::from(user_id), &name, &name, Some(registered))
+        .map_err(|e| {
+            error!(error = %e, "Could not start passkey registration");
+            Error::Configuration
+        })?;
+
+    let ceremony_id = Id::new();
+    let prf_salt = random_salt();
+    let ceremony = RegistrationCeremony {
+        registration,
+        prf_salt: prf_salt.clone(),
+    };
+    repo.save_ceremony(
+        &ceremony_id,
+        &user_id,
+        CeremonyKind::Registration,
+        encode(&ceremony)?.to_string(),
+        expires_at,
+    )
+    .await?;
+
+    Ok(PasskeyRegistrationOptions {
+        ceremony_id: ceremony_id.into(),
+        options: encode(&options)?,
+        prf_salt,
+    })
+}
+
+pub(super) async fn finish_registration(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<FinishPasskeyRegistrationRequest>,
+) -> Result<Json<PasskeyInfo>> {
+    let webauthn = relying_party(&state)?;
+    let info =
+        finish_registration_internal(webauthn, &repository(&state), session.user_id(), request)
+            .await?;
+    Ok(Json(info))
+}
+
+async fn finish_registration_internal(
+    webauthn: &Webauthn,
+    repo: &impl PasskeyRepository,
+    user_id: Id,
+    request: FinishPasskeyRegistrationRequest,
+) -> Result<PasskeyInfo> {
+    let ceremony = repo
+        .take_ceremony(&request.ceremony_id.into(), CeremonyKind::Registration)
+        .await?;
+    let ceremony: RegistrationCeremony = match ceremony {
+        Some((owner, ceremony)) if owner == user_id => decode(&ceremony)?,
+        _ => {
+            debug!("Unknown or expired passkey registration");
+            return Err(Error::CeremonyNotFound);
+        }
+    };
+    let credential: RegisterPublicKeyCredential = serde_json::from_value(request.credential)
+        .map_err(|e| {
+            debug!(error = %e, "Malformed passkey credential");
+            Error::InvalidPasskey
+        })?;
+    let passkey = webauthn
+        .finish_passkey_registration(&credential, &ceremony.registration)
+        .map_err(|e| {
+            debug!(error = %e, "Passkey registration failed");
+            Error::InvalidPasskey
+        })?;
+
+    let name: String = request
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Passkey")
+        .chars()
+        .take(MAX_NAME_CHARS)
+        .collect();
+    let new = NewPasskey {
+        user_id,
+        credential_id: &credential_id(&passkey),
+        name: &name,
+        passkey: &passkey,
+        prf_salt: &ceremony.prf_salt,
+    };
+    let Some(passkey_id) = repo.save_passkey(new).await? else {
+        error!("Passkey is already registered");
+        return Err(Error::AccountConflict);
+    };
+
+    info!(%passkey_id, "Registered passkey");
+    let row = repo
+        .list_passkeys(&user_id)
+        .await?
+        .into_iter()
+        .find(|row| row.id == passkey_id)
+        .ok_or(Error::PasskeyNotFound)?;
+    Ok(passkey_info(&row))
+}
+
+/// Store a copy of the private key sealed under the passkey's PRF output.
+/// Takes the same proof as [`start_registration`], as the copy unlocks the
+/// key for whoever holds the passkey.
+pub(super) async fn save_private_key(
+    State(state): State<AppState>,
+    session: Session,
+    Path(passkey_id): Path<Id>,
+    Json(request): Json<SavePasskeyKeyRequest>,
+) -> Result<()> {
+    let user_id = session.user_id();
+    crate::auth::reauthenticate(
+        &state,
+        &user_id,
+        request.code.as_deref(),
+        request.password.as_deref(),
+    )
+    .await?;
+    crate::auth::check_key_envelope(&request.private_key)?;
+
+    let saved = repository(&state)
+        .save_wrapped_key(&user_id, &passkey_id, &request.private_key)
+        .await?;
+    if !saved {
+        error!(%passkey_id, "Passkey not found");
+        return Err(Error::PasskeyNotFound);
+    }
+    Ok(())
+}
+
+/// Remove a passkey of the signed-in user. Takes the same proof as
+/// [`start_registration`], so a stolen session can't take away a factor.
+pub(super) async fn delete_passkey(
+    State(state): State<AppState>,
+    session: Session,
+    Path(passkey_id): Path<Id>,
+    request: Option<Json<DeletePasskeyRequest>>,
+) -> Result<()> {
+    let user_id = session.user_id();
+
+    let Json(request) = request.unwrap_or_default();
+    crate::auth::reauthenticate(
+        &state,
+        &user_id,
+        request.code.as_deref(),
+        request.password.as_deref(),
+    )
+    .await?;
+
+    delete_passkey_internal(&repository(&state), &user_id, &passkey_id).await
+}
+
+async fn delete_passkey_internal(
+    repo: &impl PasskeyRepository,
+    user_id: &Id,
+    passkey_id: &Id,
+) -> Result<()> {
+    let deleted = repo.delete_passkey(user_id, passkey_id).await?;
+    if !deleted {
+        error!(%passkey_id, "Passkey not found");
+        return Err(Error::PasskeyNotFound);
+    }
+    info!(%passkey_id, "Deleted passkey");
+    Ok(())
+}
+
+/// Start signing in with a passkey instead of the password. Usernames that
+/// don't exist or have no passkey get made-up options that no passkey can
+/// answer, so the response doesn't tell which accounts have one.
+pub(super) async fn start_login(
+    State(state): State<AppState>,
+    Json(request): Json<StartPasskeyLoginRequest>,
+) -> Result<Json<PasskeyLoginOptions>> {
+    let webauthn = relying_party(&state)?;
+    let user_id = crate::auth::find_user_by_username(&state.db, &request.username).await?;
+    let options = match user_id {
+        Some(user_id) => {
+            start_authentication(
+                webauthn,
+                &repository(&state),
+                user_id,
+                ceremony_expiry(&state),
+            )
+            .await?
+        }
+        None => None,
+    };
+    let options = match options {
+        Some(options) => options,
+        None => {
+            debug!("Passkey login for a user without passkeys");
+            decoy_options(webauthn, &request.username)?
+        }
+    };
+    Ok(Json(options))
+}
+
+pub(super) async fn finish_login(
+    State(state): State<AppState>,
+    Json(request): Json<FinishPasskeyLoginRequest>,
+) -> Result<Json<PasskeyLoginResponse>> {
+    let webauthn = relying_party(&state)?;
+    let (user_id, passkey) = verify_assertion(
+        webauthn,
+        &repository(&state),
+        &request.ceremony_id.into(),
+        request.credential,
+    )
+    .await?;
+
+    let login = crate::auth::start_session(&state, &user_id, &request.device).await?;
+    Ok(Json(login_response(login, passkey)))
+}
+
+/// Start redeeming a password login's challenge with a passkey.
+pub(super) async fn start_second_factor(
+    State(state): State<AppState>,
+    Json(request): Json<StartPasskeySecondFactorRequest>,
+) -> Result<Json<PasskeyLoginOptions>> {
+    let webauthn = relying_party(&state)?;
+    let user_id = crate::auth::login_challenge_user(&state.db, &request.challenge).await?;
+    let Some(options) = start_authentication(
+        webauthn,
+        &repository(&state),
+        user_id,
+        ceremony_expiry(&state),
+    )
+    .await?
+    else {
+        debug!("Passkey second factor for a user without passkeys");
+        return Err(AuthError::InvalidCredentials.into());
+    };
+    Ok(Json(options))
+}
+
+pub(super) async fn finish_second_factor(
+    State(state): State<AppState>,
+    Json(request): Json<FinishPasskeySecondFactorRequest>,
+) -> Result<Json<PasskeyLoginResponse>> {
+    let webauthn = relying_party(&state)?;
+    let db = &state.db;
+    let user_id = crate::auth::login_challenge_user(db, &request.challenge).await?;
+
+    let verified = verify_second_factor(
+        webauthn,
+        &repository(&state),
+        &user_id,
+        &request.ceremony_id.into(),
+        request.credential,
+    )
+    .await;
+    let passkey = match verified {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            crate::auth::fail_login_challenge(db, &request.challenge).await?;
+            return Err(e);
+        }
+    };
+
+    let login = crate::auth::redeem_login_challenge(&state, &request.challenge).await?;
+    Ok(Json(login_response(login, passkey)))
+}
+
+/// Options for signing the user in with one of their passkeys, or `None` if
+/// they have none.
+async fn start_authentication(
+    webauthn: &Webauthn,
+    repo: &impl PasskeyRepository,
+    user_id: Id,
+    expires_at: OffsetDateTime,
+) -> Result<Option<PasskeyLoginOptions>> {
+    let passkeys = repo.list_passkeys(&user_id).await?;
+    if passkeys.is_empty() {
+        return Ok(None);
+    }
+    let credentials: Vec<Passkey> = passkeys.iter().map(|row| row.passkey.clone()).collect();
+    let (options, authentication) = webauthn
+        .start_passkey_authentication(&credentials)
+        .map_err(|e| {
+            error!(error = %e, "Could not start passkey authentication");
+            Error::Configuration
+        })?;
+
+    let ceremony_id = Id::new();
+    repo.save_ceremony(
+        &ceremony_id,
+        &user_id,
+        CeremonyKind::Authentication,
+        encode(&authentication)?.to_string(),
+        expires_at,
+    )
+    .await?;
+
+    let prf_salts = passkeys
+        .iter()
+        .filter(|row| row.wrapped_private_key.is_some())
+        .map(|row| (credential_id(&row.passkey), row.prf_salt.clone()))
+        .collect();
+    Ok(Some(PasskeyLoginOptions {
+        ceremony_id: ceremony_id.into(),
+        options: encode(&options)?,
+        prf_salts,
+    }))
+}
+
+/// Options shaped like [`start_authentication`]'s, offering a credential made
+/// up from the username. Nothing is stored, so finishing fails like an
+/// expired ceremony.
+fn decoy_options(webauthn: &Webauthn, username: &str) -> Result<PasskeyLoginOptions> {
+    let (options, _) = webauthn.start_passkey_authentication(&[]).map_err(|e| {
+        error!(error = %e, "Could not start passkey authentication");
+        Error::Configuration
+    })?;
+    let credential_id = decoy_value(b"credential", username);
+    let mut options = encode(&options)?;
+    options["publicKey"]["allowCredentials"] = serde_json::json!([{
+        "type": "public-key",
+        "id": credential_id,
+    }]);
+
+    Ok(PasskeyLoginOptions {
+        ceremony_id: Id::new().into(),
+        options,
+        prf_salts: BTreeMap::from([(credential_id, decoy_value(b"prf-salt", username))]),
+    })
+}
+
+fn decoy_value(purpose: &[u8], username: &str) -> String {
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(DECOY_KEY.as_slice())
+        .expect("HMAC takes any key size");
+    mac.update(purpose);
+    mac.update(&[0]);
+    mac.update(username.as_bytes());
+    Base64UrlUnpadded::encode_string(&mac.finalize().into_bytes())
+}
+
+/// Check a passkey assertion against its ceremony; returns the user and the
+/// passkey that signed.
+async fn verify_assertion(
+    webauthn: &Webauthn,
+    repo: &impl PasskeyRepository,
+    ceremony_id: &Id,
+    credential: serde_json::Value,
+) -> Result<(Id, PasskeyRow)> {
+    let Some((user_id, ceremony)) = repo
+        .take_ceremony(ceremony_id, CeremonyKind::Authentication)
+        .await?
+    else {
+        debug!("Unknown or expired passkey login");
+        return Err(Error::CeremonyNotFound);
+    };
+    let authentication: PasskeyAuthentication = decode(&ceremony)?;
+    let credential: PublicKeyCredential = serde_json::from_value(credential).map_err(|e| {
+        debug!(error = %e, "Malformed passkey assertion");
+        Error::InvalidPasskey
+    })?;
+    let result = webauthn
+        .finish_passkey_authentication(&credential, &authentication)
+        .map_err(|e| {
+            debug!(error = %e, "Passkey assertion failed");
+            AuthError::InvalidCredentials
+        })?;
+
+    let Some(mut passkey) = repo
+        .list_passkeys(&user_id)
+        .await?
+        .into_iter()
+        .find(|row| row.passkey.cred_id() == result.cred_id())
+    else {
+        debug!("Passkey was deleted during login");
+        return Err(AuthError::InvalidCredentials.into());
+    };
+    // e.g. the signature counter moved on
+    let changed = passkey.passkey.update_credential(&result) == Some(true);
+    repo.record_use(&passkey.id, changed.then_some(&passkey.passkey))
+        .await?;
+
+    Ok((user_id, passkey))
+}
+
+/// [`verify_assertion`] for the user a login challenge belongs to.
+async fn verify_second_factor(
+    webauthn: &Webauthn,
+    repo: &impl PasskeyRepository,
+    user_id: &Id,
+    ceremony_id: &Id,
+    credential: serde_json::Value,
+) -> Result<PasskeyRow> {
+    let (owner, passkey) = verify_assertion(webauthn, repo, ceremony_id, credential).await?;
+    if owner != *user_id {
+        error!("Passkey belongs to another user");
+        return Err(AuthError::InvalidCredentials.into());
+    }
+    Ok(passkey)
+}
+
+fn login_response(login: LoginResponse, passkey: PasskeyRow) -> PasskeyLoginResponse {
+    PasskeyLoginResponse {
+        login,
+        passkey_id: passkey.id.into(),
+        wrapped_private_key: passkey.wrapped_private_key,
+    }
+}
+
+fn passkey_info(row: &PasskeyRow) -> PasskeyInfo {
+    PasskeyInfo {
+        id: row.id.into(),
+        name: row.name.clone(),
+        created_at: row.created_at,
+        last_used_at: row.last_used_at,
+        unlocks_key: row.wrapped_private_key.is_some(),
+    }
+}
+
+fn relying_party(state: &AppState) -> Result<&Webauthn> {
+    state.webauthn.as_deref().ok_or_else(|| {
+        debug!("Passkeys are not configured");
+        Error::PasskeysDisabled
+    })
+}
+
+fn repository(state: &AppState) -> DbPasskeyRepository {
+    DbPasskeyRepository {
+        db: state.db.clone(),
+    }
+}
+
+fn ceremony_expiry(state: &AppState) -> OffsetDateTime {
+    OffsetDateTime::now_utc() + Duration::seconds(state.config.webauthn.ceremony_ttl_secs)
+}
+
+fn credential_id(passkey: &Passkey) -> String {
+    Base64UrlUnpadded::encode_string(passkey.cred_id().as_ref())
+}
+
+fn random_salt() -> String {
+    let mut salt = [0u8; PRF_OUTPUT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    Base64UrlUnpadded::encode_string(&salt)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| {
+        error!(error = %e, "Could not serialize passkey ceremony");
+        Error::Database
+    })
+}
+
+fn decode<T: DeserializeOwned>(value: &str) -> Result<T> {
+    serde_json::from_str(value).map_err(|e| {
+        error!(error = %e, "Stored passkey ceremony is invalid");
+        Error::Database
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::repository::tests::InMemoryPasskeyRepository;
+    use super::*;
+    use crate::config::WebauthnConfig;
+    use axum::http::StatusCode;
+    use p256::ecdsa::{Signature, SigningKey, signature::Signer};
+    use serde_cbor_2::Value;
+    use serde_json::json;
+    use sha2::Digest;
+
+    const RP_ID: &str = "photos.example.com";
+    const ORIGIN: &str = "https://photos.example.com";
+
+    fn webauthn() -> Webauthn {
+        crate::passkey::relying_party(&WebauthnConfig {
+            rp_id: Some(RP_ID.to_string()),
+            rp_name: "Photos".to_string(),
+            origins: vec![ORIGIN.to_string()],
+            ceremony_ttl_secs: 300,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn expires_at() -> OffsetDateTime {
+        OffsetDateTime::now_utc() + Duration::minutes(5)
+    }
+
+    /// Just enough of a platform authenticator to answer ceremonies: one
+    /// P-256 credential, attested with `none`.
+    struct Authenticator {
+        credential_id: Vec<u8>,
+        key: SigningKey,
+        counter: u32,
+    }
+
+    impl Authenticator {
+        fn new(seed: u8) -> Self {
+            Self {
+                credential_id: vec![seed; 16],
+                key: SigningKey::from_slice(&[seed; 32]).unwrap(),
+                counter: 0,
+            }
+        }
+
+        fn register(&self, options: &serde_json::Value) -> serde_json::Value {
+            let client_data = client_data("webauthn.create", options);
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let public_key = Value::Map(
+                [
+                    (Value::Integer(1), Value::Integer(2)),
+                    (Value::Integer(3), Value::Integer(-7)),
+                    (Value::Integer(-1), Value::Integer(1)),
+                    (
+                        Value::Integer(-2),
+                        Value::Bytes(point.x().unwrap().to_vec()),
+                    ),
+                    (
+                        Value::Integer(-3),
+                        Value::Bytes(point.y().unwrap().to_vec()),
+                    ),
+                ]
+                .into(),
+            );
+
+            // user present and verified, with attested credential data
+            let mut auth_data = authenticator_data(0x45, 0);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            auth_data.extend(serde_cbor_2::to_vec(&public_key).unwrap());
+            let attestation = Value::Map(
+                [
+                    (Value::Text("fmt".into()), Value::Text("none".into())),
+                    (
+                        Value::Text("attStmt".into()),
+                        Value::Map(Default::default()),
+                    ),
+                    (Value::Text("authData".into()), Value::Bytes(auth_data)),
+                ]
+                .into(),
+            );
+
+            json!({
+                "id": b64(&self.credential_id),
+                "rawId": b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": b64(&serde_cbor_2::to_vec(&attestation).unwrap()),
+                    "clientDataJSON": b64(&client_data),
+                },
+            })
+        }
+
+        fn assert(&mut self, options: &serde_json::Value) -> serde_json::Value {
+            self.counter += 1;
+            let client_data = client_data("webauthn.get", options);
+            // user present and verified
+            let auth_data = authenticator_data(0x05, self.counter);
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: Signature = self.key.sign(&signed);
+
+            json!({
+                "id": b64(&self.credential_id),
+                "rawId": b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": b64(&auth_data),
+                    "clientDataJSON": b64(&client_data),
+                    "signature": b64(signature.to_der().as_bytes()),
+                    "userHandle": null,
+                },
+            })
+        }
+    }
+
+    fn authenticator_data(flags: u8, counter: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&counter.to_be_bytes());
+        data
+    }
+
+    fn client_data(kind: &str, options: &serde_json::Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": kind,
+            "challenge": options["publicKey"]["challenge"],
+            "origin": ORIGIN,
+            "crossOrigin": false,
+        }))
+        .unwrap()
+    }
+
+    fn b64(bytes: &[u8]) -> String {
+        Base64UrlUnpadded::encode_string(bytes)
+    }
+
+    async fn register(
+        webauthn: &Webauthn,
+        repo: &impl PasskeyRepository,
+        user_id: Id,
+        authenticator: &Authenticator,
+    ) -> Result<PasskeyInfo> {
+        let options = start_registration_internal(webauthn, repo, user_id, expires_at()).await?;
+        let request = FinishPasskeyRegistrationRequest {
+            ceremony_id: options.ceremony_id,
+            name: Some(" Phone ".to_string()),
+            credential: authenticator.register(&options.options),
+        };
+        finish_registration_internal(webauthn, repo, user_id, request).await
+    }
+
+    async fn sign_in(
+        webauthn: &Webauthn,
+        repo: &InMemoryPasskeyRepository,
+        user_id: Id,
+        authenticator: &mut Authenticator,
+    ) -> (Id, serde_json::Value) {
+        let options = start_authentication(webauthn, repo, user_id, expires_at())
+            .await
+            .unwrap()
+            .unwrap();
+        (
+            options.ceremony_id.into(),
+            authenticator.assert(&options.options),
+        )
+    }
+
+    fn is_unauthorized(error: &Error) -> bool {
+        error.status_and_message().0 == StatusCode::UNAUTHORIZED
+    }
+
+    #[tokio::test]
+    async fn registers_a_passkey() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+
+        let info = register(&webauthn, &repo, user_id, &Authenticator::new(1))
+            .await
+            .unwrap();
+
+        assert_eq!(info.name, "Phone");
+        assert!(!info.unlocks_key);
+        let passkeys = repo.list_passkeys(&user_id).await.unwrap();
+        assert_eq!(passkeys.len(), 1);
+        assert_eq!(Id::from(info.id), passkeys[0].id);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_register_a_passkey_twice() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let authenticator = Authenticator::new(1);
+        register(&webauthn, &repo, Id::new(), &authenticator)
+            .await
+            .unwrap();
+
+        let result = register(&webauthn, &repo, Id::new(), &authenticator).await;
+
+        assert!(matches!(result, Err(Error::AccountConflict)));
+    }
+
+    #[tokio::test]
+    async fn registrations_finish_only_for_the_user_who_started_them() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        let options = start_registration_internal(&webauthn, &repo, user_id, expires_at())
+            .await
+            .unwrap();
+        let request = FinishPasskeyRegistrationRequest {
+            ceremony_id: options.ceremony_id,
+            name: None,
+            credential: Authenticator::new(1).register(&options.options),
+        };
+
+        let result = finish_registration_internal(&webauthn, &repo, Id::new(), request).await;
+
+        assert!(matches!(result, Err(Error::CeremonyNotFound)));
+        assert!(repo.list_passkeys(&user_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn signs_in_with_a_registered_passkey() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        let mut authenticator = Authenticator::new(1);
+        let info = register(&webauthn, &repo, user_id, &authenticator)
+            .await
+            .unwrap();
+
+        let (ceremony_id, assertion) = sign_in(&webauthn, &repo, user_id, &mut authenticator).await;
+        let (owner, passkey) = verify_assertion(&webauthn, &repo, &ceremony_id, assertion)
+            .await
+            .unwrap();
+
+        assert_eq!(owner, user_id);
+        assert_eq!(passkey.id, Id::from(info.id));
+        assert!(
+            repo.list_passkeys(&user_id).await.unwrap()[0]
+                .last_used_at
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_ceremonies_complete_once() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        let mut authenticator = Authenticator::new(1);
+        register(&webauthn, &repo, user_id, &authenticator)
+            .await
+            .unwrap();
+        let (ceremony_id, assertion) = sign_in(&webauthn, &repo, user_id, &mut authenticator).await;
+        verify_assertion(&webauthn, &repo, &ceremony_id, assertion.clone())
+            .await
+            .unwrap();
+
+        let result = verify_assertion(&webauthn, &repo, &ceremony_id, assertion).await;
+
+        assert!(matches!(result, Err(Error::CeremonyNotFound)));
+    }
+
+    #[tokio::test]
+    async fn rejects_assertions_from_other_passkeys() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        register(&webauthn, &repo, user_id, &Authenticator::new(1))
+            .await
+            .unwrap();
+
+        let (ceremony_id, assertion) =
+            sign_in(&webauthn, &repo, user_id, &mut Authenticator::new(2)).await;
+        let result = verify_assertion(&webauthn, &repo, &ceremony_id, assertion).await;
+
+        assert!(is_unauthorized(&result.err().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn users_without_passkeys_get_no_sign_in_options() {
+        let repo = InMemoryPasskeyRepository::new();
+
+        let options = start_authentication(&webauthn(), &repo, Id::new(), expires_at())
+            .await
+            .unwrap();
+
+        assert!(options.is_none());
+    }
+
+    #[tokio::test]
+    async fn second_factor_takes_only_the_challenge_users_passkey() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        let other_id = Id::new();
+        let mut authenticator = Authenticator::new(1);
+        let mut other_authenticator = Authenticator::new(2);
+        register(&webauthn, &repo, user_id, &authenticator)
+            .await
+            .unwrap();
+        register(&webauthn, &repo, other_id, &other_authenticator)
+            .await
+            .unwrap();
+
+        let (ceremony_id, assertion) =
+            sign_in(&webauthn, &repo, other_id, &mut other_authenticator).await;
+        let result =
+            verify_second_factor(&webauthn, &repo, &user_id, &ceremony_id, assertion).await;
+        assert!(is_unauthorized(&result.err().unwrap()));
+
+        let (ceremony_id, assertion) = sign_in(&webauthn, &repo, user_id, &mut authenticator).await;
+        let passkey = verify_second_factor(&webauthn, &repo, &user_id, &ceremony_id, assertion)
+            .await
+            .unwrap();
+        assert_eq!(passkey.name, "Phone");
+    }
+
+    #[tokio::test]
+    async fn deletes_only_the_users_own_passkeys() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        let info = register(&webauthn, &repo, user_id, &Authenticator::new(1))
+            .await
+            .unwrap();
+
+        let result = delete_passkey_internal(&repo, &Id::new(), &info.id.into()).await;
+        assert!(matches!(result, Err(Error::PasskeyNotFound)));
+
+        delete_passkey_internal(&repo, &user_id, &info.id.into())
+            .await
+            .unwrap();
+        let options = start_authentication(&webauthn, &repo, user_id, expires_at())
+            .await
+            .unwrap();
+        assert!(options.is_none());
+    }
+
+    #[tokio::test]
+    async fn changing_passkeys_takes_reauthentication() {
+        let Some(db) = crate::database::tests::test_db().await else {
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let state = crate::tests::test_state(db, dir.path(), &[]).await;
+        let user_id = crate::auth::new_user(&state.db).await;
+        // credential ids are unique across the database
+        let authenticator = Authenticator {
+            credential_id: uuid::Uuid::from(Id::new()).as_bytes().to_vec(),
+            ..Authenticator::new(7)
+        };
+        let info = register(&webauthn(), &repository(&state), user_id, &authenticator)
+            .await
+            .unwrap();
+        let passkey_id = Id::from(info.id);
+
+        let request = SavePasskeyKeyRequest {
+            private_key: "sealed".to_string(),
+            code: None,
+            password: None,
+        };
+        let result = save_private_key(
+            State(state.clone()),
+            Session::new(user_id),
+            Path(passkey_id),
+            Json(request),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ReauthenticationRequired)));
+
+        let result = delete_passkey(
+            State(state.clone()),
+            Session::new(user_id),
+            Path(passkey_id),
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::ReauthenticationRequired)));
+        let request = DeletePasskeyRequest {
+            code: None,
+            password: Some("wrong".to_string()),
+        };
+        let result = delete_passkey(
+            State(state.clone()),
+            Session::new(user_id),
+            Path(passkey_id),
+            Some(Json(request)),
+        )
+        .await;
+        assert!(result.as_ref().is_err_and(is_unauthorized));
+
+        let passkeys = repository(&state).list_passkeys(&user_id).await.unwrap();
+        assert_eq!(passkeys.len(), 1);
+        assert!(passkeys[0].wrapped_private_key.is_none());
+    }
+
+    #[tokio::test]
+    async fn decoy_options_look_like_real_ones() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let user_id = Id::new();
+        register(&webauthn, &repo, user_id, &Authenticator::new(1))
+            .await
+            .unwrap();
+        let real = start_authentication(&webauthn, &repo, user_id, expires_at())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let decoy = decoy_options(&webauthn, "mallory").unwrap();
+
+        let keys = |options: &serde_json::Value| {
+            let mut keys: Vec<String> = options["publicKey"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(&decoy.options), keys(&real.options));
+        assert_eq!(
+            decoy.options["publicKey"]["allowCredentials"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            decoy.options.get("mediation"),
+            real.options.get("mediation")
+        );
+        assert_eq!(decoy.prf_salts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn decoy_options_stay_the_same_for_a_username() {
+        let webauthn = webauthn();
+        let credentials = |options: &PasskeyLoginOptions| {
+            options.options["publicKey"]["allowCredentials"].clone()
+        };
+
+        let first = decoy_options(&webauthn, "mallory").unwrap();
+        let again = decoy_options(&webauthn, "mallory").unwrap();
+        let other = decoy_options(&webauthn, "trent").unwrap();
+
+        assert_eq!(credentials(&first), credentials(&again));
+        assert_eq!(first.prf_salts, again.prf_salts);
+        assert_ne!(credentials(&first), credentials(&other));
+        assert_ne!(
+            first.options["publicKey"]["challenge"],
+            again.options["publicKey"]["challenge"]
+        );
+    }
+
+    #[tokio::test]
+    async fn decoy_ceremonies_cannot_be_finished() {
+        let webauthn = webauthn();
+        let repo = InMemoryPasskeyRepository::new();
+        let decoy = decoy_options(&webauthn, "mallory").unwrap();
+
+        let assertion = Authenticator::new(1).assert(&decoy.options);
+        let result = verify_assertion(&webauthn, &repo, &decoy.ceremony_id.into(), assertion).await;
+
+        assert!(matches!(result, Err(Error::CeremonyNotFound)));
+    }
+}
@@ -0,0 +1,59 @@
+//! Passkeys (WebAuthn), as the first factor of a sign-in or as the second
+//! factor of a password login. Passkeys always verify the user, so signing
+//! in with one alone skips TOTP; having one makes password logins ask for a
+//! second factor, like TOTP does.
+//!
+//! A passkey is a user account with provider `passkey` and its base64url
+//! credential id as account id; the credential itself is kept in
+//! `passkeys`. It can also carry a copy of the private key sealed under its
+//! PRF extension output (see [`sdk::crypto::prf`]), handed back whenever it
+//! signs in so the client can unlock without the passphrase.
+
+mod handlers;
+mod repository;
+mod routes;
+
+pub(crate) use routes::routes;
+
+use tracing::error;
+use webauthn_rs::prelude::{Url, Webauthn, WebauthnBuilder};
+
+use crate::config::WebauthnConfig;
+use crate::error::{Error, Result};
+
+/// The relying party passkeys belong to, or `None` while passkeys aren't
+/// configured.
+pub(crate) fn relying_party(config: &WebauthnConfig) -> Result<Option<Webauthn>> {
+    let Some(rp_id) = &config.rp_id else {
+        return Ok(None);
+    };
+    let origins = config
+        .origins
+        .iter()
+        .map(|origin| Url::parse(origin))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| {
+            error!(error = %e, "Invalid WebAuthn origin");
+            Error::Configuration
+        })?;
+    let Some((primary, others)) = origins.split_first() else {
+        error!("WebAuthn needs at least one origin");
+        return Err(Error::Configuration);
+    };
+
+    let builder = WebauthnBuilder::new(rp_id, primary).map_err(|e| {
+        error!(error = %e, "Invalid WebAuthn relying party");
+        Error::Configuration
+    })?;
+    others
+        .iter()
+        .fold(builder.rp_name(&config.rp_name), |builder, origin| {
+            builder.append_allowed_origin(origin)
+        })
+        .build()
+        .map(Some)
+        .map_err(|e| {
+            error!(error = %e, "Invalid WebAuthn relying party");
+            Error::Configuration
+        })
+}
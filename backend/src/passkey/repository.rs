@@ -0,0 +1,444 @@
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use time::OffsetDateTime;
+use tracing::error;
+use webauthn_rs::prelude::Passkey;
+
+use crate::database::DbPool;
+use crate::entity::prelude::{AppUsers, PasskeyCeremonies, Passkeys, UserAccounts};
+use crate::entity::{passkey_ceremonies, passkeys, sea_orm_active_enums::Provider, user_accounts};
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+#[derive(Clone)]
+pub(crate) struct PasskeyRow {
+    pub id: Id,
+    pub name: String,
+    pub passkey: Passkey,
+    pub prf_salt: String,
+    pub wrapped_private_key: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+/// A passkey about to be stored, once its registration finished.
+pub(crate) struct NewPasskey<'a> {
+    pub user_id: Id,
+    /// The base64url credential id, which is also the account id.
+    pub credential_id: &'a str,
+    pub name: &'a str,
+    pub passkey: &'a Passkey,
+    pub prf_salt: &'a str,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum CeremonyKind {
+    Registration,
+    Authentication,
+}
+
+impl CeremonyKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            CeremonyKind::Registration => "registration",
+            CeremonyKind::Authentication => "authentication",
+        }
+    }
+}
+
+pub(crate) trait PasskeyRepository {
+    /// The user's passkeys, oldest first.
+    async fn list_passkeys(&self, user_id: &Id) -> Result<Vec<PasskeyRow>>;
+    /// Returns the new passkey's id, or `None` if the credential is already
+    /// registered.
+    async fn save_passkey(&self, passkey: NewPasskey<'_>) -> Result<Option<Id>>;
+    /// Record a sign-in with the passkey, along with its credential if the
+    /// sign-in changed it (e.g. its signature counter).
+    async fn record_use(&self, id: &Id, updated: Option<&Passkey>) -> Result<()>;
+    /// Returns whether the user has that passkey.
+    async fn save_wrapped_key(&self, user_id: &Id, id: &Id, wrapped_key: &str) -> Result<bool>;
+    /// Returns whether the user had that passkey.
+    async fn delete_passkey(&self, user_id: &Id, id: &Id) -> Result<bool>;
+    async fn user_name(&self, user_id: &Id) -> Result<Option<String>>;
+    /// Park a ceremony's state until its second round trip. Expired ones are
+    /// cleared out on the way.
+    async fn save_ceremony(
+        &self,
+        id: &Id,
+        user_id: &Id,
+        kind: CeremonyKind,
+        state: String,
+        expires_at: OffsetDateTime,
+    ) -> Result<()>;
+    /// Remove and return the unexpired ceremony's user and state, so each
+    /// completes once.
+    async fn take_ceremony(&self, id: &Id, kind: CeremonyKind) -> Result<Option<(Id, String)>>;
+}
+
+pub(crate) struct DbPasskeyRepository {
+    pub db: DbPool,
+}
+
+impl PasskeyRepository for DbPasskeyRepository {
+    async fn list_passkeys(&self, user_id: &Id) -> Result<Vec<PasskeyRow>> {
+        let rows = Passkeys::find()
+            .filter(passkeys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .order_by_asc(passkeys::Column::CreatedAt)
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list passkeys");
+                Error::Database
+            })?;
+
+        rows.into_iter()
+            .map(|row| {
+                let passkey = serde_json::from_str(&row.credential).map_err(|e| {
+                    error!(error = %e, "Stored passkey credential is invalid");
+                    Error::Database
+                })?;
+                Ok(PasskeyRow {
+                    id: Id::from(row.id),
+                    name: row.name,
+                    passkey,
+                    prf_salt: row.prf_salt,
+                    wrapped_private_key: row.wrapped_private_key,
+                    created_at: row.created_at,
+                    last_used_at: row.last_used_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_passkey(&self, passkey: NewPasskey<'_>) -> Result<Option<Id>> {
+        let id = Id::new();
+        let user_id = uuid::Uuid::from(passkey.user_id);
+        let account_id = passkey.credential_id.to_string();
+        let credential = serde_json::to_string(passkey.passkey).map_err(|e| {
+            error!(error = %e, "Could not serialize passkey credential");
+            Error::Database
+        })?;
+        let name = passkey.name.to_string();
+        let prf_salt = passkey.prf_salt.to_string();
+
+        self.db
+            .transaction::<_, Option<Id>, sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let registered = UserAccounts::find()
+                        .filter(user_accounts::Column::AccountId.eq(account_id.as_str()))
+                        .filter(user_accounts::Column::Provider.eq(Provider::Passkey))
+                        .one(txn)
+                        .await?
+                        .is_some();
+                    if registered {
+                        return Ok(None);
+                    }
+
+                    user_accounts::ActiveModel {
+                        id: Set(uuid::Uuid::from(id)),
+                        user_id: Set(user_id),
+                        account_id: Set(account_id),
+                        password: Set(None),
+                        provider: Set(Provider::Passkey),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+
+                    passkeys::ActiveModel {
+                        id: Set(uuid::Uuid::from(id)),
+                        user_id: Set(user_id),
+                        name: Set(name),
+                        credential: Set(credential),
+                        prf_salt: Set(prf_salt),
+                        wrapped_private_key: Set(None),
+                        last_used_at: Set(None),
+                        ..Default::default()
+                    }
+                    .insert(txn)
+                    .await?;
+
+                    Ok(Some(id))
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not save passkey");
+                Error::Database
+            })
+    }
+
+    async fn record_use(&self, id: &Id, updated: Option<&Passkey>) -> Result<()> {
+        let mut update = Passkeys::update_many().col_expr(
+            passkeys::Column::LastUsedAt,
+            Expr::value(OffsetDateTime::now_utc()),
+        );
+        if let Some(passkey) = updated {
+            let credential = serde_json::to_string(passkey).map_err(|e| {
+                error!(error = %e, "Could not serialize passkey credential");
+                Error::Database
+            })?;
+            update = update.col_expr(passkeys::Column::Credential, Expr::value(credential));
+        }
+
+        update
+            .filter(passkeys::Column::Id.eq(uuid::Uuid::from(*id)))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not record passkey use");
+                Error::Database
+            })?;
+
+        Ok(())
+    }
+
+    async fn save_wrapped_key(&self, user_id: &Id, id: &Id, wrapped_key: &str) -> Result<bool> {
+        let result = Passkeys::update_many()
+            .col_expr(
+                passkeys::Column::WrappedPrivateKey,
+                Expr::value(wrapped_key),
+            )
+            .filter(passkeys::Column::Id.eq(uuid::Uuid::from(*id)))
+            .filter(passkeys::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not save passkey private key");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn delete_passkey(&self, user_id: &Id, id: &Id) -> Result<bool> {
+        // the passkey row goes with its account
+        let result = UserAccounts::delete_many()
+            .filter(user_accounts::Column::Id.eq(uuid::Uuid::from(*id)))
+            .filter(user_accounts::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(user_accounts::Column::Provider.eq(Provider::Passkey))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete passkey");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn user_name(&self, user_id: &Id) -> Result<Option<String>> {
+        let user = AppUsers::find_by_id(uuid::Uuid::from(*user_id))
+            .one(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query user");
+                Error::Database
+            })?;
+
+        Ok(user.and_then(|user| user.name))
+    }
+
+    async fn save_ceremony(
+        &self,
+        id: &Id,
+        user_id: &Id,
+        kind: CeremonyKind,
+        state: String,
+        expires_at: OffsetDateTime,
+    ) -> Result<()> {
+        PasskeyCeremonies::delete_many()
+            .filter(passkey_ceremonies::Column::ExpiresAt.lte(OffsetDateTime::now_utc()))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete expired passkey ceremonies");
+                Error::Database
+            })?;
+
+        passkey_ceremonies::ActiveModel {
+            id: Set(uuid::Uuid::from(*id)),
+            user_id: Set(uuid::Uuid::from(*user_id)),
+            kind: Set(kind.as_str().to_string()),
+            state: Set(state),
+            expires_at: Set(expires_at),
+        }
+        .insert(&self.db)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save passkey ceremony");
+            Error::Database
+        })?;
+
+        Ok(())
+    }
+
+    async fn take_ceremony(&self, id: &Id, kind: CeremonyKind) -> Result<Option<(Id, String)>> {
+        let row = PasskeyCeremonies::find_by_id(uuid::Uuid::from(*id))
+            .one(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not query passkey ceremony");
+                Error::Database
+            })?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let result = PasskeyCeremonies::delete_many()
+            .filter(passkey_ceremonies::Column::Id.eq(row.id))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not delete passkey ceremony");
+                Error::Database
+            })?;
+        // a concurrent request got it first
+        if result.rows_affected == 0 {
+            return Ok(None);
+        }
+        if row.kind != kind.as_str() || row.expires_at <= OffsetDateTime::now_utc() {
+            return Ok(None);
+        }
+
+        Ok(Some((Id::from(row.user_id), row.state)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Ceremony {
+        id: Id,
+        user_id: Id,
+        kind: &'static str,
+        state: String,
+        expires_at: OffsetDateTime,
+    }
+
+    pub struct InMemoryPasskeyRepository {
+        /// Passkeys, tagged with the user they belong to.
+        pub passkeys: RefCell<Vec<(Id, PasskeyRow)>>,
+        ceremonies: RefCell<Vec<Ceremony>>,
+    }
+
+    impl InMemoryPasskeyRepository {
+        pub fn new() -> Self {
+            Self {
+                passkeys: RefCell::new(Vec::new()),
+                ceremonies: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl PasskeyRepository for InMemoryPasskeyRepository {
+        async fn list_passkeys(&self, user_id: &Id) -> Result<Vec<PasskeyRow>> {
+            Ok(self
+                .passkeys
+                .borrow()
+                .iter()
+                .filter(|(owner, _)| owner == user_id)
+                .map(|(_, row)| row.clone())
+                .collect())
+        }
+
+        async fn save_passkey(&self, passkey: NewPasskey<'_>) -> Result<Option<Id>> {
+            let mut passkeys = self.passkeys.borrow_mut();
+            let registered = passkeys
+                .iter()
+                .any(|(_, row)| row.passkey.cred_id() == passkey.passkey.cred_id());
+            if registered {
+                return Ok(None);
+            }
+
+            let id = Id::new();
+            passkeys.push((
+                passkey.user_id,
+                PasskeyRow {
+                    id,
+                    name: passkey.name.to_string(),
+                    passkey: passkey.passkey.clone(),
+                    prf_salt: passkey.prf_salt.to_string(),
+                    wrapped_private_key: None,
+                    created_at: OffsetDateTime::now_utc(),
+                    last_used_at: None,
+                },
+            ));
+            Ok(Some(id))
+        }
+
+        async fn record_use(&self, id: &Id, updated: Option<&Passkey>) -> Result<()> {
+            if let Some((_, row)) = self
+                .passkeys
+                .borrow_mut()
+                .iter_mut()
+                .find(|(_, row)| row.id == *id)
+            {
+                row.last_used_at = Some(OffsetDateTime::now_utc());
+                if let Some(passkey) = updated {
+                    row.passkey = passkey.clone();
+                }
+            }
+            Ok(())
+        }
+
+        async fn save_wrapped_key(&self, user_id: &Id, id: &Id, wrapped_key: &str) -> Result<bool> {
+            let mut passkeys = self.passkeys.borrow_mut();
+            let Some((_, row)) = passkeys
+                .iter_mut()
+                .find(|(owner, row)| owner == user_id && row.id == *id)
+            else {
+                return Ok(false);
+            };
+            row.wrapped_private_key = Some(wrapped_key.to_string());
+            Ok(true)
+        }
+
+        async fn delete_passkey(&self, user_id: &Id, id: &Id) -> Result<bool> {
+            let mut passkeys = self.passkeys.borrow_mut();
+            let before = passkeys.len();
+            passkeys.retain(|(owner, row)| !(owner == user_id && row.id == *id));
+            Ok(passkeys.len() < before)
+        }
+
+        async fn user_name(&self, _user_id: &Id) -> Result<Option<String>> {
+            Ok(Some("alice".to_string()))
+        }
+
+        async fn save_ceremony(
+            &self,
+            id: &Id,
+            user_id: &Id,
+            kind: CeremonyKind,
+            state: String,
+            expires_at: OffsetDateTime,
+        ) -> Result<()> {
+            let mut ceremonies = self.ceremonies.borrow_mut();
+            ceremonies.retain(|ceremony| ceremony.expires_at > OffsetDateTime::now_utc());
+            ceremonies.push(Ceremony {
+                id: *id,
+                user_id: *user_id,
+                kind: kind.as_str(),
+                state,
+                expires_at,
+            });
+            Ok(())
+        }
+
+        async fn take_ceremony(&self, id: &Id, kind: CeremonyKind) -> Result<Option<(Id, String)>> {
+            let mut ceremonies = self.ceremonies.borrow_mut();
+            let Some(index) = ceremonies.iter().position(|ceremony| ceremony.id == *id) else {
+                return Ok(None);
+            };
+            let ceremony = ceremonies.remove(index);
+            if ceremony.kind != kind.as_str() || ceremony.expires_at <= OffsetDateTime::now_utc() {
+                return Ok(None);
+            }
+            Ok(Some((ceremony.user_id, ceremony.state)))
+        }
+    }
+}
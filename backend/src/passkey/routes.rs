@@ -0,0 +1,33 @@
+use axum::{
+    Router,
+    routing::{delete, get, post, put},
+};
+
+use crate::AppState;
+
+use super::handlers;
+
+/// Mounted under `/auth/passkeys`.
+pub(crate) fn routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", get(handlers::list_passkeys))
+        .route("/register", post(handlers::start_registration))
+        .route("/register/finish", post(handlers::finish_registration))
+        .route("/{passkey_id}", delete(handlers::delete_passkey))
+        .route("/{passkey_id}/private-key", put(handlers::save_private_key))
+        .route_layer(axum::middleware::from_fn(
+            crate::auth::middleware::require_auth,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            crate::auth::middleware::session_resolver,
+        ))
+        .route("/login", post(handlers::start_login))
+        .route("/login/finish", post(handlers::finish_login))
+        .route("/second-factor", post(handlers::start_second_factor))
+        .route(
+            "/second-factor/finish",
+            post(handlers::finish_second_factor),
+        )
+        .with_state(app_state)
+}
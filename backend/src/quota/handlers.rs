@@ -0,0 +1,228 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use sdk::dtos::quota::{MediaUsage, SetQuotaRequest, UsageResponse};
+use sdk::media::MediaType;
+use tracing::{debug, error, info};
+
+use super::Usage;
+use super::repository::{DbQuotaRepository, QuotaRepository, UserQuota};
+use crate::{
+    AppState,
+    error::{Error, Result},
+    session::Session,
+    ulid::Id,
+};
+
+pub(super) async fn get_usage(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Json<UsageResponse>> {
+    debug!("Getting storage usage");
+
+    let repo = DbQuotaRepository { db: state.db };
+    let usage = usage_internal(&repo, &session.user_id(), state.config.quota.default_bytes).await?;
+
+    Ok(Json(usage))
+}
+
+pub(super) async fn get_user_usage(
+    State(state): State<AppState>,
+    Path(user_id): Path<Id>,
+) -> Result<Json<UsageResponse>> {
+    debug!(%user_id, "Getting storage usage of user");
+
+    let repo = DbQuotaRepository { db: state.db };
+    let usage = usage_internal(&repo, &user_id, state.config.quota.default_bytes).await?;
+
+    Ok(Json(usage))
+}
+
+pub(super) async fn set_user_quota(
+    State(state): State<AppState>,
+    Path(user_id): Path<Id>,
+    Json(request): Json<SetQuotaRequest>,
+) -> Result<Json<UsageResponse>> {
+    info!(%user_id, quota_bytes = ?request.quota_bytes, "Setting user quota");
+
+    let repo = DbQuotaRepository { db: state.db };
+    if !repo.set_user_quota(&user_id, request.quota_bytes).await? {
+        error!(%user_id, "User not found");
+        return Err(Error::UserNotFound);
+    }
+    let usage = usage_internal(&repo, &user_id, state.config.quota.default_bytes).await?;
+
+    Ok(Json(usage))
+}
+
+/// Quota in effect for the user: their override, else the server default.
+async fn effective_quota(
+    repo: &impl QuotaRepository,
+    user_id: &Id,
+    default_quota: Option<u64>,
+) -> Result<Option<u64>> {
+    let user = repo.find_user_quota(user_id).await?.ok_or_else(|| {
+        error!(%user_id, "User not found");
+        Error::UserNotFound
+    })?;
+
+    Ok(user.quota_bytes.or(default_quota))
+}
+
+async fn usage_internal(
+    repo: &impl QuotaRepository,
+    user_id: &Id,
+    default_quota: Option<u64>,
+) -> Result<UsageResponse> {
+    let quota_bytes = effective_quota(repo, user_id, default_quota).await?;
+    let usage = repo.usage(user_id).await?;
+
+    // Report every media type, including those the user has none of.
+    let by_media_type = [MediaType::Image, MediaType::Video]
+        .into_iter()
+        .map(|media_type| {
+            let entry = usage
+                .by_media_type
+                .iter()
+                .find(|u| u.media_type == media_type);
+            MediaUsage {
+                media_type,
+                count: entry.map_or(0, |u| u.count),
+                bytes: entry.map_or(0, |u| u.bytes),
+            }
+        })
+        .collect();
+
+    Ok(UsageResponse {
+        used_bytes: usage.stored_bytes(),
+        pending_bytes: usage.pending_bytes,
+        quota_bytes,
+        by_media_type,
+    })
+}
+
+/// Fails with [`Error::QuotaExceeded`] unless `needed` more bytes fit in the
+/// user's quota on top of what they store and have reserved for uploads.
+/// To hold, `usage` must stay current until the reservation is made; see
+/// [`super::repository::lock_user_usage`].
+pub(crate) fn check_quota(
+    user_id: &Id,
+    quota: UserQuota,
+    default_quota: Option<u64>,
+    usage: &Usage,
+    needed: u64,
+) -> Result<()> {
+    let Some(quota_bytes) = quota.quota_bytes.or(default_quota) else {
+        return Ok(());
+    };
+
+    let used = usage.total_bytes();
+    if used.saturating_add(needed) > quota_bytes {
+        error!(%user_id, used, needed, quota_bytes, "Quota exceeded");
+        return Err(Error::QuotaExceeded);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota::MediaUsage as DomainMediaUsage;
+    use crate::quota::repository::tests::InMemoryQuotaRepository;
+
+    fn usage(image_bytes: u64, pending_bytes: u64) -> Usage {
+        Usage {
+            by_media_type: vec![DomainMediaUsage {
+                media_type: MediaType::Image,
+                count: 3,
+                bytes: image_bytes,
+            }],
+            pending_bytes,
+        }
+    }
+
+    #[test]
+    fn check_quota_allows_upload_that_fits() {
+        let user_id = Id::new();
+        let quota = UserQuota {
+            quota_bytes: Some(1000),
+        };
+
+        let result = check_quota(&user_id, quota, None, &usage(600, 100), 300);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn check_quota_counts_pending_uploads() {
+        let user_id = Id::new();
+        let quota = UserQuota {
+            quota_bytes: Some(1000),
+        };
+
+        let result = check_quota(&user_id, quota, None, &usage(600, 100), 301);
+
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+    }
+
+    #[test]
+    fn check_quota_falls_back_to_default() {
+        let user_id = Id::new();
+        let quota = UserQuota { quota_bytes: None };
+
+        let result = check_quota(&user_id, quota, Some(500), &usage(600, 0), 1);
+
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+    }
+
+    #[test]
+    fn check_quota_override_beats_default() {
+        let user_id = Id::new();
+        let quota = UserQuota {
+            quota_bytes: Some(10_000),
+        };
+
+        let result = check_quota(&user_id, quota, Some(500), &usage(600, 0), 1000);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn check_quota_without_quota_is_unlimited() {
+        let user_id = Id::new();
+        let quota = UserQuota { quota_bytes: None };
+
+        let result = check_quota(&user_id, quota, None, &usage(600, 0), u64::MAX);
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn usage_reports_every_media_type() {
+        let user_id = Id::new();
+        let repo = InMemoryQuotaRepository::new(user_id, None, usage(600, 100));
+
+        let response = usage_internal(&repo, &user_id, Some(5000)).await.unwrap();
+
+        assert_eq!(response.used_bytes, 600);
+        assert_eq!(response.pending_bytes, 100);
+        assert_eq!(response.quota_bytes, Some(5000));
+        let video = response
+            .by_media_type
+            .iter()
+            .find(|u| u.media_type == MediaType::Video)
+            .unwrap();
+        assert_eq!((video.count, video.bytes), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn usage_of_unknown_user_is_not_found() {
+        let repo = InMemoryQuotaRepository::new(Id::new(), None, Usage::default());
+
+        let result = usage_internal(&repo, &Id::new(), None).await;
+
+        assert!(matches!(result, Err(Error::UserNotFound)));
+    }
+}
@@ -0,0 +1,59 @@
+//! Per-user storage quotas. A user's quota is their admin-set override or,
+//! failing that, the server default; no quota at all means unlimited.
+//!
+//! Usage counts what synced and trashed files occupy in storage plus what
+//! uploads in progress have reserved. An upload is checked and reserved in
+//! one transaction holding the user row, so uploads initialised at the same
+//! time can't together overshoot the quota.
+
+mod handlers;
+pub(crate) mod repository;
+mod routes;
+
+pub(crate) use handlers::check_quota;
+pub(crate) use routes::{admin_routes, routes};
+
+use sdk::media::{MediaType, required_variants};
+
+use crate::file::File;
+
+/// Room held back per thumbnail variant while a file uploads. Thumbnails are
+/// PUT straight to storage, so their real sizes are only measured once the
+/// upload completes.
+pub(crate) const THUMBNAIL_RESERVATION: u64 = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MediaUsage {
+    pub media_type: MediaType,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Usage {
+    /// Synced and trashed files, per media type.
+    pub by_media_type: Vec<MediaUsage>,
+    /// Bytes reserved by uploads in progress.
+    pub pending_bytes: u64,
+}
+
+impl Usage {
+    pub fn stored_bytes(&self) -> u64 {
+        self.by_media_type.iter().map(|u| u.bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.stored_bytes() + self.pending_bytes
+    }
+}
+
+/// Bytes to reserve for thumbnails of a file of the given media type.
+pub(crate) fn thumbnail_reservation(media_type: MediaType) -> u64 {
+    required_variants(media_type).len() as u64 * THUMBNAIL_RESERVATION
+}
+
+/// Bytes an upload of `file` needs to fit in the quota: the ciphertext of the
+/// original plus room for its thumbnails.
+pub(crate) fn reservation(file: &File) -> Option<u64> {
+    Some(file.ciphertext_size()? + thumbnail_reservation(file.media_type))
+}
@@ -0,0 +1,195 @@
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, JoinType, QueryFilter, QuerySelect,
+    RelationTrait,
+};
+use tracing::error;
+
+use sdk::media::MediaType;
+
+use crate::database::DbPool;
+use crate::entity::prelude::{AppUsers, Files, UploadSessions};
+use crate::entity::sea_orm_active_enums::{FileState, MediaType as EntityMediaType};
+use crate::entity::{app_users, files, upload_sessions};
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+
+use super::{MediaUsage, Usage, thumbnail_reservation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UserQuota {
+    /// Admin override; `None` falls back to the server default.
+    pub quota_bytes: Option<u64>,
+}
+
+pub(crate) trait QuotaRepository {
+    /// `None` if the user doesn't exist.
+    async fn find_user_quota(&self, user_id: &Id) -> Result<Option<UserQuota>>;
+    /// Returns whether the user exists.
+    async fn set_user_quota(&self, user_id: &Id, quota_bytes: Option<u64>) -> Result<bool>;
+    async fn usage(&self, user_id: &Id) -> Result<Usage>;
+}
+
+pub(crate) struct DbQuotaRepository {
+    pub db: DbPool,
+}
+
+impl QuotaRepository for DbQuotaRepository {
+    async fn find_user_quota(&self, user_id: &Id) -> Result<Option<UserQuota>> {
+        let user = AppUsers::find_by_id(uuid::Uuid::from(*user_id))
+            .one(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get user quota");
+                Error::Database
+            })?;
+
+        Ok(user.map(|u| UserQuota {
+            quota_bytes: u.quota_bytes.map(|q| q as u64),
+        }))
+    }
+
+    async fn set_user_quota(&self, user_id: &Id, quota_bytes: Option<u64>) -> Result<bool> {
+        let quota_bytes = quota_bytes.map(|q| i64::try_from(q).unwrap_or(i64::MAX));
+        let result = AppUsers::update_many()
+            .col_expr(app_users::Column::QuotaBytes, Expr::value(quota_bytes))
+            .filter(app_users::Column::Id.eq(uuid::Uuid::from(*user_id)))
+            .exec(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not set user quota");
+                Error::Database
+            })?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn usage(&self, user_id: &Id) -> Result<Usage> {
+        usage(&self.db, user_id).await.map_err(|e| {
+            error!(error = %e, "Could not get usage");
+            Error::Database
+        })
+    }
+}
+
+/// The user's quota and usage, `None` if the user doesn't exist. Locks the
+/// user row until the transaction it runs in ends, so uploads initialised
+/// at the same time are checked against each other's reservations.
+pub(crate) async fn lock_user_usage<C: ConnectionTrait>(
+    conn: &C,
+    user_id: &Id,
+) -> std::result::Result<Option<(UserQuota, Usage)>, DbErr> {
+    let Some(user) = AppUsers::find_by_id(uuid::Uuid::from(*user_id))
+        .lock_exclusive()
+        .one(conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let quota = UserQuota {
+        quota_bytes: user.quota_bytes.map(|q| q as u64),
+    };
+
+    Ok(Some((quota, usage(conn, user_id).await?)))
+}
+
+async fn usage<C: ConnectionTrait>(conn: &C, user_id: &Id) -> std::result::Result<Usage, DbErr> {
+    let owner_id = uuid::Uuid::from(*user_id);
+
+    let stored: Vec<(EntityMediaType, i64, i64)> = Files::find()
+        .select_only()
+        .column(files::Column::MediaType)
+        .column_as(files::Column::Id.count(), "count")
+        .column_as(
+            Expr::cust("CAST(COALESCE(SUM(stored_size), 0) AS BIGINT)"),
+            "bytes",
+        )
+        .filter(files::Column::OwnerId.eq(owner_id))
+        .filter(files::Column::State.is_in([FileState::Synced, FileState::Trashed]))
+        .group_by(files::Column::MediaType)
+        .into_tuple()
+        .all(conn)
+        .await?;
+
+    let pending: Vec<(EntityMediaType, i64, i64)> = UploadSessions::find()
+        .select_only()
+        .column(files::Column::MediaType)
+        .column_as(upload_sessions::Column::FileId.count(), "count")
+        .column_as(
+            Expr::cust("CAST(COALESCE(SUM(upload_sessions.total_size), 0) AS BIGINT)"),
+            "bytes",
+        )
+        .join(JoinType::InnerJoin, upload_sessions::Relation::Files.def())
+        .filter(files::Column::OwnerId.eq(owner_id))
+        .group_by(files::Column::MediaType)
+        .into_tuple()
+        .all(conn)
+        .await?;
+
+    let by_media_type = stored
+        .into_iter()
+        .map(|(media_type, count, bytes)| MediaUsage {
+            media_type: media_type.into(),
+            count: count as u64,
+            bytes: bytes as u64,
+        })
+        .collect();
+    let pending_bytes = pending
+        .into_iter()
+        .map(|(media_type, count, bytes)| {
+            let media_type = MediaType::from(media_type);
+            bytes as u64 + count as u64 * thumbnail_reservation(media_type)
+        })
+        .sum();
+
+    Ok(Usage {
+        by_media_type,
+        pending_bytes,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    pub struct InMemoryQuotaRepository {
+        pub quotas: RefCell<HashMap<Id, Option<u64>>>,
+        pub usage: Usage,
+    }
+
+    impl InMemoryQuotaRepository {
+        pub fn new(user_id: Id, quota_bytes: Option<u64>, usage: Usage) -> Self {
+            Self {
+                quotas: RefCell::new(HashMap::from([(user_id, quota_bytes)])),
+                usage,
+            }
+        }
+    }
+
+    impl QuotaRepository for InMemoryQuotaRepository {
+        async fn find_user_quota(&self, user_id: &Id) -> Result<Option<UserQuota>> {
+            Ok(self
+                .quotas
+                .borrow()
+                .get(user_id)
+                .map(|&quota_bytes| UserQuota { quota_bytes }))
+        }
+
+        async fn set_user_quota(&self, user_id: &Id, quota_bytes: Option<u64>) -> Result<bool> {
+            match self.quotas.borrow_mut().get_mut(user_id) {
+                Some(quota) => {
+                    *quota = quota_bytes;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        async fn usage(&self, _user_id: &Id) -> Result<Usage> {
+            Ok(self.usage.clone())
+        }
+    }
+}
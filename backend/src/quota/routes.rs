@@ -0,0 +1,22 @@
+use axum::{
+    Router,
+    routing::{get, put},
+};
+
+use crate::AppState;
+
+use super::handlers;
+
+pub(crate) fn routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/usage", get(handlers::get_usage))
+        .with_state(app_state)
+}
+
+/// Mounted under `/admin` by [`crate::admin::routes`].
+pub(crate) fn admin_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/users/{user_id}/usage", get(handlers::get_user_usage))
+        .route("/users/{user_id}/quota", put(handlers::set_user_quota))
+        .with_state(app_state)
+}
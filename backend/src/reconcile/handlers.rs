@@ -0,0 +1,120 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use sdk::dtos::reconcile::{ReconcileReport, StrayUpload};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info, warn};
+
+use super::repository::{DbReconcileRepository, ReconcileRepository};
+use super::{Strays, find_strays, owner_prefix};
+use crate::AppState;
+use crate::error::Result;
+use crate::storage::StorageBackend;
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ReconcileParams {
+    /// Overrides the configured dry-run setting for this run.
+    dry_run: Option<bool>,
+}
+
+pub(super) async fn reconcile(
+    State(state): State<AppState>,
+    Query(params): Query<ReconcileParams>,
+) -> Result<Json<ReconcileReport>> {
+    let dry_run = params.dry_run.unwrap_or(state.config.reconcile.dry_run);
+    info!(dry_run, "Reconciling storage on request");
+
+    let repo = DbReconcileRepository {
+        db: state.db.clone(),
+    };
+    let grace = Duration::seconds(state.config.reconcile.grace_secs);
+    let strays = reconcile_internal(&repo, &state.storage, grace, dry_run).await?;
+
+    Ok(Json(ReconcileReport {
+        dry_run,
+        stray_objects: strays.objects,
+        stray_uploads: strays
+            .uploads
+            .into_iter()
+            .map(|u| StrayUpload {
+                key: u.key,
+                upload_id: u.upload_id,
+            })
+            .collect(),
+    }))
+}
+
+/// Find the strays of every owner and, unless `dry_run`, delete them.
+/// Failed deletions are logged and picked up again by the next run.
+async fn reconcile_internal(
+    repo: &impl ReconcileRepository,
+    storage: &impl StorageBackend,
+    grace: Duration,
+    dry_run: bool,
+) -> Result<Strays> {
+    let cutoff = OffsetDateTime::now_utc() - grace;
+    let mut all = Strays::default();
+
+    for owner_id in repo.find_owner_ids().await? {
+        let prefix = owner_prefix(&owner_id);
+        // list storage before reading rows, so every listed object predates
+        // the rows it's checked against
+        let objects = storage.list_objects(&prefix).await?;
+        let uploads = storage.list_multipart_uploads(&prefix).await?;
+        let files = repo.find_owner_files(&owner_id).await?;
+        let sessions = repo.find_owner_sessions(&owner_id).await?;
+
+        let strays = find_strays(&owner_id, &files, &sessions, objects, uploads, cutoff);
+        for key in &strays.objects {
+            warn!(%owner_id, key, dry_run, "Stray object");
+            if !dry_run && let Err(e) = storage.delete_object(key).await {
+                error!(key, error = %e, "Failed to delete stray object");
+            }
+        }
+        for upload in &strays.uploads {
+            warn!(
+                %owner_id,
+                key = upload.key,
+                upload_id = upload.upload_id,
+                dry_run,
+                "Stray multipart upload"
+            );
+            if !dry_run
+                && let Err(e) = storage
+                    .abort_multipart_upload(&upload.key, &upload.upload_id)
+                    .await
+            {
+                error!(key = upload.key, error = %e, "Failed to abort stray upload");
+            }
+        }
+
+        all.extend(strays);
+    }
+
+    Ok(all)
+}
+
+pub(crate) async fn reconcile_storage(state: AppState) {
+    let config = &state.config.reconcile;
+    let grace = Duration::seconds(config.grace_secs);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        let repo = DbReconcileRepository {
+            db: state.db.clone(),
+        };
+
+        match reconcile_internal(&repo, &state.storage, grace, config.dry_run).await {
+            Ok(strays) if strays.is_empty() => {}
+            Ok(strays) => info!(
+                objects = strays.objects.len(),
+                uploads = strays.uploads.len(),
+                dry_run = config.dry_run,
+                "Storage reconciled"
+            ),
+            Err(e) => error!(error = %e, "Failed to reconcile storage"),
+        }
+    }
+}
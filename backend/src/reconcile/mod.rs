@@ -0,0 +1,314 @@
+//! Reconciliation of stored objects against the database. A crash between a
+//! storage call and the matching DB write (in `complete_upload`,
+//! `abort_upload` or an upload cleanup) can leave objects under
+//! `files/{owner}/{file}/…` that nothing accounts for. The job walks each
+//! owner's prefix and reports, or deletes:
+//!
+//! - objects of files without a row, and objects that are neither the
+//!   original nor a required thumbnail of their file
+//! - objects of `New` or `Failed` files without an upload session, i.e. left
+//!   behind by an upload that never finished
+//! - multipart uploads no upload session refers to
+//!
+//! Anything younger than the grace period is left alone, since an upload may
+//! be between its storage call and its DB write right now.
+
+mod handlers;
+pub(crate) mod repository;
+mod routes;
+
+use std::collections::{HashMap, HashSet};
+
+use sdk::media::required_variants;
+use time::OffsetDateTime;
+
+use crate::file::{File, FileState};
+use crate::storage::{PendingUpload, StoredObject, s3_original_key, s3_thumbnail_key};
+use crate::ulid::Id;
+
+pub(crate) use handlers::reconcile_storage;
+pub(crate) use routes::admin_routes;
+
+/// Storage prefix holding every object of the owner.
+pub(crate) fn owner_prefix(owner_id: &Id) -> String {
+    format!("files/{owner_id}/")
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Strays {
+    pub objects: Vec<String>,
+    pub uploads: Vec<PendingUpload>,
+}
+
+impl Strays {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.uploads.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Strays) {
+        self.objects.extend(other.objects);
+        self.uploads.extend(other.uploads);
+    }
+}
+
+/// The file a key under the owner's prefix belongs to.
+fn file_id_of(owner_id: &Id, key: &str) -> Option<Id> {
+    let (file_id, _) = key.strip_prefix(&owner_prefix(owner_id))?.split_once('/')?;
+    file_id.parse().ok()
+}
+
+/// Keys the file is expected to occupy: its original and every thumbnail.
+fn expected_keys(file: &File) -> HashSet<String> {
+    let mut keys: HashSet<String> = required_variants(file.media_type)
+        .iter()
+        .map(|variant| s3_thumbnail_key(file, &variant.to_string()))
+        .collect();
+    keys.insert(s3_original_key(file));
+    keys
+}
+
+/// Cross-reference one owner's objects and multipart uploads with their
+/// files and upload sessions (multipart upload id by file). Entries newer
+/// than `cutoff` are skipped.
+pub(crate) fn find_strays(
+    owner_id: &Id,
+    files: &HashMap<Id, File>,
+    sessions: &HashMap<Id, Option<String>>,
+    objects: Vec<StoredObject>,
+    uploads: Vec<PendingUpload>,
+    cutoff: OffsetDateTime,
+) -> Strays {
+    let objects = objects
+        .into_iter()
+        .filter(|object| object.last_modified < cutoff)
+        .filter(|object| {
+            let Some(file) = file_id_of(owner_id, &object.key).and_then(|id| files.get(&id)) else {
+                return true;
+            };
+            let unfinished = matches!(file.state, FileState::New | FileState::Failed)
+                && !sessions.contains_key(&file.id);
+            unfinished || !expected_keys(file).contains(&object.key)
+        })
+        .map(|object| object.key)
+        .collect();
+
+    let uploads = uploads
+        .into_iter()
+        .filter(|upload| upload.initiated < cutoff)
+        .filter(|upload| {
+            file_id_of(owner_id, &upload.key)
+                .and_then(|id| sessions.get(&id))
+                .is_none_or(|upload_id| upload_id.as_deref() != Some(upload.upload_id.as_str()))
+        })
+        .collect();
+
+    Strays { objects, uploads }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdk::media::MediaType;
+    use time::Duration;
+
+    fn make_file(owner_id: Id, state: FileState) -> File {
+        File {
+            id: Id::new(),
+            encrypted_metadata: None,
+            legacy_path: None,
+            legacy_name: None,
+            state,
+            created_at: OffsetDateTime::now_utc(),
+            added_at: OffsetDateTime::now_utc(),
+            content_hash: None,
+            owner_id,
+            uploader_id: owner_id,
+            enc_key: "key".to_string(),
+            media_type: MediaType::Image,
+            content_type: "image/jpeg".to_string(),
+            width: 640,
+            height: 480,
+            duration_ms: None,
+            segment_size: sdk::segment::DEFAULT_SEGMENT_SIZE,
+            plaintext_size: 1024,
+            nonce_salt: 0,
+            enc_scheme: sdk::crypto::ENC_SCHEME_SEGMENTED,
+            trashed_at: None,
+            stored_size: None,
+        }
+    }
+
+    fn old() -> OffsetDateTime {
+        OffsetDateTime::now_utc() - Duration::days(2)
+    }
+
+    fn object(key: String) -> StoredObject {
+        StoredObject {
+            key,
+            last_modified: old(),
+        }
+    }
+
+    fn upload(key: String, upload_id: &str) -> PendingUpload {
+        PendingUpload {
+            key,
+            upload_id: upload_id.to_string(),
+            initiated: old(),
+        }
+    }
+
+    fn cutoff() -> OffsetDateTime {
+        OffsetDateTime::now_utc() - Duration::days(1)
+    }
+
+    fn all_objects(file: &File) -> Vec<StoredObject> {
+        let mut keys: Vec<String> = expected_keys(file).into_iter().collect();
+        keys.sort();
+        keys.into_iter().map(object).collect()
+    }
+
+    #[test]
+    fn keeps_objects_of_synced_and_trashed_files() {
+        let owner_id = Id::new();
+        let synced = make_file(owner_id, FileState::Synced);
+        let trashed = make_file(owner_id, FileState::Trashed);
+        let mut objects = all_objects(&synced);
+        objects.extend(all_objects(&trashed));
+        let files = HashMap::from([(synced.id, synced), (trashed.id, trashed)]);
+
+        let strays = find_strays(
+            &owner_id,
+            &files,
+            &HashMap::new(),
+            objects,
+            vec![],
+            cutoff(),
+        );
+
+        assert!(strays.is_empty());
+    }
+
+    #[test]
+    fn reports_objects_without_a_row() {
+        let owner_id = Id::new();
+        let gone = make_file(owner_id, FileState::Synced);
+        let garbage = format!("{}not-a-file/original", owner_prefix(&owner_id));
+        let mut objects = all_objects(&gone);
+        objects.push(object(garbage.clone()));
+
+        let strays = find_strays(
+            &owner_id,
+            &HashMap::new(),
+            &HashMap::new(),
+            objects,
+            vec![],
+            cutoff(),
+        );
+
+        assert_eq!(strays.objects.len(), 4);
+        assert!(strays.objects.contains(&garbage));
+    }
+
+    #[test]
+    fn reports_unexpected_variants() {
+        let owner_id = Id::new();
+        let file = make_file(owner_id, FileState::Synced);
+        let unknown = s3_thumbnail_key(&file, "9999-cover");
+        let mut objects = all_objects(&file);
+        objects.push(object(unknown.clone()));
+        let files = HashMap::from([(file.id, file)]);
+
+        let strays = find_strays(
+            &owner_id,
+            &files,
+            &HashMap::new(),
+            objects,
+            vec![],
+            cutoff(),
+        );
+
+        assert_eq!(strays.objects, vec![unknown]);
+    }
+
+    #[test]
+    fn reports_leftovers_of_unfinished_uploads() {
+        let owner_id = Id::new();
+        let failed = make_file(owner_id, FileState::Failed);
+        let new = make_file(owner_id, FileState::New);
+        let objects = vec![
+            object(s3_original_key(&failed)),
+            object(s3_original_key(&new)),
+        ];
+        let files = HashMap::from([(failed.id, failed), (new.id, new)]);
+
+        let strays = find_strays(
+            &owner_id,
+            &files,
+            &HashMap::new(),
+            objects,
+            vec![],
+            cutoff(),
+        );
+
+        assert_eq!(strays.objects.len(), 2);
+    }
+
+    #[test]
+    fn keeps_objects_of_uploads_in_progress() {
+        let owner_id = Id::new();
+        let in_progress = make_file(owner_id, FileState::SyncInProgress);
+        let recovering = make_file(owner_id, FileState::SyncInProgress);
+        let mut objects = all_objects(&in_progress);
+        objects.extend(all_objects(&recovering));
+        let sessions = HashMap::from([(in_progress.id, Some("upload-1".to_string()))]);
+        let files = HashMap::from([(in_progress.id, in_progress), (recovering.id, recovering)]);
+
+        let strays = find_strays(&owner_id, &files, &sessions, objects, vec![], cutoff());
+
+        assert!(strays.is_empty());
+    }
+
+    #[test]
+    fn reports_multipart_uploads_without_a_session() {
+        let owner_id = Id::new();
+        let file = make_file(owner_id, FileState::SyncInProgress);
+        let key = s3_original_key(&file);
+        let uploads = vec![upload(key.clone(), "current"), upload(key, "abandoned")];
+        let sessions = HashMap::from([(file.id, Some("current".to_string()))]);
+        let files = HashMap::from([(file.id, file)]);
+
+        let strays = find_strays(&owner_id, &files, &sessions, vec![], uploads, cutoff());
+
+        assert!(strays.objects.is_empty());
+        assert_eq!(strays.uploads.len(), 1);
+        assert_eq!(strays.uploads[0].upload_id, "abandoned");
+    }
+
+    #[test]
+    fn skips_entries_within_the_grace_period() {
+        let owner_id = Id::new();
+        let file = make_file(owner_id, FileState::New);
+        let now = OffsetDateTime::now_utc();
+        let objects = vec![StoredObject {
+            key: s3_original_key(&file),
+            last_modified: now,
+        }];
+        let uploads = vec![PendingUpload {
+            key: s3_original_key(&file),
+            upload_id: "fresh".to_string(),
+            initiated: now,
+        }];
+        let files = HashMap::from([(file.id, file)]);
+
+        let strays = find_strays(
+            &owner_id,
+            &files,
+            &HashMap::new(),
+            objects,
+            uploads,
+            cutoff(),
+        );
+
+        assert!(strays.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use sea_orm::{ColumnTrait, EntityTrait, JoinType, QueryFilter, QuerySelect, RelationTrait};
+use tracing::error;
+
+use crate::database::DbPool;
+use crate::entity::prelude::{AppUsers, Files, UploadSessions};
+use crate::entity::{app_users, files, upload_sessions};
+use crate::error::{Error, Result};
+use crate::file::File;
+use crate::ulid::Id;
+
+pub(crate) trait ReconcileRepository {
+    async fn find_owner_ids(&self) -> Result<Vec<Id>>;
+    /// Every file of the owner, whatever its state.
+    async fn find_owner_files(&self, owner_id: &Id) -> Result<HashMap<Id, File>>;
+    /// Multipart upload ids of the owner's upload sessions, by file.
+    async fn find_owner_sessions(&self, owner_id: &Id) -> Result<HashMap<Id, Option<String>>>;
+}
+
+pub(crate) struct DbReconcileRepository {
+    pub db: DbPool,
+}
+
+impl ReconcileRepository for DbReconcileRepository {
+    async fn find_owner_ids(&self) -> Result<Vec<Id>> {
+        let ids: Vec<uuid::Uuid> = AppUsers::find()
+            .select_only()
+            .column(app_users::Column::Id)
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list users");
+                Error::Database
+            })?;
+
+        Ok(ids.into_iter().map(Id::from).collect())
+    }
+
+    async fn find_owner_files(&self, owner_id: &Id) -> Result<HashMap<Id, File>> {
+        let files = Files::find()
+            .filter(files::Column::OwnerId.eq(uuid::Uuid::from(*owner_id)))
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list files of owner");
+                Error::Database
+            })?
+            .into_iter()
+            .map(|m| (Id::from(m.id), File::from(m)))
+            .collect();
+
+        Ok(files)
+    }
+
+    async fn find_owner_sessions(&self, owner_id: &Id) -> Result<HashMap<Id, Option<String>>> {
+        let sessions: Vec<(uuid::Uuid, Option<String>)> = UploadSessions::find()
+            .select_only()
+            .column(upload_sessions::Column::FileId)
+            .column(upload_sessions::Column::UploadId)
+            .join(JoinType::InnerJoin, upload_sessions::Relation::Files.def())
+            .filter(files::Column::OwnerId.eq(uuid::Uuid::from(*owner_id)))
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not list upload sessions of owner");
+                Error::Database
+            })?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|(file_id, upload_id)| (Id::from(file_id), upload_id))
+            .collect())
+    }
+}
@@ -0,0 +1,12 @@
+use axum::{Router, routing::post};
+
+use crate::AppState;
+
+use super::handlers;
+
+/// Mounted under `/admin` by [`crate::admin::routes`].
+pub(crate) fn admin_routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/storage/reconcile", post(handlers::reconcile))
+        .with_state(app_state)
+}
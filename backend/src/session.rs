@@ -3,14 +3,39 @@ use crate::ulid::Id;
 #[derive(Clone, Debug)]
 pub struct Session {
     user_id: Id,
+    /// The auth token the request was made with; `None` outside requests.
+    token_id: Option<Id>,
+    /// The device the token was issued to.
+    device_id: Option<Id>,
 }
 
 impl Session {
+    #[cfg(test)]
     pub fn new(user_id: Id) -> Self {
-        Self { user_id }
+        Self {
+            user_id,
+            token_id: None,
+            device_id: None,
+        }
+    }
+
+    pub fn with_token(user_id: Id, token_id: Id, device_id: Id) -> Self {
+        Self {
+            user_id,
+            token_id: Some(token_id),
+            device_id: Some(device_id),
+        }
     }
 
     pub fn user_id(&self) -> Id {
         self.user_id
     }
+
+    pub fn token_id(&self) -> Option<Id> {
+        self.token_id
+    }
+
+    pub fn device_id(&self) -> Option<Id> {
+        self.device_id
+    }
 }
@@ -0,0 +1,233 @@
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error};
+
+use crate::error::{Error, Result};
+
+use super::local::{LocalStorage, SignedMethod, object_url_path, part_url_path};
+
+/// Query parameters carried by every URL presigned by [`LocalStorage`].
+#[derive(Debug, Deserialize)]
+pub(super) struct SignedUrlParams {
+    expires: i64,
+    signature: String,
+}
+
+/// Serve a (possibly ranged) read of an object. Clients fetch individual
+/// segments this way when seeking.
+pub(super) async fn get_object(
+    State(storage): State<LocalStorage>,
+    Path(key): Path<String>,
+    Query(params): Query<SignedUrlParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    storage.verify(
+        SignedMethod::Get,
+        &object_url_path(&key),
+        params.expires,
+        &params.signature,
+        OffsetDateTime::now_utc(),
+    )?;
+
+    let (mut file, size) = storage
+        .open_object(&key)
+        .await?
+        .ok_or(Error::FileNotFound)?;
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let (status, start, len) = match range.map(|r| parse_range(r, size)) {
+        None | Some(RangeRequest::Ignored) => (StatusCode::OK, 0, size),
+        Some(RangeRequest::Bytes(start, end)) => {
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        Some(RangeRequest::Unsatisfiable) => {
+            debug!(key, ?range, "Unsatisfiable range");
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+            )
+                .into_response());
+        }
+    };
+
+    file.seek(std::io::SeekFrom::Start(start))
+        .await
+        .map_err(|e| {
+            error!(key, error = %e, "Could not seek object");
+            Error::Storage
+        })?;
+    let body = Body::from_stream(ReaderStream::new(file.take(len)));
+
+    let mut response = (status, body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", start, start + len - 1, size);
+        headers.insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).expect("content range is ASCII"),
+        );
+    }
+
+    Ok(response)
+}
+
+pub(super) async fn put_object(
+    State(storage): State<LocalStorage>,
+    Path(key): Path<String>,
+    Query(params): Query<SignedUrlParams>,
+    body: Body,
+) -> Result<Response> {
+    storage.verify(
+        SignedMethod::Put,
+        &object_url_path(&key),
+        params.expires,
+        &params.signature,
+        OffsetDateTime::now_utc(),
+    )?;
+
+    let etag = storage.write_object(&key, body).await?;
+    debug!(key, "Stored object");
+    Ok(etag_response(&etag))
+}
+
+pub(super) async fn put_part(
+    State(storage): State<LocalStorage>,
+    Path((upload_id, part_number)): Path<(String, i32)>,
+    Query(params): Query<SignedUrlParams>,
+    body: Body,
+) -> Result<Response> {
+    storage.verify(
+        SignedMethod::Put,
+        &part_url_path(&upload_id, part_number),
+        params.expires,
+        &params.signature,
+        OffsetDateTime::now_utc(),
+    )?;
+
+    let etag = storage.write_part(&upload_id, part_number, body).await?;
+    debug!(upload_id, part_number, "Stored part");
+    Ok(etag_response(&etag))
+}
+
+/// Clients read the `ETag` header of a part upload to complete the multipart
+/// upload, exactly as with S3.
+fn etag_response(etag: &str) -> Response {
+    let mut response = StatusCode::OK.into_response();
+    response.headers_mut().insert(
+        header::ETAG,
+        HeaderValue::from_str(etag).expect("ETag is hex"),
+    );
+    response
+}
+
+/// What a `Range` header asks of an object.
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    /// Inclusive offsets, clamped to the object's size.
+    Bytes(u64, u64),
+    /// A byte range that starts past the end of the object.
+    Unsatisfiable,
+    /// Another unit, several ranges or a malformed range, which RFC 9110
+    /// lets a server answer with the whole object.
+    Ignored,
+}
+
+/// Parse a single-range `Range` header (`bytes=a-b`, `bytes=a-`, `bytes=-n`)
+/// against an object of `size` bytes.
+fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some(spec) = header.strip_prefix("bytes=").map(str::trim) else {
+        return RangeRequest::Ignored;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Ignored;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeRequest::Ignored;
+    };
+    let offset = |value: &str| value.parse::<u64>().ok();
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match offset(suffix) {
+            Some(0) => return RangeRequest::Unsatisfiable,
+            Some(suffix) => (size.saturating_sub(suffix), size.checked_sub(1)),
+            None => return RangeRequest::Ignored,
+        },
+        (start, "") => match offset(start) {
+            Some(start) => (start, size.checked_sub(1)),
+            None => return RangeRequest::Ignored,
+        },
+        (start, end) => match (offset(start), offset(end)) {
+            (Some(start), Some(end)) if start <= end => {
+                (start, size.checked_sub(1).map(|last| end.min(last)))
+            }
+            _ => return RangeRequest::Ignored,
+        },
+    };
+
+    match end {
+        Some(end) if start <= end => RangeRequest::Bytes(start, end),
+        _ => RangeRequest::Unsatisfiable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_with_both_bounds() {
+        assert_eq!(parse_range("bytes=0-9", 100), RangeRequest::Bytes(0, 9));
+        assert_eq!(parse_range("bytes=26-51", 73), RangeRequest::Bytes(26, 51));
+    }
+
+    #[test]
+    fn range_end_is_clamped() {
+        assert_eq!(parse_range("bytes=50-999", 73), RangeRequest::Bytes(50, 72));
+    }
+
+    #[test]
+    fn open_ended_range() {
+        assert_eq!(parse_range("bytes=52-", 73), RangeRequest::Bytes(52, 72));
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(parse_range("bytes=-21", 73), RangeRequest::Bytes(52, 72));
+        assert_eq!(parse_range("bytes=-500", 73), RangeRequest::Bytes(0, 72));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=73-", 73), RangeRequest::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=100-200", 73),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 73), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-5", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsupported_or_malformed_ranges_are_ignored() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 73), RangeRequest::Ignored);
+        assert_eq!(parse_range("items=0-1", 73), RangeRequest::Ignored);
+        assert_eq!(parse_range("bytes=10-5", 73), RangeRequest::Ignored);
+        assert_eq!(parse_range("bytes=abc", 73), RangeRequest::Ignored);
+        assert_eq!(parse_range("bytes=x-", 73), RangeRequest::Ignored);
+    }
+}
@@ -0,0 +1,840 @@
+//! [`StorageBackend`] backed by a local directory, for self-hosted instances
+//! and CI. Presigned URLs point back at this backend (see [`super::routes`])
+//! and carry an HMAC-SHA256 signature over the method, path and expiry, so
+//! clients use them exactly like S3 presigned URLs.
+//!
+//! On-disk layout under the configured root:
+//!
+//! ```text
+//! objects/{key}                 assembled objects
+//! uploads/{upload_id}/key       target key of a multipart upload
+//! uploads/{upload_id}/{n}.part  received part bytes
+//! uploads/{upload_id}/{n}.etag  ETag of the received part
+//! tmp/                          staging area for atomic writes
+//! ```
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use futures_util::StreamExt;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, warn};
+
+use crate::error::{Error, Result};
+use crate::ulid::Id;
+use crate::upload::MAX_PARTS;
+
+use super::{ObjectInfo, PendingUpload, StorageBackend, StoredObject, UploadedPart};
+
+const OBJECTS_DIR: &str = "objects";
+const UPLOADS_DIR: &str = "uploads";
+const TMP_DIR: &str = "tmp";
+const UPLOAD_KEY_FILE: &str = "key";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HTTP method a signed URL is valid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SignedMethod {
+    Get,
+    Put,
+}
+
+impl SignedMethod {
+    fn as_str(self) -> &'static str {
+        match self {
+            SignedMethod::Get => "GET",
+            SignedMethod::Put => "PUT",
+        }
+    }
+}
+
+/// URL path serving the object stored under `key`.
+pub(super) fn object_url_path(key: &str) -> String {
+    format!("/storage/objects/{key}")
+}
+
+/// URL path accepting part `part_number` of a multipart upload.
+pub(super) fn part_url_path(upload_id: &str, part_number: i32) -> String {
+    format!("/storage/uploads/{upload_id}/parts/{part_number}")
+}
+
+#[derive(Clone)]
+pub(crate) struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+    signing_key: Arc<[u8]>,
+}
+
+impl LocalStorage {
+    /// Open (creating if needed) a storage root. `public_url` is the base URL
+    /// clients reach this backend on; signed URLs are built on top of it.
+    pub(crate) async fn new(
+        root: impl Into<PathBuf>,
+        public_url: &str,
+        signing_key: &[u8],
+    ) -> Result<Self> {
+        let root = root.into();
+        for dir in [OBJECTS_DIR, UPLOADS_DIR, TMP_DIR] {
+            fs::create_dir_all(root.join(dir)).await.map_err(|e| {
+                error!(root = %root.display(), error = %e, "Could not create storage directory");
+                Error::Storage
+            })?;
+        }
+
+        Ok(Self {
+            root,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            signing_key: signing_key.into(),
+        })
+    }
+
+    fn mac(&self, method: SignedMethod, path: &str, expires: i64) -> HmacSha256 {
+        let mut mac = <HmacSha256 as KeyInit>::new_from_slice(&self.signing_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(method.as_str().as_bytes());
+        mac.update(b"\n");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    fn signed_url(&self, method: SignedMethod, path: &str, expires_in: Duration) -> String {
+        let expires = OffsetDateTime::now_utc().unix_timestamp() + expires_in.as_secs() as i64;
+        let signature = hex::encode(self.mac(method, path, expires).finalize().into_bytes());
+        format!(
+            "{}{}?expires={}&signature={}",
+            self.public_url, path, expires, signature
+        )
+    }
+
+    /// Check a signed URL's signature and expiry. Both failures are reported
+    /// as [`Error::Forbidden`], like S3 does.
+    pub(super) fn verify(
+        &self,
+        method: SignedMethod,
+        path: &str,
+        expires: i64,
+        signature: &str,
+        now: OffsetDateTime,
+    ) -> Result<()> {
+        let signature = hex::decode(signature).map_err(|_| Error::Forbidden)?;
+        self.mac(method, path, expires)
+            .verify_slice(&signature)
+            .map_err(|_| {
+                warn!(path, "Invalid storage URL signature");
+                Error::Forbidden
+            })?;
+
+        if expires < now.unix_timestamp() {
+            warn!(path, "Expired storage URL");
+            return Err(Error::Forbidden);
+        }
+
+        Ok(())
+    }
+
+    /// Filesystem path for `key`. Keys are built by the backend, so anything
+    /// outside the conservative key alphabet, or anything that could escape
+    /// the objects directory, is rejected.
+    fn object_path(&self, key: &str) -> Result<PathBuf> {
+        let valid = !key.is_empty()
+            && key.split('/').all(|segment| {
+                !segment.is_empty()
+                    && segment != "."
+                    && segment != ".."
+                    && segment
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            });
+        if !valid {
+            warn!(key, "Rejecting invalid storage key");
+            return Err(Error::FileNotFound);
+        }
+
+        Ok(self.root.join(OBJECTS_DIR).join(key))
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf> {
+        let upload_id: Id = upload_id.parse().map_err(|_| Error::UploadNotFound)?;
+        Ok(self.root.join(UPLOADS_DIR).join(upload_id.to_string()))
+    }
+
+    /// Fail unless `upload_id` exists and was created for `key`.
+    async fn check_upload_key(&self, dir: &Path, key: &str) -> Result<()> {
+        match fs::read_to_string(dir.join(UPLOAD_KEY_FILE)).await {
+            Ok(stored) if stored == key => Ok(()),
+            Ok(_) => {
+                error!(key, "Multipart upload belongs to a different key");
+                Err(Error::UploadNotFound)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::UploadNotFound),
+            Err(e) => {
+                error!(key, error = %e, "Could not read multipart upload");
+                Err(Error::Storage)
+            }
+        }
+    }
+
+    /// Stream `body` into `dest`, staging it under `tmp/` and renaming into
+    /// place so readers never observe a partial object. Returns the ETag.
+    async fn write_atomically(&self, dest: &Path, body: Body) -> Result<String> {
+        let tmp = self.root.join(TMP_DIR).join(Id::new().to_string());
+        let result = self.write_to(&tmp, dest, body).await;
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp).await;
+        }
+        result
+    }
+
+    async fn write_to(&self, tmp: &Path, dest: &Path, body: Body) -> Result<String> {
+        let io_error = |e: std::io::Error| {
+            error!(path = %dest.display(), error = %e, "Could not write object");
+            Error::Storage
+        };
+
+        let mut file = fs::File::create(tmp).await.map_err(io_error)?;
+        let mut hasher = Sha256::new();
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                warn!(path = %dest.display(), error = %e, "Upload body interrupted");
+                Error::FileUpload
+            })?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(io_error)?;
+        }
+        file.sync_all().await.map_err(io_error)?;
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await.map_err(io_error)?;
+        }
+        fs::rename(tmp, dest).await.map_err(io_error)?;
+
+        Ok(format!("\"{}\"", hex::encode(hasher.finalize())))
+    }
+
+    /// Store a whole object (the target of a presigned PUT).
+    pub(super) async fn write_object(&self, key: &str, body: Body) -> Result<String> {
+        let path = self.object_path(key)?;
+        self.write_atomically(&path, body).await
+    }
+
+    /// Store one part of a multipart upload (the target of a presigned part
+    /// URL). Re-uploading a part replaces it, as on S3.
+    pub(super) async fn write_part(
+        &self,
+        upload_id: &str,
+        part_number: i32,
+        body: Body,
+    ) -> Result<String> {
+        if !(1..=MAX_PARTS as i32).contains(&part_number) {
+            error!(part_number, "Part number out of range");
+            return Err(Error::FileUpload);
+        }
+        let dir = self.upload_dir(upload_id)?;
+        if fs::metadata(&dir).await.is_err() {
+            return Err(Error::UploadNotFound);
+        }
+
+        let staged = dir.join(format!("{part_number}.staged"));
+        let etag = self.write_atomically(&staged, body).await?;
+
+        // The ETag goes down before the part is renamed into place, so a
+        // listed part always has one.
+        let io_error = |e: std::io::Error| {
+            error!(upload_id, part_number, error = %e, "Could not store part");
+            Error::Storage
+        };
+        fs::write(dir.join(format!("{part_number}.etag")), &etag)
+            .await
+            .map_err(io_error)?;
+        fs::rename(&staged, dir.join(format!("{part_number}.part")))
+            .await
+            .map_err(io_error)?;
+
+        Ok(etag)
+    }
+
+    /// Open an object for reading, together with its size.
+    pub(super) async fn open_object(&self, key: &str) -> Result<Option<(fs::File, u64)>> {
+        let path = self.object_path(key)?;
+        match fs::File::open(&path).await {
+            Ok(file) => {
+                let size = file
+                    .metadata()
+                    .await
+                    .map_err(|e| {
+                        error!(key, error = %e, "Could not stat object");
+                        Error::Storage
+                    })?
+                    .len();
+                Ok(Some((file, size)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                error!(key, error = %e, "Could not open object");
+                Err(Error::Storage)
+            }
+        }
+    }
+
+    /// Remove now-empty directories between `path` and the objects root, so
+    /// deleted files don't leave a trail of empty `files/{owner}/{file}` dirs.
+    async fn prune_empty_parents(&self, path: &Path) {
+        let objects = self.root.join(OBJECTS_DIR);
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if current == objects || fs::remove_dir(current).await.is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        self.object_path(key)?;
+
+        let upload_id = Id::new().to_string();
+        let dir = self.upload_dir(&upload_id)?;
+        let io_error = |e: std::io::Error| {
+            error!(key, error = %e, "Could not create multipart upload");
+            Error::Storage
+        };
+        fs::create_dir_all(&dir).await.map_err(io_error)?;
+        fs::write(dir.join(UPLOAD_KEY_FILE), key)
+            .await
+            .map_err(io_error)?;
+
+        Ok(upload_id)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String> {
+        let path = part_url_path(upload_id, part_number);
+        Ok(self.signed_url(SignedMethod::Put, &path, expires_in))
+    }
+
+    async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<i32>> {
+        let dir = self.upload_dir(upload_id)?;
+        self.check_upload_key(&dir, key).await?;
+
+        let io_error = |e: std::io::Error| {
+            error!(key, error = %e, "Could not list parts");
+            Error::Storage
+        };
+        let mut entries = fs::read_dir(&dir).await.map_err(io_error)?;
+        let mut received = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let name = entry.file_name();
+            if let Some(part_number) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".part"))
+                .and_then(|n| n.parse::<i32>().ok())
+            {
+                received.push(part_number);
+            }
+        }
+
+        received.sort_unstable();
+        Ok(received)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()> {
+        let dir = self.upload_dir(upload_id)?;
+        self.check_upload_key(&dir, key).await?;
+        let dest = self.object_path(key)?;
+
+        let mut sorted_parts = parts.to_vec();
+        sorted_parts.sort_by_key(|p| p.part_number);
+
+        for part in &sorted_parts {
+            let etag = fs::read_to_string(dir.join(format!("{}.etag", part.part_number)))
+                .await
+                .map_err(|_| {
+                    error!(
+                        key,
+                        part_number = part.part_number,
+                        "Part was never uploaded"
+                    );
+                    Error::UploadIncomplete
+                })?;
+            if etag.trim_matches('"') != part.etag.trim_matches('"') {
+                error!(key, part_number = part.part_number, "Part ETag mismatch");
+                return Err(Error::UploadIncomplete);
+            }
+        }
+
+        let tmp = self.root.join(TMP_DIR).join(Id::new().to_string());
+        let io_error = |e: std::io::Error| {
+            error!(key, error = %e, "Could not assemble multipart upload");
+            Error::Storage
+        };
+        let assembled: Result<()> = async {
+            let mut out = fs::File::create(&tmp).await.map_err(io_error)?;
+            for part in &sorted_parts {
+                let mut input = fs::File::open(dir.join(format!("{}.part", part.part_number)))
+                    .await
+                    .map_err(io_error)?;
+                tokio::io::copy(&mut input, &mut out)
+                    .await
+                    .map_err(io_error)?;
+            }
+            out.sync_all().await.map_err(io_error)?;
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await.map_err(io_error)?;
+            }
+            fs::rename(&tmp, &dest).await.map_err(io_error)
+        }
+        .await;
+
+        if let Err(e) = assembled {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+
+        fs::remove_dir_all(&dir).await.map_err(io_error)?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        let dir = self.upload_dir(upload_id)?;
+        match self.check_upload_key(&dir, key).await {
+            Ok(()) => {}
+            Err(Error::UploadNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        fs::remove_dir_all(&dir).await.map_err(|e| {
+            error!(key, error = %e, "Could not abort multipart upload");
+            Error::Storage
+        })
+    }
+
+    async fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let path = self.object_path(key)?;
+        match fs::metadata(&path).await {
+            Ok(meta) if meta.is_file() => Ok(Some(ObjectInfo { size: meta.len() })),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                error!(key, error = %e, "Could not stat object");
+                Err(Error::Storage)
+            }
+        }
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<()> {
+        let path = self.object_path(key)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => {
+                self.prune_empty_parents(&path).await;
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                error!(key, error = %e, "Could not delete object");
+                Err(Error::Storage)
+            }
+        }
+    }
+
+    async fn presign_get_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        self.object_path(key)?;
+        Ok(self.signed_url(SignedMethod::Get, &object_url_path(key), expires_in))
+    }
+
+    async fn presign_put_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        self.object_path(key)?;
+        Ok(self.signed_url(SignedMethod::Put, &object_url_path(key), expires_in))
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let objects_root = self.root.join(OBJECTS_DIR);
+        // walk only the directory the prefix pins down
+        let start = match prefix.rsplit_once('/') {
+            Some((dir, _)) => self.object_path(dir)?,
+            None => objects_root.clone(),
+        };
+        let io_error = |e: std::io::Error| {
+            error!(prefix, error = %e, "Could not list objects");
+            Error::Storage
+        };
+
+        let mut objects = Vec::new();
+        let mut dirs = vec![start];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error(e)),
+            };
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let meta = entry.metadata().await.map_err(io_error)?;
+                let path = entry.path();
+                if meta.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Some(key) = path
+                    .strip_prefix(&objects_root)
+                    .ok()
+                    .and_then(|p| p.to_str())
+                    .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"))
+                else {
+                    continue;
+                };
+                if key.starts_with(prefix) {
+                    objects.push(StoredObject {
+                        key,
+                        last_modified: meta.modified().map_err(io_error)?.into(),
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn list_multipart_uploads(&self, prefix: &str) -> Result<Vec<PendingUpload>> {
+        let io_error = |e: std::io::Error| {
+            error!(prefix, error = %e, "Could not list multipart uploads");
+            Error::Storage
+        };
+
+        let mut uploads = Vec::new();
+        let mut entries = fs::read_dir(self.root.join(UPLOADS_DIR))
+            .await
+            .map_err(io_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let key_file = entry.path().join(UPLOAD_KEY_FILE);
+            // an upload directory without its key file is being created or removed
+            let Ok(key) = fs::read_to_string(&key_file).await else {
+                continue;
+            };
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            let Some(upload_id) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let meta = fs::metadata(&key_file).await.map_err(io_error)?;
+            uploads.push(PendingUpload {
+                key,
+                upload_id,
+                initiated: meta.modified().map_err(io_error)?.into(),
+            });
+        }
+
+        Ok(uploads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "files/owner/file/original";
+
+    async fn storage(dir: &tempfile::TempDir) -> LocalStorage {
+        LocalStorage::new(dir.path(), "http://localhost:3000/", b"secret")
+            .await
+            .unwrap()
+    }
+
+    /// Split a signed URL into its path, `expires` and `signature`.
+    fn parse_signed_url(url: &str) -> (String, i64, String) {
+        let rest = url.strip_prefix("http://localhost:3000").unwrap();
+        let (path, query) = rest.split_once('?').unwrap();
+        let mut expires = 0;
+        let mut signature = String::new();
+        for pair in query.split('&') {
+            match pair.split_once('=').unwrap() {
+                ("expires", v) => expires = v.parse().unwrap(),
+                ("signature", v) => signature = v.to_string(),
+                _ => panic!("unexpected query parameter"),
+            }
+        }
+        (path.to_string(), expires, signature)
+    }
+
+    #[tokio::test]
+    async fn multipart_upload_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+
+        let upload_id = storage.create_multipart_upload(KEY).await.unwrap();
+        // parts may arrive out of order
+        let etag2 = storage
+            .write_part(&upload_id, 2, Body::from("world"))
+            .await
+            .unwrap();
+        let etag1 = storage
+            .write_part(&upload_id, 1, Body::from("hello "))
+            .await
+            .unwrap();
+
+        assert_eq!(storage.list_parts(KEY, &upload_id).await.unwrap(), [1, 2]);
+
+        let parts = [
+            UploadedPart {
+                part_number: 2,
+                etag: etag2,
+            },
+            UploadedPart {
+                part_number: 1,
+                etag: etag1,
+            },
+        ];
+        storage
+            .complete_multipart_upload(KEY, &upload_id, &parts)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage.head_object(KEY).await.unwrap(),
+            Some(ObjectInfo { size: 11 })
+        );
+        let content = fs::read(dir.path().join(OBJECTS_DIR).join(KEY))
+            .await
+            .unwrap();
+        assert_eq!(content, b"hello world");
+        // the upload is gone once assembled
+        assert!(matches!(
+            storage.list_parts(KEY, &upload_id).await,
+            Err(Error::UploadNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn complete_rejects_wrong_etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+
+        let upload_id = storage.create_multipart_upload(KEY).await.unwrap();
+        storage
+            .write_part(&upload_id, 1, Body::from("data"))
+            .await
+            .unwrap();
+
+        let parts = [UploadedPart {
+            part_number: 1,
+            etag: "\"bogus\"".to_string(),
+        }];
+        let result = storage
+            .complete_multipart_upload(KEY, &upload_id, &parts)
+            .await;
+
+        assert!(matches!(result, Err(Error::UploadIncomplete)));
+        assert_eq!(storage.head_object(KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn upload_id_is_bound_to_its_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+
+        let upload_id = storage.create_multipart_upload(KEY).await.unwrap();
+        let result = storage
+            .list_parts("files/owner/other/original", &upload_id)
+            .await;
+
+        assert!(matches!(result, Err(Error::UploadNotFound)));
+    }
+
+    #[tokio::test]
+    async fn abort_discards_parts_and_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+
+        let upload_id = storage.create_multipart_upload(KEY).await.unwrap();
+        storage
+            .write_part(&upload_id, 1, Body::from("data"))
+            .await
+            .unwrap();
+
+        storage
+            .abort_multipart_upload(KEY, &upload_id)
+            .await
+            .unwrap();
+        storage
+            .abort_multipart_upload(KEY, &upload_id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage.write_part(&upload_id, 1, Body::from("data")).await,
+            Err(Error::UploadNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn put_head_and_delete_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+        let key = "files/owner/file/512-cover";
+
+        storage
+            .write_object(key, Body::from("thumbnail"))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.head_object(key).await.unwrap(),
+            Some(ObjectInfo { size: 9 })
+        );
+
+        storage.delete_object(key).await.unwrap();
+        assert_eq!(storage.head_object(key).await.unwrap(), None);
+        // deleting again is fine, and empty parent dirs were pruned
+        storage.delete_object(key).await.unwrap();
+        assert!(
+            fs::metadata(dir.path().join(OBJECTS_DIR).join("files"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_objects_and_uploads_under_a_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+        for key in [
+            "files/owner/a/original",
+            "files/owner/a/512-cover",
+            "files/owner/b/original",
+            "files/other/c/original",
+        ] {
+            storage.write_object(key, Body::from("x")).await.unwrap();
+        }
+        let upload_id = storage.create_multipart_upload(KEY).await.unwrap();
+        storage
+            .create_multipart_upload("files/other/c/original")
+            .await
+            .unwrap();
+
+        let mut keys: Vec<String> = storage
+            .list_objects("files/owner/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        keys.sort();
+        let uploads = storage
+            .list_multipart_uploads("files/owner/")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            keys,
+            vec![
+                "files/owner/a/512-cover",
+                "files/owner/a/original",
+                "files/owner/b/original"
+            ]
+        );
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].key, KEY);
+        assert_eq!(uploads[0].upload_id, upload_id);
+        assert!(
+            storage
+                .list_objects("files/nobody/")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_escaping_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+
+        for key in ["", "../secret", "files/../../etc/passwd", "/abs", "a//b"] {
+            assert!(
+                matches!(storage.head_object(key).await, Err(Error::FileNotFound)),
+                "{key:?} should be rejected"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn presigned_urls_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+        let now = OffsetDateTime::now_utc();
+
+        let url = storage
+            .presign_get_object(KEY, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let (path, expires, signature) = parse_signed_url(&url);
+
+        assert_eq!(path, object_url_path(KEY));
+        assert!(
+            storage
+                .verify(SignedMethod::Get, &path, expires, &signature, now)
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn presigned_urls_reject_tampering_and_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(&dir).await;
+        let now = OffsetDateTime::now_utc();
+
+        let url = storage
+            .presign_upload_part(KEY, &Id::new().to_string(), 3, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let (path, expires, signature) = parse_signed_url(&url);
+
+        assert!(
+            storage
+                .verify(SignedMethod::Put, &path, expires, &signature, now)
+                .is_ok()
+        );
+        // wrong method
+        assert!(matches!(
+            storage.verify(SignedMethod::Get, &path, expires, &signature, now),
+            Err(Error::Forbidden)
+        ));
+        // different path
+        let other = path.replace("/parts/3", "/parts/4");
+        assert!(matches!(
+            storage.verify(SignedMethod::Put, &other, expires, &signature, now),
+            Err(Error::Forbidden)
+        ));
+        // extended expiry
+        assert!(matches!(
+            storage.verify(SignedMethod::Put, &path, expires + 3600, &signature, now),
+            Err(Error::Forbidden)
+        ));
+        // used after expiry
+        let later = now + time::Duration::seconds(120);
+        assert!(matches!(
+            storage.verify(SignedMethod::Put, &path, expires, &signature, later),
+            Err(Error::Forbidden)
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+//! Object storage used by both the upload flow (multipart + thumbnail PUTs)
+//! and the download flow (presigned GET).
+//!
+//! All object I/O goes through [`StorageBackend`], implemented for S3 (the
+//! production default) and for a local directory that serves its own
+//! HMAC-signed URLs, so self-hosted instances and CI can run the full
+//! upload/download protocol without an S3 endpoint. The key builders are also
+//! centralised here so the layout `files/{owner}/{file}/{variant}` has a single
+//! source of truth.
+
+mod handlers;
+mod local;
+mod routes;
+mod s3;
+
+use std::time::Duration;
+
+use sdk::media::required_variants;
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::config::{StorageConfig, StorageKind};
+use crate::error::{Error, Result};
+use crate::file::File;
+
+pub(crate) use local::LocalStorage;
+pub(crate) use routes::routes;
+pub(crate) use s3::S3Storage;
+
+/// S3 key for the file's encrypted original (the segment-encrypted object).
+pub(crate) fn s3_original_key(file: &File) -> String {
+    format!("files/{}/{}/original", file.owner_id, file.id)
+}
+
+/// S3 key for a thumbnail variant (e.g. `512-cover`).
+pub(crate) fn s3_thumbnail_key(file: &File, variant: &str) -> String {
+    format!("files/{}/{}/{}", file.owner_id, file.id, variant)
+}
+
+/// Delete a file's original and every thumbnail variant. Missing objects are
+/// skipped, so this is safe to retry after a partial failure.
+pub(crate) async fn delete_file_objects(storage: &impl StorageBackend, file: &File) -> Result<()> {
+    storage.delete_object(&s3_original_key(file)).await?;
+    for variant in required_variants(file.media_type) {
+        storage
+            .delete_object(&s3_thumbnail_key(file, &variant.to_string()))
+            .await?;
+    }
+    Ok(())
+}
+
+/// A part the client reports as uploaded when completing a multipart upload.
+#[derive(Debug, Clone)]
+pub(crate) struct UploadedPart {
+    pub part_number: i32,
+    pub etag: String,
+}
+
+/// What a HEAD request tells us about a stored object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ObjectInfo {
+    pub size: u64,
+}
+
+/// An object found by [`StorageBackend::list_objects`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredObject {
+    pub key: String,
+    pub last_modified: OffsetDateTime,
+}
+
+/// A multipart upload that was started but neither completed nor aborted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PendingUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: OffsetDateTime,
+}
+
+/// The object operations the upload and download flows rely on. Presigned
+/// URLs are handed to clients, which then talk to the storage directly.
+pub(crate) trait StorageBackend {
+    /// Start a multipart upload for `key`, returning its upload id.
+    async fn create_multipart_upload(&self, key: &str) -> Result<String>;
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String>;
+    /// Part numbers received so far, sorted ascending.
+    async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<i32>>;
+    /// Assemble the listed parts (in any order) into the final object.
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()>;
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()>;
+    /// `None` if the object doesn't exist.
+    async fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>>;
+    /// Deleting a missing object is not an error.
+    async fn delete_object(&self, key: &str) -> Result<()>;
+    /// The returned URL must serve arbitrary HTTP `Range` requests, which is
+    /// what lets a client fetch and decrypt individual segments for seeking.
+    async fn presign_get_object(&self, key: &str, expires_in: Duration) -> Result<String>;
+    async fn presign_put_object(&self, key: &str, expires_in: Duration) -> Result<String>;
+    /// Every object whose key starts with `prefix`, in no particular order.
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>>;
+    /// Multipart uploads in progress for keys starting with `prefix`.
+    async fn list_multipart_uploads(&self, prefix: &str) -> Result<Vec<PendingUpload>>;
+}
+
+/// The configured backend. An enum rather than a trait object so the trait
+/// can keep plain `async fn`s.
+#[derive(Clone)]
+pub(crate) enum Storage {
+    S3(S3Storage),
+    Local(LocalStorage),
+}
+
+impl Storage {
+    pub(crate) async fn from_config(config: &StorageConfig) -> Result<Self> {
+        match config.backend {
+            StorageKind::S3 => {
+                let (Some(url), Some(bucket)) = (&config.url, &config.bucket_name) else {
+                    error!("S3 storage requires storage_url and storage_bucket_name");
+                    return Err(Error::Configuration);
+                };
+                Ok(Storage::S3(S3Storage::new(url, bucket).await))
+            }
+            StorageKind::Local => {
+                let (Some(path), Some(public_url), Some(signing_key)) =
+                    (&config.local_path, &config.public_url, &config.signing_key)
+                else {
+                    error!(
+                        "Local storage requires storage_local_path, storage_public_url and storage_signing_key"
+                    );
+                    return Err(Error::Configuration);
+                };
+                let local = LocalStorage::new(path, public_url, signing_key.as_bytes()).await?;
+                Ok(Storage::Local(local))
+            }
+        }
+    }
+}
+
+impl StorageBackend for Storage {
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        match self {
+            Storage::S3(s) => s.create_multipart_upload(key).await,
+            Storage::Local(s) => s.create_multipart_upload(key).await,
+        }
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String> {
+        match self {
+            Storage::S3(s) => {
+                s.presign_upload_part(key, upload_id, part_number, expires_in)
+                    .await
+            }
+            Storage::Local(s) => {
+                s.presign_upload_part(key, upload_id, part_number, expires_in)
+                    .await
+            }
+        }
+    }
+
+    async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<i32>> {
+        match self {
+            Storage::S3(s) => s.list_parts(key, upload_id).await,
+            Storage::Local(s) => s.list_parts(key, upload_id).await,
+        }
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()> {
+        match self {
+            Storage::S3(s) => s.complete_multipart_upload(key, upload_id, parts).await,
+            Storage::Local(s) => s.complete_multipart_upload(key, upload_id, parts).await,
+        }
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        match self {
+            Storage::S3(s) => s.abort_multipart_upload(key, upload_id).await,
+            Storage::Local(s) => s.abort_multipart_upload(key, upload_id).await,
+        }
+    }
+
+    async fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        match self {
+            Storage::S3(s) => s.head_object(key).await,
+            Storage::Local(s) => s.head_object(key).await,
+        }
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<()> {
+        match self {
+            Storage::S3(s) => s.delete_object(key).await,
+            Storage::Local(s) => s.delete_object(key).await,
+        }
+    }
+
+    async fn presign_get_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        match self {
+            Storage::S3(s) => s.presign_get_object(key, expires_in).await,
+            Storage::Local(s) => s.presign_get_object(key, expires_in).await,
+        }
+    }
+
+    async fn presign_put_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        match self {
+            Storage::S3(s) => s.presign_put_object(key, expires_in).await,
+            Storage::Local(s) => s.presign_put_object(key, expires_in).await,
+        }
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        match self {
+            Storage::S3(s) => s.list_objects(prefix).await,
+            Storage::Local(s) => s.list_objects(prefix).await,
+        }
+    }
+
+    async fn list_multipart_uploads(&self, prefix: &str) -> Result<Vec<PendingUpload>> {
+        match self {
+            Storage::S3(s) => s.list_multipart_uploads(prefix).await,
+            Storage::Local(s) => s.list_multipart_uploads(prefix).await,
+        }
+    }
+}
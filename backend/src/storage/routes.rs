@@ -0,0 +1,20 @@
+use axum::Router;
+use axum::routing::{get, put};
+
+use super::handlers;
+use super::local::LocalStorage;
+
+/// Endpoints behind the URLs presigned by [`LocalStorage`]. They authenticate
+/// by signature alone, so they're mounted outside the session middleware.
+pub(crate) fn routes(storage: LocalStorage) -> Router {
+    Router::new()
+        .route(
+            "/storage/objects/{*key}",
+            get(handlers::get_object).put(handlers::put_object),
+        )
+        .route(
+            "/storage/uploads/{upload_id}/parts/{part_number}",
+            put(handlers::put_part),
+        )
+        .with_state(storage)
+}
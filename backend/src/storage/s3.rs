@@ -0,0 +1,328 @@
+//! [`StorageBackend`] on top of any S3-compatible service (AWS, R2, MinIO).
+
+use std::time::Duration;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::error::{Error, Result};
+
+use super::{ObjectInfo, PendingUpload, StorageBackend, StoredObject, UploadedPart};
+
+#[derive(Clone)]
+pub(crate) struct S3Storage {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    pub(crate) async fn new(endpoint_url: &str, bucket: &str) -> Self {
+        let aws_config = aws_config::defaults(BehaviorVersion::latest())
+            .region("auto")
+            .endpoint_url(endpoint_url)
+            .load()
+            .await;
+
+        Self {
+            client: aws_sdk_s3::Client::new(&aws_config),
+            bucket: bucket.to_string(),
+        }
+    }
+}
+
+/// Build a presigning config with the given lifetime.
+fn presigning_config(expires_in: Duration) -> Result<PresigningConfig> {
+    PresigningConfig::expires_in(expires_in).map_err(|e| {
+        error!(error = %e, "Invalid presigning configuration");
+        Error::Storage
+    })
+}
+
+impl StorageBackend for S3Storage {
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/octet-stream")
+            .send()
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "Failed to create S3 multipart upload");
+                Error::Storage
+            })?;
+
+        let upload_id = output.upload_id().ok_or_else(|| {
+            error!(key, "S3 did not return upload_id");
+            Error::Storage
+        })?;
+
+        Ok(upload_id.to_string())
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String> {
+        let presigned = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .presigned(presigning_config(expires_in)?)
+            .await
+            .map_err(|e| {
+                error!(part_number, error = %e, "Failed to presign UploadPart");
+                Error::Storage
+            })?;
+
+        Ok(presigned.uri().to_string())
+    }
+
+    async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<i32>> {
+        let mut received = Vec::new();
+        let mut marker: Option<String> = None;
+
+        loop {
+            let mut req = self
+                .client
+                .list_parts()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id);
+
+            if let Some(m) = &marker {
+                req = req.part_number_marker(m);
+            }
+
+            let output = req.send().await.map_err(|e| {
+                error!(error = %e, "Failed to list S3 parts");
+                Error::Storage
+            })?;
+
+            for part in output.parts() {
+                if let Some(num) = part.part_number() {
+                    received.push(num);
+                }
+            }
+
+            if output.is_truncated().unwrap_or(false) {
+                marker = output.next_part_number_marker().map(|s| s.to_string());
+                if marker.is_none() {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        received.sort_unstable();
+        Ok(received)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()> {
+        let mut sorted_parts = parts.to_vec();
+        sorted_parts.sort_by_key(|p| p.part_number);
+
+        let completed_parts: Vec<CompletedPart> = sorted_parts
+            .iter()
+            .map(|p| {
+                CompletedPart::builder()
+                    .part_number(p.part_number)
+                    .e_tag(&p.etag)
+                    .build()
+            })
+            .collect();
+
+        let multipart_upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(completed_parts))
+            .build();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(multipart_upload)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "S3 CompleteMultipartUpload failed");
+                Error::Storage
+            })?;
+
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "S3 AbortMultipartUpload failed");
+                Error::Storage
+            })?;
+
+        Ok(())
+    }
+
+    async fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Some(ObjectInfo {
+                size: output.content_length().unwrap_or(0).max(0) as u64,
+            })),
+            Err(err) => {
+                if err.as_service_error().is_some_and(|e| e.is_not_found()) {
+                    Ok(None)
+                } else {
+                    error!(key, "S3 HeadObject failed");
+                    Err(Error::Storage)
+                }
+            }
+        }
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "S3 DeleteObject failed");
+                Error::Storage
+            })?;
+
+        Ok(())
+    }
+
+    async fn presign_get_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        let presigned = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning_config(expires_in)?)
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "Failed to presign GetObject");
+                Error::Storage
+            })?;
+
+        Ok(presigned.uri().to_string())
+    }
+
+    async fn presign_put_object(&self, key: &str, expires_in: Duration) -> Result<String> {
+        let presigned = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning_config(expires_in)?)
+            .await
+            .map_err(|e| {
+                error!(key, error = %e, "Failed to presign PutObject");
+                Error::Storage
+            })?;
+
+        Ok(presigned.uri().to_string())
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| {
+                error!(prefix, error = %e, "S3 ListObjectsV2 failed");
+                Error::Storage
+            })?;
+            for object in page.contents() {
+                if let Some(key) = object.key() {
+                    objects.push(StoredObject {
+                        key: key.to_string(),
+                        last_modified: to_offset_date_time(object.last_modified()),
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn list_multipart_uploads(&self, prefix: &str) -> Result<Vec<PendingUpload>> {
+        let mut uploads = Vec::new();
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+        loop {
+            let page = self
+                .client
+                .list_multipart_uploads()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_key_marker(key_marker)
+                .set_upload_id_marker(upload_id_marker)
+                .send()
+                .await
+                .map_err(|e| {
+                    error!(prefix, error = %e, "S3 ListMultipartUploads failed");
+                    Error::Storage
+                })?;
+
+            for upload in page.uploads() {
+                if let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) {
+                    uploads.push(PendingUpload {
+                        key: key.to_string(),
+                        upload_id: upload_id.to_string(),
+                        initiated: to_offset_date_time(upload.initiated()),
+                    });
+                }
+            }
+
+            if !page.is_truncated().unwrap_or(false) {
+                return Ok(uploads);
+            }
+            key_marker = page.next_key_marker().map(str::to_string);
+            upload_id_marker = page.next_upload_id_marker().map(str::to_string);
+        }
+    }
+}
+
+/// A missing timestamp maps to the epoch, i.e. "old enough to act on".
+fn to_offset_date_time(dt: Option<&DateTime>) -> OffsetDateTime {
+    dt.and_then(|dt| OffsetDateTime::from_unix_timestamp(dt.secs()).ok())
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
@@ -0,0 +1,256 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use sdk::dtos::file::FileMetadata;
+use sdk::dtos::sync::{ChangeFeed, FileChange};
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use super::repository::{ChangeRepository, DbChangeRepository};
+use crate::{
+    AppState,
+    error::{Error, Result},
+    file::FileState,
+    session::Session,
+};
+
+/// Number of changes returned when the client doesn't ask for a limit.
+const DEFAULT_PAGE_SIZE: u64 = 500;
+/// Upper bound on the number of changes a client may ask for.
+const MAX_PAGE_SIZE: u64 = 1000;
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ChangesParams {
+    /// Last sequence number the client has applied; 0 for a full sync.
+    #[serde(default)]
+    since: u64,
+    limit: Option<u64>,
+}
+
+pub(super) async fn get_changes(
+    State(state): State<AppState>,
+    Query(params): Query<ChangesParams>,
+    session: Session,
+) -> Result<Json<ChangeFeed>> {
+    debug!(since = params.since, "Getting file changes");
+
+    // what the client says it has applied, not the page it's about to get
+    let since = sequence(params.since)?;
+    let repo = DbChangeRepository {
+        db: state.db.clone(),
+    };
+    let feed = get_changes_internal(&repo, session.clone(), params).await?;
+    crate::auth::record_sync_cursor(&state.db, &session, since).await?;
+
+    Ok(Json(feed))
+}
+
+/// The change sequence as stored; past its range is a bad cursor.
+fn sequence(since: u64) -> Result<i64> {
+    i64::try_from(since).map_err(|_| {
+        error!(since, "Change sequence out of range");
+        Error::InvalidCursor
+    })
+}
+
+async fn get_changes_internal(
+    repo: &impl ChangeRepository,
+    session: Session,
+    params: ChangesParams,
+) -> Result<ChangeFeed> {
+    let since = sequence(params.since)?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // Fetch one extra change to learn whether more follow.
+    let mut changes = repo
+        .find_changes(&session.user_id(), since, limit + 1)
+        .await?;
+    let has_more = changes.len() as u64 > limit;
+    changes.truncate(limit as usize);
+
+    let next_since = changes
+        .last()
+        .map_or(params.since, |(change, _)| change.seq as u64);
+    let changes = changes
+        .into_iter()
+        .map(|(change, file)| FileChange {
+            seq: change.seq as u64,
+            file_id: change.file_id.into(),
+            kind: change.kind.into(),
+            changed_at: change.changed_at,
+            file: file
+                .filter(|f| matches!(f.state, FileState::Synced))
+                .map(FileMetadata::from),
+        })
+        .collect();
+
+    Ok(ChangeFeed {
+        changes,
+        next_since,
+        has_more,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::File;
+    use crate::file::repository::FileRepository;
+    use crate::file::repository::tests::InMemoryFileRepository;
+    use crate::ulid::Id;
+    use axum::http::StatusCode;
+    use sdk::dtos::sync::ChangeKind;
+    use sdk::media::MediaType;
+    use time::OffsetDateTime;
+
+    fn new_file(owner_id: Id) -> File {
+        File {
+            id: Id::new(),
+            encrypted_metadata: None,
+            legacy_path: None,
+            legacy_name: None,
+            state: FileState::New,
+            created_at: OffsetDateTime::now_utc(),
+            added_at: OffsetDateTime::now_utc(),
+            content_hash: None,
+            owner_id,
+            uploader_id: owner_id,
+            enc_key: "key".to_string(),
+            media_type: MediaType::Image,
+            content_type: "image/jpeg".to_string(),
+            width: 640,
+            height: 480,
+            duration_ms: None,
+            segment_size: sdk::segment::DEFAULT_SEGMENT_SIZE,
+            plaintext_size: 4096,
+            nonce_salt: 42,
+            enc_scheme: sdk::crypto::ENC_SCHEME_SEGMENTED,
+            trashed_at: None,
+            stored_size: None,
+        }
+    }
+
+    fn params(since: u64, limit: u64) -> ChangesParams {
+        ChangesParams {
+            since,
+            limit: Some(limit),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_every_mutation_in_order() {
+        // given
+        let user_id = Id::new();
+        let mut repo = InMemoryFileRepository::new();
+        let file = new_file(user_id);
+        repo.save(&file).await.unwrap();
+        repo.update_state(&file.id, FileState::SyncInProgress)
+            .await
+            .unwrap();
+        repo.update_state(&file.id, FileState::Synced)
+            .await
+            .unwrap();
+
+        // when
+        let feed = get_changes_internal(&repo, Session::new(user_id), params(0, 10))
+            .await
+            .unwrap();
+
+        // then
+        let seqs: Vec<u64> = feed.changes.iter().map(|c| c.seq).collect();
+        let kinds: Vec<ChangeKind> = feed.changes.iter().map(|c| c.kind).collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(
+            kinds,
+            vec![ChangeKind::Created, ChangeKind::Updated, ChangeKind::Synced]
+        );
+        assert_eq!(feed.next_since, 3);
+        assert!(!feed.has_more);
+    }
+
+    #[tokio::test]
+    async fn carries_metadata_only_for_synced_files() {
+        let user_id = Id::new();
+        let mut repo = InMemoryFileRepository::new();
+        let synced = new_file(user_id);
+        let pending = new_file(user_id);
+        repo.save(&synced).await.unwrap();
+        repo.save(&pending).await.unwrap();
+        repo.update_state(&synced.id, FileState::Synced)
+            .await
+            .unwrap();
+
+        let feed = get_changes_internal(&repo, Session::new(user_id), params(0, 10))
+            .await
+            .unwrap();
+
+        for change in &feed.changes {
+            let file_id: Id = change.file_id.into();
+            assert_eq!(change.file.is_some(), file_id == synced.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_after_since() {
+        let user_id = Id::new();
+        let mut repo = InMemoryFileRepository::new();
+        for _ in 0..5 {
+            repo.save(&new_file(user_id)).await.unwrap();
+        }
+
+        let first = get_changes_internal(&repo, Session::new(user_id), params(0, 2))
+            .await
+            .unwrap();
+        let second =
+            get_changes_internal(&repo, Session::new(user_id), params(first.next_since, 10))
+                .await
+                .unwrap();
+
+        assert!(first.has_more);
+        assert_eq!(first.next_since, 2);
+        let seqs: Vec<u64> = second.changes.iter().map(|c| c.seq).collect();
+        assert_eq!(seqs, vec![3, 4, 5]);
+        assert!(!second.has_more);
+    }
+
+    #[tokio::test]
+    async fn empty_feed_keeps_since() {
+        let repo = InMemoryFileRepository::new();
+
+        let feed = get_changes_internal(&repo, Session::new(Id::new()), params(7, 10))
+            .await
+            .unwrap();
+
+        assert!(feed.changes.is_empty());
+        assert_eq!(feed.next_since, 7);
+    }
+
+    #[tokio::test]
+    async fn does_not_leak_changes_of_other_users() {
+        let user_id = Id::new();
+        let mut repo = InMemoryFileRepository::new();
+        repo.save(&new_file(Id::new())).await.unwrap();
+        repo.save(&new_file(user_id)).await.unwrap();
+
+        let feed = get_changes_internal(&repo, Session::new(user_id), params(0, 10))
+            .await
+            .unwrap();
+
+        assert_eq!(feed.changes.len(), 1);
+        assert_eq!(feed.changes[0].seq, 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_since_past_the_sequence_range() {
+        let repo = InMemoryFileRepository::new();
+
+        let result =
+            get_changes_internal(&repo, Session::new(Id::new()), params(u64::MAX, 10)).await;
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::InvalidCursor));
+        assert_eq!(error.status_and_message().0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,79 @@
+mod handlers;
+pub(crate) mod repository;
+mod routes;
+
+pub(crate) use routes::routes;
+use time::OffsetDateTime;
+
+use crate::entity::sea_orm_active_enums::FileChangeKind as EntityChangeKind;
+use crate::ulid::Id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeKind {
+    Created,
+    Synced,
+    Updated,
+    Deleted,
+    Trashed,
+    Restored,
+}
+
+impl From<EntityChangeKind> for ChangeKind {
+    fn from(k: EntityChangeKind) -> Self {
+        match k {
+            EntityChangeKind::Created => ChangeKind::Created,
+            EntityChangeKind::Synced => ChangeKind::Synced,
+            EntityChangeKind::Updated => ChangeKind::Updated,
+            EntityChangeKind::Deleted => ChangeKind::Deleted,
+            EntityChangeKind::Trashed => ChangeKind::Trashed,
+            EntityChangeKind::Restored => ChangeKind::Restored,
+        }
+    }
+}
+
+impl From<ChangeKind> for EntityChangeKind {
+    fn from(k: ChangeKind) -> Self {
+        match k {
+            ChangeKind::Created => EntityChangeKind::Created,
+            ChangeKind::Synced => EntityChangeKind::Synced,
+            ChangeKind::Updated => EntityChangeKind::Updated,
+            ChangeKind::Deleted => EntityChangeKind::Deleted,
+            ChangeKind::Trashed => EntityChangeKind::Trashed,
+            ChangeKind::Restored => EntityChangeKind::Restored,
+        }
+    }
+}
+
+impl From<ChangeKind> for sdk::dtos::sync::ChangeKind {
+    fn from(k: ChangeKind) -> Self {
+        match k {
+            ChangeKind::Created => sdk::dtos::sync::ChangeKind::Created,
+            ChangeKind::Synced => sdk::dtos::sync::ChangeKind::Synced,
+            ChangeKind::Updated => sdk::dtos::sync::ChangeKind::Updated,
+            ChangeKind::Deleted => sdk::dtos::sync::ChangeKind::Deleted,
+            ChangeKind::Trashed => sdk::dtos::sync::ChangeKind::Trashed,
+            ChangeKind::Restored => sdk::dtos::sync::ChangeKind::Restored,
+        }
+    }
+}
+
+/// One entry of a user's change feed. `seq` increases by one with every
+/// mutation of any of the user's files.
+#[derive(Debug, Clone)]
+pub(crate) struct FileChange {
+    pub seq: i64,
+    pub file_id: Id,
+    pub kind: ChangeKind,
+    pub changed_at: OffsetDateTime,
+}
+
+impl From<crate::entity::file_changes::Model> for FileChange {
+    fn from(m: crate::entity::file_changes::Model) -> Self {
+        FileChange {
+            seq: m.seq,
+            file_id: Id::from(m.file_id),
+            kind: m.kind.into(),
+            changed_at: m.changed_at,
+        }
+    }
+}
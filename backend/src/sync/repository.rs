@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use sea_orm::sea_query::{Expr, ExprTrait};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
+use tracing::error;
+
+use crate::database::DbPool;
+use crate::entity::prelude::{AppUsers, FileChanges, Files};
+use crate::entity::{app_users, file_changes, files};
+use crate::error::{Error, Result};
+use crate::file::File;
+use crate::ulid::Id;
+
+use super::{ChangeKind, FileChange};
+
+pub(crate) trait ChangeRepository {
+    /// Up to `limit` of the user's changes after `since`, oldest first, each
+    /// with the current row of its file if one still exists.
+    async fn find_changes(
+        &self,
+        user_id: &Id,
+        since: i64,
+        limit: u64,
+    ) -> Result<Vec<(FileChange, Option<File>)>>;
+}
+
+pub(crate) struct DbChangeRepository {
+    pub db: DbPool,
+}
+
+impl ChangeRepository for DbChangeRepository {
+    async fn find_changes(
+        &self,
+        user_id: &Id,
+        since: i64,
+        limit: u64,
+    ) -> Result<Vec<(FileChange, Option<File>)>> {
+        let changes = FileChanges::find()
+            .filter(file_changes::Column::UserId.eq(uuid::Uuid::from(*user_id)))
+            .filter(file_changes::Column::Seq.gt(since))
+            .order_by_asc(file_changes::Column::Seq)
+            .limit(limit)
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get file changes");
+                Error::Database
+            })?;
+
+        let file_ids: Vec<uuid::Uuid> = changes.iter().map(|c| c.file_id).collect();
+        let files: HashMap<uuid::Uuid, File> = Files::find()
+            .filter(files::Column::Id.is_in(file_ids))
+            .all(&self.db)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not get changed files");
+                Error::Database
+            })?
+            .into_iter()
+            .map(|m| (m.id, File::from(m)))
+            .collect();
+
+        let changes = changes
+            .into_iter()
+            .map(|change| {
+                let file = files.get(&change.file_id).cloned();
+                (FileChange::from(change), file)
+            })
+            .collect();
+
+        Ok(changes)
+    }
+}
+
+/// Append a change to the owner's feed. Must run in the transaction that
+/// makes the change: bumping `change_seq` locks the user row until commit, so
+/// a reader never sees a sequence number before all lower ones are visible.
+pub(crate) async fn record_change<C: ConnectionTrait>(
+    conn: &C,
+    owner_id: &Id,
+    file_id: &Id,
+    kind: ChangeKind,
+) -> std::result::Result<i64, DbErr> {
+    let user = AppUsers::update_many()
+        .col_expr(
+            app_users::Column::ChangeSeq,
+            Expr::col(app_users::Column::ChangeSeq).add(1),
+        )
+        .filter(app_users::Column::Id.eq(uuid::Uuid::from(*owner_id)))
+        .exec_with_returning(conn)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| DbErr::RecordNotFound(format!("user {owner_id}")))?;
+
+    FileChanges::insert(file_changes::ActiveModel {
+        user_id: Set(user.id),
+        seq: Set(user.change_seq),
+        file_id: Set(uuid::Uuid::from(*file_id)),
+        kind: Set(kind.into()),
+        ..Default::default()
+    })
+    .exec_without_returning(conn)
+    .await?;
+
+    Ok(user.change_seq)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::file::repository::tests::InMemoryFileRepository;
+
+    impl ChangeRepository for InMemoryFileRepository {
+        async fn find_changes(
+            &self,
+            user_id: &Id,
+            since: i64,
+            limit: u64,
+        ) -> Result<Vec<(FileChange, Option<File>)>> {
+            let files = self.files.borrow();
+            let changes = self
+                .changes
+                .borrow()
+                .iter()
+                .filter(|(owner_id, change)| owner_id == user_id && change.seq > since)
+                .take(limit as usize)
+                .map(|(_, change)| {
+                    let file = files.iter().find(|f| f.id == change.file_id).cloned();
+                    (change.clone(), file)
+                })
+                .collect();
+            Ok(changes)
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use axum::{Router, routing::get};
+
+use crate::AppState;
+
+use super::handlers;
+
+pub(crate) fn routes(app_state: AppState) -> Router {
+    Router::new()
+        .route("/sync/changes", get(handlers::get_changes))
+        .with_state(app_state)
+}
@@ -1,9 +1,9 @@
-use aws_sdk_s3::presigning::PresigningConfig;
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use axum::Json;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::time::Duration;
 use time::OffsetDateTime;
 use tracing::{debug, error, info, warn};
 
@@ -11,17 +11,23 @@ use crate::AppState;
 use crate::error::{Error, Result};
 use crate::file::repository::{DbFileRepository, FileRepository};
 use crate::file::{File, FileState};
+use crate::quota::{self, check_quota};
 use crate::session::Session;
 use crate::ulid::Id;
 
 use sdk::media::required_variants;
 
-use crate::storage::{presign_put_object, presigning_config, s3_original_key, s3_thumbnail_key};
+use crate::storage::{
+    StorageBackend, UploadedPart, delete_file_objects, s3_original_key, s3_thumbnail_key,
+};
 
 use super::repository::{DbUploadRepository, UploadRepository};
 use super::{
-    ChunkUrl, CompleteUploadRequest, CompleteUploadResponse, InitUploadRequest, InitUploadResponse,
-    MAX_CHUNK_SIZE, MAX_PARTS, MIN_CHUNK_SIZE, ThumbnailUrl, UploadSession, UploadStatusResponse,
+    BatchCompleteUploadRequest, BatchInitUploadRequest, BatchResponse, BatchResult,
+    BatchUploadStatusRequest, ChunkUrl, CompleteUploadRequest, CompleteUploadResponse,
+    InitUploadRequest, InitUploadResponse, MAX_BATCH_SIZE, MAX_CHUNK_SIZE, MAX_PART_URL_WINDOW,
+    MAX_PARTS, MIN_CHUNK_SIZE, PART_URL_WINDOW, PartUrlsParams, PartUrlsResponse, ThumbnailUrl,
+    UploadSession, UploadStatusResponse,
 };
 
 pub(super) async fn init_upload(
@@ -30,12 +36,50 @@ pub(super) async fn init_upload(
     Path(file_id): Path<Id>,
     Json(request): Json<InitUploadRequest>,
 ) -> Result<(StatusCode, Json<InitUploadResponse>)> {
+    let (status, response) = init_file_upload(&state, session.user_id(), file_id, &request).await?;
+    Ok((status, Json(response)))
+}
+
+pub(super) async fn init_uploads(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<BatchInitUploadRequest>,
+) -> Result<Json<BatchResponse<InitUploadResponse>>> {
+    debug!(count = request.files.len(), "Initializing upload sessions");
+    check_batch_size(request.files.len())?;
+
+    let items = request
+        .files
+        .into_iter()
+        .map(|item| (item.file_id, item.upload));
+    let user_id = session.user_id();
+    let response = run_batch(items, |file_id, upload| {
+        let state = &state;
+        async move {
+            init_file_upload(state, user_id, file_id, &upload)
+                .await
+                .map(|(_, response)| response)
+        }
+    })
+    .await;
+
+    Ok(Json(response))
+}
+
+/// Start (or resume) the upload of one file. The status is `201 Created` for
+/// a new session and `200 OK` for an existing one.
+async fn init_file_upload(
+    state: &AppState,
+    user_id: Id,
+    file_id: Id,
+    request: &InitUploadRequest,
+) -> Result<(StatusCode, InitUploadResponse)> {
     debug!(%file_id, "Initializing upload session");
 
     let file_repo = DbFileRepository {
         db: state.db.clone(),
     };
-    let file = load_and_authorize(&file_repo, &file_id, session.user_id()).await?;
+    let file = load_and_authorize(&file_repo, &file_id, user_id).await?;
     let mut upload_repo = DbUploadRepository {
         db: state.db.clone(),
     };
@@ -43,7 +87,7 @@ pub(super) async fn init_upload(
     let existing_session = upload_repo.find_session(&file_id).await?;
     let s3_assembled =
         if matches!(file.state, FileState::SyncInProgress) && existing_session.is_none() {
-            check_upload_complete(&state, &file).await?
+            check_upload_complete(state, &file).await?
         } else {
             false
         };
@@ -61,29 +105,33 @@ pub(super) async fn init_upload(
         InitAction::ReturnExistingSession => {
             let session = existing_session.unwrap();
             debug!(%file_id, "Returning existing upload session");
-            let response = build_init_response(&state, &file, &session).await?;
-            return Ok((StatusCode::OK, Json(response)));
+            let response = build_init_response(state, &file, &session).await?;
+            return Ok((StatusCode::OK, response));
         }
         InitAction::CleanupExpiredAndProceed => {
             let session = existing_session.unwrap();
             warn!(%file_id, "Cleaning up expired session before re-init");
-            cleanup_s3_upload(&state, &file, &session).await;
+            cleanup_s3_upload(state, &file, &session).await;
             upload_repo.delete_session(&file_id).await?;
         }
         InitAction::CrashRecoveryTransitionToSynced => {
             info!(%file_id, "S3 object already assembled, transitioning to Synced");
-            file_repo.update_state(&file_id, FileState::Synced).await?;
+            finalize_upload(state, &file_repo, &file).await?;
             return Err(Error::UploadConflict);
         }
         InitAction::ProceedWithNewUpload => {}
     }
 
-    validate_init_request(&request, state.config.upload.max_file_size)?;
+    validate_init_request(request, state.config.upload.max_file_size)?;
+    if let Err(e) = check_ciphertext_size(&file, request.total_size as u64) {
+        file_repo.update_state(&file_id, FileState::Failed).await?;
+        return Err(e);
+    }
 
     let chunk_size = request.chunk_size as i64;
     let total_chunks = ((request.total_size + chunk_size - 1) / chunk_size) as i32;
 
-    let session_count = upload_repo.count_user_sessions(&session.user_id()).await?;
+    let session_count = upload_repo.count_user_sessions(&user_id).await?;
     if session_count >= state.config.upload.max_concurrent_sessions {
         error!(
             %file_id,
@@ -93,29 +141,18 @@ pub(super) async fn init_upload(
         return Err(Error::TooManyRequests);
     }
 
-    let s3_key = s3_original_key(&file);
-    let bucket = &state.config.storage.bucket_name;
-
-    let create_output = state
-        .s3_client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(&s3_key)
-        .content_type("application/octet-stream")
-        .send()
-        .await
-        .map_err(|e| {
-            error!(%file_id, error = %e, "Failed to create S3 multipart upload");
-            Error::Storage
-        })?;
+    let needed = quota::reservation(&file).ok_or_else(|| {
+        error!(%file_id, "Invalid segment layout");
+        Error::FileUpload
+    })?;
 
-    let upload_id = create_output
-        .upload_id()
-        .ok_or_else(|| {
-            error!(%file_id, "S3 did not return upload_id");
-            Error::Storage
-        })?
-        .to_string();
+    // files below the S3 minimum part size fit in a single PUT
+    let upload_id = if request.total_size < MIN_CHUNK_SIZE {
+        None
+    } else {
+        let s3_key = s3_original_key(&file);
+        Some(state.storage.create_multipart_upload(&s3_key).await?)
+    };
 
     let now = OffsetDateTime::now_utc();
     let ttl = time::Duration::hours(state.config.upload.session_ttl_hours);
@@ -131,13 +168,31 @@ pub(super) async fn init_upload(
         expires_at,
     };
 
-    upload_repo.create_session(&session).await?;
+    let default_quota = state.config.quota.default_bytes;
+    let created = upload_repo
+        .create_session(&session, &user_id, move |quota, usage| {
+            check_quota(&user_id, quota, default_quota, usage, needed)
+        })
+        .await;
+    if let Err(e) = created {
+        if let Some(upload_id) = &session.upload_id {
+            let s3_key = s3_original_key(&file);
+            if let Err(e) = state
+                .storage
+                .abort_multipart_upload(&s3_key, upload_id)
+                .await
+            {
+                warn!(%file_id, error = %e, "Failed to abort multipart upload");
+            }
+        }
+        return Err(e);
+    }
     file_repo
         .update_state(&file_id, FileState::SyncInProgress)
         .await?;
 
-    let response = build_init_response(&state, &file, &session).await?;
-    Ok((StatusCode::CREATED, Json(response)))
+    let response = build_init_response(state, &file, &session).await?;
+    Ok((StatusCode::CREATED, response))
 }
 
 pub(super) async fn upload_status(
@@ -145,12 +200,38 @@ pub(super) async fn upload_status(
     session: Session,
     Path(file_id): Path<Id>,
 ) -> Result<Json<UploadStatusResponse>> {
+    let status = file_upload_status(&state, session.user_id(), file_id).await?;
+    Ok(Json(status))
+}
+
+pub(super) async fn uploads_status(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<BatchUploadStatusRequest>,
+) -> Result<Json<BatchResponse<UploadStatusResponse>>> {
+    debug!(count = request.file_ids.len(), "Querying upload statuses");
+    check_batch_size(request.file_ids.len())?;
+
+    let items = request.file_ids.into_iter().map(|file_id| (file_id, ()));
+    let response = run_batch(items, |file_id, ()| {
+        file_upload_status(&state, session.user_id(), file_id)
+    })
+    .await;
+
+    Ok(Json(response))
+}
+
+async fn file_upload_status(
+    state: &AppState,
+    user_id: Id,
+    file_id: Id,
+) -> Result<UploadStatusResponse> {
     debug!(%file_id, "Querying upload status");
 
     let file_repo = DbFileRepository {
         db: state.db.clone(),
     };
-    let file = load_and_authorize(&file_repo, &file_id, session.user_id()).await?;
+    let file = load_and_authorize(&file_repo, &file_id, user_id).await?;
 
     let upload_repo = DbUploadRepository {
         db: state.db.clone(),
@@ -160,42 +241,93 @@ pub(super) async fn upload_status(
         Error::UploadNotFound
     })?;
 
-    let bucket = &state.config.storage.bucket_name;
     let s3_key = s3_original_key(&file);
-
-    let received_parts =
-        list_s3_parts(&state.s3_client, bucket, &s3_key, &session.upload_id).await?;
-    let received_set: HashSet<i32> = received_parts.iter().copied().collect();
-
-    let presigning = presigning_config(state.config.upload.presigned_url_ttl_secs)?;
-    let mut missing = Vec::new();
-    for part_number in 1..=session.total_chunks {
-        if !received_set.contains(&part_number) {
-            let url = presign_upload_part(
-                &state.s3_client,
-                bucket,
+    let expires_in = presigned_url_ttl(state);
+
+    let (received_parts, missing_parts, missing, original_url) = match &session.upload_id {
+        Some(upload_id) => {
+            let received_parts = state.storage.list_parts(&s3_key, upload_id).await?;
+            let received_set: HashSet<i32> = received_parts.iter().copied().collect();
+            let missing_parts: Vec<i32> = (1..=session.total_chunks)
+                .filter(|part_number| !received_set.contains(part_number))
+                .collect();
+            let missing = presign_parts(
+                state,
                 &s3_key,
-                &session.upload_id,
-                part_number,
-                &presigning,
+                upload_id,
+                missing_parts.iter().copied().take(PART_URL_WINDOW as usize),
             )
             .await?;
-            missing.push(ChunkUrl { part_number, url });
+            (received_parts, missing_parts, missing, None)
         }
-    }
+        // a single-PUT original counts as the one and only part
+        None if state.storage.head_object(&s3_key).await?.is_some() => {
+            (vec![1], vec![], vec![], None)
+        }
+        None => {
+            let url = state
+                .storage
+                .presign_put_object(&s3_key, expires_in)
+                .await?;
+            (vec![], vec![1], vec![], Some(url))
+        }
+    };
 
     let (thumbnails_received, thumbnails_missing) =
-        check_thumbnails(&state, &file, &presigning).await?;
+        check_thumbnails(state, &file, expires_in).await?;
 
-    Ok(Json(UploadStatusResponse {
+    Ok(UploadStatusResponse {
         total_chunks: session.total_chunks,
         chunk_size: session.chunk_size,
         total_size: session.total_size,
         received: received_parts,
+        missing_parts,
         missing,
+        original_url,
         thumbnails_received,
         thumbnails_missing,
         expires_at: session.expires_at,
+    })
+}
+
+pub(super) async fn part_urls(
+    State(state): State<AppState>,
+    session: Session,
+    Path(file_id): Path<Id>,
+    Query(params): Query<PartUrlsParams>,
+) -> Result<Json<PartUrlsResponse>> {
+    debug!(%file_id, start = params.start, "Presigning part URLs");
+
+    let file_repo = DbFileRepository {
+        db: state.db.clone(),
+    };
+    let file = load_and_authorize(&file_repo, &file_id, session.user_id()).await?;
+
+    let upload_repo = DbUploadRepository {
+        db: state.db.clone(),
+    };
+    let session = upload_repo.find_session(&file_id).await?.ok_or_else(|| {
+        error!(%file_id, "Upload session not found");
+        Error::UploadNotFound
+    })?;
+
+    if session.expires_at <= OffsetDateTime::now_utc() {
+        error!(%file_id, "Upload session expired");
+        return Err(Error::UploadExpired);
+    }
+
+    let Some(upload_id) = &session.upload_id else {
+        error!(%file_id, "Single-PUT upload has no parts");
+        return Err(Error::FileUpload);
+    };
+
+    let window = part_window(session.total_chunks, params.start, params.count)?;
+    let next_start = Some(window.end() + 1).filter(|next| *next <= session.total_chunks);
+    let chunk_urls = presign_parts(&state, &s3_original_key(&file), upload_id, window).await?;
+
+    Ok(Json(PartUrlsResponse {
+        chunk_urls,
+        next_start,
     }))
 }
 
@@ -205,12 +337,42 @@ pub(super) async fn complete_upload(
     Path(file_id): Path<Id>,
     Json(request): Json<CompleteUploadRequest>,
 ) -> Result<Json<CompleteUploadResponse>> {
+    let response = complete_file_upload(&state, session.user_id(), file_id, request).await?;
+    Ok(Json(response))
+}
+
+pub(super) async fn complete_uploads(
+    State(state): State<AppState>,
+    session: Session,
+    Json(request): Json<BatchCompleteUploadRequest>,
+) -> Result<Json<BatchResponse<CompleteUploadResponse>>> {
+    debug!(count = request.files.len(), "Completing uploads");
+    check_batch_size(request.files.len())?;
+
+    let items = request
+        .files
+        .into_iter()
+        .map(|item| (item.file_id, item.upload));
+    let response = run_batch(items, |file_id, upload| {
+        complete_file_upload(&state, session.user_id(), file_id, upload)
+    })
+    .await;
+
+    Ok(Json(response))
+}
+
+async fn complete_file_upload(
+    state: &AppState,
+    user_id: Id,
+    file_id: Id,
+    request: CompleteUploadRequest,
+) -> Result<CompleteUploadResponse> {
     debug!(%file_id, "Completing upload");
 
     let file_repo = DbFileRepository {
         db: state.db.clone(),
     };
-    let file = load_and_authorize(&file_repo, &file_id, session.user_id()).await?;
+    let file = load_and_authorize(&file_repo, &file_id, user_id).await?;
 
     let upload_repo = DbUploadRepository {
         db: state.db.clone(),
@@ -227,7 +389,7 @@ pub(super) async fn complete_upload(
 
     validate_complete_request(&request, &session)?;
 
-    let missing_thumbs = find_missing_thumbnails(&state, &file).await?;
+    let missing_thumbs = find_missing_thumbnails(state, &file).await?;
     if !missing_thumbs.is_empty() {
         error!(
             %file_id,
@@ -237,45 +399,35 @@ pub(super) async fn complete_upload(
         return Err(Error::UploadIncomplete);
     }
 
-    let s3_key = s3_original_key(&file);
-    let bucket = &state.config.storage.bucket_name;
-
-    let mut sorted_parts = request.parts;
-    sorted_parts.sort_by_key(|p| p.part_number);
-
-    let completed_parts: Vec<CompletedPart> = sorted_parts
-        .iter()
-        .map(|p| {
-            CompletedPart::builder()
-                .part_number(p.part_number)
-                .e_tag(&p.etag)
-                .build()
-        })
-        .collect();
-
-    let multipart_upload = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
-
-    state
-        .s3_client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(&s3_key)
-        .upload_id(&session.upload_id)
-        .multipart_upload(multipart_upload)
-        .send()
-        .await
-        .map_err(|e| {
-            error!(%file_id, error = %e, "S3 CompleteMultipartUpload failed");
-            Error::Storage
-        })?;
+    // a single-PUT original is already in place; finalize_upload checks it
+    if let Some(upload_id) = &session.upload_id {
+        let s3_key = s3_original_key(&file);
+        let parts: Vec<UploadedPart> = request
+            .parts
+            .into_iter()
+            .map(|p| UploadedPart {
+                part_number: p.part_number,
+                etag: p.etag,
+            })
+            .collect();
+
+        state
+            .storage
+            .complete_multipart_upload(&s3_key, upload_id, &parts)
+            .await?;
+    }
 
-    file_repo.update_state(&file_id, FileState::Synced).await?;
+    match finalize_upload(state, &file_repo, &file).await {
+        Err(e @ Error::UploadSizeMismatch) => {
+            upload_repo.delete_session(&file_id).await?;
+            return Err(e);
+        }
+        result => result?,
+    }
     upload_repo.delete_session(&file_id).await?;
 
     info!(%file_id, "Upload completed successfully");
-    Ok(Json(CompleteUploadResponse { file_id }))
+    Ok(CompleteUploadResponse { file_id })
 }
 
 pub(super) async fn abort_upload(
@@ -389,7 +541,8 @@ fn decide_init_action(
     s3_object_assembled: bool,
 ) -> InitAction {
     match file_state {
-        FileState::Synced => InitAction::RejectAlreadySynced,
+        // a trashed file finished uploading before it was trashed
+        FileState::Synced | FileState::Trashed => InitAction::RejectAlreadySynced,
         FileState::SyncInProgress => match session {
             Some(session) if session.expires_at > now => InitAction::ReturnExistingSession,
             Some(_) => InitAction::CleanupExpiredAndProceed,
@@ -414,6 +567,32 @@ async fn load_and_authorize(repo: &impl FileRepository, file_id: &Id, user_id: I
     Ok(file)
 }
 
+/// Run `item` for each file in turn. A failed item is reported in its place
+/// and doesn't stop the others.
+async fn run_batch<I, T, F, Fut>(
+    items: impl IntoIterator<Item = (Id, I)>,
+    mut item: F,
+) -> BatchResponse<T>
+where
+    F: FnMut(Id, I) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut results = Vec::new();
+    for (file_id, input) in items {
+        let result = item(file_id, input).await;
+        results.push(BatchResult::new(file_id, result));
+    }
+    BatchResponse { results }
+}
+
+fn check_batch_size(len: usize) -> Result<()> {
+    if len > MAX_BATCH_SIZE {
+        error!(len, "Batch too large");
+        return Err(Error::FileUpload);
+    }
+    Ok(())
+}
+
 fn validate_init_request(request: &InitUploadRequest, max_file_size: i64) -> Result<()> {
     if request.total_size <= 0 || request.total_size > max_file_size {
         error!(total_size = request.total_size, "total_size out of range");
@@ -449,6 +628,14 @@ fn validate_complete_request(
     request: &CompleteUploadRequest,
     session: &UploadSession,
 ) -> Result<()> {
+    if session.upload_id.is_none() {
+        if !request.parts.is_empty() {
+            error!("Parts given for a single-PUT upload");
+            return Err(Error::FileUpload);
+        }
+        return Ok(());
+    }
+
     if request.parts.len() as i32 != session.total_chunks {
         error!(
             expected = session.total_chunks,
@@ -481,28 +668,41 @@ fn validate_complete_request(
     Ok(())
 }
 
-async fn presign_upload_part(
-    client: &aws_sdk_s3::Client,
-    bucket: &str,
-    key: &str,
+/// Part numbers of the window starting at `start`, clamped to the upload's
+/// parts. `count` defaults to [`PART_URL_WINDOW`].
+fn part_window(total_chunks: i32, start: i32, count: Option<i32>) -> Result<RangeInclusive<i32>> {
+    if !(1..=total_chunks).contains(&start) {
+        error!(start, total_chunks, "Part window out of range");
+        return Err(Error::FileUpload);
+    }
+
+    let count = count
+        .unwrap_or(PART_URL_WINDOW)
+        .clamp(1, MAX_PART_URL_WINDOW);
+    let end = start.saturating_add(count - 1).min(total_chunks);
+    Ok(start..=end)
+}
+
+async fn presign_parts(
+    state: &AppState,
+    s3_key: &str,
     upload_id: &str,
-    part_number: i32,
-    config: &PresigningConfig,
-) -> Result<String> {
-    let presigned = client
-        .upload_part()
-        .bucket(bucket)
-        .key(key)
-        .upload_id(upload_id)
-        .part_number(part_number)
-        .presigned(config.clone())
-        .await
-        .map_err(|e| {
-            error!(part_number, error = %e, "Failed to presign UploadPart");
-            Error::Storage
-        })?;
+    part_numbers: impl IntoIterator<Item = i32>,
+) -> Result<Vec<ChunkUrl>> {
+    let expires_in = presigned_url_ttl(state);
+    let mut urls = Vec::new();
+    for part_number in part_numbers {
+        let url = state
+            .storage
+            .presign_upload_part(s3_key, upload_id, part_number, expires_in)
+            .await?;
+        urls.push(ChunkUrl { part_number, url });
+    }
+    Ok(urls)
+}
 
-    Ok(presigned.uri().to_string())
+fn presigned_url_ttl(state: &AppState) -> Duration {
+    Duration::from_secs(state.config.upload.presigned_url_ttl_secs)
 }
 
 async fn build_init_response(
@@ -510,30 +710,30 @@ async fn build_init_response(
     file: &File,
     session: &UploadSession,
 ) -> Result<InitUploadResponse> {
-    let bucket = &state.config.storage.bucket_name;
     let s3_key = s3_original_key(file);
-    let presigning = presigning_config(state.config.upload.presigned_url_ttl_secs)?;
-
-    let mut chunk_urls = Vec::with_capacity(session.total_chunks as usize);
-    for part_number in 1..=session.total_chunks {
-        let url = presign_upload_part(
-            &state.s3_client,
-            bucket,
-            &s3_key,
-            &session.upload_id,
-            part_number,
-            &presigning,
-        )
-        .await?;
-        chunk_urls.push(ChunkUrl { part_number, url });
-    }
+    let expires_in = presigned_url_ttl(state);
+
+    let (chunk_urls, original_url) = match &session.upload_id {
+        Some(upload_id) => {
+            let window = part_window(session.total_chunks, 1, None)?;
+            let chunk_urls = presign_parts(state, &s3_key, upload_id, window).await?;
+            (chunk_urls, None)
+        }
+        None => {
+            let url = state
+                .storage
+                .presign_put_object(&s3_key, expires_in)
+                .await?;
+            (vec![], Some(url))
+        }
+    };
 
     let variants = required_variants(file.media_type);
     let mut thumbnail_urls = Vec::with_capacity(variants.len());
     for variant in variants {
         let variant = variant.to_string();
         let key = s3_thumbnail_key(file, &variant);
-        let url = presign_put_object(&state.s3_client, bucket, &key, &presigning).await?;
+        let url = state.storage.presign_put_object(&key, expires_in).await?;
         thumbnail_urls.push(ThumbnailUrl { variant, url });
     }
 
@@ -542,77 +742,18 @@ async fn build_init_response(
         chunk_size: session.chunk_size,
         expires_at: session.expires_at,
         chunk_urls,
+        original_url,
         thumbnail_urls,
     })
 }
 
-async fn list_s3_parts(
-    client: &aws_sdk_s3::Client,
-    bucket: &str,
-    key: &str,
-    upload_id: &str,
-) -> Result<Vec<i32>> {
-    let mut received = Vec::new();
-    let mut marker: Option<String> = None;
-
-    loop {
-        let mut req = client
-            .list_parts()
-            .bucket(bucket)
-            .key(key)
-            .upload_id(upload_id);
-
-        if let Some(m) = &marker {
-            req = req.part_number_marker(m);
-        }
-
-        let output = req.send().await.map_err(|e| {
-            error!(error = %e, "Failed to list S3 parts");
-            Error::Storage
-        })?;
-
-        for part in output.parts() {
-            if let Some(num) = part.part_number() {
-                received.push(num);
-            }
-        }
-
-        if output.is_truncated().unwrap_or(false) {
-            marker = output.next_part_number_marker().map(|s| s.to_string());
-            if marker.is_none() {
-                break;
-            }
-        } else {
-            break;
-        }
-    }
-
-    received.sort_unstable();
-    Ok(received)
-}
-
-async fn head_object_exists(client: &aws_sdk_s3::Client, bucket: &str, key: &str) -> Result<bool> {
-    match client.head_object().bucket(bucket).key(key).send().await {
-        Ok(_) => Ok(true),
-        Err(err) => {
-            if err.as_service_error().is_some_and(|e| e.is_not_found()) {
-                Ok(false)
-            } else {
-                error!(key, "S3 HeadObject failed");
-                Err(Error::Storage)
-            }
-        }
-    }
-}
-
 async fn find_missing_thumbnails(state: &AppState, file: &File) -> Result<Vec<String>> {
-    let bucket = &state.config.storage.bucket_name;
     let mut missing = Vec::new();
 
     for variant in required_variants(file.media_type) {
         let variant = variant.to_string();
         let key = s3_thumbnail_key(file, &variant);
-        if !head_object_exists(&state.s3_client, bucket, &key).await? {
+        if state.storage.head_object(&key).await?.is_none() {
             missing.push(variant);
         }
     }
@@ -623,19 +764,18 @@ async fn find_missing_thumbnails(state: &AppState, file: &File) -> Result<Vec<St
 async fn check_thumbnails(
     state: &AppState,
     file: &File,
-    presigning: &PresigningConfig,
+    expires_in: Duration,
 ) -> Result<(Vec<String>, Vec<ThumbnailUrl>)> {
-    let bucket = &state.config.storage.bucket_name;
     let mut received = Vec::new();
     let mut missing = Vec::new();
 
     for variant in required_variants(file.media_type) {
         let variant = variant.to_string();
         let key = s3_thumbnail_key(file, &variant);
-        if head_object_exists(&state.s3_client, bucket, &key).await? {
+        if state.storage.head_object(&key).await?.is_some() {
             received.push(variant);
         } else {
-            let url = presign_put_object(&state.s3_client, bucket, &key, presigning).await?;
+            let url = state.storage.presign_put_object(&key, expires_in).await?;
             missing.push(ThumbnailUrl { variant, url });
         }
     }
@@ -644,10 +784,9 @@ async fn check_thumbnails(
 }
 
 async fn check_upload_complete(state: &AppState, file: &File) -> Result<bool> {
-    let bucket = &state.config.storage.bucket_name;
     let s3_key = s3_original_key(file);
 
-    if !head_object_exists(&state.s3_client, bucket, &s3_key).await? {
+    if state.storage.head_object(&s3_key).await?.is_none() {
         return Ok(false);
     }
 
@@ -655,37 +794,96 @@ async fn check_upload_complete(state: &AppState, file: &File) -> Result<bool> {
     Ok(missing.is_empty())
 }
 
-async fn cleanup_s3_upload(state: &AppState, file: &File, session: &UploadSession) {
-    let bucket = &state.config.storage.bucket_name;
-    let s3_key = s3_original_key(file);
+/// Rejects a ciphertext size that doesn't match what the file's declared
+/// segment layout produces; no reader could decrypt such an object.
+fn check_ciphertext_size(file: &File, size: u64) -> Result<()> {
+    let expected = file.ciphertext_size().ok_or_else(|| {
+        error!(file_id = %file.id, "Invalid segment layout");
+        Error::FileUpload
+    })?;
 
-    if let Err(e) = state
-        .s3_client
-        .abort_multipart_upload()
-        .bucket(bucket)
-        .key(&s3_key)
-        .upload_id(&session.upload_id)
-        .send()
-        .await
-    {
-        warn!(
+    if size != expected {
+        error!(
             file_id = %file.id,
-            error = %e,
-            "Failed to abort S3 multipart upload (may already be cleaned up)"
+            size,
+            expected,
+            "Ciphertext size does not match segment layout"
         );
+        return Err(Error::UploadSizeMismatch);
+    }
+
+    Ok(())
+}
+
+/// Check the assembled original against the segment layout and mark the file
+/// synced. A mismatched upload is deleted and the file set back to `Failed`.
+async fn finalize_upload(
+    state: &AppState,
+    file_repo: &impl FileRepository,
+    file: &File,
+) -> Result<()> {
+    let original = state
+        .storage
+        .head_object(&s3_original_key(file))
+        .await?
+        .ok_or_else(|| {
+            error!(file_id = %file.id, "Assembled object not found");
+            Error::UploadIncomplete
+        })?;
+
+    if let Err(e) = check_ciphertext_size(file, original.size) {
+        if let Err(e) = delete_file_objects(&state.storage, file).await {
+            warn!(file_id = %file.id, error = %e, "Failed to delete mismatched upload");
+        }
+        file_repo.update_state(&file.id, FileState::Failed).await?;
+        return Err(e);
+    }
+
+    let mut stored_size = original.size;
+    for variant in required_variants(file.media_type) {
+        let key = s3_thumbnail_key(file, &variant.to_string());
+        let thumbnail = state.storage.head_object(&key).await?.ok_or_else(|| {
+            error!(file_id = %file.id, %key, "Thumbnail not found");
+            Error::UploadIncomplete
+        })?;
+        stored_size += thumbnail.size;
+    }
+
+    file_repo.mark_synced(&file.id, stored_size).await
+}
+
+async fn cleanup_s3_upload(state: &AppState, file: &File, session: &UploadSession) {
+    let s3_key = s3_original_key(file);
+
+    match &session.upload_id {
+        Some(upload_id) => {
+            if let Err(e) = state
+                .storage
+                .abort_multipart_upload(&s3_key, upload_id)
+                .await
+            {
+                warn!(
+                    file_id = %file.id,
+                    error = %e,
+                    "Failed to abort multipart upload (may already be cleaned up)"
+                );
+            }
+        }
+        None => {
+            if let Err(e) = state.storage.delete_object(&s3_key).await {
+                warn!(
+                    file_id = %file.id,
+                    error = %e,
+                    "Failed to delete original during cleanup"
+                );
+            }
+        }
     }
 
     for variant in required_variants(file.media_type) {
         let variant = variant.to_string();
         let key = s3_thumbnail_key(file, &variant);
-        if let Err(e) = state
-            .s3_client
-            .delete_object()
-            .bucket(bucket)
-            .key(&key)
-            .send()
-            .await
-        {
+        if let Err(e) = state.storage.delete_object(&key).await {
             warn!(
                 file_id = %file.id,
                 variant = %variant,
@@ -717,12 +915,13 @@ mod tests {
     fn make_file(owner_id: Id, uploader_id: Id, state: FileState) -> File {
         File {
             id: Id::new(),
-            path: "/test/photo.jpg".to_string(),
-            name: "photo.jpg".to_string(),
+            encrypted_metadata: None,
+            legacy_path: None,
+            legacy_name: None,
             state,
             created_at: OffsetDateTime::now_utc(),
             added_at: OffsetDateTime::now_utc(),
-            sha256: "abc123".to_string(),
+            content_hash: None,
             owner_id,
             uploader_id,
             enc_key: "key".to_string(),
@@ -735,13 +934,15 @@ mod tests {
             plaintext_size: 1024,
             nonce_salt: 0,
             enc_scheme: sdk::crypto::ENC_SCHEME_SEGMENTED,
+            trashed_at: None,
+            stored_size: None,
         }
     }
 
     fn make_session(file_id: Id, expires_at: OffsetDateTime) -> UploadSession {
         UploadSession {
             file_id,
-            upload_id: "test-upload-id".to_string(),
+            upload_id: Some("test-upload-id".to_string()),
             total_size: 100 * MIB,
             chunk_size: (16 * MIB) as i32,
             total_chunks: 7,
@@ -762,7 +963,7 @@ mod tests {
     fn make_session_for_validation(total_chunks: i32) -> UploadSession {
         UploadSession {
             file_id: Id::new(),
-            upload_id: "test".to_string(),
+            upload_id: Some("test".to_string()),
             total_size: total_chunks as i64 * 16 * MIB,
             chunk_size: (16 * MIB) as i32,
             total_chunks,
@@ -1028,6 +1229,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn init_action_rejects_trashed_file() {
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(
+            decide_init_action(&FileState::Trashed, None, now, false),
+            InitAction::RejectAlreadySynced
+        );
+    }
+
     #[test]
     fn init_action_returns_existing_active_session() {
         let now = OffsetDateTime::now_utc();
@@ -1084,6 +1294,173 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validate_complete_accepts_single_put_without_parts() {
+        let mut session = make_session_for_validation(1);
+        session.upload_id = None;
+        let req = CompleteUploadRequest { parts: vec![] };
+
+        assert!(validate_complete_request(&req, &session).is_ok());
+    }
+
+    #[test]
+    fn validate_complete_rejects_parts_for_single_put() {
+        let mut session = make_session_for_validation(1);
+        session.upload_id = None;
+        let req = CompleteUploadRequest {
+            parts: make_complete_parts(1),
+        };
+
+        let result = validate_complete_request(&req, &session);
+
+        assert!(matches!(result, Err(Error::FileUpload)));
+    }
+
+    #[test]
+    fn batch_size_is_limited() {
+        assert!(check_batch_size(MAX_BATCH_SIZE).is_ok());
+        assert!(matches!(
+            check_batch_size(MAX_BATCH_SIZE + 1),
+            Err(Error::FileUpload)
+        ));
+    }
+
+    #[test]
+    fn batch_result_carries_value_or_error() {
+        let file_id = Id::new();
+
+        let ok = BatchResult::new(file_id, Ok(CompleteUploadResponse { file_id }));
+        let failed = BatchResult::<CompleteUploadResponse>::new(file_id, Err(Error::Forbidden));
+
+        assert!(ok.result.is_some() && ok.error.is_none());
+        assert!(failed.result.is_none());
+        assert_eq!(failed.error.unwrap().status, 403);
+    }
+
+    #[tokio::test]
+    async fn batch_reports_each_item_in_request_order() {
+        let ids: Vec<Id> = (0..4).map(|_| Id::new()).collect();
+        let mut attempted = Vec::new();
+
+        let response = run_batch(ids.iter().map(|&id| (id, ())), |file_id, ()| {
+            attempted.push(file_id);
+            let result = match attempted.len() {
+                2 => Err(Error::UploadNotFound),
+                3 => Err(Error::Forbidden),
+                _ => Ok(CompleteUploadResponse { file_id }),
+            };
+            async move { result }
+        })
+        .await;
+
+        // a failure doesn't stop the items after it
+        assert_eq!(attempted, ids);
+        let outcomes: Vec<_> = response
+            .results
+            .iter()
+            .map(|r| {
+                (
+                    r.file_id,
+                    r.result.as_ref().map(|ok| ok.file_id),
+                    r.error.as_ref().map(|e| e.status),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (ids[0], Some(ids[0]), None),
+                (ids[1], None, Some(404)),
+                (ids[2], None, Some(403)),
+                (ids[3], Some(ids[3]), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_of_failures_serializes_errors_only() {
+        let file_id = Id::new();
+
+        let response = run_batch([(file_id, ())], |_, ()| async {
+            Err::<CompleteUploadResponse, _>(Error::UploadExpired)
+        })
+        .await;
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "results": [{
+                    "file_id": file_id.to_string(),
+                    "error": {"status": 410, "message": "Gone"},
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn part_window_defaults_to_first_window() {
+        assert_eq!(part_window(10_000, 1, None).unwrap(), 1..=PART_URL_WINDOW);
+    }
+
+    #[test]
+    fn part_window_is_clamped_to_last_part() {
+        assert_eq!(part_window(250, 201, None).unwrap(), 201..=250);
+        assert_eq!(part_window(3, 1, None).unwrap(), 1..=3);
+    }
+
+    #[test]
+    fn part_window_clamps_requested_count() {
+        assert_eq!(
+            part_window(10_000, 1, Some(50_000)).unwrap(),
+            1..=MAX_PART_URL_WINDOW
+        );
+        assert_eq!(part_window(10_000, 5, Some(0)).unwrap(), 5..=5);
+    }
+
+    #[test]
+    fn part_window_rejects_start_out_of_range() {
+        assert!(matches!(part_window(10, 0, None), Err(Error::FileUpload)));
+        assert!(matches!(part_window(10, 11, None), Err(Error::FileUpload)));
+    }
+
+    #[test]
+    fn ciphertext_size_accepts_layout_size() {
+        let file = make_file(Id::new(), Id::new(), FileState::New);
+        let expected = file.ciphertext_size().unwrap();
+
+        assert!(check_ciphertext_size(&file, expected).is_ok());
+    }
+
+    #[test]
+    fn ciphertext_size_rejects_plaintext_size() {
+        let file = make_file(Id::new(), Id::new(), FileState::New);
+
+        let result = check_ciphertext_size(&file, file.plaintext_size);
+
+        assert!(matches!(result, Err(Error::UploadSizeMismatch)));
+    }
+
+    #[test]
+    fn ciphertext_size_rejects_extra_bytes() {
+        let file = make_file(Id::new(), Id::new(), FileState::New);
+        let expected = file.ciphertext_size().unwrap();
+
+        let result = check_ciphertext_size(&file, expected + 1);
+
+        assert!(matches!(result, Err(Error::UploadSizeMismatch)));
+    }
+
+    #[test]
+    fn ciphertext_size_rejects_invalid_layout() {
+        let mut file = make_file(Id::new(), Id::new(), FileState::New);
+        file.segment_size = 0;
+
+        let result = check_ciphertext_size(&file, 1024);
+
+        assert!(matches!(result, Err(Error::FileUpload)));
+    }
+
     #[test]
     fn s3_original_key_format() {
         let user_id = Id::new();
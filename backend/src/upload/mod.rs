@@ -8,6 +8,7 @@ pub(crate) use routes::routes;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
+use crate::error::Error;
 use crate::ulid::Id;
 
 /// S3 minimum part size (except last part).
@@ -19,10 +20,23 @@ pub(crate) const MAX_CHUNK_SIZE: i64 = 100 * 1024 * 1024;
 /// S3 maximum number of parts per multipart upload.
 pub(crate) const MAX_PARTS: i64 = 10_000;
 
+/// Part URLs presigned per response unless the client asks for another
+/// window size. Clients fetch further windows as they go, so URLs are never
+/// signed long before they're used.
+pub(crate) const PART_URL_WINDOW: i32 = 100;
+
+/// Upper bound on the window size a client may ask for.
+pub(crate) const MAX_PART_URL_WINDOW: i32 = 1000;
+
+/// Most files a batch request may carry.
+pub(crate) const MAX_BATCH_SIZE: usize = 100;
+
 #[derive(Debug, Clone)]
 pub(crate) struct UploadSession {
     pub file_id: Id,
-    pub upload_id: String,
+    /// Multipart upload of the original; `None` when a small file is
+    /// uploaded with a single PUT instead.
+    pub upload_id: Option<String>,
     pub total_size: i64,
     pub chunk_size: i32,
     pub total_chunks: i32,
@@ -68,7 +82,12 @@ pub(super) struct InitUploadResponse {
     pub chunk_size: i32,
     #[serde(with = "time::serde::rfc3339")]
     pub expires_at: OffsetDateTime,
+    /// URLs for the first window of parts; the rest come from the part URL
+    /// endpoint. Empty for a single-PUT upload.
     pub chunk_urls: Vec<ChunkUrl>,
+    /// Presigned PUT for the whole original, for a single-PUT upload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
     pub thumbnail_urls: Vec<ThumbnailUrl>,
 }
 
@@ -78,13 +97,33 @@ pub(super) struct UploadStatusResponse {
     pub chunk_size: i32,
     pub total_size: i64,
     pub received: Vec<i32>,
+    /// Every part not received yet.
+    pub missing_parts: Vec<i32>,
+    /// URLs for the first window of missing parts.
     pub missing: Vec<ChunkUrl>,
+    /// Presigned PUT for the original of a single-PUT upload, until received.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
     pub thumbnails_received: Vec<String>,
     pub thumbnails_missing: Vec<ThumbnailUrl>,
     #[serde(with = "time::serde::rfc3339")]
     pub expires_at: OffsetDateTime,
 }
 
+#[derive(Debug, Deserialize)]
+pub(super) struct PartUrlsParams {
+    /// First part number of the window.
+    pub start: i32,
+    pub count: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct PartUrlsResponse {
+    pub chunk_urls: Vec<ChunkUrl>,
+    /// Start of the following window, if parts remain.
+    pub next_start: Option<i32>,
+}
+
 #[derive(Debug, Deserialize)]
 pub(super) struct CompletePart {
     pub part_number: i32,
@@ -93,6 +132,8 @@ pub(super) struct CompletePart {
 
 #[derive(Debug, Deserialize)]
 pub(super) struct CompleteUploadRequest {
+    /// Empty for a single-PUT upload.
+    #[serde(default)]
     pub parts: Vec<CompletePart>,
 }
 
@@ -100,3 +141,83 @@ pub(super) struct CompleteUploadRequest {
 pub(super) struct CompleteUploadResponse {
     pub file_id: Id,
 }
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BatchInitItem {
+    pub file_id: Id,
+    #[serde(flatten)]
+    pub upload: InitUploadRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BatchInitUploadRequest {
+    pub files: Vec<BatchInitItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BatchUploadStatusRequest {
+    pub file_ids: Vec<Id>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BatchCompleteItem {
+    pub file_id: Id,
+    #[serde(flatten)]
+    pub upload: CompleteUploadRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BatchCompleteUploadRequest {
+    pub files: Vec<BatchCompleteItem>,
+}
+
+/// Why one item of a batch failed; the status and message it would have got
+/// as a single request.
+#[derive(Debug, Serialize)]
+pub(super) struct BatchError {
+    pub status: u16,
+    pub message: &'static str,
+}
+
+impl From<&Error> for BatchError {
+    fn from(e: &Error) -> Self {
+        let (status, message) = e.status_and_message();
+        BatchError {
+            status: status.as_u16(),
+            message,
+        }
+    }
+}
+
+/// Outcome of one item; exactly one of `result` and `error` is set.
+#[derive(Debug, Serialize)]
+pub(super) struct BatchResult<T> {
+    pub file_id: Id,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BatchError>,
+}
+
+impl<T> BatchResult<T> {
+    pub fn new(file_id: Id, result: Result<T, Error>) -> Self {
+        match result {
+            Ok(value) => BatchResult {
+                file_id,
+                result: Some(value),
+                error: None,
+            },
+            Err(e) => BatchResult {
+                file_id,
+                result: None,
+                error: Some(BatchError::from(&e)),
+            },
+        }
+    }
+}
+
+/// Results in the order of the request.
+#[derive(Debug, Serialize)]
+pub(super) struct BatchResponse<T> {
+    pub results: Vec<BatchResult<T>>,
+}
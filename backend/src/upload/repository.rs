@@ -1,6 +1,6 @@
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, EntityTrait, JoinType, PaginatorTrait, QueryFilter, QuerySelect,
-    RelationTrait, Set,
+    RelationTrait, Set, TransactionTrait,
 };
 use time::OffsetDateTime;
 use tracing::error;
@@ -8,13 +8,23 @@ use tracing::error;
 use crate::database::DbPool;
 use crate::entity::prelude::UploadSessions;
 use crate::entity::upload_sessions;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::quota::Usage;
+use crate::quota::repository::{UserQuota, lock_user_usage};
 use crate::ulid::Id;
 
 use super::UploadSession;
 
 pub(crate) trait UploadRepository {
-    async fn create_session(&mut self, session: &UploadSession) -> Result<()>;
+    /// Store the session once `check` accepted the quota and usage of
+    /// `owner_id`, in one transaction that keeps other sessions of the owner
+    /// from being created in between.
+    async fn create_session(
+        &mut self,
+        session: &UploadSession,
+        owner_id: &Id,
+        check: impl FnOnce(UserQuota, &Usage) -> Result<()> + Send + 'static,
+    ) -> Result<()>;
     async fn find_session(&self, file_id: &Id) -> Result<Option<UploadSession>>;
     async fn delete_session(&self, file_id: &Id) -> Result<()>;
     async fn find_expired_sessions(&self, now: OffsetDateTime) -> Result<Vec<UploadSession>>;
@@ -26,8 +36,14 @@ pub(crate) struct DbUploadRepository {
 }
 
 impl UploadRepository for DbUploadRepository {
-    async fn create_session(&mut self, session: &UploadSession) -> Result<()> {
-        upload_sessions::ActiveModel {
+    async fn create_session(
+        &mut self,
+        session: &UploadSession,
+        owner_id: &Id,
+        check: impl FnOnce(UserQuota, &Usage) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        let owner_id = *owner_id;
+        let session = upload_sessions::ActiveModel {
             file_id: Set(uuid::Uuid::from(session.file_id)),
             upload_id: Set(session.upload_id.clone()),
             total_size: Set(session.total_size),
@@ -35,15 +51,27 @@ impl UploadRepository for DbUploadRepository {
             total_chunks: Set(session.total_chunks),
             created_at: Set(session.created_at),
             expires_at: Set(session.expires_at),
-        }
-        .insert(&self.db)
-        .await
-        .map_err(|e| {
-            error!(error = %e, "Could not create upload session");
-            crate::error::Error::Database
-        })?;
+        };
 
-        Ok(())
+        self.db
+            .transaction::<_, Result<()>, sea_orm::DbErr>(|txn| {
+                Box::pin(async move {
+                    let Some((quota, usage)) = lock_user_usage(txn, &owner_id).await? else {
+                        error!(%owner_id, "User not found");
+                        return Ok(Err(Error::UserNotFound));
+                    };
+                    if let Err(e) = check(quota, &usage) {
+                        return Ok(Err(e));
+                    }
+                    session.insert(txn).await?;
+                    Ok(Ok(()))
+                })
+            })
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not create upload session");
+                Error::Database
+            })?
     }
 
     async fn find_session(&self, file_id: &Id) -> Result<Option<UploadSession>> {
@@ -103,3 +131,98 @@ impl UploadRepository for DbUploadRepository {
         Ok(count as i64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_db;
+    use crate::file::repository::{DbFileRepository, FileRepository};
+    use crate::file::{File, FileState};
+    use crate::quota::repository::{DbQuotaRepository, QuotaRepository};
+    use crate::quota::{check_quota, thumbnail_reservation};
+    use sdk::media::MediaType;
+
+    fn new_file(owner_id: Id) -> File {
+        let now = OffsetDateTime::now_utc();
+        File {
+            id: Id::new(),
+            encrypted_metadata: None,
+            legacy_path: None,
+            legacy_name: None,
+            state: FileState::New,
+            created_at: now,
+            added_at: now,
+            content_hash: None,
+            owner_id,
+            uploader_id: owner_id,
+            enc_key: "key".to_string(),
+            media_type: MediaType::Image,
+            content_type: "image/jpeg".to_string(),
+            width: 640,
+            height: 480,
+            duration_ms: None,
+            segment_size: sdk::segment::DEFAULT_SEGMENT_SIZE,
+            plaintext_size: 1000,
+            nonce_salt: 42,
+            enc_scheme: sdk::crypto::ENC_SCHEME_SEGMENTED,
+            trashed_at: None,
+            stored_size: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_sessions_stay_within_quota() {
+        let Some(db) = test_db().await else { return };
+        let user_id = crate::auth::new_user(&db).await;
+        let needed = 1000 + thumbnail_reservation(MediaType::Image);
+        DbQuotaRepository { db: db.clone() }
+            .set_user_quota(&user_id, Some(3 * needed))
+            .await
+            .unwrap();
+
+        let mut files = Vec::new();
+        for _ in 0..10 {
+            let file = new_file(user_id);
+            let mut file_repo = DbFileRepository { db: db.clone() };
+            file_repo.save(&file).await.unwrap();
+            files.push(file);
+        }
+
+        // all sessions start together, to race for the last bytes
+        let start = std::sync::Arc::new(tokio::sync::Barrier::new(files.len()));
+        let tasks: Vec<_> = files
+            .into_iter()
+            .map(|file| {
+                let db = db.clone();
+                let start = start.clone();
+                tokio::spawn(async move {
+                    let now = OffsetDateTime::now_utc();
+                    let session = UploadSession {
+                        file_id: file.id,
+                        upload_id: None,
+                        total_size: 1000,
+                        chunk_size: 1000,
+                        total_chunks: 1,
+                        created_at: now,
+                        expires_at: now + time::Duration::hours(1),
+                    };
+                    start.wait().await;
+                    DbUploadRepository { db }
+                        .create_session(&session, &user_id, move |quota, usage| {
+                            check_quota(&user_id, quota, None, usage, needed)
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        let mut created = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(()) => created += 1,
+                Err(e) => assert!(matches!(e, Error::QuotaExceeded)),
+            }
+        }
+        assert_eq!(created, 3);
+    }
+}
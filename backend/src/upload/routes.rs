@@ -12,10 +12,14 @@ pub(crate) fn routes(app_state: AppState) -> Router {
             "/files/{file_id}/upload/status",
             get(handlers::upload_status),
         )
+        .route("/files/{file_id}/upload/parts", get(handlers::part_urls))
         .route(
             "/files/{file_id}/upload/complete",
             post(handlers::complete_upload),
         )
         .route("/files/{file_id}/upload", delete(handlers::abort_upload))
+        .route("/uploads/init", post(handlers::init_uploads))
+        .route("/uploads/status", post(handlers::uploads_status))
+        .route("/uploads/complete", post(handlers::complete_uploads))
         .with_state(app_state)
 }
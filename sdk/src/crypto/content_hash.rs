@@ -0,0 +1,83 @@
+//! Keyed content hashes for deduplication. A plain SHA-256 of a photo lets
+//! anyone holding the same photo confirm a user has it; an HMAC keyed from
+//! the user's private key only matches within that user's library.
+
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, KeyInit, Mac};
+use rsa::RsaPrivateKey;
+use sha2::Sha256;
+
+use super::error::{Error, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Per-user key for [`content_hash`], derived from the user's private key so
+/// every device holding the key computes the same hashes.
+#[derive(Clone)]
+pub struct ContentHashKey([u8; 32]);
+
+impl ContentHashKey {
+    pub fn from_private_key(private_key: &RsaPrivateKey) -> Result<Self> {
+        let der = super::rsa::to_der(private_key)?;
+        let mut mac = HmacSha256::new_from_slice(&der)
+            .map_err(|e| Error::EncryptionError(format!("Could not derive hash key: {e}")))?;
+        mac.update(b"content-hash");
+        Ok(Self(mac.finalize().into_bytes().into()))
+    }
+}
+
+/// Incremental [`content_hash`] for files too large to hold in memory.
+pub struct ContentHasher(HmacSha256);
+
+impl ContentHasher {
+    pub fn new(key: &ContentHashKey) -> Self {
+        Self(HmacSha256::new_from_slice(&key.0).expect("HMAC accepts any key length"))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> String {
+        Base64::encode_string(&self.0.finalize().into_bytes())
+    }
+}
+
+/// Base64 HMAC-SHA256 of the plaintext original under the user's key.
+pub fn content_hash(key: &ContentHashKey, data: &[u8]) -> String {
+    let mut hasher = ContentHasher::new(key);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::rsa::generate_key;
+
+    #[test]
+    fn same_key_and_content_give_the_same_hash() {
+        let key = ContentHashKey::from_private_key(&generate_key()).unwrap();
+
+        assert_eq!(content_hash(&key, b"photo"), content_hash(&key, b"photo"));
+        assert_ne!(content_hash(&key, b"photo"), content_hash(&key, b"other"));
+    }
+
+    #[test]
+    fn hashes_differ_between_users() {
+        let alice = ContentHashKey::from_private_key(&generate_key()).unwrap();
+        let bob = ContentHashKey::from_private_key(&generate_key()).unwrap();
+
+        assert_ne!(content_hash(&alice, b"photo"), content_hash(&bob, b"photo"));
+    }
+
+    #[test]
+    fn incremental_hash_matches_one_shot() {
+        let key = ContentHashKey::from_private_key(&generate_key()).unwrap();
+        let mut hasher = ContentHasher::new(&key);
+        hasher.update(b"pho");
+        hasher.update(b"to");
+
+        assert_eq!(hasher.finalize(), content_hash(&key, b"photo"));
+    }
+}
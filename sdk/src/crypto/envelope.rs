@@ -0,0 +1,330 @@
+//! Passphrase envelope for secrets stored on the server, chiefly the user's
+//! private key. Each seal draws a fresh salt and nonce, and the KDF
+//! parameters travel with the ciphertext so they can be raised later without
+//! breaking existing envelopes.
+//!
+//! Envelope layout, base64-encoded:
+//! `version (1) || m_cost (4) || t_cost (4) || p_cost (4) || salt (16) ||
+//! nonce (12) || ciphertext+tag`. Everything before the ciphertext is
+//! authenticated as AAD.
+//!
+//! Private keys wrapped before envelopes existed (see [`super::generate_cipher`])
+//! are still opened by [`unwrap_private_key`], which hands back a re-wrapped
+//! envelope for the client to store.
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, Generate, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use rand::RngExt;
+use rsa::RsaPrivateKey;
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use ulid::Ulid;
+
+use super::error::{Error, Result};
+
+pub const ENVELOPE_VERSION: u8 = 1;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const HEADER_SIZE: usize = 1 + 12 + SALT_SIZE + NONCE_SIZE;
+
+/// Upper bounds on the KDF parameters an envelope may ask for, so a tampered
+/// header can't make the client burn unbounded memory or time.
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// Argon2id parameters; `m_cost` is in KiB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// What new envelopes are sealed with. Envelopes sealed with anything
+    /// else are re-wrapped by [`unwrap_private_key`].
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The plaintext header of an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    pub version: u8,
+    pub params: KdfParams,
+    salt: [u8; SALT_SIZE],
+    nonce: [u8; NONCE_SIZE],
+}
+
+impl EnvelopeHeader {
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[0] = self.version;
+        bytes[1..5].copy_from_slice(&self.params.m_cost.to_be_bytes());
+        bytes[5..9].copy_from_slice(&self.params.t_cost.to_be_bytes());
+        bytes[9..13].copy_from_slice(&self.params.p_cost.to_be_bytes());
+        bytes[13..29].copy_from_slice(&self.salt);
+        bytes[29..41].copy_from_slice(&self.nonce);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::EncryptionError("Truncated envelope".to_string()));
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        let header = Self {
+            version: bytes[0],
+            params: KdfParams {
+                m_cost: u32_at(1),
+                t_cost: u32_at(5),
+                p_cost: u32_at(9),
+            },
+            salt: bytes[13..29].try_into().unwrap(),
+            nonce: bytes[29..41].try_into().unwrap(),
+        };
+        if header.version != ENVELOPE_VERSION {
+            return Err(Error::EncryptionError(format!(
+                "Unsupported envelope version {}",
+                header.version
+            )));
+        }
+        let KdfParams {
+            m_cost,
+            t_cost,
+            p_cost,
+        } = header.params;
+        if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+            return Err(Error::EncryptionError(
+                "Envelope KDF parameters out of range".to_string(),
+            ));
+        }
+        Ok(header)
+    }
+}
+
+/// Parse the header of a base64 envelope without opening it. The server uses
+/// this to refuse anything that isn't an envelope.
+pub fn parse_header(sealed: &str) -> Result<EnvelopeHeader> {
+    let bytes = Base64::decode_vec(sealed)
+        .map_err(|e| Error::EncryptionError(format!("Could not decode envelope: {e}")))?;
+    EnvelopeHeader::from_bytes(&bytes)
+}
+
+fn derive_key(passphrase: &str, params: KdfParams, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| Error::EncryptionError(format!("Invalid KDF parameters: {e}")))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| Error::EncryptionError(format!("Could not derive key: {e}")))?;
+    Ok(key)
+}
+
+/// Seal `data` under `passphrase` with the default KDF parameters.
+pub fn seal(passphrase: &str, data: &[u8]) -> Result<String> {
+    seal_with_params(passphrase, data, KdfParams::default())
+}
+
+pub fn seal_with_params(passphrase: &str, data: &[u8], params: KdfParams) -> Result<String> {
+    let header = EnvelopeHeader {
+        version: ENVELOPE_VERSION,
+        params,
+        salt: rand::rng().random(),
+        nonce: Nonce::<U12>::generate().into(),
+    };
+    let header_bytes = header.to_bytes();
+
+    let key = derive_key(passphrase, params, &header.salt)?;
+    let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key));
+    let ciphertext = cipher
+        .encrypt(
+            &Nonce::<U12>::from(header.nonce),
+            Payload {
+                msg: data,
+                aad: &header_bytes,
+            },
+        )
+        .map_err(|e| Error::EncryptionError(format!("Could not seal envelope: {e}")))?;
+
+    let mut envelope = Vec::with_capacity(HEADER_SIZE + ciphertext.len());
+    envelope.extend_from_slice(&header_bytes);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(Base64::encode_string(&envelope))
+}
+
+/// Open an envelope sealed by [`seal`], returning the data and its header.
+pub fn open(passphrase: &str, sealed: &str) -> Result<(Zeroizing<Vec<u8>>, EnvelopeHeader)> {
+    let bytes = Base64::decode_vec(sealed)
+        .map_err(|e| Error::EncryptionError(format!("Could not decode envelope: {e}")))?;
+    let header = EnvelopeHeader::from_bytes(&bytes)?;
+    let (header_bytes, ciphertext) = bytes.split_at(HEADER_SIZE);
+
+    let key = derive_key(passphrase, header.params, &header.salt)?;
+    let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key));
+    let data = cipher
+        .decrypt(
+            &Nonce::<U12>::from(header.nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header_bytes,
+            },
+        )
+        .map_err(|e| Error::EncryptionError(format!("Could not open envelope: {e}")))?;
+    Ok((Zeroizing::new(data), header))
+}
+
+/// Wrap the user's private key for storage on the server.
+pub fn wrap_private_key(passphrase: &str, private_key: &RsaPrivateKey) -> Result<String> {
+    let der = super::rsa::to_der(private_key)?;
+    seal(passphrase, &der)
+}
+
+pub struct UnwrappedKey {
+    pub private_key: RsaPrivateKey,
+    /// A fresh envelope the client should store in place of the one it
+    /// unwrapped, set when that one was in the legacy fixed-nonce format or
+    /// used outdated KDF parameters.
+    pub rewrapped: Option<String>,
+}
+
+/// Unwrap a stored private key, whichever format it was stored in.
+pub fn unwrap_private_key(user_id: &Ulid, passphrase: &str, stored: &str) -> Result<UnwrappedKey> {
+    // a legacy blob could parse as an envelope by chance, so a failed open
+    // falls through to the legacy format rather than giving up
+    if let Ok((der, header)) = open(passphrase, stored) {
+        let private_key = super::rsa::from_der(&der)?;
+        let rewrapped = (header.params != KdfParams::default())
+            .then(|| wrap_private_key(passphrase, &private_key))
+            .transpose()?;
+        return Ok(UnwrappedKey {
+            private_key,
+            rewrapped,
+        });
+    }
+
+    let (cipher, nonce) = super::generate_cipher(user_id, passphrase)?;
+    let der = Zeroizing::new(super::decrypt_data_raw(stored, &cipher, &nonce)?);
+    let private_key = super::rsa::from_der(&der)?;
+    let rewrapped = Some(wrap_private_key(passphrase, &private_key)?);
+    Ok(UnwrappedKey {
+        private_key,
+        rewrapped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::rsa::{generate_key, to_der};
+
+    /// Cheap parameters so the tests don't spend their time in Argon2.
+    const FAST: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn envelope_roundtrip() {
+        let sealed = seal_with_params("passphrase", b"secret", FAST).unwrap();
+
+        let (data, header) = open("passphrase", &sealed).unwrap();
+
+        assert_eq!(data.as_slice(), b"secret");
+        assert_eq!(header.params, FAST);
+        assert_eq!(parse_header(&sealed).unwrap(), header);
+    }
+
+    #[test]
+    fn every_seal_uses_a_fresh_salt_and_nonce() {
+        let a = parse_header(&seal_with_params("passphrase", b"secret", FAST).unwrap()).unwrap();
+        let b = parse_header(&seal_with_params("passphrase", b"secret", FAST).unwrap()).unwrap();
+
+        assert_ne!(a.salt, b.salt);
+        assert_ne!(a.nonce, b.nonce);
+    }
+
+    #[test]
+    fn wrong_passphrase_fails() {
+        let sealed = seal_with_params("passphrase", b"secret", FAST).unwrap();
+
+        assert!(open("other", &sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_header_fails() {
+        let sealed = seal_with_params("passphrase", b"secret", FAST).unwrap();
+        let mut bytes = Base64::decode_vec(&sealed).unwrap();
+        // a forged salt must not verify
+        bytes[20] ^= 0xff;
+
+        assert!(open("passphrase", &Base64::encode_string(&bytes)).is_err());
+    }
+
+    #[test]
+    fn refuses_excessive_kdf_parameters() {
+        let sealed = seal_with_params("passphrase", b"secret", FAST).unwrap();
+        let mut bytes = Base64::decode_vec(&sealed).unwrap();
+        bytes[1..5].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        assert!(parse_header(&Base64::encode_string(&bytes)).is_err());
+    }
+
+    #[test]
+    fn rewraps_legacy_private_key() {
+        let user_id = Ulid::new();
+        let private_key = generate_key();
+        let (cipher, nonce) = crate::crypto::generate_cipher(&user_id, "passphrase").unwrap();
+        let legacy =
+            crate::crypto::encrypt_data_raw(&to_der(&private_key).unwrap(), &cipher, &nonce);
+
+        let unwrapped = unwrap_private_key(&user_id, "passphrase", &legacy).unwrap();
+
+        assert_eq!(unwrapped.private_key, private_key);
+        let rewrapped = unwrapped.rewrapped.unwrap();
+        assert_eq!(
+            parse_header(&rewrapped).unwrap().params,
+            KdfParams::default()
+        );
+        let again = unwrap_private_key(&user_id, "passphrase", &rewrapped).unwrap();
+        assert_eq!(again.private_key, private_key);
+        assert!(again.rewrapped.is_none());
+    }
+
+    #[test]
+    fn rewraps_envelope_with_outdated_parameters() {
+        let user_id = Ulid::new();
+        let private_key = generate_key();
+        let stored = seal_with_params("passphrase", &to_der(&private_key).unwrap(), FAST).unwrap();
+
+        let unwrapped = unwrap_private_key(&user_id, "passphrase", &stored).unwrap();
+
+        assert_eq!(unwrapped.private_key, private_key);
+        assert!(unwrapped.rewrapped.is_some());
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_for_both_formats() {
+        let user_id = Ulid::new();
+        let private_key = generate_key();
+        let envelope = wrap_private_key("passphrase", &private_key).unwrap();
+        let (cipher, nonce) = crate::crypto::generate_cipher(&user_id, "passphrase").unwrap();
+        let legacy =
+            crate::crypto::encrypt_data_raw(&to_der(&private_key).unwrap(), &cipher, &nonce);
+
+        assert!(unwrap_private_key(&user_id, "other", &envelope).is_err());
+        assert!(unwrap_private_key(&user_id, "other", &legacy).is_err());
+        // the legacy key is also bound to the user it was wrapped for
+        assert!(unwrap_private_key(&Ulid::new(), "passphrase", &legacy).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Sealed per-file metadata: the attributes the server has no business
+//! reading (where the file came from, what it was called, when exactly it was
+//! taken). The client seals them with the file key and the server stores the
+//! opaque blob next to the plaintext fields it needs for ordering and layout.
+//!
+//! Blob layout, base64-encoded: `version (1) || nonce (12) || ciphertext+tag`.
+
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, Generate, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use base64ct::{Base64, Encoding};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+use super::error::{Error, Result};
+
+/// Version byte of the sealed blob, bound into the AAD.
+pub const METADATA_VERSION: u8 = 1;
+
+const NONCE_SIZE: usize = 12;
+
+/// Upper bound on the encoded blob the server accepts.
+pub const MAX_SEALED_METADATA_SIZE: usize = 16 * 1024;
+
+/// The private attributes of a file, readable only by key holders.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivateMetadata {
+    /// Path of the file on the device it was uploaded from.
+    pub path: String,
+    pub name: String,
+    /// Exact capture date with its original offset. The plaintext ordering
+    /// date the server keeps may be coarser.
+    #[serde(with = "time::serde::rfc3339")]
+    pub date: OffsetDateTime,
+    /// Anything else the client wants to keep private (camera, location...).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// The metadata key is derived from the file key so the random metadata nonce
+/// can never collide with a segment nonce under the same key.
+fn metadata_key(file_key: &Key<Aes256Gcm>) -> Key<Aes256Gcm> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"file-metadata");
+    hasher.update(file_key);
+    Key::<Aes256Gcm>::from(<[u8; 32]>::from(hasher.finalize()))
+}
+
+/// Binding the file id means a blob can't be moved onto another file.
+fn metadata_aad(version: u8, file_id: Ulid) -> [u8; 17] {
+    let mut aad = [0u8; 17];
+    aad[0] = version;
+    aad[1..17].copy_from_slice(&file_id.to_bytes());
+    aad
+}
+
+/// Seal `metadata` for the file `file_id` under its file key.
+pub fn seal_metadata(
+    file_id: Ulid,
+    file_key: &Key<Aes256Gcm>,
+    metadata: &PrivateMetadata,
+) -> Result<String> {
+    let plaintext = serde_json::to_vec(metadata)
+        .map_err(|e| Error::EncryptionError(format!("Could not serialize metadata: {e}")))?;
+    let cipher = Aes256Gcm::new(&metadata_key(file_key));
+    let nonce = Nonce::<U12>::generate();
+    let aad = metadata_aad(METADATA_VERSION, file_id);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|e| Error::EncryptionError(format!("Could not encrypt metadata: {e}")))?;
+
+    let mut blob = Vec::with_capacity(1 + NONCE_SIZE + ciphertext.len());
+    blob.push(METADATA_VERSION);
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&ciphertext);
+    Ok(Base64::encode_string(&blob))
+}
+
+/// Open a blob sealed by [`seal_metadata`] for the same file and key.
+pub fn open_metadata(
+    file_id: Ulid,
+    file_key: &Key<Aes256Gcm>,
+    sealed: &str,
+) -> Result<PrivateMetadata> {
+    let blob = Base64::decode_vec(sealed)
+        .map_err(|e| Error::EncryptionError(format!("Could not decode metadata: {e}")))?;
+    let Some((&version, rest)) = blob.split_first() else {
+        return Err(Error::EncryptionError("Empty metadata".to_string()));
+    };
+    if version != METADATA_VERSION {
+        return Err(Error::EncryptionError(format!(
+            "Unsupported metadata version {version}"
+        )));
+    }
+    if rest.len() < NONCE_SIZE {
+        return Err(Error::EncryptionError("Truncated metadata".to_string()));
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+    let nonce = Nonce::<U12>::try_from(nonce).expect("nonce has the right length");
+
+    let cipher = Aes256Gcm::new(&metadata_key(file_key));
+    let aad = metadata_aad(version, file_id);
+    let plaintext = cipher
+        .decrypt(
+            &nonce,
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|e| Error::EncryptionError(format!("Could not decrypt metadata: {e}")))?;
+    serde_json::from_slice(&plaintext)
+        .map_err(|e| Error::EncryptionError(format!("Could not parse metadata: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn metadata() -> PrivateMetadata {
+        PrivateMetadata {
+            path: "/home/pics/holiday".to_string(),
+            name: "beach.jpg".to_string(),
+            date: datetime!(2024-05-01 10:00 +02:00),
+            attributes: BTreeMap::from([("camera".to_string(), "X100".to_string())]),
+        }
+    }
+
+    #[test]
+    fn metadata_roundtrip() {
+        let id = Ulid::new();
+        let key = Key::<Aes256Gcm>::generate();
+
+        let sealed = seal_metadata(id, &key, &metadata()).unwrap();
+
+        assert!(!sealed.contains("beach"));
+        assert_eq!(open_metadata(id, &key, &sealed).unwrap(), metadata());
+    }
+
+    #[test]
+    fn metadata_is_bound_to_the_file() {
+        let key = Key::<Aes256Gcm>::generate();
+        let sealed = seal_metadata(Ulid::new(), &key, &metadata()).unwrap();
+
+        assert!(open_metadata(Ulid::new(), &key, &sealed).is_err());
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let id = Ulid::new();
+        let sealed = seal_metadata(id, &Key::<Aes256Gcm>::generate(), &metadata()).unwrap();
+
+        assert!(open_metadata(id, &Key::<Aes256Gcm>::generate(), &sealed).is_err());
+    }
+
+    #[test]
+    fn truncated_blob_fails() {
+        let id = Ulid::new();
+        let key = Key::<Aes256Gcm>::generate();
+        let sealed = seal_metadata(id, &key, &metadata()).unwrap();
+        let mut blob = Base64::decode_vec(&sealed).unwrap();
+        blob.truncate(8);
+
+        assert!(open_metadata(id, &key, &Base64::encode_string(&blob)).is_err());
+    }
+}
@@ -14,7 +14,12 @@ use ulid::Ulid;
 
 use self::error::Error;
 
+pub mod content_hash;
+pub mod envelope;
 pub mod error;
+pub mod metadata;
+pub mod prf;
+pub mod recovery;
 pub mod rsa;
 
 /// Encryption scheme version for per-segment AES-256-GCM. Bound into every
@@ -24,7 +29,6 @@ pub const ENC_SCHEME_SEGMENTED: u8 = 2;
 
 pub trait CryptoFileDesc {
     fn id(&self) -> Ulid;
-    fn sha256(&self) -> &str;
 }
 
 /// 96-bit GCM nonce for a segment: a per-file random `salt` (32 bits) followed
@@ -147,6 +151,8 @@ fn hash(data: &[u8]) -> String {
     Base64::encode_string(&hash)
 }
 
+/// Legacy: the nonce comes from [`generate_cipher`] and is the same for every
+/// call, so this must not be used for new data. Use [`envelope::seal`].
 pub fn encrypt_data_raw(data: &[u8], cipher: &Aes256Gcm, nonce: &Nonce<U12>) -> String {
     let encrypted = cipher.encrypt(nonce, data).unwrap();
     Base64::encode_string(&encrypted)
@@ -157,12 +163,15 @@ pub fn decrypt_data_raw(
     cipher: &Aes256Gcm,
     nonce: &Nonce<U12>,
 ) -> error::Result<Vec<u8>> {
-    let decoded = Base64::decode_vec(data).unwrap();
+    let decoded = Base64::decode_vec(data)
+        .map_err(|e| Error::EncryptionError(format!("Could not decode data, error: {}", e)))?;
     cipher
         .decrypt(nonce, decoded.as_ref())
         .map_err(|e| Error::EncryptionError(format!("Could not decrypt data, error: {}", e)))
 }
 
+/// Legacy key derivation for private keys wrapped before [`envelope`]: the
+/// user id is the salt and the nonce is fixed. Only kept to open those.
 pub fn generate_cipher(user_id: &Ulid, passphrase: &str) -> error::Result<(Aes256Gcm, Nonce<U12>)> {
     let salt = user_id.to_bytes();
     let nonce_bytes: [u8; 12] = salt[4..16].try_into().unwrap();
@@ -190,9 +199,6 @@ mod tests {
         fn id(&self) -> Ulid {
             self.id
         }
-        fn sha256(&self) -> &str {
-            ""
-        }
     }
 
     fn key() -> Key<Aes256Gcm> {
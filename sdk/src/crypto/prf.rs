@@ -0,0 +1,62 @@
+//! Unlocking with a passkey instead of the passphrase. The WebAuthn PRF
+//! extension has the authenticator derive a 32-byte secret from a
+//! per-credential salt the server hands out; a copy of the private key is
+//! sealed under that secret (as an [`envelope`](super::envelope)) and stored
+//! with the passkey. The secret itself never leaves the client.
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use rsa::RsaPrivateKey;
+use rsa::pkcs8::der::zeroize::Zeroizing;
+
+use super::envelope;
+use super::error::{Error, Result};
+
+/// Length of a PRF output, and of the salts handed to it.
+pub const PRF_OUTPUT_LEN: usize = 32;
+
+/// Canonical form the envelope is sealed under.
+fn secret(prf_output: &[u8]) -> Result<Zeroizing<String>> {
+    if prf_output.len() != PRF_OUTPUT_LEN {
+        return Err(Error::EncryptionError(format!(
+            "PRF output must be {PRF_OUTPUT_LEN} bytes, got {}",
+            prf_output.len()
+        )));
+    }
+    Ok(Zeroizing::new(Base64UrlUnpadded::encode_string(prf_output)))
+}
+
+/// Seal a copy of the private key under a passkey's PRF output.
+pub fn wrap_passkey_key(prf_output: &[u8], private_key: &RsaPrivateKey) -> Result<String> {
+    envelope::wrap_private_key(&secret(prf_output)?, private_key)
+}
+
+/// Open the copy stored with the passkey that produced `prf_output`.
+pub fn unwrap_passkey_key(prf_output: &[u8], wrapped: &str) -> Result<RsaPrivateKey> {
+    let (der, _) = envelope::open(&secret(prf_output)?, wrapped)?;
+    super::rsa::from_der(&der)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::rsa::generate_key;
+
+    #[test]
+    fn unwraps_with_the_same_prf_output() {
+        let private_key = generate_key();
+        let prf_output = [7u8; PRF_OUTPUT_LEN];
+
+        let wrapped = wrap_passkey_key(&prf_output, &private_key).unwrap();
+
+        assert_eq!(
+            unwrap_passkey_key(&prf_output, &wrapped).unwrap(),
+            private_key
+        );
+        assert!(unwrap_passkey_key(&[8u8; PRF_OUTPUT_LEN], &wrapped).is_err());
+    }
+
+    #[test]
+    fn rejects_outputs_of_the_wrong_length() {
+        assert!(wrap_passkey_key(&[7u8; 16], &generate_key()).is_err());
+    }
+}
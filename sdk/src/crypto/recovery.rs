@@ -0,0 +1,116 @@
+//! Recovery phrase for the private key. The user writes down 24 BIP39 words
+//! once; a second copy of the private key is sealed under them (as an
+//! [`envelope`](super::envelope)) and stored on the server, so a forgotten
+//! passphrase doesn't lose every file key with it.
+
+use bip39::{Language, Mnemonic};
+use rand::RngExt;
+use rsa::RsaPrivateKey;
+
+use super::envelope;
+use super::error::{Error, Result};
+
+/// A 24-word English BIP39 phrase (256 bits of entropy).
+#[derive(Clone, PartialEq, Eq)]
+pub struct RecoveryPhrase(Mnemonic);
+
+impl RecoveryPhrase {
+    pub fn generate() -> Self {
+        let entropy: [u8; 32] = rand::rng().random();
+        Self(Mnemonic::from_entropy_in(Language::English, &entropy).expect("32 bytes is valid"))
+    }
+
+    /// Parse a phrase as typed by the user: case and spacing don't matter,
+    /// but the words and their checksum must be valid.
+    pub fn parse(phrase: &str) -> Result<Self> {
+        let phrase = phrase.to_lowercase();
+        let mnemonic = Mnemonic::parse_in(Language::English, phrase.as_str())
+            .map_err(|e| Error::EncryptionError(format!("Invalid recovery phrase: {e}")))?;
+        if mnemonic.word_count() != 24 {
+            return Err(Error::EncryptionError(
+                "Recovery phrase must have 24 words".to_string(),
+            ));
+        }
+        Ok(Self(mnemonic))
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.words()
+    }
+
+    /// Canonical form the envelope is sealed under.
+    fn secret(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Seal a recovery copy of the private key under `phrase`.
+pub fn wrap_recovery_key(phrase: &RecoveryPhrase, private_key: &RsaPrivateKey) -> Result<String> {
+    envelope::wrap_private_key(&phrase.secret(), private_key)
+}
+
+/// Open the recovery copy and re-wrap the private key under a new
+/// passphrase. The returned envelope replaces the stored private key.
+pub fn recover_private_key(
+    phrase: &RecoveryPhrase,
+    recovery_key: &str,
+    new_passphrase: &str,
+) -> Result<(RsaPrivateKey, String)> {
+    let (der, _) = envelope::open(&phrase.secret(), recovery_key)?;
+    let private_key = super::rsa::from_der(&der)?;
+    let wrapped = envelope::wrap_private_key(new_passphrase, &private_key)?;
+    Ok((private_key, wrapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::rsa::generate_key;
+
+    #[test]
+    fn generates_24_words_that_parse_back() {
+        let phrase = RecoveryPhrase::generate();
+        let words: Vec<&str> = phrase.words().collect();
+        assert_eq!(words.len(), 24);
+
+        let typed = format!("  {}  ", words.join("   ").to_uppercase());
+        assert!(RecoveryPhrase::parse(&typed).unwrap() == phrase);
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let valid = format!("{} art", ["abandon"; 23].join(" "));
+        let invalid = ["abandon"; 24].join(" ");
+
+        assert!(RecoveryPhrase::parse(&valid).is_ok());
+        assert!(RecoveryPhrase::parse(&invalid).is_err());
+    }
+
+    #[test]
+    fn rejects_short_phrases() {
+        let twelve = format!("{} about", ["abandon"; 11].join(" "));
+
+        assert!(RecoveryPhrase::parse(&twelve).is_err());
+    }
+
+    #[test]
+    fn recovers_private_key_under_new_passphrase() {
+        let private_key = generate_key();
+        let phrase = RecoveryPhrase::generate();
+        let recovery_key = wrap_recovery_key(&phrase, &private_key).unwrap();
+
+        let (recovered, wrapped) =
+            recover_private_key(&phrase, &recovery_key, "new passphrase").unwrap();
+
+        assert_eq!(recovered, private_key);
+        let (der, _) = envelope::open("new passphrase", &wrapped).unwrap();
+        assert_eq!(crate::crypto::rsa::from_der(&der).unwrap(), private_key);
+    }
+
+    #[test]
+    fn wrong_phrase_fails() {
+        let recovery_key = wrap_recovery_key(&RecoveryPhrase::generate(), &generate_key()).unwrap();
+
+        assert!(recover_private_key(&RecoveryPhrase::generate(), &recovery_key, "new").is_err());
+    }
+}
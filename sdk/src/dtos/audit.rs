@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+/// What an audit entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    /// A wrong password or second factor.
+    LoginFailed,
+    /// A sign-in refused unseen because its account or IP has to wait.
+    LoginThrottled,
+    AccountLocked,
+    IpLocked,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: Ulid,
+    pub kind: AuditEventKind,
+    /// The account involved, if it exists.
+    pub user_id: Option<Ulid>,
+    /// The username as given at sign-in.
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use ulid::Ulid;
 
 #[derive(Serialize, Deserialize)]
@@ -7,6 +10,37 @@ pub struct SaveRsaKeysRequest {
     pub public_key: String,
 }
 
+/// Replaces the stored private key envelope after the client re-wrapped it,
+/// either under a new passphrase or as handed back by
+/// [`crate::crypto::envelope::unwrap_private_key`]. The key pair itself
+/// doesn't change.
+#[derive(Serialize, Deserialize)]
+pub struct UpdatePrivateKeyRequest {
+    /// The envelope the client unwrapped; the swap is refused if it's no
+    /// longer the stored one.
+    pub previous_private_key: String,
+    pub private_key: String,
+}
+
+/// Stores the copy of the private key sealed under the recovery phrase (see
+/// [`crate::crypto::recovery`]). To recover, the client fetches it back,
+/// re-wraps the key under a new passphrase and swaps that in with an
+/// [`UpdatePrivateKeyRequest`].
+#[derive(Serialize, Deserialize)]
+pub struct SaveRecoveryKeyRequest {
+    /// The recovery key the client last fetched, `None` if there was none;
+    /// the save is refused if it's no longer the stored one.
+    #[serde(default)]
+    pub previous_recovery_key: Option<String>,
+    pub recovery_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PrivateKeyResponse {
     pub value: Option<String>,
@@ -16,10 +50,276 @@ pub struct PrivateKeyResponse {
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+    #[serde(flatten)]
+    pub device: DeviceDetails,
 }
 
+/// What a client tells the server about itself when signing in.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeviceDetails {
+    /// The device from an earlier sign-in, to sign in on it again rather
+    /// than add another one.
+    #[serde(default)]
+    pub device_id: Option<Ulid>,
+    /// Shown in the device list, e.g. "Alice's phone".
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// e.g. "android", "linux", "cli".
+    #[serde(default)]
+    pub platform: Option<String>,
+}
+
+/// Tokens of a session, returned on login and on every refresh.
 #[derive(Serialize, Deserialize)]
 pub struct LoginResponse {
     pub user_id: Ulid,
+    /// Pass back as `device_id` on the next sign-in from this device.
+    pub device_id: Ulid,
     pub auth_token: String,
+    /// When `auth_token` stops working; refresh before then.
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    /// Single use: each refresh hands out a new one.
+    pub refresh_token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub refresh_expires_at: OffsetDateTime,
+}
+
+/// What a password login leads to. The fields of the inner value sit next
+/// to `status`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LoginOutcome {
+    SignedIn(LoginResponse),
+    /// The account has two-factor sign-in on; redeem the challenge with one
+    /// of its `methods`.
+    SecondFactorRequired(LoginChallenge),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginChallenge {
+    pub challenge: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    pub methods: Vec<SecondFactor>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactor {
+    /// A [`TotpLoginRequest`].
+    Totp,
+    /// A passkey assertion, started with a [`StartPasskeySecondFactorRequest`].
+    Passkey,
+}
+
+/// Finishes a password login with a code from the authenticator app, or
+/// one of the backup codes.
+#[derive(Serialize, Deserialize)]
+pub struct TotpLoginRequest {
+    pub challenge: String,
+    pub code: String,
+}
+
+/// A pending enrolment: show `provisioning_uri` as a QR code, or `secret`
+/// for typing in, then confirm with the first code.
+#[derive(Serialize, Deserialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// Shown once; only their hashes are kept.
+#[derive(Serialize, Deserialize)]
+pub struct TotpBackupCodesResponse {
+    pub backup_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TotpStatus {
+    pub enabled: bool,
+    pub backup_codes_remaining: u64,
+}
+
+/// Starts adding a passkey. Once the account has TOTP or a passkey, it takes
+/// a current TOTP or backup code, or the password, so a stolen session alone
+/// can't add one.
+#[derive(Default, Serialize, Deserialize)]
+pub struct StartPasskeyRegistrationRequest {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Hand `options` to `navigator.credentials.create()`, then send the
+/// credential back in a [`FinishPasskeyRegistrationRequest`].
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyRegistrationOptions {
+    pub ceremony_id: Ulid,
+    pub options: serde_json::Value,
+    /// Base64url salt to evaluate the PRF extension with, for sealing a copy
+    /// of the private key with [`crate::crypto::prf`].
+    pub prf_salt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FinishPasskeyRegistrationRequest {
+    pub ceremony_id: Ulid,
+    /// Shown in the passkey list, e.g. "Phone".
+    #[serde(default)]
+    pub name: Option<String>,
+    pub credential: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyInfo {
+    pub id: Ulid,
+    pub name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_used_at: Option<OffsetDateTime>,
+    /// Whether a copy of the private key is sealed under its PRF output.
+    pub unlocks_key: bool,
+}
+
+/// A copy of the private key sealed with
+/// [`crate::crypto::prf::wrap_passkey_key`]. Like
+/// [`StartPasskeyRegistrationRequest`], it takes a current code or the
+/// password once the account has a second factor.
+#[derive(Serialize, Deserialize)]
+pub struct SavePasskeyKeyRequest {
+    pub private_key: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Removes a passkey. Like [`StartPasskeyRegistrationRequest`], it takes a
+/// current code or the password once the account has a second factor.
+#[derive(Default, Serialize, Deserialize)]
+pub struct DeletePasskeyRequest {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Starts signing in with a passkey instead of the password.
+#[derive(Serialize, Deserialize)]
+pub struct StartPasskeyLoginRequest {
+    pub username: String,
+}
+
+/// Starts redeeming a [`LoginChallenge`] with a passkey.
+#[derive(Serialize, Deserialize)]
+pub struct StartPasskeySecondFactorRequest {
+    pub challenge: String,
+}
+
+/// Hand `options` to `navigator.credentials.get()`.
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyLoginOptions {
+    pub ceremony_id: Ulid,
+    pub options: serde_json::Value,
+    /// PRF salts by base64url credential id, for the extension's
+    /// `evalByCredential`. Only passkeys that unlock a key are listed.
+    pub prf_salts: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FinishPasskeyLoginRequest {
+    pub ceremony_id: Ulid,
+    pub credential: serde_json::Value,
+    #[serde(flatten)]
+    pub device: DeviceDetails,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FinishPasskeySecondFactorRequest {
+    pub challenge: String,
+    pub ceremony_id: Ulid,
+    pub credential: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyLoginResponse {
+    #[serde(flatten)]
+    pub login: LoginResponse,
+    /// The passkey that signed in.
+    pub passkey_id: Ulid,
+    /// The private key copy sealed under this passkey's PRF output, if any.
+    pub wrapped_private_key: Option<String>,
+}
+
+/// Open `authorization_url` in a browser to sign in at the identity
+/// provider; the client's redirect URI then receives a one-time `code`
+/// (see [`CodeLoginRequest`]) or an `error`.
+#[derive(Serialize, Deserialize)]
+pub struct OidcAuthorizeResponse {
+    pub authorization_url: String,
+}
+
+/// Redeems the one-time code a finished sign-in sent to the client's redirect
+/// URI (desktop login, OpenID Connect), or that finishes linking an OpenID
+/// Connect account. The verifier is the PKCE secret whose S256 challenge the
+/// client passed when it started the sign-in.
+#[derive(Serialize, Deserialize)]
+pub struct CodeLoginRequest {
+    pub code: String,
+    pub code_verifier: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// A signed-in session of the user.
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: Ulid,
+    pub device_id: Ulid,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// When the session can no longer be refreshed.
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+/// A device the user signed in from.
+#[derive(Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: Ulid,
+    pub name: String,
+    pub platform: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_seen_at: OffsetDateTime,
+    /// Change sequence the device last synced from, if it ever synced.
+    pub last_sync_cursor: Option<u64>,
+    /// Whether it still has a session; signed out devices stay listed until
+    /// revoked.
+    pub signed_in: bool,
+    /// Whether this is the device making the request.
+    pub current: bool,
+}
+
+/// Counters of the server's in-memory session cache, since it started.
+#[derive(Serialize, Deserialize)]
+pub struct SessionCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
 }
@@ -12,10 +12,26 @@ pub struct FilesUploadRequest {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileMetadata {
     pub id: ulid::Ulid,
-    pub path: String,
+    /// Path, name, exact date and other private attributes, sealed with the
+    /// file key (see [`crate::crypto::metadata`]). Required on upload; `None`
+    /// only for files uploaded before metadata was encrypted.
+    pub encrypted_metadata: Option<String>,
+    /// Plaintext path of a file uploaded before metadata was encrypted, for
+    /// its owner's client to seal (see [`SealMetadataRequest`]). Ignored on
+    /// upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_path: Option<String>,
+    /// Plaintext name of such a file, alongside `legacy_path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_name: Option<String>,
+    /// Capture date the server orders the library by. Clients may coarsen it;
+    /// the exact date lives in the sealed metadata.
     #[serde(with = "time::serde::iso8601")]
     pub date: OffsetDateTime,
-    pub sha256: String,
+    /// Keyed hash of the plaintext original (see
+    /// [`crate::crypto::content_hash`]). Required on upload; `None` only for
+    /// files uploaded before content hashes were keyed.
+    pub content_hash: Option<String>,
     pub key: String,
 
     /// What kind of media this is. Client-supplied (the server can't inspect
@@ -43,6 +59,90 @@ pub struct FileMetadata {
     pub enc_scheme: u8,
 }
 
+/// What happened to one file of a [`FilesUploadRequest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileUploadStatus {
+    Created,
+    /// A file with this id and the same content hash and key already exists.
+    AlreadyExists,
+    /// A different file of the user's with this id already exists.
+    Conflict,
+    /// The id can't be used. Reported the same whether or not another
+    /// user's file has it, so ids of other users' files stay hidden.
+    NotFound,
+    /// The metadata is invalid; `reason` says why.
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileUploadResult {
+    pub id: ulid::Ulid,
+    pub status: FileUploadStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// One result per requested file, in request order. Created files are
+/// persisted even when others in the batch are rejected.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FilesUploadResponse {
+    pub files: Vec<FileUploadResult>,
+}
+
+/// Sealed metadata for a file uploaded before metadata was encrypted. The
+/// server stores it and forgets the file's plaintext path and name.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealMetadataRequest {
+    pub encrypted_metadata: String,
+}
+
+/// Keyed content hash for a file uploaded before hashes were keyed. The
+/// server stores it and forgets the file's plaintext SHA-256.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentHashRequest {
+    pub content_hash: String,
+}
+
+/// Keyed content hashes to look up in the caller's library, so a client can
+/// skip uploading files it already has.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentLookupRequest {
+    pub content_hashes: Vec<String>,
+}
+
+/// A library file whose content matches a looked-up hash.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KnownContent {
+    pub content_hash: String,
+    pub file_id: ulid::Ulid,
+    /// The match is in the trash and can be restored instead of re-uploaded.
+    pub trashed: bool,
+}
+
+/// The matches among the requested hashes; hashes without one are omitted.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentLookupResponse {
+    pub files: Vec<KnownContent>,
+}
+
+/// One page of a user's synced files. Pass `next_cursor` back as the `cursor`
+/// query parameter to fetch the following page; `None` means this was the
+/// last one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileMetadataPage {
+    pub files: Vec<FileMetadata>,
+    pub next_cursor: Option<String>,
+}
+
+/// A file in the trash. It can be restored until the server purges it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashedFile {
+    pub file: FileMetadata,
+    #[serde(with = "time::serde::rfc3339")]
+    pub trashed_at: OffsetDateTime,
+}
+
 /// Response handed back for a download request: a short-lived presigned S3 GET
 /// URL the client fetches directly (supporting HTTP Range for seeking).
 #[derive(Debug, Serialize, Deserialize)]
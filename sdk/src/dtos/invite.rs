@@ -0,0 +1,43 @@
+use std::num::NonZeroU32;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+/// Admin request for an invite code. Unset fields get a single use and the
+/// server's default lifetime.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CreateInviteRequest {
+    #[serde(default)]
+    pub max_uses: Option<NonZeroU32>,
+    #[serde(default)]
+    pub expires_in_secs: Option<NonZeroU32>,
+    /// Who the invite is for, to tell invites apart.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteInfo {
+    pub id: Ulid,
+    pub note: Option<String>,
+    pub max_uses: u32,
+    pub uses: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub revoked_at: Option<OffsetDateTime>,
+    /// `None` once the admin who created it is deleted.
+    pub created_by: Option<Ulid>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// A new invite. The code is only ever shown here; the server keeps just its
+/// digest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedInvite {
+    #[serde(flatten)]
+    pub invite: InviteInfo,
+    pub code: String,
+}
@@ -1,2 +1,7 @@
+pub mod audit;
 pub mod auth;
 pub mod file;
+pub mod invite;
+pub mod quota;
+pub mod reconcile;
+pub mod sync;
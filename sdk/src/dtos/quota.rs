@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+use crate::media::MediaType;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaUsage {
+    pub media_type: MediaType,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Storage a user occupies. Synced and trashed files count towards the quota,
+/// as do bytes reserved by uploads still in progress.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageResponse {
+    pub used_bytes: u64,
+    pub pending_bytes: u64,
+    /// `None` means unlimited.
+    pub quota_bytes: Option<u64>,
+    pub by_media_type: Vec<MediaUsage>,
+}
+
+/// Admin override of a user's quota; `None` falls back to the server default.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetQuotaRequest {
+    pub quota_bytes: Option<u64>,
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StrayUpload {
+    pub key: String,
+    pub upload_id: String,
+}
+
+/// Objects and multipart uploads no file or upload session accounts for.
+/// Deleted unless `dry_run` is set.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    pub dry_run: bool,
+    pub stray_objects: Vec<String>,
+    pub stray_uploads: Vec<StrayUpload>,
+}
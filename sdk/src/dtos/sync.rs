@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::file::FileMetadata;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// Metadata was uploaded; the file becomes visible once it's synced.
+    Created,
+    /// The upload completed and the file can be downloaded.
+    Synced,
+    /// The file row changed in any other way.
+    Updated,
+    /// The file is gone for good; drop it locally.
+    Deleted,
+    /// The file was moved to the trash.
+    Trashed,
+    /// The file was restored from the trash and is synced again.
+    Restored,
+}
+
+/// One entry of a user's change feed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileChange {
+    pub seq: u64,
+    pub file_id: ulid::Ulid,
+    pub kind: ChangeKind,
+    #[serde(with = "time::serde::rfc3339")]
+    pub changed_at: OffsetDateTime,
+    /// Current metadata of the file if it's synced, `None` otherwise. Clients
+    /// upsert the file when it's present and remove it when it isn't, so
+    /// applying the feed in order leaves them with exactly the server state.
+    pub file: Option<FileMetadata>,
+}
+
+/// Changes after the requested sequence number, in order. Pass `next_since`
+/// back as `since` to continue; keep going while `has_more` is set.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeFeed {
+    pub changes: Vec<FileChange>,
+    pub next_since: u64,
+    pub has_more: bool,
+}